@@ -1,15 +1,26 @@
 // adds a "capability", which is a super naive implementation
 // to add things to the kube cluster.
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::net::Ipv4Addr;
+use std::path::Path;
 use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::{spawn_error, HakeError};
+use crate::kind::{Kind, HOST_PV_DIR};
+use crate::kubectl;
+use crate::logging::LogCommand;
 
 pub fn cert_manager() -> Result<()> {
     Command::new("kubectl")
         .arg("apply")
-        .arg("--validate=false")
         .arg("-f")
-        .arg("https://github.com/jetstack/cert-manager/releases/download/v0.15.0/cert-manager.yaml")
-        .output()?;
+        .arg(cert_manager_manifest(CERT_MANAGER_VERSION))
+        .logged()
+        .output()
+        .map_err(|e| spawn_error("kubectl", e))?;
 
     Ok(())
 }
@@ -21,7 +32,808 @@ pub fn ingress_nginx() -> Result<()> {
 fn run_kubectl(command: &str) -> Result<()> {
     Command::new("kubectl")
         .args(command.split(" ").collect::<Vec<&str>>())
-        .output()?;
+        .logged()
+        .output()
+        .map_err(|e| spawn_error("kubectl", e))?;
+
+    Ok(())
+}
+
+/// Runs kubectl against a cluster, failing with kubectl's error output.
+fn kubectl_on(cluster: &str, args: &[&str]) -> Result<()> {
+    let out = kubectl::output(cluster, args)?;
+    if !out.status.success() {
+        return Err(HakeError::CommandFailed {
+            command: format!("kubectl {}", args.join(" ")),
+            message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+pub fn ingress_manifest(controller: &str, provider: &str) -> Result<&'static str> {
+    match (controller, provider) {
+        ("nginx", "kind") => Ok("https://raw.githubusercontent.com/kubernetes/ingress-nginx/master/deploy/static/provider/kind/deploy.yaml"),
+        ("nginx", _) => Ok("https://raw.githubusercontent.com/kubernetes/ingress-nginx/master/deploy/static/provider/do/deploy.yaml"),
+        ("contour", _) => Ok("https://projectcontour.io/quickstart/contour.yaml"),
+        _ => Err(anyhow!("Unknown ingress controller: {}", controller)),
+    }
+}
+
+/// Installs an ingress controller on a cluster and waits for it to be ready.
+pub fn ingress(cluster: &str, controller: &str, provider: &str) -> Result<()> {
+    kubectl_on(
+        cluster,
+        &["apply", "-f", ingress_manifest(controller, provider)?],
+    )?;
+
+    match controller {
+        "nginx" => kubectl_on(
+            cluster,
+            &[
+                "rollout",
+                "status",
+                "deployment/ingress-nginx-controller",
+                "--namespace",
+                "ingress-nginx",
+                "--timeout=300s",
+            ],
+        ),
+        _ => {
+            if provider == "kind" {
+                // envoy has to run on the node with the host ports mapped.
+                kubectl_on(
+                    cluster,
+                    &[
+                        "patch",
+                        "daemonset",
+                        "envoy",
+                        "--namespace",
+                        "projectcontour",
+                        "-p",
+                        r#"{"spec":{"template":{"spec":{"nodeSelector":{"ingress-ready":"true"},"tolerations":[{"key":"node-role.kubernetes.io/control-plane","operator":"Equal","effect":"NoSchedule"},{"key":"node-role.kubernetes.io/master","operator":"Equal","effect":"NoSchedule"}]}}}}"#,
+                    ],
+                )?;
+            }
+            kubectl_on(
+                cluster,
+                &[
+                    "rollout",
+                    "status",
+                    "daemonset/envoy",
+                    "--namespace",
+                    "projectcontour",
+                    "--timeout=300s",
+                ],
+            )
+        }
+    }
+}
+
+/// cert-manager release installed by `--with-cert-manager` without a version.
+pub const CERT_MANAGER_VERSION: &str = "v1.14.4";
+/// Attempts at reaching the cert-manager webhook, 5 seconds apart, after its
+/// deployment is ready and before its CA is injected.
+const CERT_MANAGER_WEBHOOK_ATTEMPTS: u32 = 24;
+/// ClusterIssuer of self-signed certificates created by
+/// `--self-signed-issuer`.
+pub const SELF_SIGNED_ISSUER: &str = "hake-selfsigned";
+
+fn cert_manager_manifest(version: &str) -> String {
+    format!(
+        "https://github.com/cert-manager/cert-manager/releases/download/{}/cert-manager.yaml",
+        version
+    )
+}
+
+fn self_signed_issuer_manifest() -> String {
+    format!(
+        r#"apiVersion: cert-manager.io/v1
+kind: ClusterIssuer
+metadata:
+  name: {}
+spec:
+  selfSigned: {{}}
+"#,
+        SELF_SIGNED_ISSUER
+    )
+}
+
+/// Installs a release of cert-manager and waits for its webhook to accept
+/// resources, then creates a self-signed ClusterIssuer if asked to.
+pub fn cert_manager_release(cluster: &str, version: &str, self_signed_issuer: bool) -> Result<()> {
+    kubectl_on(cluster, &["apply", "-f", &cert_manager_manifest(version)])?;
+    kubectl_on(
+        cluster,
+        &[
+            "wait",
+            "deployment",
+            "--all",
+            "--for=condition=Available",
+            "--namespace",
+            "cert-manager",
+            "--timeout=300s",
+        ],
+    )?;
+
+    // the webhook is ready once it validates resources, which takes longer
+    // than its deployment as its CA is injected afterwards. A server side dry
+    // run is validated without creating the issuer.
+    let mut args = vec!["apply", "-f", "-"];
+    if !self_signed_issuer {
+        args.push("--dry-run=server");
+    }
+    let issuer = self_signed_issuer_manifest();
+    let mut attempts = 0;
+    loop {
+        let out = kubectl::output_with_input(cluster, &args, &issuer)?;
+        if out.status.success() {
+            return Ok(());
+        }
+        attempts += 1;
+        if attempts == CERT_MANAGER_WEBHOOK_ATTEMPTS {
+            return Err(anyhow!(
+                "The cert-manager webhook was not ready: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            ));
+        }
+        thread::sleep(Duration::from_secs(5));
+    }
+}
+
+/// Operator Lifecycle Manager release installed by `--with-olm` without a
+/// version.
+pub const OLM_VERSION: &str = "v0.27.0";
+
+fn olm_manifest(version: &str, file: &str) -> String {
+    format!(
+        "https://github.com/operator-framework/operator-lifecycle-manager/releases/download/{}/{}",
+        version, file
+    )
+}
+
+/// Installs a release of the Operator Lifecycle Manager and waits for its
+/// operators and the package server, which serves the catalogs, to be ready.
+pub fn olm(cluster: &str, version: &str) -> Result<()> {
+    // the CRDs are too large for the annotation of a client side apply
+    let crds = olm_manifest(version, "crds.yaml");
+    kubectl_on(cluster, &["apply", "--server-side", "-f", &crds])?;
+    kubectl_on(
+        cluster,
+        &[
+            "wait",
+            "--for=condition=Established",
+            "-f",
+            &crds,
+            "--timeout=120s",
+        ],
+    )?;
+    kubectl_on(
+        cluster,
+        &["apply", "-f", &olm_manifest(version, "olm.yaml")],
+    )?;
+    for deployment in ["olm-operator", "catalog-operator"] {
+        kubectl_on(
+            cluster,
+            &[
+                "rollout",
+                "status",
+                &format!("deployment/{}", deployment),
+                "--namespace",
+                "olm",
+                "--timeout=300s",
+            ],
+        )?;
+    }
+    kubectl_on(
+        cluster,
+        &[
+            "wait",
+            "csv/packageserver",
+            "--for=jsonpath={.status.phase}=Succeeded",
+            "--namespace",
+            "olm",
+            "--timeout=300s",
+        ],
+    )
+}
+
+pub const CNIS: &[&str] = &["calico", "cilium", "none"];
+
+const CALICO_MANIFEST: &str =
+    "https://raw.githubusercontent.com/projectcalico/calico/v3.26.1/manifests/calico.yaml";
+
+/// Installs a CNI on a cluster created without the default one.
+pub fn cni(cluster: &str, cni: &str) -> Result<()> {
+    match cni {
+        "calico" => {
+            kubectl_on(cluster, &["apply", "-f", CALICO_MANIFEST])?;
+            kubectl_on(
+                cluster,
+                &[
+                    "rollout",
+                    "status",
+                    "daemonset/calico-node",
+                    "--namespace",
+                    "kube-system",
+                    "--timeout=300s",
+                ],
+            )
+        }
+        "cilium" => {
+            let status = kubectl::command(cluster, "cilium")
+                .args(["install", "--wait"])
+                .status()
+                .map_err(|_| anyhow!("Could not find the cilium CLI in PATH"))?;
+            if !status.success() {
+                return Err(anyhow!("cilium install failed"));
+            }
+
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+const METALLB_MANIFEST: &str =
+    "https://raw.githubusercontent.com/metallb/metallb/v0.13.12/config/manifests/metallb-native.yaml";
+
+/// Takes a range of addresses from the end of a subnet like 172.18.0.0/16, to
+/// be handed out to LoadBalancer services without colliding with the nodes.
+fn loadbalancer_address_range(subnet: &str) -> Option<String> {
+    let parts: Vec<&str> = subnet.split('/').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+    let network: Ipv4Addr = parts[0].parse().ok()?;
+    let prefix: u32 = parts[1].parse().ok()?;
+    if prefix == 0 || prefix > 24 {
+        return None;
+    }
+
+    let broadcast = u32::from(network) | (u32::MAX >> prefix);
+    Some(format!(
+        "{}-{}",
+        Ipv4Addr::from(broadcast - 55),
+        Ipv4Addr::from(broadcast - 5)
+    ))
+}
+
+/// Installs MetalLB on a kind cluster with an address pool taken from the
+/// docker network of its nodes, so LoadBalancer services get an IP reachable
+/// from the host.
+pub fn loadbalancer(cluster: &str) -> Result<()> {
+    let subnet = Kind::get_network_subnet(&Kind::cluster_network(cluster))?;
+    let range = loadbalancer_address_range(&subnet)
+        .ok_or_else(|| anyhow!("Subnet {} is too small for a LoadBalancer pool", subnet))?;
+
+    kubectl_on(cluster, &["apply", "-f", METALLB_MANIFEST])?;
+    kubectl_on(
+        cluster,
+        &[
+            "rollout",
+            "status",
+            "deployment/controller",
+            "--namespace",
+            "metallb-system",
+            "--timeout=300s",
+        ],
+    )?;
+
+    let pool = format!(
+        r#"apiVersion: metallb.io/v1beta1
+kind: IPAddressPool
+metadata:
+  name: hake
+  namespace: metallb-system
+spec:
+  addresses:
+  - {}
+---
+apiVersion: metallb.io/v1beta1
+kind: L2Advertisement
+metadata:
+  name: hake
+  namespace: metallb-system
+"#,
+        range
+    );
+    let out = kubectl::apply_manifest(cluster, &pool)?;
+    if !out.status.success() {
+        return Err(anyhow!(
+            "Could not configure MetalLB address pool: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+const DASHBOARD_MANIFEST: &str =
+    "https://raw.githubusercontent.com/kubernetes/dashboard/v2.7.0/aio/deploy/recommended.yaml";
+pub const DASHBOARD_NAMESPACE: &str = "kubernetes-dashboard";
+/// ServiceAccount bound to cluster-admin that logs in to the dashboard.
+pub const DASHBOARD_ADMIN: &str = "hake-admin";
+
+fn dashboard_admin_manifest() -> String {
+    format!(
+        r#"apiVersion: v1
+kind: ServiceAccount
+metadata:
+  name: {admin}
+  namespace: {namespace}
+---
+apiVersion: rbac.authorization.k8s.io/v1
+kind: ClusterRoleBinding
+metadata:
+  name: {admin}
+roleRef:
+  apiGroup: rbac.authorization.k8s.io
+  kind: ClusterRole
+  name: cluster-admin
+subjects:
+- kind: ServiceAccount
+  name: {admin}
+  namespace: {namespace}
+"#,
+        admin = DASHBOARD_ADMIN,
+        namespace = DASHBOARD_NAMESPACE
+    )
+}
+
+/// Installs the Kubernetes Dashboard with an admin ServiceAccount to log in
+/// with, and waits for it to be ready.
+pub fn dashboard(cluster: &str) -> Result<()> {
+    kubectl_on(cluster, &["apply", "-f", DASHBOARD_MANIFEST])?;
+    let out = kubectl::apply_manifest(cluster, &dashboard_admin_manifest())?;
+    if !out.status.success() {
+        return Err(anyhow!(
+            "Could not create the dashboard admin: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    kubectl_on(
+        cluster,
+        &[
+            "rollout",
+            "status",
+            "deployment/kubernetes-dashboard",
+            "--namespace",
+            DASHBOARD_NAMESPACE,
+            "--timeout=300s",
+        ],
+    )
+}
+
+/// Persistent volumes created for `--pv`.
+const HOST_VOLUMES: u32 = 10;
+const HOST_VOLUME_SIZE: &str = "10Gi";
+
+/// StorageClass and persistent volumes in subdirectories of the host directory
+/// mounted in the nodes. Every node sees the same directory, so the data
+/// follows the pods to any node, and volumes are retained after their claims
+/// are deleted.
+fn host_volumes_manifest(count: u32) -> String {
+    let mut manifest = String::from(
+        r#"apiVersion: storage.k8s.io/v1
+kind: StorageClass
+metadata:
+  name: hake-host
+provisioner: kubernetes.io/no-provisioner
+reclaimPolicy: Retain
+volumeBindingMode: Immediate
+"#,
+    );
+    for i in 1..=count {
+        manifest.push_str(&format!(
+            r#"---
+apiVersion: v1
+kind: PersistentVolume
+metadata:
+  name: hake-pv-{i}
+spec:
+  storageClassName: hake-host
+  capacity:
+    storage: {size}
+  accessModes:
+  - ReadWriteOnce
+  - ReadWriteMany
+  persistentVolumeReclaimPolicy: Retain
+  hostPath:
+    path: {dir}/pv-{i}
+    type: DirectoryOrCreate
+"#,
+            i = i,
+            size = HOST_VOLUME_SIZE,
+            dir = HOST_PV_DIR
+        ));
+    }
+
+    manifest
+}
+
+/// Creates the hake-host StorageClass with volumes backed by the host
+/// directory given to `--pv`.
+pub fn host_volumes(cluster: &str) -> Result<()> {
+    let out = kubectl::apply_manifest(cluster, &host_volumes_manifest(HOST_VOLUMES))?;
+    if !out.status.success() {
+        return Err(anyhow!(
+            "Could not create the host persistent volumes: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
 
     Ok(())
 }
+
+const NVIDIA_DEVICE_PLUGIN_MANIFEST: &str =
+    "https://raw.githubusercontent.com/NVIDIA/k8s-device-plugin/v0.14.5/nvidia-device-plugin.yml";
+
+/// Installs the NVIDIA device plugin, which advertises the nvidia.com/gpu
+/// resource of the nodes.
+pub fn gpu_device_plugin(cluster: &str) -> Result<()> {
+    kubectl_on(cluster, &["apply", "-f", NVIDIA_DEVICE_PLUGIN_MANIFEST])?;
+    kubectl_on(
+        cluster,
+        &[
+            "rollout",
+            "status",
+            "daemonset/nvidia-device-plugin-daemonset",
+            "--namespace",
+            "kube-system",
+            "--timeout=300s",
+        ],
+    )
+}
+
+/// A Helm chart to install, from `--helm repo/chart[@version][:values.yaml]`.
+#[derive(Debug, PartialEq)]
+pub struct HelmChart {
+    pub chart: String,
+    pub version: Option<String>,
+    pub values: Option<String>,
+}
+
+/// Splits a chart like [oci://]repo/chart[@version][:values.yaml] into the
+/// chart, with its version, and the values file.
+pub fn split_values(spec: &str) -> (&str, Option<&str>) {
+    // oci:// references have a colon of their own
+    let start = if spec.starts_with("oci://") {
+        "oci://".len()
+    } else {
+        0
+    };
+    match spec[start..].find(':') {
+        Some(i) => (&spec[..start + i], Some(&spec[start + i + 1..])),
+        None => (spec, None),
+    }
+}
+
+impl HelmChart {
+    pub fn parse(spec: &str) -> Result<HelmChart> {
+        let (chart, values) = split_values(spec);
+        let values = values.map(String::from);
+        let (scheme, chart) = match chart.strip_prefix("oci://") {
+            Some(chart) => ("oci://", chart),
+            None => ("", chart),
+        };
+        let (chart, version) = match chart.split_once('@') {
+            Some((chart, version)) => (chart, Some(String::from(version))),
+            None => (chart, None),
+        };
+        if !chart.contains('/') || chart.ends_with('/') {
+            return Err(anyhow!(
+                "Invalid chart {}, expected repo/chart[@version][:values.yaml]",
+                spec
+            ));
+        }
+
+        Ok(HelmChart {
+            chart: format!("{}{}", scheme, chart),
+            version,
+            values,
+        })
+    }
+
+    /// Name of the release, and of its namespace: the name of the chart.
+    pub fn release(&self) -> &str {
+        self.chart.rsplit('/').next().unwrap_or(&self.chart)
+    }
+}
+
+/// Installs a Helm chart in a namespace named after it and waits for its
+/// resources to be ready.
+pub fn helm(cluster: &str, chart: &HelmChart) -> Result<()> {
+    let release = chart.release();
+    let mut command = kubectl::command(cluster, "helm");
+    command.args([
+        "upgrade",
+        "--install",
+        release,
+        &chart.chart,
+        "--namespace",
+        release,
+        "--create-namespace",
+        "--wait",
+    ]);
+    if let Some(version) = &chart.version {
+        command.args(["--version", version]);
+    }
+    if let Some(values) = &chart.values {
+        command.args(["--values", values]);
+    }
+
+    let out = command
+        .logged()
+        .output()
+        .map_err(|e| spawn_error("helm", e))?;
+    if !out.status.success() {
+        return Err(HakeError::CommandFailed {
+            command: format!("helm install {}", chart.chart),
+            message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+pub fn is_url(manifest: &str) -> bool {
+    manifest.starts_with("http://") || manifest.starts_with("https://")
+}
+
+/// Fails early for manifests that are not a URL nor an existing path.
+pub fn check_manifest(manifest: &str) -> Result<()> {
+    if !is_url(manifest) && !Path::new(manifest).exists() {
+        return Err(anyhow!("Manifest {} does not exist", manifest));
+    }
+
+    Ok(())
+}
+
+/// Objects in the output of `kubectl apply -o json`, a List or a single one.
+fn applied_objects(applied: &Value) -> Vec<&Value> {
+    match applied["items"].as_array() {
+        Some(items) => items.iter().collect(),
+        None => vec![applied],
+    }
+}
+
+/// kubectl arguments to wait for the objects returned by `kubectl apply -o
+/// json` that take a while to be ready: workloads and CRDs.
+fn wait_commands(applied: &Value) -> Vec<Vec<String>> {
+    applied_objects(applied)
+        .into_iter()
+        .filter_map(|object| {
+            let name = object["metadata"]["name"].as_str()?;
+            let namespace = object["metadata"]["namespace"]
+                .as_str()
+                .unwrap_or("default");
+            let args = match object["kind"].as_str()? {
+                kind @ "Deployment" | kind @ "DaemonSet" | kind @ "StatefulSet" => vec![
+                    String::from("rollout"),
+                    String::from("status"),
+                    format!("{}/{}", kind.to_lowercase(), name),
+                    String::from("--namespace"),
+                    String::from(namespace),
+                    String::from("--timeout=300s"),
+                ],
+                "CustomResourceDefinition" => vec![
+                    String::from("wait"),
+                    String::from("--for"),
+                    String::from("condition=established"),
+                    String::from("--timeout=60s"),
+                    format!("crd/{}", name),
+                ],
+                _ => return None,
+            };
+
+            Some(args)
+        })
+        .collect()
+}
+
+/// Applies manifests from a file, directory or URL and waits for the
+/// workloads and CRDs they create to be ready. Objects without a namespace go
+/// to `namespace`, or to the default one.
+pub fn apply(cluster: &str, manifest: &str, namespace: Option<&str>) -> Result<()> {
+    let mut args = vec!["apply", "-f", manifest, "-o", "json"];
+    if let Some(namespace) = namespace {
+        args.extend(&["--namespace", namespace]);
+    }
+    let out = kubectl::output(cluster, &args)?;
+    if !out.status.success() {
+        return Err(HakeError::CommandFailed {
+            command: format!("kubectl apply -f {}", manifest),
+            message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    let applied: Value = serde_json::from_slice(&out.stdout)?;
+    for args in wait_commands(&applied) {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        kubectl_on(cluster, &args)?;
+    }
+
+    Ok(())
+}
+
+/// Names of the CRDs returned by `kubectl apply -o json`, failing for any
+/// other kind of object as `--crd` is meant for CRDs only.
+fn crd_names(manifest: &str, applied: &Value) -> Result<Vec<String>> {
+    applied_objects(applied)
+        .into_iter()
+        .map(|object| {
+            let kind = object["kind"].as_str().unwrap_or_default();
+            let name = object["metadata"]["name"].as_str().unwrap_or_default();
+            if kind != "CustomResourceDefinition" {
+                return Err(anyhow!(
+                    "{} has a {} {}, only CRDs can be installed with --crd, use --apply for the rest",
+                    manifest,
+                    kind,
+                    name
+                ));
+            }
+
+            Ok(format!("crd/{}", name))
+        })
+        .collect()
+}
+
+/// Applies the CRDs in a file, directory or URL and waits for the API server
+/// to serve them, failing for those that do not become Established.
+pub fn crds(cluster: &str, manifest: &str) -> Result<()> {
+    let out = kubectl::output(cluster, &["apply", "-f", manifest, "-o", "json"])?;
+    if !out.status.success() {
+        return Err(HakeError::CommandFailed {
+            command: format!("kubectl apply -f {}", manifest),
+            message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    let applied: Value = serde_json::from_slice(&out.stdout)?;
+    let names = crd_names(manifest, &applied)?;
+    if names.is_empty() {
+        return Err(anyhow!("{} has no CRDs", manifest));
+    }
+    let mut args = vec!["wait", "--for", "condition=established", "--timeout=60s"];
+    args.extend(names.iter().map(String::as_str));
+    let out = kubectl::output(cluster, &args)?;
+    if !out.status.success() {
+        return Err(anyhow!(
+            "CRDs in {} were not Established: {}",
+            manifest,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::add::{
+        crd_names, host_volumes_manifest, loadbalancer_address_range, wait_commands, HelmChart,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_host_volumes_manifest() {
+        let manifest = host_volumes_manifest(2);
+        let docs: Vec<serde_yaml::Value> = manifest
+            .split("---\n")
+            .map(|doc| serde_yaml::from_str(doc).unwrap())
+            .collect();
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[0]["kind"].as_str(), Some("StorageClass"));
+        assert_eq!(
+            docs[2]["spec"]["hostPath"]["path"].as_str(),
+            Some("/var/hake-pv/pv-2")
+        );
+    }
+
+    #[test]
+    fn test_loadbalancer_address_range() {
+        assert_eq!(
+            loadbalancer_address_range("172.18.0.0/16"),
+            Some(String::from("172.18.255.200-172.18.255.250"))
+        );
+        assert_eq!(
+            loadbalancer_address_range("192.168.10.0/24"),
+            Some(String::from("192.168.10.200-192.168.10.250"))
+        );
+        assert_eq!(loadbalancer_address_range("192.168.10.0/28"), None);
+        assert_eq!(loadbalancer_address_range("fc00:f853::/64"), None);
+        assert_eq!(loadbalancer_address_range("172.18.0.0"), None);
+    }
+
+    #[test]
+    fn test_parse_helm_chart() {
+        assert_eq!(
+            HelmChart::parse("bitnami/redis").unwrap(),
+            HelmChart {
+                chart: String::from("bitnami/redis"),
+                version: None,
+                values: None,
+            }
+        );
+        let chart = HelmChart::parse("jetstack/cert-manager@v1.0.1:values/cm.yaml").unwrap();
+        assert_eq!(chart.chart, "jetstack/cert-manager");
+        assert_eq!(chart.version.as_deref(), Some("v1.0.1"));
+        assert_eq!(chart.values.as_deref(), Some("values/cm.yaml"));
+        assert_eq!(chart.release(), "cert-manager");
+
+        let chart = HelmChart::parse("oci://registry.local/charts/app@1.2.3").unwrap();
+        assert_eq!(chart.chart, "oci://registry.local/charts/app");
+        assert_eq!(chart.version.as_deref(), Some("1.2.3"));
+        assert_eq!(chart.release(), "app");
+
+        assert!(HelmChart::parse("redis").is_err());
+        assert!(HelmChart::parse("bitnami/").is_err());
+    }
+
+    #[test]
+    fn test_wait_commands() {
+        let applied = json!({"kind": "List", "items": [
+            {"kind": "Namespace", "metadata": {"name": "operator"}},
+            {"kind": "CustomResourceDefinition", "metadata": {"name": "widgets.example.com"}},
+            {"kind": "Deployment", "metadata": {"name": "operator", "namespace": "operator"}}
+        ]});
+
+        assert_eq!(
+            wait_commands(&applied),
+            vec![
+                vec![
+                    "wait",
+                    "--for",
+                    "condition=established",
+                    "--timeout=60s",
+                    "crd/widgets.example.com"
+                ],
+                vec![
+                    "rollout",
+                    "status",
+                    "deployment/operator",
+                    "--namespace",
+                    "operator",
+                    "--timeout=300s"
+                ],
+            ]
+        );
+
+        let applied = json!({"kind": "DaemonSet", "metadata": {"name": "agent"}});
+        assert_eq!(
+            wait_commands(&applied),
+            vec![vec![
+                "rollout",
+                "status",
+                "daemonset/agent",
+                "--namespace",
+                "default",
+                "--timeout=300s"
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_crd_names() {
+        let applied = json!({"kind": "List", "items": [
+            {"kind": "CustomResourceDefinition", "metadata": {"name": "widgets.example.com"}},
+            {"kind": "CustomResourceDefinition", "metadata": {"name": "gadgets.example.com"}}
+        ]});
+        assert_eq!(
+            crd_names("crds/", &applied).unwrap(),
+            vec!["crd/widgets.example.com", "crd/gadgets.example.com"]
+        );
+
+        let applied = json!({"kind": "Deployment", "metadata": {"name": "operator"}});
+        let err = crd_names("deploy.yaml", &applied).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("deploy.yaml has a Deployment operator"));
+    }
+}
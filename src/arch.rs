@@ -0,0 +1,179 @@
+// Architecture of the node images. kind runs its nodes on the architecture of
+// the docker host, and an image without a variant for it, like most older
+// kindest/node releases on Apple Silicon, runs emulated and tends to break.
+use anyhow::{anyhow, Result};
+use console::Style;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use std::process::Stdio;
+
+use crate::error::{spawn_error, HakeError};
+use crate::logging::LogCommand;
+use crate::runtime;
+
+pub const ARCHS: &[&str] = &["amd64", "arm64"];
+
+/// Architecture in the names docker images use, like amd64 for x86_64.
+pub fn normalize(arch: &str) -> String {
+    match arch {
+        "x86_64" | "x86-64" | "amd64" => String::from("amd64"),
+        "aarch64" | "arm64" | "arm64/v8" => String::from("arm64"),
+        arch => String::from(arch),
+    }
+}
+
+pub fn parse(arch: &str) -> Result<String> {
+    let arch = normalize(arch);
+    if !ARCHS.contains(&arch.as_str()) {
+        return Err(anyhow!(
+            "Unknown architecture {}, expected one of: {}",
+            arch,
+            ARCHS.join(", ")
+        ));
+    }
+
+    Ok(arch)
+}
+
+/// Architecture of the docker host, which can differ from the one of hake,
+/// like with Rosetta.
+pub fn host() -> String {
+    let out = runtime::command()
+        .args(["info", "--format", "{{.Architecture}}"])
+        .logged()
+        .output();
+    match out {
+        Ok(out) if out.status.success() => normalize(String::from_utf8_lossy(&out.stdout).trim()),
+        _ => normalize(std::env::consts::ARCH),
+    }
+}
+
+/// Architectures in the output of `docker manifest inspect`, empty for
+/// single-architecture manifests, which do not list them.
+fn manifest_archs(manifest: &str) -> Vec<String> {
+    let manifest: Value = match serde_json::from_str(manifest) {
+        Ok(manifest) => manifest,
+        Err(_) => return vec![],
+    };
+
+    manifest["manifests"]
+        .as_array()
+        .map(|manifests| {
+            manifests
+                .iter()
+                .filter_map(|m| m["platform"]["architecture"].as_str())
+                .map(normalize)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Architectures an image is published for, None when they cannot be told,
+/// like for images only in the local docker.
+pub fn image_archs(image: &str) -> Option<Vec<String>> {
+    let out = runtime::command()
+        .args(["manifest", "inspect", image])
+        .stderr(Stdio::null())
+        .logged()
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let archs = manifest_archs(&String::from_utf8_lossy(&out.stdout));
+
+    if archs.is_empty() {
+        None
+    } else {
+        Some(archs)
+    }
+}
+
+/// Checks that a node image has a variant for `arch`, the one of the host
+/// unless given. An architecture other than the host's is pulled so kind
+/// runs it emulated.
+pub fn prepare_node_image(image: Option<&str>, arch: Option<&str>) -> Result<()> {
+    if image.is_none() && arch.is_none() {
+        return Ok(());
+    }
+    let host = host();
+    let image = match (image, arch) {
+        (Some(image), _) => image,
+        (None, Some(arch)) if arch != host => {
+            return Err(anyhow!(
+                "--arch {} needs --image, kind picks its default image for the host",
+                arch
+            ));
+        }
+        (None, _) => return Ok(()),
+    };
+    let target = arch.map(String::from).unwrap_or_else(|| host.clone());
+
+    if let Some(archs) = image_archs(image) {
+        if !archs.contains(&target) {
+            warn!(
+                "Image {} has no {} variant, only {}. The nodes will run emulated and are likely to break, pick an image built for {}",
+                image,
+                target,
+                archs.join(", "),
+                target
+            );
+        }
+    }
+
+    if target != host {
+        let cyan = Style::new().cyan();
+        info!(
+            "Pulling {} for {}, it runs emulated on this {} host",
+            cyan.apply_to(image),
+            cyan.apply_to(&target),
+            host
+        );
+        let platform = format!("linux/{}", target);
+        let args = ["pull", "--platform", &platform, image];
+        let status = runtime::command()
+            .args(args)
+            .stdout(Stdio::null())
+            .logged()
+            .status()
+            .map_err(|e| spawn_error(runtime::binary(), e))?;
+        if !status.success() {
+            return Err(HakeError::CommandFailed {
+                command: format!("{} {}", runtime::binary(), args.join(" ")),
+                message: format!("exit status {}", status),
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arch::{manifest_archs, normalize, parse};
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("x86_64"), "amd64");
+        assert_eq!(normalize("aarch64"), "arm64");
+        assert_eq!(normalize("arm64"), "arm64");
+        assert_eq!(parse("aarch64").unwrap(), "arm64");
+        assert!(parse("s390x").is_err());
+    }
+
+    #[test]
+    fn test_manifest_archs() {
+        let manifest = r#"{
+            "schemaVersion": 2,
+            "manifests": [
+                {"digest": "sha256:1", "platform": {"architecture": "amd64", "os": "linux"}},
+                {"digest": "sha256:2", "platform": {"architecture": "arm64", "os": "linux"}}
+            ]
+        }"#;
+        assert_eq!(manifest_archs(manifest), vec!["amd64", "arm64"]);
+        assert!(manifest_archs(r#"{"schemaVersion": 2, "layers": []}"#).is_empty());
+        assert!(manifest_archs("not json").is_empty());
+    }
+}
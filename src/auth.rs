@@ -0,0 +1,59 @@
+// Provider API keys kept in the OS keychain (macOS Keychain, Secret Service,
+// Windows Credential Manager), as an alternative to environment variables.
+use anyhow::{anyhow, Result};
+use keyring::Entry;
+
+const SERVICE: &str = "hake";
+
+/// Providers that need credentials, by the name used in the keychain.
+pub const PROVIDERS: &[&str] = &["digitalocean"];
+
+/// Accepts the same provider aliases as `create --provider`.
+pub fn provider_name(provider: &str) -> Result<&'static str> {
+    let provider = match provider {
+        "do" => "digitalocean",
+        provider => provider,
+    };
+
+    PROVIDERS
+        .iter()
+        .find(|p| **p == provider)
+        .copied()
+        .ok_or_else(|| anyhow!("Provider {} does not use credentials", provider))
+}
+
+fn entry(provider: &str) -> Result<Entry> {
+    Ok(Entry::new(SERVICE, provider_name(provider)?)?)
+}
+
+pub fn store_token(provider: &str, token: &str) -> Result<()> {
+    Ok(entry(provider)?.set_password(token)?)
+}
+
+/// Reads the token of a provider, None if it was never stored.
+pub fn token(provider: &str) -> Result<Option<String>> {
+    match entry(provider)?.get_password() {
+        Ok(token) => Ok(Some(token)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn delete_token(provider: &str) -> Result<()> {
+    match entry(provider)?.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::provider_name;
+
+    #[test]
+    fn test_provider_name() {
+        assert_eq!(provider_name("do").unwrap(), "digitalocean");
+        assert_eq!(provider_name("digitalocean").unwrap(), "digitalocean");
+        assert!(provider_name("kind").is_err());
+    }
+}
@@ -0,0 +1,186 @@
+// Creation of several clusters at once, like base-1 to base-5 for shards of
+// an e2e suite. Every cluster is created by its own hake process, so each
+// one takes its own lock and gets its own history entry, and a failure in
+// one of them does not take down the others.
+use anyhow::{anyhow, Result};
+use console::Style;
+use tracing::info;
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::spawn_error;
+use crate::logging::LogCommand;
+use crate::logs::prefix_lines;
+use crate::table;
+
+/// Options that only make sense for the batch, and are not passed on to the
+/// processes creating each cluster.
+const BATCH_OPTIONS: &[&str] = &["--name", "--count", "--parallel"];
+
+/// Names of the clusters of a batch: base-1, base-2...
+pub fn names(base: &str, count: u16) -> Vec<String> {
+    (1..=count).map(|i| format!("{}-{}", base, i)).collect()
+}
+
+/// Arguments to create a single cluster of the batch, from the arguments
+/// hake was run with.
+fn child_args(args: &[String], name: &str) -> Vec<String> {
+    let mut child = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if BATCH_OPTIONS.contains(&arg.as_str()) {
+            args.next();
+            continue;
+        }
+        if BATCH_OPTIONS
+            .iter()
+            .any(|option| arg.starts_with(&format!("{}=", option)))
+        {
+            continue;
+        }
+        child.push(arg.clone());
+    }
+    child.push(String::from("--name"));
+    child.push(String::from(name));
+
+    child
+}
+
+struct Outcome {
+    name: String,
+    success: bool,
+    duration: Duration,
+}
+
+fn create_one(exe: &Path, args: &[String], name: &str) -> Result<bool> {
+    let prefix = Style::new()
+        .cyan()
+        .apply_to(format!("[{}]", name))
+        .to_string();
+    let mut child = Command::new(exe)
+        .args(child_args(args, name))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged()
+        .spawn()
+        .map_err(|e| spawn_error(&exe.to_string_lossy(), e))?;
+    let readers = vec![
+        prefix_lines(prefix.clone(), child.stdout.take().unwrap()),
+        prefix_lines(prefix, child.stderr.take().unwrap()),
+    ];
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    Ok(child.wait()?.success())
+}
+
+/// Creates `count` clusters named after `base`, at most `parallel` at a time,
+/// by running `exe` with the create arguments in `args` for each of them.
+/// Prints a summary and fails if any of them did.
+pub fn create(exe: &Path, args: &[String], base: &str, count: u16, parallel: usize) -> Result<()> {
+    let cyan = Style::new().cyan();
+    info!(
+        "Creating {} clusters: {}-1 to {}-{}",
+        count,
+        cyan.apply_to(base),
+        base,
+        count
+    );
+
+    let queue = Arc::new(Mutex::new(
+        names(base, count).into_iter().collect::<VecDeque<_>>(),
+    ));
+    let outcomes = Arc::new(Mutex::new(vec![]));
+    let workers: Vec<_> = (0..parallel.max(1).min(count as usize))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let outcomes = Arc::clone(&outcomes);
+            let exe = exe.to_path_buf();
+            let args = args.to_vec();
+            thread::spawn(move || loop {
+                let name = match queue.lock().unwrap().pop_front() {
+                    Some(name) => name,
+                    None => break,
+                };
+                let start = Instant::now();
+                let success = match create_one(&exe, &args, &name) {
+                    Ok(success) => success,
+                    Err(e) => {
+                        eprintln!("[{}] {:#}", name, e);
+                        false
+                    }
+                };
+                outcomes.lock().unwrap().push(Outcome {
+                    name,
+                    success,
+                    duration: start.elapsed(),
+                });
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let mut outcomes = outcomes.lock().unwrap();
+    outcomes.sort_by_key(|outcome| names(base, count).iter().position(|n| *n == outcome.name));
+    let mut rows = vec![vec![
+        String::from("NAME"),
+        String::from("RESULT"),
+        String::from("DURATION"),
+    ]];
+    for outcome in outcomes.iter() {
+        rows.push(vec![
+            outcome.name.clone(),
+            String::from(if outcome.success { "created" } else { "failed" }),
+            format!("{}s", outcome.duration.as_secs()),
+        ]);
+    }
+    table::print(&rows);
+
+    let failed = outcomes.iter().filter(|outcome| !outcome.success).count();
+    if failed > 0 {
+        return Err(anyhow!("{} of {} clusters failed", failed, count));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::{child_args, names};
+
+    #[test]
+    fn test_names() {
+        assert_eq!(names("e2e", 3), vec!["e2e-1", "e2e-2", "e2e-3"]);
+        assert!(names("e2e", 0).is_empty());
+    }
+
+    #[test]
+    fn test_child_args() {
+        let args: Vec<String> = vec![
+            "-v",
+            "create",
+            "--name",
+            "e2e",
+            "--count=3",
+            "--workers",
+            "2",
+            "--parallel",
+            "2",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(
+            child_args(&args, "e2e-2"),
+            vec!["-v", "create", "--workers", "2", "--name", "e2e-2"]
+        );
+    }
+}
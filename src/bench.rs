@@ -0,0 +1,154 @@
+// Benchmarks cluster creation: creates and deletes a cluster a number of times
+// and reports how long each phase took, to compare kind releases, container
+// engines or cache settings.
+use anyhow::Result;
+use console::Style;
+use tracing::info;
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::cluster::{self, CreateOptions};
+use crate::table;
+
+/// Generating the kind configuration, registry credentials included.
+pub const CONFIG: &str = "config";
+/// Running `kind create cluster`.
+pub const KIND_CREATE: &str = "kind create";
+/// Installing the addons once the cluster is up.
+pub const ADDONS: &str = "addons";
+const DELETE: &str = "delete";
+const TOTAL: &str = "total";
+
+/// Phases timed in the current run, when benchmarking.
+static PHASES: Mutex<Option<Vec<(&'static str, Duration)>>> = Mutex::new(None);
+
+/// Records how long a phase took, if a benchmark is running.
+pub fn record(phase: &'static str, duration: Duration) {
+    if let Some(phases) = PHASES.lock().unwrap().as_mut() {
+        phases.push((phase, duration));
+    }
+}
+
+fn start_run() {
+    *PHASES.lock().unwrap() = Some(vec![]);
+}
+
+fn finish_run() -> Vec<(&'static str, Duration)> {
+    PHASES.lock().unwrap().take().unwrap_or_default()
+}
+
+/// Nearest-rank percentile of sorted durations.
+fn percentile(sorted: &[Duration], p: u32) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+    let rank = (sorted.len() * p as usize).div_ceil(100);
+
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+/// Table with the percentiles of every phase, in the order they ran.
+fn summary(runs: &[Vec<(&'static str, Duration)>]) -> Vec<Vec<String>> {
+    let mut phases: Vec<&str> = vec![];
+    for (phase, _) in runs.iter().flatten() {
+        if !phases.contains(phase) {
+            phases.push(phase);
+        }
+    }
+
+    let mut rows = vec![["PHASE", "RUNS", "MIN", "P50", "P90", "P99", "MAX"]
+        .iter()
+        .map(|h| h.to_string())
+        .collect::<Vec<String>>()];
+    for phase in phases {
+        let mut durations: Vec<Duration> = runs
+            .iter()
+            .flatten()
+            .filter(|(p, _)| *p == phase)
+            .map(|(_, d)| *d)
+            .collect();
+        durations.sort();
+        rows.push(vec![
+            phase.to_string(),
+            durations.len().to_string(),
+            format_duration(durations[0]),
+            format_duration(percentile(&durations, 50)),
+            format_duration(percentile(&durations, 90)),
+            format_duration(percentile(&durations, 99)),
+            format_duration(durations[durations.len() - 1]),
+        ]);
+    }
+
+    rows
+}
+
+/// Creates and deletes a cluster `runs` times and prints the timings of
+/// every phase. A failed run stops the benchmark.
+pub fn bench(opts: CreateOptions, runs: u32) -> Result<()> {
+    let cyan = Style::new().cyan();
+    let name = opts.name.clone();
+    let mut results = vec![];
+    for run in 1..=runs {
+        info!(
+            "Benchmark run: {}",
+            cyan.apply_to(format!("{}/{}", run, runs))
+        );
+        start_run();
+        let started = Instant::now();
+        let created = cluster::create(opts.clone());
+        let deleting = Instant::now();
+        let deleted = cluster::delete(&name);
+        record(DELETE, deleting.elapsed());
+        record(TOTAL, started.elapsed());
+        let phases = finish_run();
+        created?;
+        deleted?;
+        results.push(phases);
+    }
+
+    table::print(&summary(&results));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench::{percentile, summary};
+    use std::time::Duration;
+
+    #[test]
+    fn test_percentile() {
+        let durations: Vec<Duration> = (1..=10).map(Duration::from_secs).collect();
+        assert_eq!(percentile(&durations, 50), Duration::from_secs(5));
+        assert_eq!(percentile(&durations, 90), Duration::from_secs(9));
+        assert_eq!(percentile(&durations, 99), Duration::from_secs(10));
+        assert_eq!(percentile(&durations[..1], 50), Duration::from_secs(1));
+        assert_eq!(percentile(&[], 50), Duration::default());
+    }
+
+    #[test]
+    fn test_summary() {
+        let runs = vec![
+            vec![
+                ("config", Duration::from_millis(200)),
+                ("kind create", Duration::from_secs(30)),
+            ],
+            vec![
+                ("config", Duration::from_millis(100)),
+                ("kind create", Duration::from_secs(40)),
+            ],
+        ];
+        let rows = summary(&runs);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[1],
+            vec!["config", "2", "0.1s", "0.1s", "0.2s", "0.2s", "0.2s"]
+        );
+        assert_eq!(rows[2][0], "kind create");
+    }
+}
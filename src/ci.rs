@@ -0,0 +1,127 @@
+// CI mode, turned on with --ci or by the CI environment variable most CI
+// systems set: no colors, spinners or prompts, timestamped logs, and a time
+// limit on the steps that could otherwise hang a pipeline.
+use anyhow::Result;
+
+use std::env;
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::deadline;
+use crate::error::HakeError;
+
+static CI: AtomicBool = AtomicBool::new(false);
+
+/// Longest a single external step, like `kind create cluster`, can run in
+/// CI mode.
+pub const STEP_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+pub fn enable() {
+    CI.store(true, Ordering::Relaxed);
+    console::set_colors_enabled(false);
+}
+
+pub fn enabled() -> bool {
+    CI.load(Ordering::Relaxed)
+}
+
+/// Whether the value of the CI environment variable means hake runs in CI.
+fn is_ci(value: Option<&str>) -> bool {
+    match value {
+        Some(value) => matches!(value.to_lowercase().as_str(), "true" | "1" | "yes"),
+        None => false,
+    }
+}
+
+/// Whether the environment says hake runs in CI.
+pub fn detected() -> bool {
+    is_ci(env::var("CI").ok().as_deref())
+}
+
+/// Time limit for external steps: the one of CI mode, or what is left before
+/// the deadline of the operation when that is sooner. None otherwise.
+pub fn step_timeout() -> Option<Duration> {
+    let ci = if enabled() { Some(STEP_TIMEOUT) } else { None };
+    match (ci, deadline::remaining()) {
+        (Some(ci), Some(remaining)) => Some(ci.min(remaining)),
+        (ci, remaining) => ci.or(remaining),
+    }
+}
+
+/// Waits for a child process, killing it once it runs for longer than the
+/// step timeout.
+pub fn wait(child: &mut Child, step: &str) -> Result<ExitStatus> {
+    let timeout = match step_timeout() {
+        Some(timeout) => timeout,
+        None => return Ok(child.wait()?),
+    };
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if start.elapsed() > timeout {
+            child.kill()?;
+            child.wait()?;
+            return Err(HakeError::Timeout {
+                step: String::from(step),
+                timeout,
+            }
+            .into());
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+/// Formats seconds since the epoch as an UTC timestamp, like
+/// 2020-06-01T12:30:05Z.
+pub fn format_timestamp(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+
+    // days to civil date, from Howard Hinnant's algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ci::{format_timestamp, is_ci};
+
+    #[test]
+    fn test_is_ci() {
+        assert!(is_ci(Some("true")));
+        assert!(is_ci(Some("1")));
+        assert!(is_ci(Some("True")));
+        assert!(!is_ci(Some("false")));
+        assert!(!is_ci(Some("")));
+        assert!(!is_ci(None));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(1590000000), "2020-05-20T18:40:00Z");
+        assert_eq!(format_timestamp(951782400), "2000-02-29T00:00:00Z");
+    }
+}
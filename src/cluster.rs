@@ -0,0 +1,2094 @@
+// Cluster lifecycle operations, shared by the command line and by programs
+// that embed hake.
+use anyhow::{anyhow, Context, Result};
+use console::Style;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use structopt::StructOpt;
+use tracing::{info, warn};
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::add::HelmChart;
+use crate::arch;
+use crate::cluster_dir;
+use crate::config::UserConfig;
+use crate::dashboard;
+use crate::deadline;
+use crate::dryrun;
+use crate::error::{self, HakeError};
+use crate::forward;
+use crate::gitops::{self, Bootstrap};
+use crate::hooks::{self, Hooks};
+use crate::kind::Kind;
+use crate::labels::{self, Labels};
+use crate::logs::{self, LogOptions};
+use crate::metadata::{self, ClusterMetadata};
+use crate::node;
+use crate::progress;
+use crate::pull_secret;
+use crate::r#do::RemoteCluster;
+use crate::remote_state;
+use crate::runtime;
+use crate::state;
+use crate::{add, bench, current, kind_binary, kubeconfig, kubectl, r#do, snapshot, ttl};
+
+pub const DEFAULT_NAME: &str = "hake-default";
+pub const DEFAULT_PROVIDER: &str = "kind";
+const API_SERVER_TIMEOUT: u64 = 120;
+
+#[derive(StructOpt, Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+/// Options to create a cluster, parsed from the `create` arguments. They are
+/// recorded in the metadata of the cluster so `recreate` can use them again.
+pub struct CreateOptions {
+    /// Name of the cluster
+    #[structopt(long, default_value = DEFAULT_NAME)]
+    pub name: String,
+
+    /// Configures access to an ECR private registry
+    #[structopt(long)]
+    pub ecr: Option<String>,
+
+    /// How kind nodes authenticate to the ECR registry: containerd (default for kind v0.11.0 and
+    /// newer), kubelet, or secret to leave it to the pull secrets of --ecr-secret
+    #[structopt(long)]
+    pub registry_auth: Option<String>,
+
+    /// Creates a kubernetes.io/dockerconfigjson secret with the ECR credentials, like
+    /// <namespace>/<name>. Can be repeated
+    #[structopt(long = "ecr-secret", requires = "ecr")]
+    pub ecr_secrets: Vec<String>,
+
+    /// Configure access to local Docker registry
+    #[structopt(long)]
+    pub use_local_registry: Option<String>,
+
+    /// Pass extra port mappings
+    #[structopt(long)]
+    pub extra_port_mappings: Option<String>,
+
+    /// Maps a host port to a node port, like 8080:30080 or 5353:30053/udp. Can be repeated
+    #[structopt(long = "port")]
+    pub ports: Vec<String>,
+
+    /// Installs an ingress controller after creating the cluster: nginx (default) or contour
+    #[structopt(long)]
+    pub with_ingress: Option<Option<String>>,
+
+    /// Mounts a host directory into the nodes, like /host/path:/container/path[:ro]. Can be repeated
+    #[structopt(long = "mount")]
+    pub mounts: Vec<String>,
+
+    /// Enables or disables a Kubernetes feature gate, like SomeFeature=true. Can be repeated
+    #[structopt(long = "feature-gate")]
+    pub feature_gates: Vec<String>,
+
+    /// File with a kubeadm config patch applied to every node. Can be repeated
+    #[structopt(long = "kubeadm-patch")]
+    pub kubeadm_patches: Vec<String>,
+
+    /// Enables API server audit logging with the policy in a file, logs go to
+    /// ~/.hake/<name>/audit
+    #[structopt(long)]
+    pub audit_policy: Option<String>,
+
+    /// Encrypts secrets at rest in etcd with a key generated for the cluster
+    #[structopt(long)]
+    pub encrypt_secrets: bool,
+
+    /// CNI to install instead of the default one: calico, cilium or none
+    #[structopt(long)]
+    pub cni: Option<String>,
+
+    /// IP family of the kind cluster networking: ipv4 (default), ipv6 or dual
+    #[structopt(long)]
+    pub ip_family: Option<String>,
+
+    /// Pod subnet of a kind cluster, instead of 10.244.0.0/16
+    #[structopt(long)]
+    pub pod_subnet: Option<String>,
+
+    /// Service subnet of a kind cluster, instead of 10.96.0.0/12
+    #[structopt(long)]
+    pub service_subnet: Option<String>,
+
+    /// Host directory backing the persistent volumes of the hake-host StorageClass
+    #[structopt(long)]
+    pub pv: Option<String>,
+
+    /// Exposes the host GPUs to kind nodes and installs the NVIDIA device plugin, needs the
+    /// NVIDIA container runtime as docker's default
+    #[structopt(long)]
+    pub gpus: bool,
+
+    /// Image pulled on the host and loaded into the kind nodes after creating the cluster. Can be
+    /// repeated
+    #[structopt(long)]
+    pub preload: Vec<String>,
+
+    /// Pulls Docker Hub images through a cache shared by every kind cluster, kept across
+    /// deletes
+    #[structopt(long)]
+    pub image_cache: bool,
+
+    /// Installs MetalLB so LoadBalancer services get an IP from the kind network
+    #[structopt(long)]
+    pub with_loadbalancer: bool,
+
+    /// Installs cert-manager after creating the cluster, the given release or v1.14.4
+    #[structopt(long)]
+    pub with_cert_manager: Option<Option<String>>,
+
+    /// Creates the hake-selfsigned ClusterIssuer of self-signed certificates
+    #[structopt(long, requires = "with-cert-manager")]
+    pub self_signed_issuer: bool,
+
+    /// Installs the Operator Lifecycle Manager after creating the cluster, the given release or
+    /// v0.27.0
+    #[structopt(long)]
+    pub with_olm: Option<Option<String>>,
+
+    /// Installs the Kubernetes Dashboard with an admin to log in with, see the dashboard command
+    #[structopt(long)]
+    pub with_dashboard: bool,
+
+    /// Adds the cluster to ~/.kube/config as the hake-<name> context
+    #[structopt(long)]
+    pub merge_kubeconfig: bool,
+
+    /// Shows the output of kind instead of a spinner, set with the global -v
+    #[structopt(skip)]
+    #[serde(skip)]
+    pub verbose: bool,
+
+    /// Provider: kind (default) or digitalocean
+    #[structopt(long)]
+    pub provider: Option<String>,
+
+    /// Number of worker nodes of a kind cluster
+    #[structopt(long, default_value = "0")]
+    pub workers: u16,
+
+    /// Reuses the cluster if it already exists and is healthy, instead of failing
+    #[structopt(long)]
+    #[serde(skip)]
+    pub reuse: bool,
+
+    /// Turns an invalid --name into a valid one, like Feature/X into feature-x, instead of failing
+    #[structopt(long)]
+    #[serde(skip)]
+    pub normalize_name: bool,
+
+    /// Creates this many clusters, named <name>-1 to <name>-<count>
+    #[structopt(long)]
+    #[serde(skip)]
+    pub count: Option<u16>,
+
+    /// Number of clusters of a --count batch created at the same time
+    #[structopt(long, default_value = "4")]
+    #[serde(skip)]
+    pub parallel: usize,
+
+    /// Profile from the configuration file to start from
+    #[structopt(long)]
+    #[serde(skip)]
+    pub profile: Option<String>,
+
+    /// Node image for kind clusters, like kindest/node:v1.18.2
+    #[structopt(long)]
+    pub image: Option<String>,
+
+    /// kind release to download and use instead of the one in PATH, like v0.8.1
+    #[structopt(long)]
+    pub kind_version: Option<String>,
+
+    /// Metadata
+    #[structopt(long)]
+    pub metadata: Option<String>,
+
+    /// Installs a Helm chart once the cluster is ready, like repo/chart[@version][:values.yaml]. Can be repeated
+    #[structopt(long)]
+    pub helm: Vec<String>,
+
+    /// Applies manifests from a file, directory or URL once the cluster is ready, and waits for
+    /// their deployments and CRDs. Can be repeated, they are applied in order
+    #[structopt(long)]
+    pub apply: Vec<String>,
+
+    /// Installs CRDs from a file, directory or URL as soon as the API server is ready, and waits
+    /// for them to be Established. Can be repeated
+    #[structopt(long = "crd")]
+    pub crds: Vec<String>,
+
+    /// Creates a namespace with a pull secret for the ECR registry of the cluster, used by its
+    /// default ServiceAccount. Can be repeated
+    #[structopt(long = "namespace")]
+    pub namespaces: Vec<String>,
+
+    /// Installs a GitOps controller synced to a repository, like flux=<git-url>[#branch[:path]]
+    /// or argocd=<git-url>[#revision[:path]]
+    #[structopt(long)]
+    pub bootstrap: Option<String>,
+
+    /// Extra DigitalOcean node pool, like name=gpu,size=s-4vcpu-8gb,count=2,label=role=gpu,taint=dedicated=gpu:NoSchedule. Can be repeated
+    #[structopt(long = "node-pool")]
+    pub node_pools: Vec<String>,
+
+    /// VPC of a DigitalOcean cluster, its default one for the region otherwise
+    #[structopt(long)]
+    pub vpc_uuid: Option<String>,
+
+    /// Architecture of the kind nodes, amd64 or arm64. The one of the docker host by default,
+    /// others run emulated
+    #[structopt(long)]
+    pub arch: Option<String>,
+
+    /// Container runtime of the kind nodes, docker or podman. docker when it is installed by
+    /// default, or HAKE_RUNTIME
+    #[structopt(long)]
+    pub runtime: Option<String>,
+
+    /// Existing docker network for the kind nodes to join instead of the kind one, so other
+    /// containers on it reach the cluster by name
+    #[structopt(long)]
+    pub network: Option<String>,
+
+    /// Runs the control plane of a DigitalOcean cluster in high availability
+    #[structopt(long)]
+    pub ha: bool,
+
+    /// Auto-scales the node pool of a DigitalOcean cluster, between --min and --max nodes
+    #[structopt(long)]
+    pub auto_scale: bool,
+
+    /// Minimum number of nodes when auto-scaling, 1 by default
+    #[structopt(long = "min", requires = "auto-scale")]
+    pub min_nodes: Option<u16>,
+
+    /// Maximum number of nodes when auto-scaling, nodepool.count by default
+    #[structopt(long = "max", requires = "auto-scale")]
+    pub max_nodes: Option<u16>,
+
+    /// Tag of a DigitalOcean cluster, like branch:pr-123, besides hake and owner:<user>. Can be repeated
+    #[structopt(long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Label of the cluster, like ticket=DEV-123, shown and filtered by in `list`. Can be repeated
+    #[structopt(long = "label")]
+    pub labels: Vec<String>,
+
+    /// Time to live, like 30m or 4h, after which `gc` deletes the cluster
+    #[structopt(long)]
+    pub ttl: Option<String>,
+
+    /// Gives up when kind or DigitalOcean take longer than this to create the cluster, like 10m,
+    /// and deletes what was created
+    #[structopt(long)]
+    pub timeout: Option<String>,
+
+    /// Scripts run around the lifecycle of the cluster, from the configuration file
+    #[structopt(skip)]
+    pub hooks: Hooks,
+}
+
+impl CreateOptions {
+    /// Options for a plain kind cluster, to be customized by the caller.
+    pub fn new(name: &str) -> CreateOptions {
+        CreateOptions {
+            name: String::from(name),
+            ..Default::default()
+        }
+    }
+}
+
+pub enum ClusterType {
+    Kind,
+    DigitalOcean,
+}
+
+/// `path` made absolute from the current directory, so recreating the
+/// cluster from another one finds the same files. URLs are kept as they are.
+fn absolute(path: &str) -> String {
+    if add::is_url(path) || Path::new(path).is_absolute() {
+        return String::from(path);
+    }
+
+    env::current_dir()
+        .map(|dir| dir.join(path).to_string_lossy().into_owned())
+        .unwrap_or_else(|_| String::from(path))
+}
+
+/// Host path of a mount like /host/path:/container/path[:ro], unless it is
+/// a Windows one, absolute already.
+fn mount_host_path(mount: &str) -> Option<(&str, &str)> {
+    match mount.as_bytes() {
+        [letter, b':', ..] if letter.is_ascii_alphabetic() => None,
+        _ => mount.split_once(':'),
+    }
+}
+
+/// Values file of a Helm chart like repo/chart[@version][:values.yaml].
+fn helm_values(chart: &str) -> Option<(&str, &str)> {
+    match add::split_values(chart) {
+        (chart, Some(values)) => Some((chart, values)),
+        (_, None) => None,
+    }
+}
+
+/// Options as recorded in the metadata of the cluster, with the paths of
+/// local files made absolute.
+pub fn recorded_options(opts: &CreateOptions) -> CreateOptions {
+    let mut recorded = opts.clone();
+    for patch in recorded.kubeadm_patches.iter_mut() {
+        *patch = absolute(patch);
+    }
+    for manifest in recorded.apply.iter_mut().chain(recorded.crds.iter_mut()) {
+        *manifest = absolute(manifest);
+    }
+    for mount in recorded.mounts.iter_mut() {
+        if let Some((host, container)) = mount_host_path(mount) {
+            *mount = format!("{}:{}", absolute(host), container);
+        }
+    }
+    for chart in recorded.helm.iter_mut() {
+        if let Some((chart_name, values)) = helm_values(chart) {
+            *chart = format!("{}:{}", chart_name, absolute(values));
+        }
+    }
+    recorded.audit_policy = recorded.audit_policy.as_deref().map(absolute);
+    recorded.pv = recorded.pv.as_deref().map(absolute);
+    // null would read back as no ingress at all
+    recorded.with_ingress = recorded
+        .with_ingress
+        .map(|controller| Some(controller.unwrap_or_else(|| String::from("nginx"))));
+    recorded.with_cert_manager = recorded
+        .with_cert_manager
+        .map(|version| Some(version.unwrap_or_else(|| String::from(add::CERT_MANAGER_VERSION))));
+    recorded.with_olm = recorded
+        .with_olm
+        .map(|version| Some(version.unwrap_or_else(|| String::from(add::OLM_VERSION))));
+
+    recorded
+}
+
+/// Options of a cluster imported from an archive, for `recreate`: named like
+/// the new cluster, without the hooks of whoever exported it, which would run
+/// here, and without the local files that are not on this machine.
+pub fn imported_options(opts: &CreateOptions, name: &str) -> CreateOptions {
+    let missing = |path: &str| {
+        let missing = !add::is_url(path) && !Path::new(path).exists();
+        if missing {
+            warn!("{} is not on this machine, recreate goes without it", path);
+        }
+        missing
+    };
+    let mut imported = opts.clone();
+    imported.name = String::from(name);
+    imported.hooks = Hooks::default();
+    imported.kubeadm_patches.retain(|patch| !missing(patch));
+    imported.apply.retain(|manifest| !missing(manifest));
+    imported.crds.retain(|manifest| !missing(manifest));
+    imported
+        .mounts
+        .retain(|mount| mount_host_path(mount).is_none_or(|(host, _)| !missing(host)));
+    for chart in imported.helm.iter_mut() {
+        if let Some((chart_name, values)) = helm_values(chart) {
+            if missing(values) {
+                *chart = String::from(chart_name);
+            }
+        }
+    }
+    imported.audit_policy = imported.audit_policy.filter(|policy| !missing(policy));
+    imported.pv = imported.pv.filter(|pv| !missing(pv));
+
+    imported
+}
+
+/// Checks that a cluster can be created again with its recorded options
+/// before it is deleted: the files they name still exist and the node image
+/// can be pulled. Otherwise the cluster would be gone with nothing in its
+/// place.
+pub fn check_recreate(opts: &CreateOptions) -> Result<()> {
+    let mut files: Vec<(&str, &str)> = vec![];
+    files.extend(
+        opts.kubeadm_patches
+            .iter()
+            .map(|patch| ("kubeadm patch", patch.as_str())),
+    );
+    files.extend(
+        opts.audit_policy
+            .iter()
+            .map(|policy| ("audit policy", policy.as_str())),
+    );
+    files.extend(
+        opts.mounts
+            .iter()
+            .filter_map(|mount| mount_host_path(mount))
+            .map(|(host, _)| ("mounted directory", host)),
+    );
+    files.extend(
+        opts.helm
+            .iter()
+            .filter_map(|chart| helm_values(chart))
+            .map(|(_, values)| ("Helm values file", values)),
+    );
+    for (what, path) in files {
+        if !Path::new(path).exists() {
+            return Err(anyhow!(
+                "The {} {} does not exist, the cluster would not be created again",
+                what,
+                path
+            ));
+        }
+    }
+    for manifest in opts.apply.iter().chain(opts.crds.iter()) {
+        add::check_manifest(manifest)?;
+    }
+    let kind = matches!(opts.provider.as_deref(), None | Some("kind"));
+    if let (Some(image), true, false) = (&opts.image, kind, dryrun::enabled()) {
+        Kind::pull_image(image)?;
+    }
+
+    Ok(())
+}
+
+pub fn create(opts: CreateOptions) -> Result<()> {
+    let recorded = recorded_options(&opts);
+
+    let name = opts.name.clone();
+    if cluster_exists(&name) {
+        if opts.reuse {
+            return reuse(&name);
+        }
+        return Err(HakeError::ClusterExists(name).into());
+    }
+
+    let provider = match opts.provider.as_deref().unwrap_or(DEFAULT_PROVIDER) {
+        "digitalocean" | "do" => "digitalocean",
+        provider => provider,
+    };
+    if provider == "kind" {
+        Kind::check_name_free(&name)?;
+    }
+
+    let ttl = match &opts.ttl {
+        Some(ttl) => Some(ttl::parse_duration(ttl)?),
+        None => None,
+    };
+    let timeout = match &opts.timeout {
+        Some(timeout) => Some(Duration::from_secs(ttl::parse_duration(timeout)?)),
+        None => None,
+    };
+    let labels = labels::parse_all(&opts.labels)?;
+
+    let ingress = opts
+        .with_ingress
+        .clone()
+        .map(|controller| controller.unwrap_or_else(|| String::from("nginx")));
+    let cert_manager = opts
+        .with_cert_manager
+        .clone()
+        .map(|version| version.unwrap_or_else(|| String::from(add::CERT_MANAGER_VERSION)));
+    let olm = opts
+        .with_olm
+        .clone()
+        .map(|version| version.unwrap_or_else(|| String::from(add::OLM_VERSION)));
+    if let Some(controller) = &ingress {
+        add::ingress_manifest(controller, provider)?;
+    }
+    if let Some(cni) = &opts.cni {
+        if !add::CNIS.contains(&cni.as_str()) {
+            return Err(anyhow!("Unknown CNI: {}", cni));
+        }
+        if provider != "kind" {
+            return Err(anyhow!("--cni is only supported on kind clusters"));
+        }
+    }
+    if opts.workers > 0 && provider != "kind" {
+        return Err(anyhow!(
+            "--workers is only supported on kind clusters, use nodepool.count in --metadata"
+        ));
+    }
+    if !opts.node_pools.is_empty() && provider != "digitalocean" {
+        return Err(anyhow!(
+            "--node-pool is only supported on DigitalOcean clusters, use --workers"
+        ));
+    }
+    if opts.arch.is_some() && provider != "kind" {
+        return Err(anyhow!("--arch is only supported on kind clusters"));
+    }
+    if opts.network.is_some() && provider != "kind" {
+        return Err(anyhow!(
+            "--network is only supported on kind clusters, use --vpc-uuid"
+        ));
+    }
+    if let Some(container_runtime) = &opts.runtime {
+        if provider != "kind" {
+            return Err(anyhow!("--runtime is only supported on kind clusters"));
+        }
+        runtime::parse(container_runtime)?;
+    }
+    if provider != "digitalocean" {
+        let given = [
+            ("--tag", !opts.tags.is_empty()),
+            ("--vpc-uuid", opts.vpc_uuid.is_some()),
+            ("--ha", opts.ha),
+            ("--auto-scale", opts.auto_scale),
+        ];
+        if let Some((flag, _)) = given.iter().find(|(_, given)| *given) {
+            return Err(anyhow!(
+                "{} is only supported on DigitalOcean clusters",
+                flag
+            ));
+        }
+    }
+    if opts.with_loadbalancer && provider != "kind" {
+        return Err(anyhow!(
+            "--with-loadbalancer is only supported on kind clusters"
+        ));
+    }
+
+    for spec in opts.ecr_secrets.iter() {
+        pull_secret::parse(spec)?;
+    }
+    if opts.registry_auth.as_deref() == Some("secret")
+        && opts.ecr_secrets.is_empty()
+        && opts.namespaces.is_empty()
+    {
+        return Err(anyhow!(
+            "--registry-auth secret needs --ecr-secret or --namespace to create pull secrets"
+        ));
+    }
+    for manifest in opts.crds.iter().chain(opts.apply.iter()) {
+        add::check_manifest(manifest)?;
+    }
+    let bootstrap = match &opts.bootstrap {
+        Some(spec) => Some(Bootstrap::parse(spec)?),
+        None => None,
+    };
+    let charts = opts
+        .helm
+        .iter()
+        .map(|spec| HelmChart::parse(spec))
+        .collect::<Result<Vec<HelmChart>>>()?;
+
+    // kind clusters are local, only cloud ones are shared
+    let shared = match provider {
+        "kind" => None,
+        _ => remote_state::Backend::configured()?,
+    };
+    if let Some(backend) = &shared {
+        remote_state::ensure_available(backend, &name)?;
+    }
+
+    hooks::run("pre_create", &opts.hooks.pre_create, &name, provider)?;
+
+    let cyan = Style::new().cyan();
+    info!("Creating cluster: {}", cyan.apply_to(&name));
+    progress::phase("create", 0, &format!("Creating cluster {}", name));
+
+    let ecr = opts.ecr.clone();
+    let local_registry = opts.use_local_registry.clone();
+    if !dryrun::enabled() {
+        state::set(&name, provider, state::CREATING)?;
+    }
+    deadline::set(timeout);
+    let created = match provider {
+        "digitalocean" => r#do::create(&name, &opts),
+        "kind" => {
+            kind_binary::select(opts.kind_version.as_deref())?;
+            if opts.runtime.is_some() {
+                runtime::select(opts.runtime.as_deref())?;
+            }
+            let mut cluster = Kind::new(&name);
+            cluster.configure_private_registry(opts.ecr);
+            if let Some(mode) = &opts.registry_auth {
+                cluster.set_registry_auth(mode)?;
+            }
+
+            if let Some(container_name) = opts.use_local_registry {
+                cluster.use_local_registry(&container_name)?;
+            }
+            if let Some(extra_port_mapping) = opts.extra_port_mappings {
+                cluster.extra_port_mapping(&extra_port_mapping);
+            }
+            for port in opts.ports.iter() {
+                cluster.add_port_mapping(port)?;
+            }
+            if ingress.is_some() {
+                cluster.with_ingress();
+            }
+            if opts.cni.is_some() {
+                cluster.disable_default_cni();
+            }
+            if let Some(family) = &opts.ip_family {
+                cluster.set_ip_family(family)?;
+            }
+            if let Some(subnet) = &opts.pod_subnet {
+                cluster.set_pod_subnet(subnet)?;
+            }
+            if let Some(subnet) = &opts.service_subnet {
+                cluster.set_service_subnet(subnet)?;
+            }
+            for mount in opts.mounts.iter() {
+                cluster.add_mount(mount)?;
+            }
+            if let Some(dir) = &opts.pv {
+                cluster.use_host_volumes(dir)?;
+            }
+            if opts.gpus {
+                cluster.enable_gpus();
+            }
+            if opts.image_cache {
+                cluster.use_image_cache();
+            }
+            for gate in opts.feature_gates.iter() {
+                cluster.add_feature_gate(gate)?;
+            }
+            for patch in opts.kubeadm_patches.iter() {
+                cluster.add_kubeadm_patch(patch)?;
+            }
+            if let Some(policy) = &opts.audit_policy {
+                cluster.set_audit_policy(policy)?;
+            }
+            if opts.encrypt_secrets {
+                cluster.encrypt_secrets();
+            }
+            cluster.set_workers(opts.workers);
+            if let Some(image) = &opts.image {
+                cluster.set_node_image(image);
+            }
+            if let Some(network) = &opts.network {
+                cluster.set_network(network);
+            }
+            if let Some(arch) = &opts.arch {
+                cluster.set_arch(&arch::parse(arch)?);
+            }
+            cluster.set_verbose(opts.verbose);
+
+            cluster.create()
+        }
+        _ => Err(anyhow!("Unknown provider: {}", provider)),
+    };
+    deadline::set(None);
+    let created = match created {
+        Err(e) if timeout.is_some() && error::is_timeout(&e) && !dryrun::enabled() => {
+            warn!("Creating {} timed out, deleting what was created", name);
+            rollback(&name, provider);
+            Err(e.context(format!(
+                "Cluster {} was not created within {}",
+                name,
+                opts.timeout.as_deref().unwrap_or_default()
+            )))
+        }
+        created => created,
+    };
+    if !dryrun::enabled() {
+        match &created {
+            Ok(()) => state::set_status(&name, state::RUNNING)?,
+            // files left behind are for `delete` to remove
+            Err(_) if cluster_dir(&name).exists() => state::set_status(&name, state::FAILED)?,
+            Err(_) => state::remove(&name)?,
+        }
+    }
+    created?;
+
+    if dryrun::enabled() {
+        if opts.merge_kubeconfig {
+            dryrun::print("would merge the kubeconfig into ~/.kube/config");
+        }
+        if !opts.preload.is_empty() && provider == "kind" {
+            dryrun::print(&format!("would preload: {}", opts.preload.join(" ")));
+        }
+        for manifest in opts.crds.iter() {
+            dryrun::print(&format!("would install CRDs: {}", manifest));
+        }
+        for namespace in opts.namespaces.iter() {
+            match &ecr {
+                Some(registry) => dryrun::print(&format!(
+                    "would create namespace {} with a pull secret for {}",
+                    namespace, registry
+                )),
+                None => dryrun::print(&format!("would create namespace {}", namespace)),
+            }
+        }
+        for spec in opts.ecr_secrets.iter() {
+            dryrun::print(&format!("would create pull secret {}", spec));
+        }
+        if let Some(cni) = &opts.cni {
+            dryrun::print(&format!("would install CNI: {}", cni));
+        }
+        if let Some(controller) = &ingress {
+            dryrun::print(&format!("would install ingress controller: {}", controller));
+        }
+        if opts.with_loadbalancer {
+            dryrun::print("would install LoadBalancer support: metallb");
+        }
+        if let Some(version) = &cert_manager {
+            dryrun::print(&format!("would install cert-manager {}", version));
+        }
+        if let Some(version) = &olm {
+            dryrun::print(&format!(
+                "would install the Operator Lifecycle Manager {}",
+                version
+            ));
+        }
+        if opts.with_dashboard {
+            dryrun::print("would install the Kubernetes Dashboard");
+        }
+        if opts.gpus && provider == "kind" {
+            dryrun::print("would install the NVIDIA container toolkit and device plugin");
+        }
+        if opts.pv.is_some() && provider == "kind" {
+            dryrun::print("would create the hake-host StorageClass");
+        }
+        for chart in charts.iter() {
+            dryrun::print(&format!("would install Helm chart: {}", chart.chart));
+        }
+        for manifest in opts.apply.iter() {
+            dryrun::print(&format!("would apply: {}", manifest));
+        }
+        if let Some(spec) = &opts.bootstrap {
+            dryrun::print(&format!("would bootstrap GitOps: {}", spec));
+        }
+        return hooks::run("post_create", &opts.hooks.post_create, &name, provider);
+    }
+
+    let mut metadata = ClusterMetadata::new(provider);
+    metadata.ecr = ecr;
+    metadata.local_registry = local_registry;
+    metadata.options = Some(recorded);
+    metadata.expires_at = ttl.map(|ttl| ttl::now() + ttl);
+    metadata.labels = labels;
+    metadata.kubernetes_version = metadata::get_kubernetes_version(&name);
+    if provider == "kind" {
+        metadata.node_image = Kind::get_node_image(&name);
+    }
+    metadata.write(&name)?;
+    if let Some(backend) = &shared {
+        let id = r#do::read_cluster_id(&name).ok();
+        if let Err(e) = remote_state::register(backend, &name, provider, id, metadata.expires_at) {
+            warn!("Could not add {} to the shared state: {:#}", name, e);
+        }
+    }
+    let addons_started = Instant::now();
+    progress::phase("addons", 70, "Installing addons");
+
+    if opts.merge_kubeconfig {
+        kubeconfig::merge(&name)?;
+    }
+
+    if !opts.crds.is_empty() || !opts.namespaces.is_empty() || !opts.ecr_secrets.is_empty() {
+        if !kubectl::wait_for_api_server(&name, API_SERVER_TIMEOUT) {
+            return Err(anyhow!(
+                "API server of {} was not ready after {} seconds",
+                name,
+                API_SERVER_TIMEOUT
+            ));
+        }
+        for manifest in opts.crds.iter() {
+            info!("Installing CRDs: {}", cyan.apply_to(manifest));
+            add::crds(&name, manifest)?;
+        }
+        create_namespaces(
+            &name,
+            &opts.namespaces,
+            &opts.ecr_secrets,
+            metadata.ecr.as_deref(),
+        )?;
+    }
+
+    if !opts.preload.is_empty() && provider == "kind" {
+        info!(
+            "Preloading images: {}",
+            cyan.apply_to(opts.preload.join(" "))
+        );
+        Kind::preload(&name, &opts.preload, opts.verbose)?;
+    }
+
+    if let Some(cni) = &opts.cni {
+        info!("Installing CNI: {}", cyan.apply_to(cni));
+        add::cni(&name, cni)?;
+    }
+
+    if let Some(controller) = ingress {
+        info!(
+            "Installing ingress controller: {}",
+            cyan.apply_to(&controller)
+        );
+        add::ingress(&name, &controller, provider)?;
+    }
+
+    if opts.with_loadbalancer {
+        info!(
+            "Installing LoadBalancer support: {}",
+            cyan.apply_to("metallb")
+        );
+        add::loadbalancer(&name)?;
+    }
+
+    if let Some(version) = &cert_manager {
+        info!("Installing cert-manager: {}", cyan.apply_to(version));
+        add::cert_manager_release(&name, version, opts.self_signed_issuer)?;
+    }
+
+    if let Some(version) = &olm {
+        info!(
+            "Installing the Operator Lifecycle Manager: {}",
+            cyan.apply_to(version)
+        );
+        add::olm(&name, version)?;
+    }
+
+    if opts.with_dashboard {
+        info!("Installing {}", cyan.apply_to("the Kubernetes Dashboard"));
+        add::dashboard(&name)?;
+    }
+
+    if opts.gpus && provider == "kind" {
+        info!("Configuring GPUs: {}", cyan.apply_to("nvidia"));
+        Kind::configure_gpus(&name)?;
+        add::gpu_device_plugin(&name)?;
+    }
+
+    if let (Some(dir), "kind") = (&opts.pv, provider) {
+        info!("Creating persistent volumes in: {}", cyan.apply_to(dir));
+        add::host_volumes(&name)?;
+    }
+
+    let has_extras = !charts.is_empty() || !opts.apply.is_empty() || bootstrap.is_some();
+    if has_extras && !kubectl::wait_for_api_server(&name, API_SERVER_TIMEOUT) {
+        return Err(anyhow!(
+            "API server of {} was not ready after {} seconds",
+            name,
+            API_SERVER_TIMEOUT
+        ));
+    }
+    for chart in charts.iter() {
+        info!("Installing Helm chart: {}", cyan.apply_to(&chart.chart));
+        add::helm(&name, chart)?;
+    }
+    for manifest in opts.apply.iter() {
+        info!("Applying: {}", cyan.apply_to(manifest));
+        add::apply(&name, manifest, None)?;
+    }
+    if let Some(bootstrap) = &bootstrap {
+        info!(
+            "Bootstrapping GitOps from: {}",
+            cyan.apply_to(&bootstrap.url)
+        );
+        gitops::bootstrap(&name, bootstrap)?;
+    }
+
+    bench::record(bench::ADDONS, addons_started.elapsed());
+
+    hooks::run("post_create", &opts.hooks.post_create, &name, provider)?;
+    progress::phase("done", 100, &format!("Cluster {} is ready", name));
+
+    // with --reuse the output is the same whether the cluster was created or not
+    if opts.reuse {
+        println!("{}", kubectl::export_kubeconfig(&name));
+    }
+
+    Ok(())
+}
+
+/// Deletes what a create that timed out left behind, remote resources
+/// included, so the name can be used again right away. The directory of the
+/// cluster is kept when that fails, for `delete` to try again.
+fn rollback(name: &str, provider: &str) {
+    let deleted = match provider {
+        "digitalocean" => match r#do::read_cluster_id(name) {
+            Ok(id) => r#do::delete_by_id(id.trim()),
+            // the cluster was not created, or its id not saved yet
+            Err(_) => Ok(()),
+        },
+        // a kind create that fails already removed what it created
+        _ => return,
+    };
+    if let Err(e) = deleted {
+        warn!("Could not delete what was created of {}: {:#}", name, e);
+        return;
+    }
+    let dir = cluster_dir(name);
+    if dir.exists() {
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            warn!("Could not remove {}: {:#}", dir.display(), e);
+        }
+    }
+}
+
+/// Creates the namespaces of `--namespace` and, when the cluster uses an ECR
+/// registry, a pull secret for it in each one, added to their default
+/// ServiceAccount, then the pull secrets of `--ecr-secret`.
+fn create_namespaces(
+    name: &str,
+    namespaces: &[String],
+    ecr_secrets: &[String],
+    ecr: Option<&str>,
+) -> Result<()> {
+    if namespaces.is_empty() && ecr_secrets.is_empty() {
+        return Ok(());
+    }
+    let docker_config = match ecr {
+        Some(registry) => Some(
+            Kind::get_docker_login(registry)
+                .with_context(|| format!("Could not get docker login for {}", registry))?,
+        ),
+        None => None,
+    };
+
+    let cyan = Style::new().cyan();
+    for namespace in namespaces {
+        info!("Creating namespace: {}", cyan.apply_to(namespace));
+        pull_secret::create_namespace(name, namespace)?;
+        if let Some(docker_config) = &docker_config {
+            pull_secret::create(name, namespace, pull_secret::DEFAULT_NAME, docker_config)?;
+            pull_secret::attach(name, namespace, pull_secret::DEFAULT_NAME)?;
+        }
+    }
+    if let Some(docker_config) = &docker_config {
+        for spec in ecr_secrets {
+            let (namespace, secret) = pull_secret::parse(spec)?;
+            info!("Creating pull secret: {}", cyan.apply_to(spec));
+            pull_secret::create_namespace(name, namespace)?;
+            pull_secret::create(name, namespace, secret, docker_config)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Brings the addons of a cluster created with `current` to the ones in
+/// `spec`: installs the ingress controller, LoadBalancer support, images and
+/// GitOps bootstrap that are new, installs the CRDs and Helm charts again and
+/// applies the manifests again. Addons removed from `spec` are left installed.
+pub fn update_addons(name: &str, current: &CreateOptions, spec: &CreateOptions) -> Result<()> {
+    let mut metadata = ClusterMetadata::read(name).ok_or_else(|| {
+        anyhow!(
+            "Cluster {} was created by an older version of hake and cannot be updated",
+            name
+        )
+    })?;
+    let provider = metadata.provider.clone();
+    let spec = recorded_options(spec);
+    let labels = labels::parse_all(&spec.labels)?;
+    let cyan = Style::new().cyan();
+
+    for manifest in spec.crds.iter() {
+        info!("Installing CRDs: {}", cyan.apply_to(manifest));
+        add::crds(name, manifest)?;
+    }
+    create_namespaces(
+        name,
+        &spec.namespaces,
+        &spec.ecr_secrets,
+        metadata.ecr.as_deref(),
+    )?;
+    if let Some(Some(controller)) = &spec.with_ingress {
+        if spec.with_ingress != current.with_ingress {
+            info!(
+                "Installing ingress controller: {}",
+                cyan.apply_to(controller)
+            );
+            add::ingress(name, controller, &provider)?;
+        }
+    }
+    if spec.with_loadbalancer && !current.with_loadbalancer {
+        info!(
+            "Installing LoadBalancer support: {}",
+            cyan.apply_to("metallb")
+        );
+        add::loadbalancer(name)?;
+    }
+    if let Some(Some(version)) = &spec.with_cert_manager {
+        if spec.with_cert_manager != current.with_cert_manager
+            || spec.self_signed_issuer != current.self_signed_issuer
+        {
+            info!("Installing cert-manager: {}", cyan.apply_to(version));
+            add::cert_manager_release(name, version, spec.self_signed_issuer)?;
+        }
+    }
+    if let Some(Some(version)) = &spec.with_olm {
+        if spec.with_olm != current.with_olm {
+            info!(
+                "Installing the Operator Lifecycle Manager: {}",
+                cyan.apply_to(version)
+            );
+            add::olm(name, version)?;
+        }
+    }
+    if spec.with_dashboard && !current.with_dashboard {
+        info!("Installing {}", cyan.apply_to("the Kubernetes Dashboard"));
+        add::dashboard(name)?;
+    }
+    let images: Vec<String> = spec
+        .preload
+        .iter()
+        .filter(|image| !current.preload.contains(image))
+        .cloned()
+        .collect();
+    if !images.is_empty() && provider == "kind" {
+        info!("Preloading images: {}", cyan.apply_to(images.join(" ")));
+        Kind::preload(name, &images, spec.verbose)?;
+    }
+    for chart in spec.helm.iter() {
+        let chart = HelmChart::parse(chart)?;
+        info!("Installing Helm chart: {}", cyan.apply_to(&chart.chart));
+        add::helm(name, &chart)?;
+    }
+    for manifest in spec.apply.iter() {
+        info!("Applying: {}", cyan.apply_to(manifest));
+        add::apply(name, manifest, None)?;
+    }
+    if let Some(bootstrap) = &spec.bootstrap {
+        if spec.bootstrap != current.bootstrap {
+            let bootstrap = Bootstrap::parse(bootstrap)?;
+            info!(
+                "Bootstrapping GitOps from: {}",
+                cyan.apply_to(&bootstrap.url)
+            );
+            gitops::bootstrap(name, &bootstrap)?;
+        }
+    }
+
+    metadata.labels.extend(labels);
+    metadata.options = Some(spec);
+    metadata.write(name)
+}
+
+/// Checks that an existing cluster is healthy and prints its kubeconfig.
+fn reuse(name: &str) -> Result<()> {
+    let cyan = Style::new().cyan();
+    info!("Reusing cluster: {}", cyan.apply_to(name));
+    if !kubectl::api_server_healthy(name) {
+        return Err(anyhow!(
+            "Cluster {} exists but its API server is not healthy, try `hake start` or `hake recreate`",
+            name
+        ));
+    }
+    println!("{}", kubectl::export_kubeconfig(name));
+
+    Ok(())
+}
+
+/// Deletes a cluster and creates it again with the options it was created with.
+pub fn recreate(name: &str, verbose: bool) -> Result<()> {
+    ensure_exists(name)?;
+    let cyan = Style::new().cyan();
+    info!("Recreating cluster: {}", cyan.apply_to(name));
+
+    match ClusterMetadata::read(name).and_then(|metadata| metadata.options) {
+        Some(mut opts) => {
+            opts.verbose = verbose;
+            check_recreate(&opts)?;
+            delete(name)?;
+            create(opts)
+        }
+        // clusters created by older versions of hake only saved the kind arguments
+        None => match cluster_type(name) {
+            ClusterType::Kind => Kind::recreate(name, verbose),
+            ClusterType::DigitalOcean => Err(anyhow!(
+                "Cluster {} was created by an older version of hake and cannot be recreated",
+                name
+            )),
+        },
+    }
+}
+
+/// Node image for a Kubernetes version, like v1.19.1, unless it already is one.
+fn node_image(version: &str) -> String {
+    if version.contains(':') {
+        String::from(version)
+    } else if version.starts_with('v') {
+        format!("kindest/node:{}", version)
+    } else {
+        format!("kindest/node:v{}", version)
+    }
+}
+
+/// Moves a cluster to a newer Kubernetes version. Kind clusters are recreated
+/// on a newer node image and get their workloads back, DigitalOcean upgrades
+/// its clusters in place.
+pub fn upgrade(name: &str, version: Option<String>, verbose: bool) -> Result<()> {
+    ensure_exists(name)?;
+    let cyan = Style::new().cyan();
+    info!("Upgrading cluster: {}", cyan.apply_to(name));
+
+    match cluster_type(name) {
+        ClusterType::DigitalOcean => {
+            r#do::upgrade(name, version.as_deref())?;
+            if !dryrun::enabled() {
+                if let Some(mut metadata) = ClusterMetadata::read(name) {
+                    metadata.kubernetes_version = metadata::get_kubernetes_version(name);
+                    metadata.write(name)?;
+                }
+            }
+            Ok(())
+        }
+        ClusterType::Kind => {
+            let version = version.ok_or_else(|| {
+                anyhow!("--version is required for kind clusters, like --version v1.19.1")
+            })?;
+            let mut opts = ClusterMetadata::read(name)
+                .and_then(|metadata| metadata.options)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Cluster {} was created by an older version of hake and cannot be upgraded",
+                        name
+                    )
+                })?;
+            opts.image = Some(node_image(&version));
+            opts.verbose = verbose;
+            check_recreate(&opts)?;
+
+            if dryrun::enabled() {
+                dryrun::print("would save the workloads of the cluster");
+                dryrun::print(&format!(
+                    "would recreate the cluster with node image {}",
+                    opts.image.as_deref().unwrap_or_default()
+                ));
+                dryrun::print("would apply the saved workloads");
+                return Ok(());
+            }
+
+            let workloads = snapshot::take(name)?;
+            let saved = snapshot::save(name, &workloads)?;
+            info!("Saved the workloads to {}", cyan.apply_to(saved.display()));
+
+            delete(name)?;
+            create(opts)?;
+            if !kubectl::wait_for_api_server(name, API_SERVER_TIMEOUT) {
+                return Err(anyhow!(
+                    "API server of {} is not ready, apply {} once it is",
+                    name,
+                    saved.display()
+                ));
+            }
+            info!("Applying the saved workloads");
+            snapshot::restore(name, &workloads)?;
+            fs::remove_file(saved)?;
+
+            Ok(())
+        }
+    }
+}
+
+/// Changes the number of nodes of a DigitalOcean node pool.
+pub fn scale(name: &str, count: u16, pool: Option<&str>) -> Result<()> {
+    ensure_exists(name)?;
+    if let ClusterType::Kind = cluster_type(name) {
+        return Err(anyhow!(
+            "Only DigitalOcean clusters can be scaled, recreate kind clusters with --workers"
+        ));
+    }
+
+    r#do::scale(name, count, pool)
+}
+
+fn ensure_digitalocean(name: &str) -> Result<()> {
+    ensure_exists(name)?;
+    if let ClusterType::Kind = cluster_type(name) {
+        return Err(anyhow!(
+            "Node pools are only supported on DigitalOcean clusters"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Adds a node pool to a DigitalOcean cluster, in the format of `--node-pool`.
+pub fn add_node_pool(name: &str, spec: &str) -> Result<()> {
+    ensure_digitalocean(name)?;
+    r#do::add_node_pool(name, spec)
+}
+
+pub fn remove_node_pool(name: &str, pool: &str) -> Result<()> {
+    ensure_digitalocean(name)?;
+    r#do::remove_node_pool(name, pool)
+}
+
+/// Returns the node pools of a DigitalOcean cluster as table rows.
+pub fn node_pools(name: &str) -> Result<Vec<Vec<String>>> {
+    ensure_digitalocean(name)?;
+    r#do::node_pools(name)
+}
+
+/// Downloads the kubeconfig of a DigitalOcean cluster again, its credentials
+/// expire after a week.
+pub fn refresh_kubeconfig(name: &str) -> Result<()> {
+    ensure_exists(name)?;
+    if let ClusterType::Kind = cluster_type(name) {
+        return Err(anyhow!(
+            "Only the kubeconfigs of DigitalOcean clusters expire and can be refreshed"
+        ));
+    }
+
+    r#do::refresh_kubeconfig(name)?;
+    info!(
+        "Refreshed kubeconfig: {}",
+        Style::new().cyan().apply_to(name)
+    );
+
+    Ok(())
+}
+
+/// Refreshes the kubeconfig of a DigitalOcean cluster about to expire. The
+/// old one is kept if it cannot be refreshed, like when offline.
+pub fn refresh_expiring_kubeconfig(name: &str) {
+    if let ClusterType::Kind = cluster_type(name) {
+        return;
+    }
+    if !r#do::kubeconfig_expiring(name) {
+        return;
+    }
+
+    if let Err(e) = refresh_kubeconfig(name) {
+        warn!("Could not refresh the kubeconfig of {}: {:#}", name, e);
+    }
+}
+
+pub fn cluster_exists(name: &str) -> bool {
+    state::get(name).is_some()
+}
+
+pub fn ensure_exists(name: &str) -> Result<()> {
+    if !cluster_exists(name) {
+        return Err(HakeError::ClusterNotFound(String::from(name)).into());
+    }
+
+    Ok(())
+}
+
+pub fn cluster_type(name: &str) -> ClusterType {
+    if let Some(metadata) = ClusterMetadata::read(name) {
+        if metadata.provider == "digitalocean" {
+            return ClusterType::DigitalOcean;
+        }
+        return ClusterType::Kind;
+    }
+
+    if cluster_dir(name).join("cluster_uuid").exists() {
+        ClusterType::DigitalOcean
+    } else {
+        ClusterType::Kind
+    }
+}
+
+pub fn delete(name: &str) -> Result<()> {
+    delete_with_hooks(name, true)
+}
+
+/// Deletes a cluster after running its pre_delete hooks. A failing hook
+/// stops the delete when `strict`, and is only warned about otherwise, for
+/// deletes of many clusters no one is watching, like `gc`.
+fn delete_with_hooks(name: &str, strict: bool) -> Result<()> {
+    ensure_exists(name)?;
+    let provider = match cluster_type(name) {
+        ClusterType::Kind => "kind",
+        ClusterType::DigitalOcean => "digitalocean",
+    };
+    let hooked = delete_hooks(name)
+        .and_then(|hooks| hooks::run("pre_delete", &hooks.pre_delete, name, provider));
+    match hooked {
+        Err(e) if !strict => warn!(
+            "The pre_delete hook of {} failed, deleting it anyway: {:#}",
+            name, e
+        ),
+        hooked => hooked?,
+    }
+
+    let cyan = Style::new().cyan();
+    info!("Deleting cluster: {}", cyan.apply_to(name));
+    progress::phase("delete", 0, &format!("Deleting cluster {}", name));
+    if dryrun::enabled() {
+        dryrun::print(&format!(
+            "would remove context {} from ~/.kube/config",
+            kubeconfig::context_name(name)
+        ));
+    } else {
+        // a kubeconfig hake cannot read does not keep the cluster around
+        if let Err(e) = kubeconfig::remove(name) {
+            warn!(
+                "Could not remove context {} from the main kubeconfig: {:#}",
+                kubeconfig::context_name(name),
+                e
+            );
+        }
+        current::clear(name)?;
+        forward::stop_all(name);
+    }
+    match cluster_type(name) {
+        ClusterType::Kind => {
+            kind_binary::use_recorded(name)?;
+            runtime::use_recorded(name)?;
+            let cluster = Kind::new(name);
+            cluster.delete()
+        }
+        ClusterType::DigitalOcean => r#do::delete(name),
+    }?;
+    if !dryrun::enabled() {
+        state::remove(name)?;
+    }
+    if provider != "kind" {
+        if let Some(backend) = remote_state::Backend::configured()? {
+            if let Err(e) = remote_state::unregister(&backend, name) {
+                warn!("Could not remove {} from the shared state: {:#}", name, e);
+            }
+        }
+    }
+    progress::phase("done", 100, &format!("Deleted cluster {}", name));
+
+    Ok(())
+}
+
+/// Hooks recorded when the cluster was created, or the ones in the
+/// configuration file for clusters created by older versions of hake.
+fn delete_hooks(name: &str) -> Result<Hooks> {
+    match ClusterMetadata::read(name).and_then(|metadata| metadata.options) {
+        Some(opts) => Ok(opts.hooks),
+        None => Ok(UserConfig::load()?.hooks),
+    }
+}
+
+/// Deletes several clusters, going on with the rest when one of them fails.
+pub fn delete_clusters(names: &[String]) -> Result<()> {
+    let mut failed = 0;
+    for name in names {
+        if let Err(e) = delete_with_hooks(name, false) {
+            warn!("Could not delete cluster {}: {:#}", name, e);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} clusters could not be deleted",
+            failed,
+            names.len()
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn all_clusters() -> Vec<String> {
+    state::names()
+}
+
+/// Regex matching the whole name of a cluster, from a glob like pr-* or, with
+/// `regex`, from a regular expression.
+fn name_pattern(pattern: &str, regex: bool) -> Result<Regex> {
+    let pattern = if regex {
+        format!("^(?:{})$", pattern)
+    } else {
+        let glob: Vec<String> = pattern
+            .chars()
+            .map(|c| match c {
+                '*' => String::from(".*"),
+                '?' => String::from("."),
+                c => regex::escape(&c.to_string()),
+            })
+            .collect();
+        format!("^{}$", glob.concat())
+    };
+
+    Regex::new(&pattern).map_err(|e| anyhow!("Invalid pattern: {}", e))
+}
+
+/// Clusters whose names match a glob or a regular expression, sorted.
+pub fn matching_clusters(pattern: &str, regex: bool) -> Result<Vec<String>> {
+    let pattern = name_pattern(pattern, regex)?;
+    let mut names: Vec<String> = all_clusters()
+        .into_iter()
+        .filter(|name| pattern.is_match(name))
+        .collect();
+    names.sort();
+
+    Ok(names)
+}
+
+/// Rows describing every cluster with all the labels of `selector`, the first
+/// one being the header.
+pub fn list_rows(selector: &Labels) -> Vec<Vec<String>> {
+    let mut rows = vec![vec![
+        String::from("NAME"),
+        String::from("PROVIDER"),
+        String::from("STATUS"),
+        String::from("VERSION"),
+        String::from("NODES"),
+        String::from("AGE"),
+        String::from("LABELS"),
+    ]];
+
+    let clusters = state::load()
+        .map(|state| state.clusters)
+        .unwrap_or_default();
+    for (name, cluster) in clusters {
+        let metadata = ClusterMetadata::read(&name);
+        let cluster_labels = metadata
+            .as_ref()
+            .map(|m| m.labels.clone())
+            .unwrap_or_default();
+        if !labels::matches(&cluster_labels, selector) {
+            continue;
+        }
+        let provider = match cluster_type(&name) {
+            ClusterType::Kind => "kind",
+            ClusterType::DigitalOcean => "digitalocean",
+        };
+        // the provider knows nothing about clusters half-created
+        let (status, nodes) =
+            if cluster.status == state::CREATING || cluster.status == state::FAILED {
+                (cluster.status, String::from("-"))
+            } else {
+                cluster_status(&name)
+            };
+        let version = metadata
+            .as_ref()
+            .and_then(|m| m.kubernetes_version.clone())
+            .unwrap_or_else(|| String::from("-"));
+        let age = match &metadata {
+            Some(m) => ttl::format_age(ttl::now().saturating_sub(m.created_at)),
+            None => String::from("-"),
+        };
+
+        rows.push(vec![
+            name,
+            String::from(provider),
+            status,
+            version,
+            nodes,
+            age,
+            labels::format(&cluster_labels),
+        ]);
+    }
+
+    rows
+}
+
+/// Rows of `list --remote`: the local clusters and the DigitalOcean clusters
+/// missing from the local state, like the ones created on another machine or
+/// by someone else. With a tag, only the clusters that have it, and with a
+/// label selector only the local clusters that match it.
+pub fn remote_rows(tag: Option<&str>, selector: &Labels) -> Result<Vec<Vec<String>>> {
+    let remote = r#do::remote_clusters(tag)?;
+    let local_ids: HashMap<String, String> = all_clusters()
+        .into_iter()
+        .filter(|name| matches!(cluster_type(name), ClusterType::DigitalOcean))
+        .filter_map(|name| {
+            let id = r#do::read_cluster_id(&name).ok()?;
+            Some((name, id.trim().to_string()))
+        })
+        .collect();
+
+    // remote only clusters have no labels to match
+    let remote = if selector.is_empty() { remote } else { vec![] };
+
+    Ok(merge_remote(
+        list_rows(selector),
+        &local_ids,
+        &remote,
+        tag.is_some(),
+    ))
+}
+
+/// Adds a SOURCE column to the rows of `list_rows` and the remote clusters
+/// without a local cluster of the same id, marked as remote only.
+fn merge_remote(
+    local: Vec<Vec<String>>,
+    local_ids: &HashMap<String, String>,
+    remote: &[RemoteCluster],
+    tagged: bool,
+) -> Vec<Vec<String>> {
+    let remote_ids: HashSet<&str> = remote.iter().map(|c| c.id.as_str()).collect();
+    let mut rows = vec![];
+    for (i, mut row) in local.into_iter().enumerate() {
+        if i == 0 {
+            row.push(String::from("SOURCE"));
+            rows.push(row);
+            continue;
+        }
+        let on_remote = local_ids
+            .get(&row[0])
+            .is_some_and(|id| remote_ids.contains(id.as_str()));
+        // with a tag, local clusters are listed only when tagged remotely
+        if tagged && !on_remote {
+            continue;
+        }
+        row.push(String::from("local"));
+        rows.push(row);
+    }
+
+    let known: HashSet<&str> = local_ids.values().map(String::as_str).collect();
+    for cluster in remote.iter().filter(|c| !known.contains(c.id.as_str())) {
+        rows.push(vec![
+            cluster.name.clone(),
+            String::from("digitalocean"),
+            cluster.state.clone(),
+            cluster.version.clone(),
+            cluster.nodes.to_string(),
+            String::from("-"),
+            String::from("-"),
+            String::from("remote only"),
+        ]);
+    }
+
+    rows
+}
+
+/// Returns the status and number of nodes of a cluster.
+pub fn cluster_status(name: &str) -> (String, String) {
+    match cluster_type(name) {
+        ClusterType::Kind => match Kind::get_node_containers(name) {
+            Ok(nodes) if nodes.is_empty() => (String::from("gone"), String::from("0")),
+            Ok(nodes) => {
+                let status = if nodes.iter().all(|(_, state)| state == "running") {
+                    "running"
+                } else {
+                    "stopped"
+                };
+                (String::from(status), nodes.len().to_string())
+            }
+            Err(_) => (String::from("unknown"), String::from("-")),
+        },
+        ClusterType::DigitalOcean => match r#do::get_cluster_state(name) {
+            Ok((state, nodes)) => (state, nodes.to_string()),
+            Err(_) => (String::from("unknown"), String::from("-")),
+        },
+    }
+}
+
+/// Prints the logs of the nodes of a kind cluster.
+pub fn logs(name: &str, opts: &LogOptions) -> Result<()> {
+    ensure_exists(name)?;
+    if let ClusterType::DigitalOcean = cluster_type(name) {
+        return Err(anyhow!("Only the logs of kind clusters can be read"));
+    }
+
+    logs::logs(name, opts)
+}
+
+fn ensure_kind_nodes(name: &str) -> Result<()> {
+    ensure_exists(name)?;
+    if let ClusterType::DigitalOcean = cluster_type(name) {
+        return Err(anyhow!("Only the nodes of kind clusters can be reached"));
+    }
+
+    runtime::use_recorded(name)
+}
+
+/// Runs a command in a node of a kind cluster and returns its exit code.
+pub fn node_exec(name: &str, node: Option<&str>, command: &[String]) -> Result<i32> {
+    ensure_kind_nodes(name)?;
+    node::exec(name, node, command)
+}
+
+/// Opens a shell in a node of a kind cluster and returns its exit code.
+pub fn node_ssh(name: &str, node: Option<&str>) -> Result<i32> {
+    ensure_kind_nodes(name)?;
+    node::ssh(name, node)
+}
+
+/// Starts a port-forward to a service or pod of a cluster in the background.
+pub fn forward(name: &str, target: &str, namespace: Option<&str>, ports: &[String]) -> Result<()> {
+    ensure_exists(name)?;
+    refresh_expiring_kubeconfig(name);
+    forward::start(name, target, namespace, ports)?;
+
+    Ok(())
+}
+
+/// Prints a login token for the dashboard of a cluster and serves it.
+pub fn dashboard(name: &str, port: u16, no_open: bool) -> Result<()> {
+    ensure_exists(name)?;
+    refresh_expiring_kubeconfig(name);
+    dashboard::dashboard(name, port, no_open)
+}
+
+/// Copies files between the host and a node of a kind cluster.
+pub fn cp(name: &str, source: &str, destination: &str) -> Result<()> {
+    ensure_kind_nodes(name)?;
+    node::cp(name, source, destination)
+}
+
+pub fn stop(name: &str) -> Result<()> {
+    ensure_exists(name)?;
+    if let ClusterType::DigitalOcean = cluster_type(name) {
+        return Err(anyhow!("Only kind clusters can be stopped"));
+    }
+
+    let cyan = Style::new().cyan();
+    info!("Stopping cluster: {}", cyan.apply_to(name));
+    runtime::use_recorded(name)?;
+    Kind::stop(name)?;
+    state::set_status(name, state::STOPPED)
+}
+
+pub fn start(name: &str) -> Result<()> {
+    ensure_exists(name)?;
+    if let ClusterType::DigitalOcean = cluster_type(name) {
+        return Err(anyhow!("Only kind clusters can be started"));
+    }
+
+    let cyan = Style::new().cyan();
+    info!("Starting cluster: {}", cyan.apply_to(name));
+    runtime::use_recorded(name)?;
+    Kind::start(name)?;
+    state::set_status(name, state::RUNNING)?;
+
+    if !kubectl::wait_for_api_server(name, API_SERVER_TIMEOUT) {
+        return Err(anyhow!(
+            "API server of {} did not come back after {} seconds",
+            name,
+            API_SERVER_TIMEOUT
+        ));
+    }
+    info!("API server is ready");
+
+    Ok(())
+}
+
+pub fn export(name: &str, output: Option<String>) -> Result<()> {
+    ensure_exists(name)?;
+    if let ClusterType::DigitalOcean = cluster_type(name) {
+        return Err(anyhow!("Only kind clusters can be exported"));
+    }
+
+    let output = output.unwrap_or_else(|| format!("{}.tar.gz", name));
+    let cyan = Style::new().cyan();
+    info!(
+        "Exporting cluster {} to {}",
+        cyan.apply_to(name),
+        cyan.apply_to(&output)
+    );
+
+    Kind::export(name, &output)
+}
+
+pub fn import(name: &str, archive: &str, verbose: bool) -> Result<()> {
+    if cluster_exists(name) {
+        return Err(HakeError::ClusterExists(String::from(name)).into());
+    }
+
+    let cyan = Style::new().cyan();
+    info!(
+        "Importing cluster {} from {}",
+        cyan.apply_to(name),
+        cyan.apply_to(archive)
+    );
+
+    Kind::import(archive, name, verbose)?;
+    state::set(name, "kind", state::RUNNING)
+}
+
+/// Brings a cluster created without hake under its management: a kind
+/// cluster by name, or a DigitalOcean one by id, named like on DigitalOcean
+/// unless given a name.
+pub fn adopt(name: Option<&str>, id: Option<&str>) -> Result<()> {
+    let (name, provider) = match (name, id) {
+        (Some(name), None) => (String::from(name), "kind"),
+        (name, Some(id)) => match name {
+            Some(name) => (String::from(name), "digitalocean"),
+            None => (r#do::cluster_name(id)?, "digitalocean"),
+        },
+        (None, None) => {
+            return Err(anyhow!(
+                "Give the name of a kind cluster or the --id of a DigitalOcean one"
+            ))
+        }
+    };
+    if cluster_exists(&name) {
+        return Err(HakeError::ClusterExists(name).into());
+    }
+
+    let cyan = Style::new().cyan();
+    info!("Adopting cluster: {}", cyan.apply_to(&name));
+    if dryrun::enabled() {
+        dryrun::print(&format!(
+            "would write the kubeconfig and metadata of {}",
+            name
+        ));
+        return Ok(());
+    }
+    match id {
+        Some(id) => r#do::adopt(id, &name)?,
+        None => Kind::adopt(&name)?,
+    }
+
+    let mut metadata = ClusterMetadata::new(provider);
+    metadata.kubernetes_version = metadata::get_kubernetes_version(&name);
+    if provider == "kind" {
+        metadata.node_image = Kind::get_node_image(&name);
+    }
+    metadata.write(&name)?;
+    state::set(&name, provider, state::RUNNING)
+}
+
+/// Deletes the expired clusters, and with `shared` the expired clusters of
+/// everyone in the shared state.
+pub fn gc(shared: bool) -> Result<()> {
+    let expired: Vec<String> = all_clusters()
+        .into_iter()
+        .filter(|cluster| ttl::is_expired(cluster))
+        .collect();
+    let deleted = delete_clusters(&expired);
+
+    if shared {
+        let backend = remote_state::Backend::configured()?
+            .ok_or_else(|| anyhow!("There is no remote state in the configuration file"))?;
+        remote_state::gc(&backend)?;
+    }
+
+    deleted
+}
+
+/// The clusters in the shared state as table rows.
+pub fn shared_rows() -> Result<Vec<Vec<String>>> {
+    let backend = remote_state::Backend::configured()?
+        .ok_or_else(|| anyhow!("There is no remote state in the configuration file"))?;
+
+    Ok(remote_state::rows(
+        &remote_state::fetch(&backend)?,
+        ttl::now(),
+    ))
+}
+
+/// Removes the directory and state of a cluster that does not exist
+/// anymore, with `force`.
+fn remove_state(cluster: &str, force: bool) -> Result<()> {
+    let dir = cluster_dir(cluster);
+    if dryrun::enabled() {
+        dryrun::print(&format!("would remove {}", dir.display()));
+    } else if force {
+        info!("Removing {}", dir.display());
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        state::remove(cluster)?;
+    } else {
+        info!("Not removing {}. Use --force", dir.display());
+    }
+
+    Ok(())
+}
+
+/// Clusters on DigitalOcean without a local cluster of the same id.
+fn orphans<'a>(remote: &'a [RemoteCluster], known_ids: &HashSet<String>) -> Vec<&'a RemoteCluster> {
+    remote
+        .iter()
+        .filter(|cluster| !known_ids.contains(&cluster.id))
+        .collect()
+}
+
+/// Removes the state of the DigitalOcean clusters deleted on DigitalOcean,
+/// and reports the clusters of the user there that hake has no state for.
+/// Those cost money, so they are left for the user to adopt or delete.
+fn clean_digitalocean(clusters: &[String], force: bool) -> Result<()> {
+    let mut known_ids = HashSet::new();
+    for cluster in clusters
+        .iter()
+        .filter(|c| matches!(cluster_type(c), ClusterType::DigitalOcean))
+    {
+        if let Ok(id) = r#do::read_cluster_id(cluster) {
+            known_ids.insert(id.trim().to_string());
+        }
+        match r#do::get_cluster_state(cluster) {
+            Ok((state, _)) if state == "gone" => {
+                info!("Cluster {} does not exist on DigitalOcean anymore", cluster);
+                remove_state(cluster, force)?;
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Could not check cluster {} on DigitalOcean: {:#}",
+                cluster, e
+            ),
+        }
+    }
+
+    let remote = r#do::remote_clusters(Some("owner:me"))?;
+    for cluster in orphans(&remote, &known_ids) {
+        info!(
+            "DigitalOcean cluster {} has no hake state. Use `hake adopt --id {}` to keep it, or delete it on DigitalOcean",
+            cluster.name, cluster.id
+        );
+    }
+
+    Ok(())
+}
+
+/// Removes what is left of half-deleted clusters: hake state without node
+/// containers or without a DigitalOcean cluster, node containers without hake
+/// state and the kind docker network once nothing uses it. DigitalOcean
+/// clusters of the user without hake state are reported. Kind clusters
+/// without hake state may not be hake's at all, so they are only removed
+/// with `include_unmanaged` too.
+pub fn clean(force: bool, include_unmanaged: bool) -> Result<()> {
+    let kind_clusters = Kind::get_kind_containers()?;
+    let clusters = all_clusters();
+
+    for cluster in clusters.iter() {
+        if let ClusterType::DigitalOcean = cluster_type(cluster) {
+            continue;
+        }
+        if !kind_clusters.contains(cluster) {
+            remove_state(cluster, force)?;
+        }
+    }
+    if r#do::has_api_token() {
+        clean_digitalocean(&clusters, force)?;
+    }
+
+    for cluster in kind_clusters.iter() {
+        if !clusters.contains(cluster) {
+            if dryrun::enabled() {
+                dryrun::print(&format!(
+                    "would run: kind delete cluster --name {}",
+                    cluster
+                ));
+            } else if force && include_unmanaged {
+                info!("Removing node containers of kind cluster {}", cluster);
+                Kind::delete_cluster(cluster)?;
+            } else if force {
+                info!(
+                    "Kind cluster {} has node containers but no hake state, so it may not be hake's. Use --include-unmanaged to remove it too",
+                    cluster
+                );
+            } else {
+                info!(
+                    "Kind cluster {} has node containers but no hake state. Use --force to remove it, or `hake adopt --name {}` to keep it",
+                    cluster, cluster
+                );
+            }
+        }
+    }
+
+    if Kind::network_unused() {
+        if dryrun::enabled() {
+            dryrun::print("would remove the kind docker network");
+        } else if force {
+            info!("Removing the kind docker network");
+            Kind::remove_network()?;
+        } else {
+            info!("The kind docker network is not used. Use --force to remove it");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cluster::{
+        check_recreate, imported_options, merge_remote, name_pattern, node_image, orphans,
+        recorded_options, CreateOptions,
+    };
+    use crate::r#do::RemoteCluster;
+    use std::collections::{HashMap, HashSet};
+    use std::env;
+
+    #[test]
+    fn test_name_pattern() {
+        let glob = name_pattern("pr-*", false).unwrap();
+        assert!(glob.is_match("pr-123"));
+        assert!(!glob.is_match("main-pr-123"));
+
+        let glob = name_pattern("pr-?.a", false).unwrap();
+        assert!(glob.is_match("pr-1.a"));
+        assert!(!glob.is_match("pr-1xa"));
+
+        let regex = name_pattern(r"pr-\d+", true).unwrap();
+        assert!(regex.is_match("pr-42"));
+        assert!(!regex.is_match("pr-42-old"));
+        assert!(name_pattern("pr-(", true).is_err());
+    }
+
+    #[test]
+    fn test_recorded_options() {
+        let mut opts = CreateOptions::new("dev");
+        opts.with_ingress = Some(None);
+        opts.with_cert_manager = Some(None);
+        opts.with_olm = Some(Some(String::from("v0.26.0")));
+        let recorded = recorded_options(&opts);
+        assert_eq!(recorded.with_ingress, Some(Some(String::from("nginx"))));
+        assert_eq!(
+            recorded.with_cert_manager,
+            Some(Some(String::from("v1.14.4")))
+        );
+        assert_eq!(recorded.with_olm, Some(Some(String::from("v0.26.0"))));
+
+        // a bare flag would read back as not given
+        let json = serde_json::to_string(&recorded).unwrap();
+        let read: CreateOptions = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, recorded);
+    }
+
+    #[test]
+    fn test_recorded_options_paths() {
+        let cwd = env::current_dir().unwrap();
+        let mut opts = CreateOptions::new("dev");
+        opts.kubeadm_patches = vec![String::from("patches/api.yaml")];
+        opts.mounts = vec![String::from("data:/data:ro"), String::from("/srv:/srv")];
+        opts.helm = vec![
+            String::from("bitnami/redis"),
+            String::from("bitnami/redis@17.0.0:redis.yaml"),
+            String::from("oci://registry-1.docker.io/bitnamicharts/redis"),
+            String::from("oci://registry-1.docker.io/bitnamicharts/redis:redis.yaml"),
+        ];
+        opts.apply = vec![String::from("https://example.com/app.yaml")];
+        let recorded = recorded_options(&opts);
+        assert_eq!(
+            recorded.kubeadm_patches,
+            vec![cwd.join("patches/api.yaml").to_string_lossy()]
+        );
+        assert_eq!(
+            recorded.mounts,
+            vec![
+                format!("{}:/data:ro", cwd.join("data").display()),
+                String::from("/srv:/srv")
+            ]
+        );
+        assert_eq!(
+            recorded.helm,
+            vec![
+                String::from("bitnami/redis"),
+                format!("bitnami/redis@17.0.0:{}", cwd.join("redis.yaml").display()),
+                String::from("oci://registry-1.docker.io/bitnamicharts/redis"),
+                format!(
+                    "oci://registry-1.docker.io/bitnamicharts/redis:{}",
+                    cwd.join("redis.yaml").display()
+                )
+            ]
+        );
+        assert_eq!(recorded.apply, opts.apply);
+        // recording twice changes nothing
+        assert_eq!(recorded_options(&recorded), recorded);
+    }
+
+    #[test]
+    fn test_imported_options() {
+        let cwd = env::current_dir().unwrap().to_string_lossy().into_owned();
+        let mut opts = CreateOptions::new("old");
+        opts.hooks.pre_delete = vec![String::from("curl https://example.com/gone")];
+        opts.kubeadm_patches = vec![String::from("/does/not/exist.yaml"), cwd.clone()];
+        opts.apply = vec![String::from("https://example.com/app.yaml")];
+        opts.mounts = vec![
+            format!("{}:/src", cwd),
+            String::from("/does/not/exist:/data"),
+        ];
+        opts.helm = vec![String::from("bitnami/redis:/does/not/exist.yaml")];
+        opts.pv = Some(String::from("/does/not/exist"));
+
+        let imported = imported_options(&opts, "new");
+        assert_eq!(imported.name, "new");
+        assert!(imported.hooks.pre_delete.is_empty());
+        assert_eq!(imported.kubeadm_patches, vec![cwd.clone()]);
+        assert_eq!(imported.apply, opts.apply);
+        assert_eq!(imported.mounts, vec![format!("{}:/src", cwd)]);
+        assert_eq!(imported.helm, vec![String::from("bitnami/redis")]);
+        assert_eq!(imported.pv, None);
+    }
+
+    #[test]
+    fn test_check_recreate() {
+        let mut opts = CreateOptions::new("dev");
+        opts.kubeadm_patches = vec![String::from("/does/not/exist.yaml")];
+        assert!(check_recreate(&opts).is_err());
+
+        opts.kubeadm_patches = vec![];
+        opts.mounts = vec![format!("{}:/src", env!("CARGO_MANIFEST_DIR"))];
+        assert!(check_recreate(&opts).is_ok());
+
+        opts.helm = vec![String::from(
+            "oci://registry-1.docker.io/bitnamicharts/redis",
+        )];
+        assert!(check_recreate(&opts).is_ok());
+    }
+
+    #[test]
+    fn test_node_image() {
+        assert_eq!(node_image("v1.19.1"), "kindest/node:v1.19.1");
+        assert_eq!(node_image("1.19.1"), "kindest/node:v1.19.1");
+        assert_eq!(
+            node_image("registry.local/node:v1.19.1"),
+            "registry.local/node:v1.19.1"
+        );
+    }
+
+    #[test]
+    fn test_merge_remote() {
+        let row = |name: &str, provider: &str| {
+            vec![name, provider, "running", "v1.28.2", "2", "3d", "-"]
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<String>>()
+        };
+        let local = vec![
+            row("NAME", "PROVIDER"),
+            row("dev", "kind"),
+            row("staging", "digitalocean"),
+        ];
+        let mut local_ids = HashMap::new();
+        local_ids.insert(String::from("staging"), String::from("id-1"));
+        let remote = |id: &str, name: &str| RemoteCluster {
+            id: String::from(id),
+            name: String::from(name),
+            region: String::from("lon1"),
+            version: String::from("1.28.2-do.0"),
+            state: String::from("running"),
+            nodes: 3,
+            tags: vec![],
+        };
+        let remote = vec![remote("id-1", "staging"), remote("id-2", "pr-42")];
+
+        let rows = merge_remote(local.clone(), &local_ids, &remote, false);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0][7], "SOURCE");
+        assert_eq!(rows[1][7], "local");
+        assert_eq!(rows[2][7], "local");
+        assert_eq!(
+            rows[3],
+            vec![
+                "pr-42",
+                "digitalocean",
+                "running",
+                "1.28.2-do.0",
+                "3",
+                "-",
+                "-",
+                "remote only"
+            ]
+        );
+
+        // tagged: kind clusters and untagged local ones are left out
+        let rows = merge_remote(local, &local_ids, &remote[1..], true);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][0], "pr-42");
+    }
+
+    #[test]
+    fn test_orphans() {
+        let remote = |id: &str| RemoteCluster {
+            id: String::from(id),
+            name: format!("cluster-{}", id),
+            region: String::from("lon1"),
+            version: String::from("1.28.2-do.0"),
+            state: String::from("running"),
+            nodes: 1,
+            tags: vec![String::from("owner:me")],
+        };
+        let remote = vec![remote("1"), remote("2")];
+        let known_ids: HashSet<String> = vec![String::from("1")].into_iter().collect();
+
+        let orphaned: Vec<&str> = orphans(&remote, &known_ids)
+            .iter()
+            .map(|c| c.id.as_str())
+            .collect();
+        assert_eq!(orphaned, vec!["2"]);
+    }
+}
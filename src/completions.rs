@@ -0,0 +1,100 @@
+// Shell completion scripts. clap generates them with --name completing file
+// names, so for bash, zsh and fish they are changed to ask `hake
+// cluster-names` for the clusters that exist at the time of completing.
+use regex::Regex;
+use structopt::clap::Shell;
+
+/// Lists the clusters, quietly when hake fails.
+const CLUSTER_NAMES: &str = "hake cluster-names 2>/dev/null";
+
+/// The completion script of `shell` with --name completing the clusters.
+pub fn complete_names(shell: Shell, script: &str) -> String {
+    match shell {
+        Shell::Bash => script.replace(
+            "--name)\n                    COMPREPLY=($(compgen -f \"${cur}\"))",
+            &format!(
+                "--name)\n                    COMPREPLY=($(compgen -W \"$({})\" -- \"${{cur}}\"))",
+                CLUSTER_NAMES
+            ),
+        ),
+        Shell::Zsh => {
+            let function = format!(
+                "_hake_cluster_names() {{\n    compadd -- ${{(f)\"$({})\"}}\n}}\n\n_hake() {{",
+                CLUSTER_NAMES
+            );
+            let script = script.replacen("_hake() {", &function, 1);
+            let name = Regex::new(r"'--name=\[([^\]]*)\]' \\").unwrap();
+
+            name.replace_all(&script, "'--name=[$1]: :_hake_cluster_names' \\")
+                .into_owned()
+        }
+        Shell::Fish => {
+            let name = Regex::new(r"(?m)^(complete .* -l name)( -d .*)?$").unwrap();
+
+            name.replace_all(
+                script,
+                format!("$1 -r -f -a '({})'$2", CLUSTER_NAMES).as_str(),
+            )
+            .into_owned()
+        }
+        // they only complete the names of the options
+        Shell::PowerShell | Shell::Elvish => String::from(script),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::completions::complete_names;
+    use structopt::clap::Shell;
+
+    #[test]
+    fn test_complete_names_bash() {
+        let script = r#"            case "${prev}" in
+
+                --name)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --output)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+"#;
+        let completed = complete_names(Shell::Bash, script);
+        assert!(completed.contains(
+            r#"COMPREPLY=($(compgen -W "$(hake cluster-names 2>/dev/null)" -- "${cur}"))"#
+        ));
+        // other options still complete files
+        assert!(completed.contains(r#"COMPREPLY=($(compgen -f "${cur}"))"#));
+    }
+
+    #[test]
+    fn test_complete_names_zsh() {
+        let script = r#"autoload -U is-at-least
+
+_hake() {
+    _arguments "${_arguments_options[@]}" \
+'--name=[Name of the cluster]' \
+'*--namespace=[Creates a namespace]' \
+"#;
+        let completed = complete_names(Shell::Zsh, script);
+        assert!(completed.contains("_hake_cluster_names() {\n"));
+        assert!(completed.contains("'--name=[Name of the cluster]: :_hake_cluster_names' \\"));
+        assert!(completed.contains("'*--namespace=[Creates a namespace]' \\"));
+    }
+
+    #[test]
+    fn test_complete_names_fish() {
+        let script = r#"complete -c hake -n "__fish_seen_subcommand_from create" -l name -d 'Name of the cluster'
+complete -c hake -n "__fish_seen_subcommand_from create" -l namespace -d 'Creates a namespace'
+complete -c hake -n "__fish_seen_subcommand_from recreate" -l name
+"#;
+        assert_eq!(
+            complete_names(Shell::Fish, script),
+            r#"complete -c hake -n "__fish_seen_subcommand_from create" -l name -r -f -a '(hake cluster-names 2>/dev/null)' -d 'Name of the cluster'
+complete -c hake -n "__fish_seen_subcommand_from create" -l namespace -d 'Creates a namespace'
+complete -c hake -n "__fish_seen_subcommand_from recreate" -l name -r -f -a '(hake cluster-names 2>/dev/null)'
+"#
+        );
+    }
+}
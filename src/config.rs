@@ -0,0 +1,446 @@
+// User configuration with defaults for the create options, read from
+// ~/.config/hake/config.toml:
+//
+//     provider = "kind"
+//     verbose = true
+//
+//     [kind]
+//     ecr = "123456789012.dkr.ecr.us-east-1.amazonaws.com"
+//     node_image = "kindest/node:v1.18.2"
+//     version = "v0.8.1"
+//     runtime = "podman"
+//
+//     [digitalocean]
+//     region = "ams3"
+//     size = "s-4vcpu-8gb"
+//
+//     [http]
+//     max_attempts = 5
+//     ca_bundle = "/etc/ssl/corporate-root.pem"
+//
+//     [hooks]
+//     post_create = ["./scripts/seed-secrets.sh"]
+//
+//     [profiles.operator-dev]
+//     workers = 3
+//     ingress = "nginx"
+//
+// Flags given on the command line always win over the selected profile, and
+// the profile wins over the defaults.
+use anyhow::{anyhow, Context, Result};
+use serde_derive::Deserialize;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cluster::{CreateOptions, DEFAULT_PROVIDER};
+use crate::hooks::Hooks;
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KindDefaults {
+    /// ECR registry to configure access to.
+    pub ecr: Option<String>,
+    /// How nodes authenticate to the ECR registry, containerd or kubelet.
+    pub registry_auth: Option<String>,
+    /// Name of the local registry container.
+    pub local_registry: Option<String>,
+    pub node_image: Option<String>,
+    /// kind release to download and use instead of the one in PATH.
+    pub version: Option<String>,
+    /// Container runtime, docker or podman.
+    pub runtime: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DigitalOceanDefaults {
+    pub region: Option<String>,
+    /// Size of the droplets of the node pool.
+    pub size: Option<String>,
+    /// Tags of every cluster, besides hake and owner:<user>.
+    pub tags: Vec<String>,
+}
+
+/// Where the state shared by a team is kept.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StateDefaults {
+    /// Shared state of the cloud clusters, like s3://bucket/prefix or
+    /// git+git@github.com:org/state.git.
+    pub remote: Option<String>,
+}
+
+/// Settings of the clients of provider APIs.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpDefaults {
+    /// Attempts per request before giving up on transient errors.
+    pub max_attempts: Option<u32>,
+    /// PEM file with extra root certificates to trust, like the one of a
+    /// corporate proxy.
+    pub ca_bundle: Option<String>,
+}
+
+/// A named set of create options, selected with `create --profile`.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub provider: Option<String>,
+    pub ecr: Option<String>,
+    pub local_registry: Option<String>,
+    pub node_image: Option<String>,
+    pub kind_version: Option<String>,
+    pub arch: Option<String>,
+    pub runtime: Option<String>,
+    pub network: Option<String>,
+    pub workers: Option<u16>,
+    pub ports: Vec<String>,
+    pub mounts: Vec<String>,
+    pub feature_gates: Vec<String>,
+    pub kubeadm_patches: Vec<String>,
+    pub audit_policy: Option<String>,
+    pub encrypt_secrets: bool,
+    pub cni: Option<String>,
+    pub ip_family: Option<String>,
+    pub pod_subnet: Option<String>,
+    pub service_subnet: Option<String>,
+    /// Ingress controller to install.
+    pub ingress: Option<String>,
+    pub pv: Option<String>,
+    pub gpus: bool,
+    pub image_cache: bool,
+    pub loadbalancer: bool,
+    pub dashboard: bool,
+    /// Release of cert-manager to install.
+    pub cert_manager: Option<String>,
+    pub self_signed_issuer: bool,
+    /// Release of the Operator Lifecycle Manager to install.
+    pub olm: Option<String>,
+    pub merge_kubeconfig: bool,
+    pub metadata: Option<String>,
+    pub node_pools: Vec<String>,
+    pub tags: Vec<String>,
+    /// Labels of the clusters, like team=payments.
+    pub labels: Vec<String>,
+    pub vpc_uuid: Option<String>,
+    pub ha: bool,
+    pub auto_scale: bool,
+    pub min_nodes: Option<u16>,
+    pub max_nodes: Option<u16>,
+    pub helm: Vec<String>,
+    /// Images loaded into the nodes after creating the cluster.
+    pub preload: Vec<String>,
+    /// Manifests applied after creating the cluster, in order.
+    pub addons: Vec<String>,
+    /// CRDs installed as soon as the API server is ready.
+    pub crds: Vec<String>,
+    /// Namespaces created with a pull secret for the registry.
+    pub namespaces: Vec<String>,
+    /// Pull secrets with the ECR credentials, like namespace/name.
+    pub ecr_secrets: Vec<String>,
+    pub bootstrap: Option<String>,
+    pub ttl: Option<String>,
+    pub timeout: Option<String>,
+    /// Run after the hooks of the configuration file.
+    pub hooks: Hooks,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserConfig {
+    pub provider: Option<String>,
+    pub verbose: bool,
+    /// Desktop notifications when create, delete and upgrade finish.
+    pub notify: bool,
+    pub kind: KindDefaults,
+    pub digitalocean: DigitalOceanDefaults,
+    pub http: HttpDefaults,
+    pub state: StateDefaults,
+    pub hooks: Hooks,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Path of the configuration file, honoring XDG_CONFIG_HOME. On Windows it
+/// lives in %APPDATA%\hake.
+pub fn config_path() -> Option<PathBuf> {
+    let base = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ if cfg!(windows) => dirs::config_dir()?,
+        _ => dirs::home_dir()?.join(".config"),
+    };
+
+    Some(base.join("hake").join("config.toml"))
+}
+
+impl UserConfig {
+    pub fn parse(contents: &str) -> Result<UserConfig> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Reads the user configuration, which is optional.
+    pub fn load() -> Result<UserConfig> {
+        match config_path() {
+            Some(path) if path.exists() => UserConfig::read(&path),
+            _ => Ok(UserConfig::default()),
+        }
+    }
+
+    fn read(path: &Path) -> Result<UserConfig> {
+        let contents = fs::read_to_string(path)?;
+        UserConfig::parse(&contents).with_context(|| format!("Invalid {}", path.display()))
+    }
+
+    /// Fills the options not given on the command line with the selected
+    /// profile and then with the defaults.
+    pub fn apply(&self, opts: &mut CreateOptions) -> Result<()> {
+        let mut hooks = self.hooks.clone();
+        if let Some(name) = &opts.profile {
+            let profile = self
+                .profiles
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown profile: {}", name))?;
+            profile.apply(opts);
+            hooks.extend(&profile.hooks);
+        }
+        hooks.extend(&opts.hooks);
+        opts.hooks = hooks;
+
+        if opts.provider.is_none() {
+            opts.provider = self.provider.clone();
+        }
+        opts.verbose |= self.verbose;
+
+        if opts.provider.as_deref().unwrap_or(DEFAULT_PROVIDER) == DEFAULT_PROVIDER {
+            self.apply_kind(opts);
+        } else {
+            self.apply_digitalocean(opts);
+        }
+
+        Ok(())
+    }
+
+    fn apply_kind(&self, opts: &mut CreateOptions) {
+        if opts.ecr.is_none() {
+            opts.ecr = self.kind.ecr.clone();
+        }
+        if opts.registry_auth.is_none() {
+            opts.registry_auth = self.kind.registry_auth.clone();
+        }
+        if opts.use_local_registry.is_none() {
+            opts.use_local_registry = self.kind.local_registry.clone();
+        }
+        if opts.image.is_none() {
+            opts.image = self.kind.node_image.clone();
+        }
+        if opts.kind_version.is_none() {
+            opts.kind_version = self.kind.version.clone();
+        }
+        if opts.runtime.is_none() {
+            opts.runtime = self.kind.runtime.clone();
+        }
+    }
+
+    fn apply_digitalocean(&self, opts: &mut CreateOptions) {
+        // metadata given on the command line comes last, so it overrides these
+        let mut defaults = vec![];
+        if let Some(region) = &self.digitalocean.region {
+            defaults.push(format!("region={}", region));
+        }
+        if let Some(size) = &self.digitalocean.size {
+            defaults.push(format!("nodepool.size={}", size));
+        }
+        if !defaults.is_empty() {
+            defaults.extend(opts.metadata.take());
+            opts.metadata = Some(defaults.join("&"));
+        }
+        prepend(&mut opts.tags, &self.digitalocean.tags);
+    }
+}
+
+impl Profile {
+    fn apply(&self, opts: &mut CreateOptions) {
+        fill(&mut opts.provider, &self.provider);
+        fill(&mut opts.ecr, &self.ecr);
+        fill(&mut opts.use_local_registry, &self.local_registry);
+        fill(&mut opts.image, &self.node_image);
+        fill(&mut opts.kind_version, &self.kind_version);
+        fill(&mut opts.arch, &self.arch);
+        fill(&mut opts.runtime, &self.runtime);
+        fill(&mut opts.network, &self.network);
+        fill(&mut opts.cni, &self.cni);
+        fill(&mut opts.audit_policy, &self.audit_policy);
+        fill(&mut opts.ip_family, &self.ip_family);
+        fill(&mut opts.pod_subnet, &self.pod_subnet);
+        fill(&mut opts.service_subnet, &self.service_subnet);
+        fill(&mut opts.metadata, &self.metadata);
+        fill(&mut opts.bootstrap, &self.bootstrap);
+        fill(&mut opts.ttl, &self.ttl);
+        fill(&mut opts.timeout, &self.timeout);
+        fill(&mut opts.pv, &self.pv);
+        fill(&mut opts.vpc_uuid, &self.vpc_uuid);
+        if opts.with_ingress.is_none() {
+            opts.with_ingress = self.ingress.clone().map(Some);
+        }
+        if opts.with_cert_manager.is_none() {
+            opts.with_cert_manager = self.cert_manager.clone().map(Some);
+        }
+        if opts.with_olm.is_none() {
+            opts.with_olm = self.olm.clone().map(Some);
+        }
+        if opts.workers == 0 {
+            opts.workers = self.workers.unwrap_or(0);
+        }
+        opts.encrypt_secrets |= self.encrypt_secrets;
+        opts.gpus |= self.gpus;
+        opts.image_cache |= self.image_cache;
+        opts.with_loadbalancer |= self.loadbalancer;
+        opts.with_dashboard |= self.dashboard;
+        opts.self_signed_issuer |= self.self_signed_issuer;
+        opts.merge_kubeconfig |= self.merge_kubeconfig;
+        opts.ha |= self.ha;
+        opts.auto_scale |= self.auto_scale;
+        opts.min_nodes = opts.min_nodes.or(self.min_nodes);
+        opts.max_nodes = opts.max_nodes.or(self.max_nodes);
+
+        // repeatable options add up, the ones from the profile go first
+        prepend(&mut opts.ports, &self.ports);
+        prepend(&mut opts.mounts, &self.mounts);
+        prepend(&mut opts.feature_gates, &self.feature_gates);
+        prepend(&mut opts.kubeadm_patches, &self.kubeadm_patches);
+        prepend(&mut opts.node_pools, &self.node_pools);
+        prepend(&mut opts.tags, &self.tags);
+        prepend(&mut opts.labels, &self.labels);
+        prepend(&mut opts.helm, &self.helm);
+        prepend(&mut opts.preload, &self.preload);
+        prepend(&mut opts.apply, &self.addons);
+        prepend(&mut opts.crds, &self.crds);
+        prepend(&mut opts.namespaces, &self.namespaces);
+        prepend(&mut opts.ecr_secrets, &self.ecr_secrets);
+    }
+}
+
+fn fill(option: &mut Option<String>, default: &Option<String>) {
+    if option.is_none() {
+        *option = default.clone();
+    }
+}
+
+fn prepend(values: &mut Vec<String>, defaults: &[String]) {
+    let given = std::mem::replace(values, defaults.to_vec());
+    values.extend(given);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cluster::CreateOptions;
+    use crate::config::UserConfig;
+
+    #[test]
+    fn test_parse() {
+        let config = UserConfig::parse(
+            r#"
+provider = "digitalocean"
+
+[kind]
+node_image = "kindest/node:v1.18.2"
+
+[digitalocean]
+region = "ams3"
+
+[http]
+max_attempts = 3
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.provider.as_deref(), Some("digitalocean"));
+        assert_eq!(config.http.max_attempts, Some(3));
+        assert!(!config.verbose);
+        assert_eq!(
+            config.kind.node_image.as_deref(),
+            Some("kindest/node:v1.18.2")
+        );
+        assert_eq!(config.digitalocean.region.as_deref(), Some("ams3"));
+        assert_eq!(config.digitalocean.size, None);
+
+        assert_eq!(UserConfig::parse("").unwrap(), UserConfig::default());
+        assert!(UserConfig::parse("provder = \"kind\"").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let config = UserConfig::parse(
+            r#"
+provider = "digitalocean"
+[kind]
+ecr = "default-registry"
+[digitalocean]
+region = "ams3"
+size = "s-4vcpu-8gb"
+tags = ["team:infra"]
+"#,
+        )
+        .unwrap();
+
+        let mut opts = CreateOptions::new("test");
+        opts.ecr = Some(String::from("my-registry"));
+        opts.metadata = Some(String::from("region=lon1"));
+        opts.tags = vec![String::from("branch:pr-123")];
+        config.apply(&mut opts).unwrap();
+
+        assert_eq!(opts.provider.as_deref(), Some("digitalocean"));
+        assert_eq!(opts.ecr.as_deref(), Some("my-registry"));
+        assert_eq!(
+            opts.metadata.as_deref(),
+            Some("region=ams3&nodepool.size=s-4vcpu-8gb&region=lon1")
+        );
+        assert_eq!(opts.tags, vec!["team:infra", "branch:pr-123"]);
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        let config = UserConfig::parse(
+            r#"
+[kind]
+node_image = "kindest/node:v1.18.2"
+version = "v0.8.1"
+runtime = "podman"
+
+[hooks]
+post_create = ["seed.sh"]
+
+[profiles.operator-dev]
+workers = 3
+ecr = "profile-registry"
+ingress = "nginx"
+ports = ["8080:30080"]
+preload = ["postgres:15"]
+hooks = { post_create = ["dns.sh"], pre_delete = ["dns.sh --remove"] }
+"#,
+        )
+        .unwrap();
+
+        let mut opts = CreateOptions::new("pr-123");
+        opts.profile = Some(String::from("operator-dev"));
+        opts.ecr = Some(String::from("my-registry"));
+        opts.ports = vec![String::from("9090:30090")];
+        config.apply(&mut opts).unwrap();
+
+        assert_eq!(opts.workers, 3);
+        assert_eq!(opts.ecr.as_deref(), Some("my-registry"));
+        assert_eq!(opts.with_ingress, Some(Some(String::from("nginx"))));
+        assert_eq!(opts.ports, vec!["8080:30080", "9090:30090"]);
+        assert_eq!(opts.preload, vec!["postgres:15"]);
+        assert_eq!(opts.hooks.post_create, vec!["seed.sh", "dns.sh"]);
+        assert_eq!(opts.hooks.pre_delete, vec!["dns.sh --remove"]);
+        assert_eq!(opts.image.as_deref(), Some("kindest/node:v1.18.2"));
+        assert_eq!(opts.kind_version.as_deref(), Some("v0.8.1"));
+        assert_eq!(opts.runtime.as_deref(), Some("podman"));
+
+        opts.profile = Some(String::from("missing"));
+        assert!(config.apply(&mut opts).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+// Estimated cost of the clusters running on a cloud provider. Local kind
+// clusters are free.
+use anyhow::Result;
+
+use crate::cluster::{self, ClusterType};
+use crate::metadata::ClusterMetadata;
+use crate::r#do;
+use crate::ttl;
+
+/// Price of running a cluster, in US dollars.
+#[derive(Debug, Default, PartialEq)]
+pub struct Cost {
+    pub hourly: f64,
+    pub monthly: f64,
+}
+
+impl Cost {
+    /// What the cluster has cost since it was created, assuming it always had
+    /// the nodes it has now.
+    pub fn since(&self, created_at: u64, now: u64) -> f64 {
+        let hours = now.saturating_sub(created_at) as f64 / 3600.0;
+        self.hourly * hours
+    }
+}
+
+/// Cost of a cluster, None for clusters that are free.
+pub fn cluster_cost(name: &str) -> Result<Option<Cost>> {
+    match cluster::cluster_type(name) {
+        ClusterType::Kind => Ok(None),
+        ClusterType::DigitalOcean => Ok(Some(r#do::cost(name)?)),
+    }
+}
+
+pub fn format_dollars(amount: f64) -> String {
+    format!("${:.2}", amount)
+}
+
+/// Rows with the cost of the given clusters and their total. Clusters that
+/// are free are left out.
+pub fn cost_rows(names: &[String]) -> Result<Vec<Vec<String>>> {
+    let mut rows = vec![];
+    let mut total = (Cost::default(), 0.0);
+
+    for name in names {
+        let cost = match cluster_cost(name)? {
+            Some(cost) => cost,
+            None => continue,
+        };
+        let so_far =
+            ClusterMetadata::read(name).map(|metadata| cost.since(metadata.created_at, ttl::now()));
+
+        rows.push(vec![
+            name.clone(),
+            format_dollars(cost.hourly),
+            format_dollars(cost.monthly),
+            so_far.map_or_else(|| String::from("-"), format_dollars),
+        ]);
+        total.0.hourly += cost.hourly;
+        total.0.monthly += cost.monthly;
+        total.1 += so_far.unwrap_or(0.0);
+    }
+
+    if rows.len() > 1 {
+        rows.push(vec![
+            String::from("TOTAL"),
+            format_dollars(total.0.hourly),
+            format_dollars(total.0.monthly),
+            format_dollars(total.1),
+        ]);
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cost::{format_dollars, Cost};
+
+    #[test]
+    fn test_since() {
+        let cost = Cost {
+            hourly: 0.06,
+            monthly: 40.0,
+        };
+        assert_eq!(format_dollars(cost.since(0, 3 * 3600)), "$0.18");
+        assert_eq!(cost.since(100, 50), 0.0);
+    }
+}
@@ -0,0 +1,90 @@
+// The cluster last selected with `hake use`, kept in ~/.hake/.current so
+// shell prompts can show it without running docker or reading kubeconfigs.
+use anyhow::Result;
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::metadata::ClusterMetadata;
+use crate::ttl;
+
+/// Hidden so it is not taken for a cluster directory.
+fn path() -> PathBuf {
+    crate::get_config_dir().join(".current")
+}
+
+pub fn set(name: &str) -> Result<()> {
+    fs::write(path(), name)?;
+
+    Ok(())
+}
+
+/// Name of the current cluster, if any.
+pub fn get() -> Option<String> {
+    let name = fs::read_to_string(path()).ok()?;
+    let name = name.trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(String::from(name))
+    }
+}
+
+/// Forgets the current cluster if it is `name`, once it is deleted.
+pub fn clear(name: &str) -> Result<()> {
+    if get().as_deref() == Some(name) {
+        fs::remove_file(path())?;
+    }
+
+    Ok(())
+}
+
+/// Describes a cluster for a prompt, like "dev (kind v1.18.2, 3h)".
+fn describe(name: &str, metadata: Option<&ClusterMetadata>, now: u64) -> String {
+    let metadata = match metadata {
+        Some(metadata) => metadata,
+        None => return String::from(name),
+    };
+    let provider = match &metadata.kubernetes_version {
+        Some(version) => format!("{} {}", metadata.provider, version),
+        None => metadata.provider.clone(),
+    };
+    let age = ttl::format_age(now.saturating_sub(metadata.created_at));
+
+    format!("{} ({}, {})", name, provider, age)
+}
+
+/// What `hake current` prints: nothing without a current cluster, so prompts
+/// stay clean.
+pub fn show(short: bool) -> Option<String> {
+    let name = get()?;
+    if short {
+        return Some(name);
+    }
+
+    Some(describe(
+        &name,
+        ClusterMetadata::read(&name).as_ref(),
+        ttl::now(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::current::describe;
+    use crate::metadata::ClusterMetadata;
+
+    #[test]
+    fn test_describe() {
+        let mut metadata = ClusterMetadata::new("kind");
+        metadata.created_at = 1000;
+        assert_eq!(describe("dev", Some(&metadata), 1090), "dev (kind, 1m)");
+
+        metadata.kubernetes_version = Some(String::from("v1.18.2"));
+        assert_eq!(
+            describe("dev", Some(&metadata), 1000 + 3 * 3600),
+            "dev (kind v1.18.2, 3h)"
+        );
+        assert_eq!(describe("dev", None, 0), "dev");
+    }
+}
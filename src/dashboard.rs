@@ -0,0 +1,134 @@
+// Opens the Kubernetes Dashboard installed with `--with-dashboard`: prints a
+// login token of the hake-admin ServiceAccount, copies it to the clipboard
+// and serves the dashboard through `kubectl proxy` until interrupted.
+use anyhow::{anyhow, Result};
+use console::{Style, Term};
+use tracing::{info, warn};
+
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crate::add::{DASHBOARD_ADMIN, DASHBOARD_NAMESPACE};
+use crate::error::{spawn_error, HakeError};
+use crate::kubectl;
+use crate::logging::LogCommand;
+use crate::ui::clipboard_sequence;
+
+/// How long the login tokens last.
+const TOKEN_DURATION: &str = "24h";
+
+/// URL of the dashboard through `kubectl proxy` on `port`.
+pub fn url(port: u16) -> String {
+    format!(
+        "http://localhost:{}/api/v1/namespaces/{}/services/https:kubernetes-dashboard:/proxy/",
+        port, DASHBOARD_NAMESPACE
+    )
+}
+
+/// Creates a login token for the dashboard admin.
+pub fn token(cluster: &str) -> Result<String> {
+    let duration = format!("--duration={}", TOKEN_DURATION);
+    let args = [
+        "create",
+        "token",
+        DASHBOARD_ADMIN,
+        "--namespace",
+        DASHBOARD_NAMESPACE,
+        &duration,
+    ];
+    let out = kubectl::output(cluster, &args)?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        if stderr.contains("NotFound") || stderr.contains("not found") {
+            return Err(anyhow!(
+                "Cluster {} has no dashboard, create it with --with-dashboard",
+                cluster
+            ));
+        }
+        return Err(HakeError::CommandFailed {
+            command: format!("kubectl {}", args.join(" ")),
+            message: stderr.trim().to_string(),
+        }
+        .into());
+    }
+
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Opens a URL in the default browser.
+fn open(url: &str) {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    let opened = command
+        .arg(url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .logged()
+        .status();
+    if !matches!(opened, Ok(status) if status.success()) {
+        warn!("Could not open a browser, go to {}", url);
+    }
+}
+
+/// Prints a login token and serves the dashboard on `port` until
+/// interrupted, opening it in a browser unless `no_open`.
+pub fn dashboard(cluster: &str, port: u16, no_open: bool) -> Result<()> {
+    let token = token(cluster)?;
+    println!("{}", token);
+    let term = Term::stdout();
+    if term.is_term() {
+        print!("{}", clipboard_sequence(&token));
+        info!("Copied the token to the clipboard");
+    }
+
+    let url = url(port);
+    let mut proxy = Command::new("kubectl")
+        .arg("--kubeconfig")
+        .arg(kubectl::kubeconfig_path(cluster))
+        .args(["proxy", "--port", &port.to_string()])
+        .stdout(Stdio::null())
+        .logged()
+        .spawn()
+        .map_err(|e| spawn_error("kubectl", e))?;
+
+    // the proxy needs a moment to listen
+    thread::sleep(Duration::from_secs(1));
+    if let Some(status) = proxy.try_wait()? {
+        return Err(anyhow!(
+            "kubectl proxy exited with {}, is port {} in use?",
+            status,
+            port
+        ));
+    }
+    info!(
+        "Dashboard at {}, press Ctrl-C to stop",
+        Style::new().cyan().apply_to(&url)
+    );
+    if !no_open {
+        open(&url);
+    }
+    proxy.wait()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dashboard::url;
+
+    #[test]
+    fn test_url() {
+        assert_eq!(
+            url(8001),
+            "http://localhost:8001/api/v1/namespaces/kubernetes-dashboard/services/https:kubernetes-dashboard:/proxy/"
+        );
+    }
+}
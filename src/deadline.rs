@@ -0,0 +1,20 @@
+// Deadline of the operation running, from create --timeout. The steps that
+// can take long, like `kind create cluster` or waiting for a DigitalOcean
+// cluster to run, give up once it passes.
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Sets the deadline `timeout` from now, or removes it.
+pub fn set(timeout: Option<Duration>) {
+    *DEADLINE.lock().unwrap() = timeout.map(|timeout| Instant::now() + timeout);
+}
+
+/// Time left before the deadline, if there is one.
+pub fn remaining() -> Option<Duration> {
+    DEADLINE
+        .lock()
+        .unwrap()
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
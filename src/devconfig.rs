@@ -0,0 +1,129 @@
+// Configuration for inner loop tools, Tilt and Skaffold, pointing at a
+// cluster managed by hake and at its local registry.
+use anyhow::{anyhow, Result};
+
+use crate::kubeconfig;
+use crate::kubectl;
+use crate::metadata::ClusterMetadata;
+
+/// Where the nodes of a kind cluster find the local registry, see
+/// `Kind::use_local_registry`.
+const LOCAL_REGISTRY: &str = "localhost:5000";
+
+pub const TOOLS: &[&str] = &["tilt", "skaffold"];
+
+/// What the tools need to know about a cluster.
+#[derive(Debug)]
+pub struct DevTarget {
+    pub name: String,
+    pub kubeconfig: String,
+    pub context: String,
+    pub registry: Option<String>,
+}
+
+impl DevTarget {
+    pub fn new(name: &str) -> Result<DevTarget> {
+        let registry = ClusterMetadata::read(name)
+            .and_then(|metadata| metadata.local_registry)
+            .map(|_| String::from(LOCAL_REGISTRY));
+
+        Ok(DevTarget {
+            name: String::from(name),
+            kubeconfig: kubectl::kubeconfig_path(name).display().to_string(),
+            context: kubeconfig::current_context(name)?,
+            registry,
+        })
+    }
+}
+
+fn tiltfile(target: &DevTarget) -> String {
+    let mut tiltfile = format!(
+        "# Generated by hake for cluster {}, run tilt with:\n#   KUBECONFIG={} tilt up\nallow_k8s_contexts('{}')\n",
+        target.name, target.kubeconfig, target.context
+    );
+    if let Some(registry) = &target.registry {
+        tiltfile.push_str(&format!("default_registry('{}')\n", registry));
+    }
+
+    tiltfile
+}
+
+fn skaffold(target: &DevTarget) -> String {
+    let default_repo = match &target.registry {
+        Some(registry) => format!(" --default-repo {}", registry),
+        None => String::new(),
+    };
+
+    format!(
+        r#"# Generated by hake for cluster {name}, run skaffold with:
+#   skaffold dev --kubeconfig {kubeconfig}{default_repo}
+apiVersion: skaffold/v2beta8
+kind: Config
+metadata:
+  name: {name}
+build:
+  local:
+    push: {push}
+deploy:
+  kubeContext: {context}
+  kubectl:
+    manifests:
+    - k8s/*.yaml
+"#,
+        name = target.name,
+        kubeconfig = target.kubeconfig,
+        default_repo = default_repo,
+        push = target.registry.is_some(),
+        context = target.context,
+    )
+}
+
+/// Renders the configuration of a tool for a cluster.
+pub fn render(tool: &str, target: &DevTarget) -> Result<String> {
+    match tool {
+        "tilt" => Ok(tiltfile(target)),
+        "skaffold" => Ok(skaffold(target)),
+        _ => Err(anyhow!(
+            "Unknown tool {}, expected one of: {}",
+            tool,
+            TOOLS.join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::devconfig::{render, DevTarget};
+
+    fn target(registry: Option<&str>) -> DevTarget {
+        DevTarget {
+            name: String::from("dev"),
+            kubeconfig: String::from("/home/me/.hake/dev/kubeconfig"),
+            context: String::from("kind-dev"),
+            registry: registry.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_tiltfile() {
+        assert_eq!(
+            render("tilt", &target(Some("localhost:5000"))).unwrap(),
+            "# Generated by hake for cluster dev, run tilt with:\n#   KUBECONFIG=/home/me/.hake/dev/kubeconfig tilt up\nallow_k8s_contexts('kind-dev')\ndefault_registry('localhost:5000')\n"
+        );
+        assert!(!render("tilt", &target(None))
+            .unwrap()
+            .contains("default_registry"));
+    }
+
+    #[test]
+    fn test_skaffold() {
+        let config = render("skaffold", &target(Some("localhost:5000"))).unwrap();
+        assert!(config.contains(
+            "skaffold dev --kubeconfig /home/me/.hake/dev/kubeconfig --default-repo localhost:5000\n"
+        ));
+        assert!(config.contains("  kubeContext: kind-dev\n"));
+        assert!(config.contains("    push: true\n"));
+
+        assert!(render("garden", &target(None)).is_err());
+    }
+}
@@ -10,15 +10,32 @@ use reqwest::StatusCode;
 use anyhow::{anyhow, Result};
 use console::Style;
 
-use std::collections::{HashMap, HashSet};
-use std::fs::{create_dir, remove_dir_all, File};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{create_dir, create_dir_all, remove_dir_all, File};
 use std::io::prelude::*;
 use std::vec::Vec;
 use std::{env, io, thread, time};
 
 use serde_derive::{Deserialize, Serialize};
 
-const ENV_DO_PROVIDER: &str = "HAKE_PROVIDER_DIGITALOCEAN_API_KEY";
+use crate::auth;
+use crate::cluster::CreateOptions;
+use crate::cost::Cost;
+use crate::deadline;
+use crate::dryrun;
+use crate::error::HakeError;
+use crate::progress::Progress;
+use tracing::{debug, info};
+
+pub(crate) const ENV_DO_PROVIDER: &str = "HAKE_PROVIDER_DIGITALOCEAN_API_KEY";
+/// Seconds to wait for an upgrade, the nodes are replaced one at a time.
+const UPGRADE_TIMEOUT: u64 = 60 * 60;
+/// Seconds to wait for new nodes to join a cluster.
+const SCALE_TIMEOUT: u64 = 20 * 60;
+/// How long the credentials of a kubeconfig are valid for.
+const KUBECONFIG_LIFETIME: time::Duration = time::Duration::from_secs(7 * 24 * 60 * 60);
+/// How long before expiring a kubeconfig is downloaded again.
+const REFRESH_BEFORE: time::Duration = time::Duration::from_secs(24 * 60 * 60);
 
 #[derive(Serialize, Deserialize, Debug)]
 struct NodeStatus {
@@ -35,6 +52,13 @@ struct Node {
     updated_at: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Taint {
+    key: String,
+    value: String,
+    effect: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 struct NodePool {
     id: Option<String>,
@@ -42,9 +66,106 @@ struct NodePool {
     size: String,
     count: u16,
     tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    labels: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auto_scale: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_nodes: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_nodes: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    taints: Option<Vec<Taint>>,
+    #[serde(default)]
     nodes: Vec<Node>,
 }
 
+#[derive(Deserialize, Debug)]
+struct NodePoolResponse {
+    node_pool: NodePool,
+}
+
+/// Parses a taint like dedicated=gpu:NoSchedule, or dedicated:NoSchedule.
+fn parse_taint(taint: &str) -> Result<Taint> {
+    let (key_value, effect) = taint
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow!("Invalid taint {}, expected key=value:Effect", taint))?;
+    let (key, value) = key_value.split_once('=').unwrap_or((key_value, ""));
+
+    Ok(Taint {
+        key: String::from(key),
+        value: String::from(value),
+        effect: String::from(effect),
+    })
+}
+
+/// Parses a node pool like
+/// name=gpu,size=s-4vcpu-8gb,count=2,label=role=gpu,taint=dedicated=gpu:NoSchedule.
+/// label and taint can be repeated, size defaults to `default_size` and count to 1.
+/// min and max make the pool auto-scale between them.
+fn parse_node_pool(spec: &str, default_size: &str) -> Result<NodePool> {
+    let mut pool = NodePool {
+        size: String::from(default_size),
+        count: 1,
+        ..Default::default()
+    };
+
+    for field in spec.split(',') {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid node pool field: {}", field))?;
+        match key {
+            "name" => pool.name = String::from(value),
+            "size" => pool.size = String::from(value),
+            "count" => {
+                pool.count = value
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid node pool count: {}", value))?
+            }
+            "min" | "max" => {
+                let nodes = value
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid node pool {}: {}", key, value))?;
+                pool.auto_scale = Some(true);
+                if key == "min" {
+                    pool.min_nodes = Some(nodes);
+                } else {
+                    pool.max_nodes = Some(nodes);
+                }
+            }
+            "label" => {
+                let (label, label_value) = value
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Invalid label {}, expected key=value", value))?;
+                pool.labels
+                    .get_or_insert_with(BTreeMap::new)
+                    .insert(String::from(label), String::from(label_value));
+            }
+            "taint" => pool
+                .taints
+                .get_or_insert_with(Vec::new)
+                .push(parse_taint(value)?),
+            _ => return Err(anyhow!("Unknown node pool field: {}", key)),
+        }
+    }
+
+    if pool.name.is_empty() {
+        return Err(anyhow!("Node pool without a name: {}", spec));
+    }
+    if pool.auto_scale.is_some() {
+        let min = *pool.min_nodes.get_or_insert(1);
+        let max = *pool.max_nodes.get_or_insert(pool.count);
+        auto_scale_bounds(pool.count, min, max)?;
+    }
+
+    Ok(pool)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ClusterStatus {
+    state: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 struct KubernetesCluster {
     id: Option<String>,
@@ -54,10 +175,14 @@ struct KubernetesCluster {
     cluster_subnet: Option<String>,
     service_subnet: Option<String>,
     vpc_uuid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ha: Option<bool>,
     ipv4: Option<String>,
     endpoint: Option<String>,
     tags: Option<Vec<String>>,
     node_pools: Vec<NodePool>,
+    #[serde(skip_serializing)]
+    status: Option<ClusterStatus>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -65,6 +190,29 @@ struct KubernetesClusterResponse {
     kubernetes_cluster: KubernetesCluster,
 }
 
+#[derive(Deserialize, Debug)]
+struct UpgradeVersion {
+    slug: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct UpgradesResponse {
+    available_upgrade_versions: Option<Vec<UpgradeVersion>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Size {
+    slug: String,
+    price_monthly: f64,
+    price_hourly: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct SizesResponse {
+    sizes: Vec<Size>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct LoadBalancer {
     // This is Option because it is not mandatory when creating the cluster
@@ -102,7 +250,7 @@ impl Default for Metadata {
 }
 
 impl Metadata {
-    pub fn from_string(data: &str) -> Metadata {
+    pub fn from_string(data: &str) -> Result<Metadata> {
         let mut metadata = Metadata::default();
         let map = parse_metadata(data);
 
@@ -111,88 +259,296 @@ impl Metadata {
                 "region" => metadata.region = value,
                 "version" => metadata.version = value,
                 "nodepool.size" => metadata.nodepool_size = value,
-                "nodepool.count" => metadata.nodepool_count = value.parse::<u16>().unwrap(),
+                "nodepool.count" => {
+                    metadata.nodepool_count = value
+                        .parse::<u16>()
+                        .map_err(|_| anyhow!("Invalid nodepool.count: {}", value))?
+                }
                 _ => {}
             }
         }
 
-        metadata
+        Ok(metadata)
     }
 }
 
-pub fn create(name: &str, metadata: Option<String>) -> Result<()> {
-    let provider_metadata = metadata.unwrap_or("".to_string());
-    let cluster_spec = Metadata::from_string(&provider_metadata);
+/// Tag of every cluster created with hake.
+pub const TAG: &str = "hake";
+
+/// Replaces the characters DigitalOcean does not allow in tags, it only
+/// takes letters, digits, colons, dashes and underscores.
+fn sanitize_tag(tag: &str) -> String {
+    tag.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == ':' || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn parse_tag(tag: &str) -> Result<String> {
+    if tag.is_empty() || tag.len() > 255 || sanitize_tag(tag) != tag {
+        return Err(anyhow!(
+            "Invalid tag: {}, tags take up to 255 letters, digits, colons, dashes and underscores",
+            tag
+        ));
+    }
+
+    Ok(String::from(tag))
+}
 
-    let new_cluster = KubernetesCluster {
+/// Tags of a new cluster: hake, its owner and the given ones.
+fn cluster_tags(tags: &[String]) -> Result<Vec<String>> {
+    let mut all = vec![String::from(TAG)];
+    if let Some(user) = crate::user_name() {
+        all.push(sanitize_tag(&format!("owner:{}", user)));
+    }
+    for tag in tags {
+        let tag = parse_tag(tag)?;
+        if !all.contains(&tag) {
+            all.push(tag);
+        }
+    }
+
+    Ok(all)
+}
+
+/// Tag to filter clusters on, where `owner:me` is the current user.
+fn filter_tag(tag: &str) -> String {
+    match (tag, crate::user_name()) {
+        ("owner:me", Some(user)) => sanitize_tag(&format!("owner:{}", user)),
+        _ => String::from(tag),
+    }
+}
+
+/// Checks the auto-scaling bounds of a node pool of `count` nodes.
+fn auto_scale_bounds(count: u16, min: u16, max: u16) -> Result<()> {
+    if max == 0 || min > max {
+        return Err(anyhow!(
+            "Invalid auto-scaling bounds, min {} and max {}",
+            min,
+            max
+        ));
+    }
+    if count < min || count > max {
+        return Err(anyhow!(
+            "A node pool of {} nodes is out of its auto-scaling bounds, min {} and max {}",
+            count,
+            min,
+            max
+        ));
+    }
+
+    Ok(())
+}
+
+/// The request creating a cluster with the node pool described by the
+/// metadata and the extra node pools, in the format of `parse_node_pool`.
+fn cluster_request(name: &str, opts: &CreateOptions) -> Result<KubernetesCluster> {
+    let provider_metadata = opts.metadata.clone().unwrap_or_default();
+    let cluster_spec = Metadata::from_string(&provider_metadata)?;
+    let mut extra_pools = opts
+        .node_pools
+        .iter()
+        .map(|spec| parse_node_pool(spec, &cluster_spec.nodepool_size))
+        .collect::<Result<Vec<NodePool>>>()?;
+
+    let mut pool = NodePool {
+        size: cluster_spec.nodepool_size,
+        count: cluster_spec.nodepool_count,
+        name: format!("nodepool-{}", &name),
+        ..Default::default()
+    };
+    if opts.auto_scale {
+        let min = opts.min_nodes.unwrap_or(1);
+        let max = opts.max_nodes.unwrap_or(pool.count);
+        auto_scale_bounds(pool.count, min, max)?;
+        pool.auto_scale = Some(true);
+        pool.min_nodes = Some(min);
+        pool.max_nodes = Some(max);
+    }
+
+    let mut new_cluster = KubernetesCluster {
         id: None,
         name: String::from(name),
         region: cluster_spec.region,
         version: cluster_spec.version,
-        node_pools: vec![NodePool {
-            size: cluster_spec.nodepool_size,
-            count: cluster_spec.nodepool_count,
-            name: format!("nodepool-{}", &name),
-            ..Default::default()
-        }],
+        vpc_uuid: opts.vpc_uuid.clone(),
+        ha: if opts.ha { Some(true) } else { None },
+        node_pools: vec![pool],
+        tags: Some(cluster_tags(&opts.tags)?),
         ..Default::default()
     };
+    new_cluster.node_pools.append(&mut extra_pools);
+
+    Ok(new_cluster)
+}
 
+/// Creates a cluster as described by the options of `create`.
+pub fn create(name: &str, opts: &CreateOptions) -> Result<()> {
+    let new_cluster = cluster_request(name, opts)?;
+
+    if dryrun::enabled() {
+        dryrun::print(&format!(
+            "would POST https://api.digitalocean.com/v2/kubernetes/clusters\n{}",
+            serde_json::to_string_pretty(&new_cluster)?
+        ));
+        return Ok(());
+    }
+
+    debug!("cluster spec: {}", serde_json::to_string(&new_cluster)?);
     let client = get_do_api_client()?;
-    let resp = client
-        .post("https://api.digitalocean.com/v2/kubernetes/clusters")
-        .header(CONTENT_TYPE, "application/json")
-        .json(&new_cluster)
-        .send()?;
+    let resp = send(
+        &client,
+        client
+            .post("https://api.digitalocean.com/v2/kubernetes/clusters")
+            .header(CONTENT_TYPE, "application/json")
+            .json(&new_cluster),
+    )?;
 
     if resp.status() != StatusCode::CREATED {
-        println!("{:?}", &resp.text()?.to_string());
-        return Err(anyhow!("Could not create cluster:"));
+        return Err(api_error(resp));
     }
 
     let json_response: KubernetesClusterResponse = resp.json()?;
 
-    let cluster_id = json_response.kubernetes_cluster.id.unwrap();
+    let cluster_id = json_response
+        .kubernetes_cluster
+        .id
+        .ok_or_else(|| anyhow!("DigitalOcean did not return the id of the new cluster"))?;
     let cyan = Style::new().cyan();
-    println!("Cluster created with id: {}", cyan.apply_to(&cluster_id));
+    info!("Cluster created with id: {}", cyan.apply_to(&cluster_id));
 
-    let cluster_dir = format!("{}/{}", crate::get_config_dir(), name);
+    let cluster_dir = crate::cluster_dir(name);
     create_dir(&cluster_dir)?;
 
+    // need to wait for the server to be "prepared"
+    thread::sleep(time::Duration::from_secs(10));
+
+    download_kubeconfig(&client, &cluster_id, name)?;
+
+    let mut cluster_uuid = File::create(cluster_dir.join("cluster_uuid"))?;
+
+    cluster_uuid.write_all(cluster_id.as_bytes())?;
+
+    // with create --timeout, a cluster that does not get to run in time is
+    // rolled back
+    if let Some(remaining) = deadline::remaining() {
+        if !wait_for_cluster(&client, &cluster_id, remaining.as_secs(), "Running", |_| {
+            true
+        })? {
+            return Err(HakeError::Timeout {
+                step: String::from("Creating the DigitalOcean cluster"),
+                timeout: remaining,
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Downloads the kubeconfig of a cluster into its directory. Its credentials
+/// are valid for `KUBECONFIG_LIFETIME`.
+fn download_kubeconfig(
+    client: &reqwest::blocking::Client,
+    cluster_id: &str,
+    name: &str,
+) -> Result<()> {
     let url = format!(
         "https://api.digitalocean.com/v2/kubernetes/clusters/{}/kubeconfig",
-        &cluster_id
+        cluster_id
     );
+    let mut resp = send(
+        client,
+        client.get(&url).header(CONTENT_TYPE, "application/json"),
+    )?;
+    if resp.status() != StatusCode::OK {
+        return Err(api_error(resp));
+    }
 
-    // need to wait for the server to be "prepared"
-    thread::sleep(time::Duration::from_secs(10));
+    let mut out = File::create(crate::cluster_dir(name).join("kubeconfig"))?;
+    io::copy(&mut resp, &mut out)?;
 
-    let mut resp = client
-        .get(&url)
-        .header(CONTENT_TYPE, "application/json")
-        .send()?;
+    Ok(())
+}
 
-    let mut out =
-        File::create(format!("{}/kubeconfig", &cluster_dir)).expect("failed to create file");
-    io::copy(&mut resp, &mut out).expect("failed to copy content");
+/// Name of the cluster with the given id.
+pub fn cluster_name(cluster_id: &str) -> Result<String> {
+    let client = get_do_api_client()?;
+
+    Ok(get_cluster(&client, cluster_id)?.name)
+}
 
-    let mut cluster_uuid = File::create(format!("{}/cluster_uuid", &cluster_dir))?;
+/// Takes over a cluster created outside of hake, under `name`: creates its
+/// directory, downloads its kubeconfig and records its id.
+pub fn adopt(cluster_id: &str, name: &str) -> Result<()> {
+    let client = get_do_api_client()?;
+    get_cluster(&client, cluster_id)?;
 
-    cluster_uuid.write_all(&cluster_id.as_bytes())?;
+    let cluster_dir = crate::cluster_dir(name);
+    create_dir_all(&cluster_dir)?;
+    download_kubeconfig(&client, cluster_id, name)?;
+    let mut cluster_uuid = File::create(cluster_dir.join("cluster_uuid"))?;
+    cluster_uuid.write_all(cluster_id.as_bytes())?;
 
     Ok(())
 }
 
+/// Downloads the kubeconfig of a cluster again, with new credentials.
+pub fn refresh_kubeconfig(name: &str) -> Result<()> {
+    let cluster_id = read_cluster_id(name)?;
+    if dryrun::enabled() {
+        dryrun::print(&format!(
+            "would GET https://api.digitalocean.com/v2/kubernetes/clusters/{}/kubeconfig",
+            cluster_id
+        ));
+        return Ok(());
+    }
+
+    let client = get_do_api_client()?;
+    download_kubeconfig(&client, &cluster_id, name)
+}
+
+/// Whether credentials downloaded at `downloaded` expire within
+/// `REFRESH_BEFORE` of `now`.
+fn expires_soon(downloaded: time::SystemTime, now: time::SystemTime) -> bool {
+    match now.duration_since(downloaded) {
+        Ok(age) => age + REFRESH_BEFORE >= KUBECONFIG_LIFETIME,
+        // downloaded in the future, the clock changed
+        Err(_) => false,
+    }
+}
+
+/// Whether the kubeconfig of a cluster is about to expire, going by when it
+/// was downloaded.
+pub fn kubeconfig_expiring(name: &str) -> bool {
+    let downloaded = std::fs::metadata(crate::cluster_dir(name).join("kubeconfig"))
+        .and_then(|metadata| metadata.modified());
+    match downloaded {
+        Ok(downloaded) => expires_soon(downloaded, time::SystemTime::now()),
+        Err(_) => true,
+    }
+}
+
 // Return a list of droplets for a given cluster
 fn get_droplets_ids_for_cluster(cluster_id: &str) -> Result<Vec<u32>> {
     let client = get_do_api_client()?;
-    let resp = client
-        .get(&format!(
-            "https://api.digitalocean.com/v2/kubernetes/clusters/{}",
-            cluster_id
-        ))
-        .header(ACCEPT, "application/json")
-        .send()?;
+    let resp = send(
+        &client,
+        client
+            .get(&format!(
+                "https://api.digitalocean.com/v2/kubernetes/clusters/{}",
+                cluster_id
+            ))
+            .header(ACCEPT, "application/json"),
+    )?;
+    if resp.status() != StatusCode::OK {
+        return Err(api_error(resp));
+    }
 
     let json_response: KubernetesClusterResponse = resp.json()?;
 
@@ -208,8 +564,103 @@ fn get_droplets_ids_for_cluster(cluster_id: &str) -> Result<Vec<u32>> {
     Ok(droplet_ids)
 }
 
+/// Gets the API key from the environment or, failing that, the OS keychain.
 fn get_api_token() -> Result<String> {
-    Ok(env::var(ENV_DO_PROVIDER)?)
+    if let Ok(token) = env::var(ENV_DO_PROVIDER) {
+        return Ok(token);
+    }
+    if let Some(token) = auth::token("digitalocean")? {
+        return Ok(token);
+    }
+
+    Err(HakeError::MissingCredentials(format!(
+        "run `hake auth login digitalocean` or export {} with a DigitalOcean API key",
+        ENV_DO_PROVIDER
+    ))
+    .into())
+}
+
+/// Whether an API key is configured, without checking it.
+pub fn has_api_token() -> bool {
+    env::var(ENV_DO_PROVIDER).is_ok() || matches!(auth::token("digitalocean"), Ok(Some(_)))
+}
+
+/// Checks the API key against the account endpoint, false if it is rejected.
+pub fn api_token_valid() -> Result<bool> {
+    let client = get_do_api_client()?;
+    let resp = send(
+        &client,
+        client
+            .get("https://api.digitalocean.com/v2/account")
+            .header(ACCEPT, "application/json"),
+    )?;
+
+    match resp.status() {
+        StatusCode::OK => Ok(true),
+        StatusCode::UNAUTHORIZED => Ok(false),
+        _ => Err(api_error(resp)),
+    }
+}
+
+/// Body of the errors returned by the DigitalOcean API.
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    id: String,
+    message: String,
+    request_id: Option<String>,
+}
+
+/// What the user can do about an error, for the common ones.
+fn error_hint(status: u16, error: &ApiError) -> Option<&'static str> {
+    let message = error.message.to_lowercase();
+    if status == 401 {
+        Some("check the API key, or store a new one with `hake auth login digitalocean`")
+    } else if status == 429 {
+        Some("the API rate limit was reached, try again in a minute")
+    } else if message.contains("limit") || message.contains("quota") {
+        Some("ask DigitalOcean for a higher droplet limit, or delete unused clusters")
+    } else if message.contains("version") {
+        Some("see the available versions with `doctl kubernetes options versions`")
+    } else if message.contains("region") {
+        Some("see the available regions with `doctl kubernetes options regions`")
+    } else if message.contains("size") {
+        Some("see the available sizes with `doctl kubernetes options sizes`")
+    } else {
+        None
+    }
+}
+
+/// Message for an error response, from the error DigitalOcean returns in the
+/// body when there is one.
+fn describe_error(status: u16, body: &str) -> String {
+    let error: ApiError = match serde_json::from_str(body) {
+        Ok(error) => error,
+        Err(_) if body.trim().is_empty() => return String::from("empty response"),
+        Err(_) => return String::from(body.trim()),
+    };
+
+    let mut message = format!("{} ({}", error.message, error.id);
+    if let Some(request_id) = &error.request_id {
+        message.push_str(&format!(", request id {}", request_id));
+    }
+    message.push(')');
+    if let Some(hint) = error_hint(status, &error) {
+        message.push_str(&format!("; {}", hint));
+    }
+
+    message
+}
+
+/// Turns an error response of the DigitalOcean API into an error.
+fn api_error(resp: reqwest::blocking::Response) -> anyhow::Error {
+    let status = resp.status().as_u16();
+    let body = resp.text().unwrap_or_default();
+
+    HakeError::ProviderApi {
+        status,
+        message: describe_error(status, &body),
+    }
+    .into()
 }
 
 fn auth_headers() -> Result<reqwest::header::HeaderMap> {
@@ -225,8 +676,16 @@ fn auth_headers() -> Result<reqwest::header::HeaderMap> {
     Ok(headers)
 }
 
+/// Sends a request to the DigitalOcean API, retrying it on transient errors.
+fn send(
+    client: &reqwest::blocking::Client,
+    request: reqwest::blocking::RequestBuilder,
+) -> Result<reqwest::blocking::Response> {
+    crate::http::send(client, request.build()?)
+}
+
 fn get_do_api_client() -> Result<reqwest::blocking::Client> {
-    Ok(reqwest::blocking::Client::builder()
+    Ok(crate::http::client_builder()?
         .default_headers(auth_headers()?)
         .build()?)
 }
@@ -235,10 +694,15 @@ fn get_load_balancer_pointing_at_droplet_id(
     droplet_ids: HashSet<u32>,
 ) -> Result<Vec<LoadBalancer>> {
     let client = get_do_api_client()?;
-    let resp = client
-        .get("https://api.digitalocean.com/v2/load_balancers")
-        .header(ACCEPT, "application/json")
-        .send()?;
+    let resp = send(
+        &client,
+        client
+            .get("https://api.digitalocean.com/v2/load_balancers")
+            .header(ACCEPT, "application/json"),
+    )?;
+    if resp.status() != StatusCode::OK {
+        return Err(api_error(resp));
+    }
 
     let load_balancers: LoadBalancerListResponse = resp.json()?;
 
@@ -250,76 +714,564 @@ fn get_load_balancer_pointing_at_droplet_id(
 }
 
 fn delete_load_balancer(lb: LoadBalancer) -> Result<()> {
-    let lb_id = lb.id.expect("Got an empty id for load_balancer");
+    let lb_id = lb
+        .id
+        .ok_or_else(|| anyhow!("Got an empty id for load_balancer"))?;
     let cyan = Style::new().cyan();
-    println!("Removing Load Balancer: {}", cyan.apply_to(&lb_id));
+    info!("Removing Load Balancer: {}", cyan.apply_to(&lb_id));
 
     let client = get_do_api_client()?;
-    let resp = client
-        .delete(&format!(
+    let resp = send(
+        &client,
+        client.delete(&format!(
             "https://api.digitalocean.com/v2/load_balancers/{}",
             lb_id
-        ))
-        .send()?;
+        )),
+    )?;
 
     if resp.status() == StatusCode::NO_CONTENT {
         Ok(())
     } else {
-        Err(anyhow!(
-            "Could not remove Load Balancer with id: {}. Status code is: {}",
-            lb_id,
-            resp.status()
-        ))
+        Err(api_error(resp).context(format!("Could not remove Load Balancer with id: {}", lb_id)))
     }
 }
 
 fn delete_residuals(cluster_id: &str) -> Result<()> {
-    let droplet_ids: HashSet<u32> = get_droplets_ids_for_cluster(&cluster_id)?
+    let droplet_ids: HashSet<u32> = get_droplets_ids_for_cluster(cluster_id)?
         .into_iter()
         .collect();
 
     let lbs = get_load_balancer_pointing_at_droplet_id(droplet_ids);
-    match lbs {
-        Ok(lbs) => {
-            for lb in lbs {
-                delete_load_balancer(lb)?;
-            }
+    if let Ok(lbs) = lbs {
+        for lb in lbs {
+            delete_load_balancer(lb)?;
         }
-        _ => {}
     }
 
     Ok(())
 }
 
-pub fn delete(name: &str) -> Result<()> {
-    let config_dir = crate::get_config_dir();
-
-    let doid = format!("{}/{}/cluster_uuid", config_dir, name);
-    let mut file = File::open(doid)?;
+pub fn read_cluster_id(name: &str) -> Result<String> {
+    let mut file = File::open(crate::cluster_dir(name).join("cluster_uuid"))?;
     let mut cluster_id = String::new();
     file.read_to_string(&mut cluster_id)?;
 
-    delete_residuals(&cluster_id)?;
+    Ok(cluster_id)
+}
+
+#[derive(Deserialize, Debug)]
+struct KubernetesClusterListResponse {
+    kubernetes_clusters: Vec<KubernetesCluster>,
+}
+
+/// A cluster of the account, as listed by the API.
+#[derive(Debug, PartialEq)]
+pub struct RemoteCluster {
+    pub id: String,
+    pub name: String,
+    pub region: String,
+    pub version: String,
+    pub state: String,
+    pub nodes: u32,
+    pub tags: Vec<String>,
+}
+
+/// Lists the clusters of the account, with the given tag if any.
+pub fn remote_clusters(tag: Option<&str>) -> Result<Vec<RemoteCluster>> {
+    let client = get_do_api_client()?;
+    let resp = send(
+        &client,
+        client
+            .get("https://api.digitalocean.com/v2/kubernetes/clusters?per_page=200")
+            .header(ACCEPT, "application/json"),
+    )?;
+    if resp.status() != StatusCode::OK {
+        return Err(api_error(resp));
+    }
+
+    let clusters = resp
+        .json::<KubernetesClusterListResponse>()?
+        .kubernetes_clusters;
+    Ok(with_tag(clusters, tag.map(filter_tag).as_deref()))
+}
+
+fn with_tag(clusters: Vec<KubernetesCluster>, tag: Option<&str>) -> Vec<RemoteCluster> {
+    clusters
+        .into_iter()
+        .filter(|cluster| match tag {
+            Some(tag) => cluster.tags.iter().flatten().any(|t| t == tag),
+            None => true,
+        })
+        .map(|cluster| RemoteCluster {
+            state: match &cluster.status {
+                Some(status) => status.state.clone(),
+                None => String::from("unknown"),
+            },
+            nodes: cluster
+                .node_pools
+                .iter()
+                .map(|pool| u32::from(pool.count))
+                .sum(),
+            id: cluster.id.unwrap_or_default(),
+            name: cluster.name,
+            region: cluster.region,
+            version: cluster.version,
+            tags: cluster.tags.unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Returns the state of a cluster as reported by DigitalOcean, like running
+/// or provisioning, and its number of nodes. Clusters that do not exist
+/// anymore are reported as gone.
+pub fn get_cluster_state(name: &str) -> Result<(String, usize)> {
+    let cluster_id = read_cluster_id(name)?;
+    let client = get_do_api_client()?;
+    let resp = send(
+        &client,
+        client
+            .get(&format!(
+                "https://api.digitalocean.com/v2/kubernetes/clusters/{}",
+                cluster_id
+            ))
+            .header(ACCEPT, "application/json"),
+    )?;
+
+    if resp.status() == StatusCode::NOT_FOUND {
+        return Ok((String::from("gone"), 0));
+    }
+    if resp.status() != StatusCode::OK {
+        return Err(api_error(resp));
+    }
+
+    let cluster = resp.json::<KubernetesClusterResponse>()?.kubernetes_cluster;
+    let nodes = cluster.node_pools.iter().map(|np| np.nodes.len()).sum();
+    let state = match cluster.status {
+        Some(status) => status.state,
+        None => String::from("unknown"),
+    };
+
+    Ok((state, nodes))
+}
+
+fn get_cluster(client: &reqwest::blocking::Client, cluster_id: &str) -> Result<KubernetesCluster> {
+    let resp = send(
+        client,
+        client
+            .get(&format!(
+                "https://api.digitalocean.com/v2/kubernetes/clusters/{}",
+                cluster_id
+            ))
+            .header(ACCEPT, "application/json"),
+    )?;
+    if resp.status() != StatusCode::OK {
+        return Err(api_error(resp));
+    }
+
+    Ok(resp.json::<KubernetesClusterResponse>()?.kubernetes_cluster)
+}
+
+/// Newest version a cluster can be upgraded to, if any.
+fn latest_upgrade(client: &reqwest::blocking::Client, cluster_id: &str) -> Result<Option<String>> {
+    let resp = send(
+        client,
+        client
+            .get(&format!(
+                "https://api.digitalocean.com/v2/kubernetes/clusters/{}/upgrades",
+                cluster_id
+            ))
+            .header(ACCEPT, "application/json"),
+    )?;
+    if resp.status() != StatusCode::OK {
+        return Err(api_error(resp));
+    }
+    let upgrades: UpgradesResponse = resp.json()?;
+
+    Ok(upgrades
+        .available_upgrade_versions
+        .unwrap_or_default()
+        .pop()
+        .map(|v| v.slug))
+}
+
+/// Upgrades a cluster to a version slug, like 1.18.8-do.0, or to the newest
+/// one available, and waits for the upgrade to finish.
+pub fn upgrade(name: &str, version: Option<&str>) -> Result<()> {
+    let cluster_id = read_cluster_id(name)?;
+    let client = get_do_api_client()?;
+    let version = match version {
+        Some(version) => String::from(version),
+        None => latest_upgrade(&client, &cluster_id)?
+            .ok_or_else(|| anyhow!("Cluster {} is already on the newest version", name))?,
+    };
+
+    let url = format!(
+        "https://api.digitalocean.com/v2/kubernetes/clusters/{}/upgrade",
+        cluster_id
+    );
+    if dryrun::enabled() {
+        dryrun::print(&format!(
+            "would POST {}\n{{\"version\": \"{}\"}}",
+            url, version
+        ));
+        return Ok(());
+    }
 
     let cyan = Style::new().cyan();
-    println!("Removing Cluster: {}", cyan.apply_to(&cluster_id));
+    info!("Upgrading to {}", cyan.apply_to(&version));
+    let resp = send(
+        &client,
+        client
+            .post(&url)
+            .header(CONTENT_TYPE, "application/json")
+            .json(&serde_json::json!({ "version": version })),
+    )?;
+    if resp.status() != StatusCode::ACCEPTED {
+        return Err(api_error(resp));
+    }
+
+    let upgraded = wait_for_cluster(&client, &cluster_id, UPGRADE_TIMEOUT, "Upgraded", |c| {
+        c.version == version
+    })?;
+    if !upgraded {
+        return Err(anyhow!(
+            "Upgrade of {} did not finish after {} minutes",
+            name,
+            UPGRADE_TIMEOUT / 60
+        ));
+    }
+
+    Ok(())
+}
+
+fn cluster_state(cluster: &KubernetesCluster) -> &str {
+    cluster
+        .status
+        .as_ref()
+        .map(|status| status.state.as_str())
+        .unwrap_or("unknown")
+}
+
+/// Polls a cluster until it is running and `done`, showing its state in the
+/// meantime. Returns false if `timeout` seconds pass first.
+fn wait_for_cluster<F>(
+    client: &reqwest::blocking::Client,
+    cluster_id: &str,
+    timeout: u64,
+    finished: &str,
+    done: F,
+) -> Result<bool>
+where
+    F: Fn(&KubernetesCluster) -> bool,
+{
+    let progress = Progress::new();
+    let deadline = time::Instant::now() + time::Duration::from_secs(timeout);
+    while time::Instant::now() < deadline {
+        let cluster = get_cluster(client, cluster_id)?;
+        if cluster_state(&cluster) == "running" && done(&cluster) {
+            progress.finish(finished);
+            return Ok(true);
+        }
+        progress.step(&format!("Cluster is {}", cluster_state(&cluster)));
+        thread::sleep(time::Duration::from_secs(15));
+    }
+
+    Ok(false)
+}
+
+/// Finds a node pool by name or id. It can be left out when the cluster has
+/// a single pool.
+fn find_pool<'a>(pools: &'a [NodePool], pool: Option<&str>) -> Result<&'a NodePool> {
+    match pool {
+        Some(pool) => pools
+            .iter()
+            .find(|p| p.name == pool || p.id.as_deref() == Some(pool))
+            .ok_or_else(|| anyhow!("Node pool {} not found", pool)),
+        None if pools.len() == 1 => Ok(&pools[0]),
+        None => Err(anyhow!(
+            "The cluster has several node pools, pick one with --pool: {}",
+            pools
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Whether every node of a pool is running and there are `count` of them.
+fn pool_ready(pool: &NodePool, count: u16) -> bool {
+    pool.nodes.len() == count as usize && pool.nodes.iter().all(|n| n.status.state == "running")
+}
+
+/// Changes the number of nodes of a node pool and waits for them to be running.
+pub fn scale(name: &str, count: u16, pool: Option<&str>) -> Result<()> {
+    let cluster_id = read_cluster_id(name)?;
     let client = get_do_api_client()?;
-    let resp = client
-        .delete(&format!(
-            "https://api.digitalocean.com/v2/kubernetes/clusters/{}",
-            cluster_id
-        ))
-        .send()?;
+    let cluster = get_cluster(&client, &cluster_id)?;
+    let pool = find_pool(&cluster.node_pools, pool)?;
+    let pool_id = pool.id.clone().ok_or_else(|| {
+        anyhow!(
+            "DigitalOcean did not return the id of node pool {}",
+            pool.name
+        )
+    })?;
 
-    if resp.status() != StatusCode::NO_CONTENT {
+    let url = format!(
+        "https://api.digitalocean.com/v2/kubernetes/clusters/{}/node_pools/{}",
+        cluster_id, pool_id
+    );
+    let body = serde_json::json!({ "name": pool.name, "count": count });
+    if dryrun::enabled() {
+        dryrun::print(&format!("would PUT {}\n{}", url, body));
+        return Ok(());
+    }
+
+    let cyan = Style::new().cyan();
+    info!(
+        "Scaling node pool {} from {} to {} nodes",
+        cyan.apply_to(&pool.name),
+        pool.count,
+        count
+    );
+    let resp = send(
+        &client,
+        client
+            .put(&url)
+            .header(CONTENT_TYPE, "application/json")
+            .json(&body),
+    )?;
+    if resp.status() != StatusCode::ACCEPTED && resp.status() != StatusCode::OK {
+        return Err(api_error(resp));
+    }
+
+    let scaled = wait_for_cluster(
+        &client,
+        &cluster_id,
+        SCALE_TIMEOUT,
+        "Nodes are running",
+        |c| {
+            c.node_pools
+                .iter()
+                .any(|p| p.id.as_deref() == Some(pool_id.as_str()) && pool_ready(p, count))
+        },
+    )?;
+    if !scaled {
+        return Err(anyhow!(
+            "Node pool {} did not reach {} nodes after {} minutes",
+            pool.name,
+            count,
+            SCALE_TIMEOUT / 60
+        ));
+    }
+
+    Ok(())
+}
+
+/// Adds a node pool, in the format of `parse_node_pool`, and waits for its
+/// nodes to be running.
+pub fn add_node_pool(name: &str, spec: &str) -> Result<()> {
+    let cluster_id = read_cluster_id(name)?;
+    let pool = parse_node_pool(spec, &Metadata::default().nodepool_size)?;
+    let url = format!(
+        "https://api.digitalocean.com/v2/kubernetes/clusters/{}/node_pools",
+        cluster_id
+    );
+    if dryrun::enabled() {
+        dryrun::print(&format!(
+            "would POST {}\n{}",
+            url,
+            serde_json::to_string_pretty(&pool)?
+        ));
+        return Ok(());
+    }
+
+    let cyan = Style::new().cyan();
+    info!("Adding node pool {}", cyan.apply_to(&pool.name));
+    let client = get_do_api_client()?;
+    let resp = send(
+        &client,
+        client
+            .post(&url)
+            .header(CONTENT_TYPE, "application/json")
+            .json(&pool),
+    )?;
+    if resp.status() != StatusCode::CREATED {
+        return Err(api_error(resp));
+    }
+    let created = resp.json::<NodePoolResponse>()?.node_pool;
+
+    let ready = wait_for_cluster(
+        &client,
+        &cluster_id,
+        SCALE_TIMEOUT,
+        "Nodes are running",
+        |c| {
+            c.node_pools
+                .iter()
+                .any(|p| p.id == created.id && pool_ready(p, pool.count))
+        },
+    )?;
+    if !ready {
         return Err(anyhow!(
-            "Could not remove Cluster with id: {}. Status code is: {}",
-            &cluster_id,
-            resp.status()
+            "Node pool {} was not ready after {} minutes",
+            pool.name,
+            SCALE_TIMEOUT / 60
         ));
     }
 
-    remove_dir_all(format!("{}/{}", config_dir, name))?;
+    Ok(())
+}
+
+/// Removes a node pool, by name or id.
+pub fn remove_node_pool(name: &str, pool: &str) -> Result<()> {
+    let cluster_id = read_cluster_id(name)?;
+    let client = get_do_api_client()?;
+    let cluster = get_cluster(&client, &cluster_id)?;
+    if cluster.node_pools.len() == 1 {
+        return Err(anyhow!("Cannot remove the only node pool of {}", name));
+    }
+    let pool = find_pool(&cluster.node_pools, Some(pool))?;
+    let pool_id = pool.id.clone().ok_or_else(|| {
+        anyhow!(
+            "DigitalOcean did not return the id of node pool {}",
+            pool.name
+        )
+    })?;
+
+    let url = format!(
+        "https://api.digitalocean.com/v2/kubernetes/clusters/{}/node_pools/{}",
+        cluster_id, pool_id
+    );
+    if dryrun::enabled() {
+        dryrun::print(&format!("would DELETE {}", url));
+        return Ok(());
+    }
+
+    let cyan = Style::new().cyan();
+    info!("Removing node pool {}", cyan.apply_to(&pool.name));
+    let resp = send(&client, client.delete(&url))?;
+    if resp.status() != StatusCode::NO_CONTENT {
+        return Err(api_error(resp));
+    }
+
+    Ok(())
+}
+
+/// Returns the name, size, node count, labels and taints of every node pool.
+pub fn node_pools(name: &str) -> Result<Vec<Vec<String>>> {
+    let cluster_id = read_cluster_id(name)?;
+    let client = get_do_api_client()?;
+    let cluster = get_cluster(&client, &cluster_id)?;
+
+    Ok(cluster
+        .node_pools
+        .iter()
+        .map(|pool| {
+            let labels = pool
+                .labels
+                .iter()
+                .flatten()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<String>>();
+            let taints = pool
+                .taints
+                .iter()
+                .flatten()
+                .map(|t| format!("{}={}:{}", t.key, t.value, t.effect))
+                .collect::<Vec<String>>();
+            vec![
+                pool.name.clone(),
+                pool.size.clone(),
+                pool.count.to_string(),
+                labels.join(","),
+                taints.join(","),
+            ]
+        })
+        .collect())
+}
+
+/// Adds up the hourly and monthly prices of the nodes of every pool.
+fn pool_prices(pools: &[NodePool], sizes: &[Size]) -> Result<Cost> {
+    let mut cost = Cost::default();
+    for pool in pools {
+        let size = sizes
+            .iter()
+            .find(|s| s.slug == pool.size)
+            .ok_or_else(|| anyhow!("No price for droplet size {}", pool.size))?;
+        cost.hourly += size.price_hourly * f64::from(pool.count);
+        cost.monthly += size.price_monthly * f64::from(pool.count);
+    }
+
+    Ok(cost)
+}
+
+/// Cost of the nodes of a cluster, with the current prices of its droplet sizes.
+pub fn cost(name: &str) -> Result<Cost> {
+    let cluster_id = read_cluster_id(name)?;
+    let client = get_do_api_client()?;
+    let cluster = get_cluster(&client, &cluster_id)?;
+
+    let resp = send(
+        &client,
+        client
+            .get("https://api.digitalocean.com/v2/sizes?per_page=200")
+            .header(ACCEPT, "application/json"),
+    )?;
+    if resp.status() != StatusCode::OK {
+        return Err(api_error(resp));
+    }
+    let sizes: SizesResponse = resp.json()?;
+
+    pool_prices(&cluster.node_pools, &sizes.sizes)
+}
+
+pub fn delete(name: &str) -> Result<()> {
+    let cluster_dir = crate::cluster_dir(name);
+    let cluster_id = read_cluster_id(name)?;
+
+    if dryrun::enabled() {
+        dryrun::print("would DELETE the load balancers pointing at the cluster's droplets");
+        dryrun::print(&format!(
+            "would DELETE https://api.digitalocean.com/v2/kubernetes/clusters/{}",
+            cluster_id
+        ));
+        dryrun::print(&format!("would remove {}", cluster_dir.display()));
+        return Ok(());
+    }
+
+    delete_by_id(&cluster_id)?;
+    remove_dir_all(cluster_dir)?;
+
+    Ok(())
+}
+
+/// Deletes a cluster and its load balancers by id, for clusters created
+/// somewhere else, like the ones in the shared state.
+pub fn delete_by_id(cluster_id: &str) -> Result<()> {
+    delete_residuals(cluster_id)?;
+
+    let cyan = Style::new().cyan();
+    info!("Removing Cluster: {}", cyan.apply_to(cluster_id));
+    let client = get_do_api_client()?;
+    let resp = send(
+        &client,
+        client.delete(&format!(
+            "https://api.digitalocean.com/v2/kubernetes/clusters/{}",
+            cluster_id
+        )),
+    )?;
+
+    // someone else, or an earlier run that failed later, removed it already
+    if resp.status() == StatusCode::NOT_FOUND {
+        info!("Cluster {} was already removed", cyan.apply_to(cluster_id));
+        return Ok(());
+    }
+    if resp.status() != StatusCode::NO_CONTENT {
+        return Err(
+            api_error(resp).context(format!("Could not remove Cluster with id: {}", cluster_id))
+        );
+    }
 
     Ok(())
 }
@@ -330,7 +1282,7 @@ fn parse_metadata(metadata: &str) -> HashMap<String, String> {
 
     // there should be a more idiomatic way of doing this!
     for field in fields {
-        let split_field: Vec<&str> = field.split("=").into_iter().collect();
+        let split_field: Vec<&str> = field.split("=").collect();
         if split_field.len() != 2 {
             continue;
         }
@@ -343,9 +1295,31 @@ fn parse_metadata(metadata: &str) -> HashMap<String, String> {
 
 #[cfg(test)]
 mod tests {
+    use crate::cluster::CreateOptions;
     use crate::r#do;
     use std::collections::HashMap;
 
+    #[test]
+    fn test_describe_error() {
+        let body = r#"{"id": "unprocessable_entity", "message": "validation error: invalid version slug", "request_id": "4d9d8375-3c56-4925-a3e7-eb137fed17e9"}"#;
+        assert_eq!(
+            r#do::describe_error(422, body),
+            "validation error: invalid version slug (unprocessable_entity, request id \
+             4d9d8375-3c56-4925-a3e7-eb137fed17e9); see the available versions with \
+             `doctl kubernetes options versions`"
+        );
+
+        let body =
+            r#"{"id": "forbidden", "message": "You do not have access for the attempted action."}"#;
+        assert_eq!(
+            r#do::describe_error(403, body),
+            "You do not have access for the attempted action. (forbidden)"
+        );
+
+        assert_eq!(r#do::describe_error(502, "Bad Gateway\n"), "Bad Gateway");
+        assert_eq!(r#do::describe_error(500, ""), "empty response");
+    }
+
     // Taken from https://stackoverflow.com/a/27582993/75928
     macro_rules! map(
     { $($key:expr => $value:expr),+ } => {
@@ -359,6 +1333,121 @@ mod tests {
     };
     );
 
+    fn pool(name: &str, states: &[&str]) -> r#do::NodePool {
+        r#do::NodePool {
+            id: Some(format!("{}-id", name)),
+            name: String::from(name),
+            count: states.len() as u16,
+            nodes: states
+                .iter()
+                .enumerate()
+                .map(|(i, state)| r#do::Node {
+                    id: i.to_string(),
+                    name: format!("{}-{}", name, i),
+                    status: r#do::NodeStatus {
+                        state: String::from(*state),
+                    },
+                    droplet_id: None,
+                    created_at: String::new(),
+                    updated_at: String::new(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_node_pool() {
+        let pool = r#do::parse_node_pool(
+            "name=gpu,count=2,label=role=gpu,label=team=a,taint=dedicated=gpu:NoSchedule",
+            "s-4vcpu-8gb",
+        )
+        .unwrap();
+        assert_eq!(pool.name, "gpu");
+        assert_eq!(pool.size, "s-4vcpu-8gb");
+        assert_eq!(pool.count, 2);
+        let labels = pool.labels.unwrap();
+        assert_eq!(labels["role"], "gpu");
+        assert_eq!(labels["team"], "a");
+        assert_eq!(
+            pool.taints.unwrap(),
+            vec![r#do::Taint {
+                key: String::from("dedicated"),
+                value: String::from("gpu"),
+                effect: String::from("NoSchedule"),
+            }]
+        );
+
+        let pool = r#do::parse_node_pool("name=small,size=s-1vcpu-2gb", "s-4vcpu-8gb").unwrap();
+        assert_eq!(pool.size, "s-1vcpu-2gb");
+        assert_eq!(pool.count, 1);
+        assert_eq!(pool.labels, None);
+
+        let pool = r#do::parse_node_pool("name=spot,count=2,max=5", "s-4vcpu-8gb").unwrap();
+        assert_eq!(pool.auto_scale, Some(true));
+        assert_eq!(pool.min_nodes, Some(1));
+        assert_eq!(pool.max_nodes, Some(5));
+        assert!(r#do::parse_node_pool("name=spot,count=6,max=5", "").is_err());
+        assert!(r#do::parse_node_pool("name=spot,min=3,max=2", "").is_err());
+
+        assert!(r#do::parse_node_pool("size=s-1vcpu-2gb", "").is_err());
+        assert!(r#do::parse_node_pool("name=a,count=many", "").is_err());
+        assert!(r#do::parse_node_pool("name=a,taint=dedicated", "").is_err());
+    }
+
+    #[test]
+    fn test_pool_prices() {
+        let sizes = vec![
+            r#do::Size {
+                slug: String::from("s-1vcpu-2gb"),
+                price_monthly: 10.0,
+                price_hourly: 0.01488,
+            },
+            r#do::Size {
+                slug: String::from("s-4vcpu-8gb"),
+                price_monthly: 40.0,
+                price_hourly: 0.05952,
+            },
+        ];
+        let mut small = pool("default", &["running", "running"]);
+        small.size = String::from("s-1vcpu-2gb");
+        let mut large = pool("large", &["running"]);
+        large.size = String::from("s-4vcpu-8gb");
+
+        let cost = r#do::pool_prices(&[small, large], &sizes).unwrap();
+        assert_eq!(cost.monthly, 60.0);
+        assert!((cost.hourly - 0.08928).abs() < 1e-9);
+
+        let mut unknown = pool("gpu", &["running"]);
+        unknown.size = String::from("gpu-h100x1-80gb");
+        assert!(r#do::pool_prices(&[unknown], &sizes).is_err());
+    }
+
+    #[test]
+    fn test_find_pool() {
+        let pools = vec![pool("default", &["running"])];
+        assert_eq!(r#do::find_pool(&pools, None).unwrap().name, "default");
+
+        let pools = vec![pool("default", &[]), pool("gpu", &[])];
+        assert_eq!(r#do::find_pool(&pools, Some("gpu")).unwrap().name, "gpu");
+        assert_eq!(r#do::find_pool(&pools, Some("gpu-id")).unwrap().name, "gpu");
+        assert!(r#do::find_pool(&pools, None).is_err());
+        assert!(r#do::find_pool(&pools, Some("missing")).is_err());
+    }
+
+    #[test]
+    fn test_pool_ready() {
+        assert!(r#do::pool_ready(
+            &pool("default", &["running", "running"]),
+            2
+        ));
+        assert!(!r#do::pool_ready(
+            &pool("default", &["running", "provisioning"]),
+            2
+        ));
+        assert!(!r#do::pool_ready(&pool("default", &["running"]), 2));
+    }
+
     #[test]
     fn test_parse_metadata() {
         assert_eq!(
@@ -375,4 +1464,88 @@ mod tests {
         assert_eq!(r#do::parse_metadata("&"), HashMap::new());
         assert_eq!(r#do::parse_metadata(""), HashMap::new());
     }
+
+    #[test]
+    fn test_expires_soon() {
+        let now = std::time::SystemTime::now();
+        let days = |d: u64| std::time::Duration::from_secs(d * 24 * 60 * 60);
+
+        assert!(!r#do::expires_soon(now, now));
+        assert!(!r#do::expires_soon(now - days(5), now));
+        assert!(r#do::expires_soon(now - days(6), now));
+        assert!(r#do::expires_soon(now - days(30), now));
+        assert!(!r#do::expires_soon(now + days(1), now));
+    }
+
+    #[test]
+    fn test_tags() {
+        assert_eq!(r#do::sanitize_tag("owner:jane.doe"), "owner:jane-doe");
+        assert_eq!(r#do::parse_tag("branch:pr-123").unwrap(), "branch:pr-123");
+        assert!(r#do::parse_tag("branch:feature/login").is_err());
+        assert!(r#do::parse_tag("").is_err());
+
+        let tags = r#do::cluster_tags(&["hake".to_string(), "team:infra".to_string()]).unwrap();
+        assert_eq!(tags[0], "hake");
+        assert_eq!(tags.iter().filter(|t| *t == "hake").count(), 1);
+        assert_eq!(tags.last().unwrap(), "team:infra");
+    }
+
+    #[test]
+    fn test_with_tag() {
+        let cluster = |name: &str, tags: &[&str]| r#do::KubernetesCluster {
+            id: Some(format!("{}-id", name)),
+            name: name.to_string(),
+            region: "lon1".to_string(),
+            version: "1.28.2-do.0".to_string(),
+            tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+            ..Default::default()
+        };
+        let clusters = || {
+            vec![
+                cluster("pr-1", &["hake", "owner:jane"]),
+                cluster("pr-2", &["hake", "owner:joe"]),
+            ]
+        };
+
+        let remote = r#do::with_tag(clusters(), Some("owner:joe"));
+        assert_eq!(
+            remote,
+            vec![r#do::RemoteCluster {
+                id: "pr-2-id".to_string(),
+                name: "pr-2".to_string(),
+                region: "lon1".to_string(),
+                version: "1.28.2-do.0".to_string(),
+                state: "unknown".to_string(),
+                nodes: 0,
+                tags: vec!["hake".to_string(), "owner:joe".to_string()],
+            }]
+        );
+        assert_eq!(r#do::with_tag(clusters(), None).len(), 2);
+    }
+
+    #[test]
+    fn test_cluster_request() {
+        let mut opts = CreateOptions::new("staging");
+        opts.metadata = Some(String::from("nodepool.count=3"));
+        opts.vpc_uuid = Some(String::from("c33931f2-a26a-4e61-b85c-4e95a2ec431b"));
+        opts.ha = true;
+        opts.auto_scale = true;
+        opts.max_nodes = Some(6);
+
+        let request = r#do::cluster_request("staging", &opts).unwrap();
+        assert_eq!(request.vpc_uuid, opts.vpc_uuid);
+        assert_eq!(request.ha, Some(true));
+        let pool = &request.node_pools[0];
+        assert_eq!(pool.auto_scale, Some(true));
+        assert_eq!(pool.min_nodes, Some(1));
+        assert_eq!(pool.max_nodes, Some(6));
+
+        opts.min_nodes = Some(4);
+        assert!(r#do::cluster_request("staging", &opts).is_err());
+
+        let request = r#do::cluster_request("staging", &CreateOptions::new("staging")).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("ha").is_none());
+        assert!(json["node_pools"][0].get("auto_scale").is_none());
+    }
 }
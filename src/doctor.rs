@@ -0,0 +1,322 @@
+// Pre-flight checks of the tools and resources hake needs, so a missing binary
+// or a stopped docker daemon shows up before creating a cluster instead of
+// halfway through it.
+use anyhow::{anyhow, Result};
+use console::Style;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::error::spawn_error;
+use crate::kind::{Kind, MIN_KIND_VERSION};
+use crate::logging::LogCommand;
+use crate::r#do;
+use crate::rootless;
+use crate::runtime;
+
+const GIB: u64 = 1024 * 1024 * 1024;
+/// Memory a single node cluster needs to start its control plane.
+const MIN_MEMORY: u64 = 2 * GIB;
+/// Disk needed to pull a node image and run a cluster.
+const MIN_DISK: u64 = 5 * GIB;
+
+/// What the checks should expect to be configured.
+#[derive(Debug, Default)]
+pub struct Requirements {
+    /// Clusters are created with access to ECR.
+    pub ecr: bool,
+    /// Clusters are created on DigitalOcean.
+    pub digitalocean: bool,
+    /// Clusters are created with ipv6 or dual-stack networking.
+    pub ipv6: bool,
+}
+
+enum Outcome {
+    Pass(String),
+    Fail(String),
+    Skip(String),
+}
+
+fn docker_output(args: &[&str]) -> Result<String> {
+    let out = runtime::command()
+        .args(args)
+        .logged()
+        .output()
+        .map_err(|e| spawn_error(runtime::binary(), e))?;
+    if !out.status.success() {
+        return Err(anyhow!("{}", String::from_utf8_lossy(&out.stderr).trim()));
+    }
+
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Go template for a field of the docker output, or of the podman one, which
+/// names most of them differently.
+fn field(docker: &'static str, podman: &'static str) -> &'static str {
+    if runtime::is_podman() {
+        podman
+    } else {
+        docker
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / GIB as f64)
+}
+
+/// Reads MemAvailable, in bytes, from the contents of /proc/meminfo.
+fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+
+    Some(kb * 1024)
+}
+
+/// Reads the available space, in bytes, from the output of `df -Pk`.
+fn parse_df(df: &str) -> Option<u64> {
+    let kb = df
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kb * 1024)
+}
+
+fn check_docker() -> Outcome {
+    match docker_output(&[
+        "version",
+        "--format",
+        field("{{.Server.Version}}", "{{.Client.Version}}"),
+    ]) {
+        Ok(version) => Outcome::Pass(format!("daemon {}", version)),
+        Err(e) => Outcome::Fail(format!("daemon not reachable: {}", e)),
+    }
+}
+
+/// Rootless runtimes need cgroup v2 with the controllers of kind delegated.
+fn check_rootless() -> Outcome {
+    if !rootless::enabled() {
+        return Outcome::Skip(String::from("not a rootless runtime"));
+    }
+
+    match rootless::check_cgroups() {
+        Ok(()) => Outcome::Pass(format!(
+            "cgroup v2 with {} delegated, ports from {}",
+            rootless::REQUIRED_CONTROLLERS.join(", "),
+            rootless::unprivileged_port_start()
+        )),
+        Err(e) => Outcome::Fail(e.to_string()),
+    }
+}
+
+fn check_kind() -> Outcome {
+    let version = match Kind::version() {
+        Ok(version) => version,
+        Err(e) => return Outcome::Fail(e.to_string()),
+    };
+    let (major, minor, patch) = MIN_KIND_VERSION;
+    match Kind::parse_version(&version) {
+        Some(v) if v >= MIN_KIND_VERSION => Outcome::Pass(version),
+        Some(_) => Outcome::Fail(format!(
+            "{} is too old, hake needs v{}.{}.{} or newer",
+            version, major, minor, patch
+        )),
+        None => Outcome::Fail(format!("could not parse the version: {}", version)),
+    }
+}
+
+/// kind enables IPv6 on its docker network when the daemon supports it, which
+/// ipv6 and dual-stack clusters need.
+fn check_ipv6() -> Outcome {
+    if cfg!(target_os = "linux") && !PathBuf::from("/proc/net/if_inet6").exists() {
+        return Outcome::Fail(String::from("IPv6 is disabled in the kernel"));
+    }
+
+    match docker_output(&[
+        "network",
+        "inspect",
+        "kind",
+        "--format",
+        field("{{.EnableIPv6}}", "{{.IPv6Enabled}}"),
+    ]) {
+        Ok(enabled) if enabled == "true" => {
+            Outcome::Pass(String::from("enabled on the kind network"))
+        }
+        Ok(_) => Outcome::Fail(String::from(
+            "the kind network has no IPv6, enable ipv6 in the docker daemon and remove the network with `docker network rm kind`",
+        )),
+        Err(_) => Outcome::Skip(String::from(
+            "no kind network yet, kind creates it with IPv6 if the docker daemon supports it",
+        )),
+    }
+}
+
+fn check_binary(binary: &str, args: &[&str]) -> Outcome {
+    match Command::new(binary).args(args).logged().output() {
+        Ok(_) => Outcome::Pass(String::from("found")),
+        Err(e) => Outcome::Fail(spawn_error(binary, e).to_string()),
+    }
+}
+
+fn available_memory() -> Result<u64> {
+    if cfg!(target_os = "linux") {
+        let meminfo = fs::read_to_string("/proc/meminfo")?;
+        return parse_meminfo(&meminfo)
+            .ok_or_else(|| anyhow!("MemAvailable is missing from /proc/meminfo"));
+    }
+
+    // Docker Desktop runs in a VM, what counts is the memory given to it.
+    let total = docker_output(&[
+        "info",
+        "--format",
+        field("{{.MemTotal}}", "{{.Host.MemTotal}}"),
+    ])?;
+    total
+        .parse::<u64>()
+        .map_err(|_| anyhow!("unexpected memory reported by docker: {}", total))
+}
+
+fn check_memory() -> Outcome {
+    match available_memory() {
+        Ok(bytes) if bytes >= MIN_MEMORY => Outcome::Pass(format_size(bytes)),
+        Ok(bytes) => Outcome::Fail(format!(
+            "{} available, at least {} needed",
+            format_size(bytes),
+            format_size(MIN_MEMORY)
+        )),
+        Err(e) => Outcome::Skip(e.to_string()),
+    }
+}
+
+/// Filesystem the node images end up in, docker's root directory when it is
+/// local, the home directory otherwise.
+fn images_dir() -> PathBuf {
+    if let Ok(root) = docker_output(&[
+        "info",
+        "--format",
+        field("{{.DockerRootDir}}", "{{.Store.GraphRoot}}"),
+    ]) {
+        let root = PathBuf::from(root);
+        if root.exists() {
+            return root;
+        }
+    }
+
+    dirs::home_dir().unwrap_or_else(|| PathBuf::from("."))
+}
+
+fn check_disk() -> Outcome {
+    if cfg!(windows) {
+        return Outcome::Skip(String::from("not checked on Windows"));
+    }
+
+    let dir = images_dir();
+    let out = match Command::new("df").arg("-Pk").arg(&dir).logged().output() {
+        Ok(out) => out,
+        Err(e) => return Outcome::Skip(e.to_string()),
+    };
+    match parse_df(&String::from_utf8_lossy(&out.stdout)) {
+        Some(bytes) if bytes >= MIN_DISK => {
+            Outcome::Pass(format!("{} free in {}", format_size(bytes), dir.display()))
+        }
+        Some(bytes) => Outcome::Fail(format!(
+            "{} free in {}, at least {} needed",
+            format_size(bytes),
+            dir.display(),
+            format_size(MIN_DISK)
+        )),
+        None => Outcome::Skip(format!(
+            "could not read the free space of {}",
+            dir.display()
+        )),
+    }
+}
+
+fn check_digitalocean(required: bool) -> Outcome {
+    if !r#do::has_api_token() {
+        let message = String::from("no API key, run `hake auth login digitalocean`");
+        return if required {
+            Outcome::Fail(message)
+        } else {
+            Outcome::Skip(message)
+        };
+    }
+
+    match r#do::api_token_valid() {
+        Ok(true) => Outcome::Pass(String::from("API key accepted")),
+        Ok(false) => Outcome::Fail(String::from("API key rejected")),
+        Err(e) => Outcome::Fail(e.to_string()),
+    }
+}
+
+fn report(check: &str, outcome: &Outcome) {
+    let (mark, detail) = match outcome {
+        Outcome::Pass(detail) => (Style::new().green().apply_to("ok"), detail),
+        Outcome::Fail(detail) => (Style::new().red().apply_to("failed"), detail),
+        Outcome::Skip(detail) => (Style::new().dim().apply_to("skipped"), detail),
+    };
+    println!("  {:<28} {:<8} {}", check, mark, detail);
+}
+
+/// Runs every check and fails if any of them did.
+pub fn doctor(requirements: &Requirements) -> Result<()> {
+    let mut checks = vec![
+        (runtime::binary(), check_docker()),
+        ("rootless", check_rootless()),
+        ("kind", check_kind()),
+        ("kubectl", check_binary("kubectl", &["version", "--client"])),
+    ];
+    if requirements.ecr {
+        checks.push((
+            "docker-credential-ecr-login",
+            check_binary("docker-credential-ecr-login", &["version"]),
+        ));
+    }
+    if requirements.ipv6 {
+        checks.push(("ipv6", check_ipv6()));
+    }
+    checks.push(("memory", check_memory()));
+    checks.push(("disk", check_disk()));
+    checks.push((
+        "digitalocean",
+        check_digitalocean(requirements.digitalocean),
+    ));
+
+    println!("Checks:");
+    for (check, outcome) in checks.iter() {
+        report(check, outcome);
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, Outcome::Fail(_)))
+        .count();
+    if failed > 0 {
+        return Err(anyhow!("{} of {} checks failed", failed, checks.len()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::doctor::{parse_df, parse_meminfo};
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = "MemTotal:       16309500 kB\nMemFree:         1021304 kB\nMemAvailable:    8154752 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some(8154752 * 1024));
+        assert_eq!(parse_meminfo("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn test_parse_df() {
+        let df = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n/dev/nvme0n1p2   479151816 301467432 153275552      67% /\n";
+        assert_eq!(parse_df(df), Some(153275552 * 1024));
+        assert_eq!(parse_df(""), None);
+    }
+}
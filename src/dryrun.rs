@@ -0,0 +1,28 @@
+// Global dry-run mode: operations print what they would do instead of
+// touching clusters, files or provider APIs.
+use console::Style;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    DRY_RUN.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Prints an action that was skipped because of the dry-run mode.
+pub fn print(action: &str) {
+    let yellow = Style::new().yellow();
+    println!("{} {}", yellow.apply_to("[dry-run]"), action);
+}
+
+/// Prints the contents of a file that would have been written.
+pub fn print_file(path: &str, contents: &str) {
+    print(&format!("would write {}:", path));
+    for line in contents.lines() {
+        println!("    {}", line);
+    }
+}
@@ -0,0 +1,195 @@
+// Errors that hake reports to the user, each with its own exit code so
+// scripts can tell them apart.
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum HakeError {
+    /// A binary hake depends on is not in PATH.
+    MissingBinary(String),
+    /// The user has no home directory to keep the clusters' configuration.
+    NoHome,
+    ClusterNotFound(String),
+    ClusterExists(String),
+    /// The credentials for a provider are not configured.
+    MissingCredentials(String),
+    /// A provider API returned an unexpected response.
+    ProviderApi {
+        status: u16,
+        message: String,
+    },
+    /// An external command exited with an error.
+    CommandFailed {
+        command: String,
+        message: String,
+    },
+    /// Another hake process holds the lock of what the operation changes.
+    OperationInProgress(String),
+    /// A step took longer than it was given.
+    Timeout {
+        step: String,
+        timeout: Duration,
+    },
+    /// The name of a new cluster is not one kind and DNS accept.
+    InvalidName {
+        name: String,
+        reason: String,
+    },
+    /// A check of `status` failed.
+    Unhealthy {
+        name: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for HakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HakeError::MissingBinary(binary) => write!(
+                f,
+                "Could not find `{}`, make sure it is installed and in your PATH",
+                binary
+            ),
+            HakeError::NoHome => write!(f, "Could not find the home directory of the user"),
+            HakeError::ClusterNotFound(name) => write!(f, "Cluster {} does not exist", name),
+            HakeError::ClusterExists(name) => write!(f, "Cluster {} already exists", name),
+            HakeError::MissingCredentials(hint) => write!(f, "Missing credentials: {}", hint),
+            HakeError::ProviderApi { status, message } => {
+                write!(f, "Provider API error ({}): {}", status, message)
+            }
+            HakeError::CommandFailed { command, message } => {
+                write!(f, "`{}` failed: {}", command, message)
+            }
+            HakeError::OperationInProgress(target) => write!(
+                f,
+                "Another hake operation is in progress on {}, try again once it finishes",
+                target
+            ),
+            HakeError::Timeout { step, timeout } => write!(
+                f,
+                "{} did not finish within {}",
+                step,
+                crate::ttl::format_age(timeout.as_secs())
+            ),
+            HakeError::InvalidName { name, reason } => {
+                write!(f, "Invalid cluster name {:?}: {}", name, reason)?;
+                match crate::names::normalize(name) {
+                    normalized if normalized.is_empty() => Ok(()),
+                    normalized => write!(f, ", like {}", normalized),
+                }
+            }
+            HakeError::Unhealthy { name, reason } => {
+                write!(f, "Cluster {} is not healthy: {}", name, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HakeError {}
+
+impl HakeError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            HakeError::MissingBinary(_) => 3,
+            HakeError::NoHome => 4,
+            HakeError::ClusterNotFound(_) => 5,
+            HakeError::ClusterExists(_) => 6,
+            HakeError::MissingCredentials(_) => 7,
+            HakeError::ProviderApi { .. } => 8,
+            HakeError::CommandFailed { .. } => 9,
+            HakeError::OperationInProgress(_) => 10,
+            HakeError::Timeout { .. } => 11,
+            HakeError::InvalidName { .. } => 12,
+            HakeError::Unhealthy { .. } => 13,
+        }
+    }
+}
+
+/// Returns the exit code for an error, 1 for errors that are not a HakeError.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<HakeError>())
+        .map(HakeError::exit_code)
+        .unwrap_or(1)
+}
+
+/// Whether an operation failed because a step took too long.
+pub fn is_timeout(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        matches!(
+            e.downcast_ref::<HakeError>(),
+            Some(HakeError::Timeout { .. })
+        )
+    })
+}
+
+/// Converts the error of spawning a command, telling apart missing binaries.
+pub fn spawn_error(binary: &str, err: io::Error) -> anyhow::Error {
+    if err.kind() == io::ErrorKind::NotFound {
+        HakeError::MissingBinary(String::from(binary)).into()
+    } else {
+        anyhow::Error::new(err).context(format!("Could not run {}", binary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{exit_code, is_timeout, spawn_error, HakeError};
+    use anyhow::{anyhow, Context};
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn test_exit_code() {
+        let err: anyhow::Error = HakeError::ClusterNotFound(String::from("test")).into();
+        assert_eq!(exit_code(&err), 5);
+
+        let err = Err::<(), _>(HakeError::NoHome)
+            .context("Reading config")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), 4);
+
+        assert_eq!(exit_code(&anyhow!("other")), 1);
+    }
+
+    #[test]
+    fn test_invalid_name() {
+        let err = HakeError::InvalidName {
+            name: String::from("Feature/X"),
+            reason: String::from("names only have lowercase letters, digits and '-'"),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Invalid cluster name \"Feature/X\": names only have lowercase letters, digits and '-', like feature-x"
+        );
+        assert_eq!(err.exit_code(), 12);
+    }
+
+    #[test]
+    fn test_is_timeout() {
+        let err = Err::<(), _>(HakeError::Timeout {
+            step: String::from("kind create cluster"),
+            timeout: Duration::from_secs(600),
+        })
+        .context("Creating dev")
+        .unwrap_err();
+        assert!(is_timeout(&err));
+        assert_eq!(exit_code(&err), 11);
+        assert_eq!(
+            format!("{}", err.root_cause()),
+            "kind create cluster did not finish within 10m"
+        );
+
+        assert!(!is_timeout(&anyhow!("other")));
+    }
+
+    #[test]
+    fn test_spawn_error() {
+        let err = spawn_error("kind", io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(exit_code(&err), 3);
+
+        let err = spawn_error("kind", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(exit_code(&err), 1);
+    }
+}
@@ -0,0 +1,338 @@
+// Port-forwards kept running in the background with kubectl, so the services
+// of a cluster stay reachable without a terminal per forward. They are tracked
+// in forwards.json in the directory of the cluster, with the output of each
+// kubectl in a log file next to it.
+use anyhow::{anyhow, Result};
+use console::Style;
+use serde_derive::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::spawn_error;
+use crate::kubectl;
+use crate::logging::LogCommand;
+
+/// Time kubectl gets to fail, like for an unknown service or a port in use,
+/// before the forward is taken as started.
+const STARTUP: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Forward {
+    pub id: u32,
+    pub pid: u32,
+    /// What kubectl forwards to, like svc/web or pod/web-0.
+    pub target: String,
+    pub namespace: Option<String>,
+    /// Ports as given to kubectl, like 8080:80.
+    pub ports: Vec<String>,
+    /// Seconds since the epoch.
+    pub started_at: u64,
+}
+
+fn forwards_path(cluster: &str) -> PathBuf {
+    crate::cluster_dir(cluster).join("forwards.json")
+}
+
+fn log_path(cluster: &str, id: u32) -> PathBuf {
+    crate::cluster_dir(cluster).join(format!("forward-{}.log", id))
+}
+
+/// Port-forwards of a cluster, running or not.
+pub fn read(cluster: &str) -> Result<Vec<Forward>> {
+    match fs::read_to_string(forwards_path(cluster)) {
+        Ok(contents) => Ok(serde_json::from_str(&contents)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write(cluster: &str, forwards: &[Forward]) -> Result<()> {
+    fs::write(
+        forwards_path(cluster),
+        serde_json::to_string_pretty(forwards)?,
+    )?;
+
+    Ok(())
+}
+
+fn next_id(forwards: &[Forward]) -> u32 {
+    forwards.iter().map(|f| f.id).max().unwrap_or(0) + 1
+}
+
+/// Whether a process is still running.
+pub(crate) fn alive(pid: u32) -> bool {
+    if cfg!(windows) {
+        let filter = format!("PID eq {}", pid);
+        return match Command::new("tasklist")
+            .args(["/FI", &filter, "/NH"])
+            .logged()
+            .output()
+        {
+            Ok(out) => String::from_utf8_lossy(&out.stdout).contains(&pid.to_string()),
+            Err(_) => false,
+        };
+    }
+
+    matches!(
+        Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(Stdio::null())
+            .logged()
+            .status(),
+        Ok(status) if status.success()
+    )
+}
+
+/// Whether a command line read from /proc/<pid>/cmdline runs `program`
+/// with every one of `args`.
+fn runs(cmdline: &[u8], program: &str, args: &[&str]) -> bool {
+    let cmdline: Vec<String> = cmdline
+        .split(|byte| *byte == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    let binary = cmdline
+        .first()
+        .and_then(|argv0| Path::new(argv0).file_stem())
+        .and_then(|stem| stem.to_str());
+
+    binary == Some(program) && args.iter().all(|arg| cmdline[1..].iter().any(|a| a == arg))
+}
+
+/// Whether `pid` is still the process hake started as `program` with `args`.
+/// A pid file outlives its process, and the pid can then be taken by any
+/// other one, which must not get signals meant for kubectl or hake. Without
+/// /proc to read its command line, only whether it runs is known.
+pub(crate) fn running_as(pid: u32, program: &str, args: &[&str]) -> bool {
+    if !Path::new("/proc/self/cmdline").exists() {
+        return alive(pid);
+    }
+
+    match fs::read(format!("/proc/{}/cmdline", pid)) {
+        Ok(cmdline) => runs(&cmdline, program, args),
+        Err(_) => false,
+    }
+}
+
+/// Whether the kubectl of a port-forward still runs.
+fn forwarding(forward: &Forward) -> bool {
+    running_as(
+        forward.pid,
+        "kubectl",
+        &["port-forward", forward.target.as_str()],
+    )
+}
+
+pub(crate) fn kill(pid: u32) -> Result<()> {
+    let pid = pid.to_string();
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("taskkill");
+        command.args(["/PID", &pid, "/F"]);
+        command
+    } else {
+        let mut command = Command::new("kill");
+        command.arg(&pid);
+        command
+    };
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .logged()
+        .status()
+        .map_err(|e| spawn_error("kill", e))?;
+
+    Ok(())
+}
+
+/// Starts `kubectl port-forward` in the background and records it.
+pub fn start(
+    cluster: &str,
+    target: &str,
+    namespace: Option<&str>,
+    ports: &[String],
+) -> Result<Forward> {
+    if ports.is_empty() {
+        return Err(anyhow!("Missing the ports to forward, like 8080:80"));
+    }
+    let mut forwards = read(cluster)?;
+    let id = next_id(&forwards);
+    let log = log_path(cluster, id);
+
+    let mut command = Command::new("kubectl");
+    command
+        .arg("--kubeconfig")
+        .arg(kubectl::kubeconfig_path(cluster))
+        .arg("port-forward");
+    if let Some(namespace) = namespace {
+        command.args(["--namespace", namespace]);
+    }
+    let output = File::create(&log)?;
+    let mut child = command
+        .arg(target)
+        .args(ports)
+        .stdin(Stdio::null())
+        .stdout(output.try_clone()?)
+        .stderr(output)
+        .logged()
+        .spawn()
+        .map_err(|e| spawn_error("kubectl", e))?;
+
+    thread::sleep(STARTUP);
+    if let Some(status) = child.try_wait()? {
+        let output = fs::read_to_string(&log).unwrap_or_default();
+        fs::remove_file(&log).ok();
+        return Err(anyhow!(
+            "kubectl port-forward {} exited with {}: {}",
+            target,
+            status,
+            output.trim()
+        ));
+    }
+
+    let forward = Forward {
+        id,
+        pid: child.id(),
+        target: String::from(target),
+        namespace: namespace.map(String::from),
+        ports: ports.to_vec(),
+        started_at: crate::ttl::now(),
+    };
+    forwards.push(forward.clone());
+    write(cluster, &forwards)?;
+
+    let cyan = Style::new().cyan();
+    info!(
+        "Forwarding {} to {}, logs in {}",
+        cyan.apply_to(ports.join(" ")),
+        cyan.apply_to(target),
+        log.display()
+    );
+
+    Ok(forward)
+}
+
+/// Rows of `forward list`, with a header.
+pub fn rows(forwards: &[Forward], running: &dyn Fn(&Forward) -> bool) -> Vec<Vec<String>> {
+    let mut rows = vec![vec![
+        String::from("ID"),
+        String::from("TARGET"),
+        String::from("NAMESPACE"),
+        String::from("PORTS"),
+        String::from("PID"),
+        String::from("STATUS"),
+    ]];
+    for forward in forwards {
+        rows.push(vec![
+            forward.id.to_string(),
+            forward.target.clone(),
+            forward
+                .namespace
+                .clone()
+                .unwrap_or_else(|| String::from("default")),
+            forward.ports.join(","),
+            forward.pid.to_string(),
+            String::from(if running(forward) {
+                "running"
+            } else {
+                "exited"
+            }),
+        ]);
+    }
+
+    rows
+}
+
+pub fn list(cluster: &str) -> Result<Vec<Vec<String>>> {
+    Ok(rows(&read(cluster)?, &forwarding))
+}
+
+/// Stops the given port-forwards of a cluster, all of them when none is
+/// given, and forgets them.
+pub fn stop(cluster: &str, ids: &[u32]) -> Result<()> {
+    let forwards = read(cluster)?;
+    if let Some(id) = ids.iter().find(|id| !forwards.iter().any(|f| f.id == **id)) {
+        return Err(anyhow!("Cluster {} has no port-forward {}", cluster, id));
+    }
+
+    let (stopped, kept): (Vec<Forward>, Vec<Forward>) = forwards
+        .into_iter()
+        .partition(|f| ids.is_empty() || ids.contains(&f.id));
+    for forward in &stopped {
+        if forwarding(forward) {
+            info!("Stopping port-forward {} to {}", forward.id, forward.target);
+            kill(forward.pid)?;
+        }
+        fs::remove_file(log_path(cluster, forward.id)).ok();
+    }
+
+    write(cluster, &kept)
+}
+
+/// Stops every port-forward of a cluster about to be deleted.
+pub fn stop_all(cluster: &str) {
+    if !forwards_path(cluster).exists() {
+        return;
+    }
+    if let Err(e) = stop(cluster, &[]) {
+        warn!("Could not stop the port-forwards of {}: {:#}", cluster, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::forward::{next_id, rows, runs, Forward};
+
+    fn forward(id: u32, pid: u32) -> Forward {
+        Forward {
+            id,
+            pid,
+            target: String::from("svc/web"),
+            namespace: None,
+            ports: vec![String::from("8080:80"), String::from("8443:443")],
+            started_at: 1600000000,
+        }
+    }
+
+    #[test]
+    fn test_next_id() {
+        assert_eq!(next_id(&[]), 1);
+        assert_eq!(next_id(&[forward(3, 100), forward(1, 101)]), 4);
+    }
+
+    #[test]
+    fn test_rows() {
+        let rows = rows(&[forward(1, 100), forward(2, 200)], &|f| f.pid == 100);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[1],
+            vec![
+                "1",
+                "svc/web",
+                "default",
+                "8080:80,8443:443",
+                "100",
+                "running"
+            ]
+        );
+        assert_eq!(rows[2][5], "exited");
+    }
+
+    #[test]
+    fn test_runs() {
+        let cmdline = b"/usr/local/bin/kubectl\0--kubeconfig\0/h/kubeconfig\0port-forward\0svc/web\x008080:80\0";
+        assert!(runs(cmdline, "kubectl", &["port-forward", "svc/web"]));
+        assert!(!runs(cmdline, "kubectl", &["port-forward", "svc/api"]));
+        assert!(!runs(cmdline, "hake", &["port-forward"]));
+        assert!(runs(
+            b"/home/me/bin/hake\0refresh-creds\0--watch\0--interval\x0030m\0",
+            "hake",
+            &["refresh-creds", "--watch"]
+        ));
+        assert!(!runs(b"/usr/sbin/nginx\0-g\0daemon off;\0", "kubectl", &[]));
+        assert!(!runs(b"", "kubectl", &[]));
+    }
+}
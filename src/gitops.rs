@@ -0,0 +1,190 @@
+// Bootstraps a GitOps controller, Flux or Argo CD, pointed at a git
+// repository, so the contents of the cluster come from the repository and
+// not from hake.
+use anyhow::{anyhow, Result};
+
+use crate::add;
+use crate::error::HakeError;
+use crate::kubectl;
+
+const FLUX_MANIFEST: &str = "https://github.com/fluxcd/flux2/releases/latest/download/install.yaml";
+const ARGOCD_MANIFEST: &str =
+    "https://raw.githubusercontent.com/argoproj/argo-cd/stable/manifests/install.yaml";
+
+#[derive(Debug, PartialEq)]
+pub enum Controller {
+    Flux,
+    ArgoCd,
+}
+
+/// A controller and the repository it syncs, from
+/// `--bootstrap flux=<url>[#branch[:path]]`.
+#[derive(Debug, PartialEq)]
+pub struct Bootstrap {
+    pub controller: Controller,
+    pub url: String,
+    pub branch: Option<String>,
+    pub path: Option<String>,
+}
+
+impl Bootstrap {
+    pub fn parse(spec: &str) -> Result<Bootstrap> {
+        let (controller, repo) = spec
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid bootstrap {}, expected flux=<git-url>", spec))?;
+        let controller = match controller {
+            "flux" => Controller::Flux,
+            "argocd" => Controller::ArgoCd,
+            _ => return Err(anyhow!("Unknown GitOps controller: {}", controller)),
+        };
+        let (url, reference) = match repo.split_once('#') {
+            Some((url, reference)) => (url, Some(reference)),
+            None => (repo, None),
+        };
+        if url.is_empty() {
+            return Err(anyhow!("Missing the repository in {}", spec));
+        }
+        let (branch, path) = match reference.map(|r| r.split_once(':').unwrap_or((r, ""))) {
+            Some((branch, path)) => (
+                Some(String::from(branch)).filter(|b| !b.is_empty()),
+                Some(String::from(path)).filter(|p| !p.is_empty()),
+            ),
+            None => (None, None),
+        };
+
+        Ok(Bootstrap {
+            controller,
+            url: String::from(url),
+            branch,
+            path,
+        })
+    }
+
+    /// Objects pointing the controller at the repository.
+    fn sync_manifest(&self) -> String {
+        let path = self.path.as_deref().unwrap_or("./");
+        match self.controller {
+            Controller::Flux => format!(
+                r#"apiVersion: source.toolkit.fluxcd.io/v1
+kind: GitRepository
+metadata:
+  name: hake
+  namespace: flux-system
+spec:
+  interval: 1m
+  url: {}
+  ref:
+    branch: {}
+---
+apiVersion: kustomize.toolkit.fluxcd.io/v1
+kind: Kustomization
+metadata:
+  name: hake
+  namespace: flux-system
+spec:
+  interval: 10m
+  path: {}
+  prune: true
+  sourceRef:
+    kind: GitRepository
+    name: hake
+"#,
+                self.url,
+                self.branch.as_deref().unwrap_or("main"),
+                path
+            ),
+            Controller::ArgoCd => format!(
+                r#"apiVersion: argoproj.io/v1alpha1
+kind: Application
+metadata:
+  name: hake
+  namespace: argocd
+spec:
+  project: default
+  source:
+    repoURL: {}
+    targetRevision: {}
+    path: {}
+  destination:
+    server: https://kubernetes.default.svc
+    namespace: default
+  syncPolicy:
+    automated:
+      prune: true
+      selfHeal: true
+"#,
+                self.url,
+                self.branch.as_deref().unwrap_or("HEAD"),
+                path
+            ),
+        }
+    }
+}
+
+/// Installs the controller and points it at the repository.
+pub fn bootstrap(cluster: &str, bootstrap: &Bootstrap) -> Result<()> {
+    match bootstrap.controller {
+        Controller::Flux => add::apply(cluster, FLUX_MANIFEST, None)?,
+        Controller::ArgoCd => {
+            // the Argo CD manifests do not create their namespace
+            kubectl::apply_manifest(
+                cluster,
+                "apiVersion: v1\nkind: Namespace\nmetadata:\n  name: argocd\n",
+            )?;
+            add::apply(cluster, ARGOCD_MANIFEST, Some("argocd"))?
+        }
+    }
+
+    let out = kubectl::apply_manifest(cluster, &bootstrap.sync_manifest())?;
+    if !out.status.success() {
+        return Err(HakeError::CommandFailed {
+            command: String::from("kubectl apply"),
+            message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gitops::{Bootstrap, Controller};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Bootstrap::parse("flux=https://github.com/org/fleet").unwrap(),
+            Bootstrap {
+                controller: Controller::Flux,
+                url: String::from("https://github.com/org/fleet"),
+                branch: None,
+                path: None,
+            }
+        );
+
+        let bootstrap =
+            Bootstrap::parse("argocd=git@github.com:org/fleet.git#dev:clusters/test").unwrap();
+        assert_eq!(bootstrap.controller, Controller::ArgoCd);
+        assert_eq!(bootstrap.url, "git@github.com:org/fleet.git");
+        assert_eq!(bootstrap.branch.as_deref(), Some("dev"));
+        assert_eq!(bootstrap.path.as_deref(), Some("clusters/test"));
+
+        let bootstrap = Bootstrap::parse("flux=https://github.com/org/fleet#:apps").unwrap();
+        assert_eq!(bootstrap.branch, None);
+        assert_eq!(bootstrap.path.as_deref(), Some("apps"));
+
+        assert!(Bootstrap::parse("https://github.com/org/fleet").is_err());
+        assert!(Bootstrap::parse("jenkins=https://github.com/org/fleet").is_err());
+        assert!(Bootstrap::parse("flux=").is_err());
+    }
+
+    #[test]
+    fn test_sync_manifest() {
+        let bootstrap = Bootstrap::parse("flux=https://github.com/org/fleet#dev").unwrap();
+        let manifest = bootstrap.sync_manifest();
+        assert!(manifest.contains("url: https://github.com/org/fleet\n"));
+        assert!(manifest.contains("branch: dev\n"));
+        assert!(manifest.contains("path: ./\n"));
+    }
+}
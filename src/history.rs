@@ -0,0 +1,193 @@
+// Append-only log of the operations run with hake, one JSON object per line
+// in ~/.hake/.history, so it is possible to tell who deleted a cluster on a
+// shared machine, and when.
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+use tracing::warn;
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::ttl;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the epoch when the operation started.
+    pub time: u64,
+    pub user: String,
+    pub command: String,
+    /// Arguments hake was run with.
+    pub args: Vec<String>,
+    /// The error, when the operation failed.
+    pub error: Option<String>,
+    /// Duration of the operation, in seconds.
+    pub duration: f64,
+}
+
+impl Entry {
+    pub fn new(command: &str, started: u64, duration: Duration, error: Option<String>) -> Entry {
+        let user = env::var("USER")
+            .or_else(|_| env::var("USERNAME"))
+            .unwrap_or_else(|_| String::from("unknown"));
+
+        Entry {
+            time: started,
+            user,
+            command: String::from(command),
+            args: env::args().skip(1).collect(),
+            error,
+            duration: duration.as_secs_f64(),
+        }
+    }
+
+    fn outcome(&self) -> &str {
+        match self.error {
+            Some(_) => "failed",
+            None => "ok",
+        }
+    }
+}
+
+/// Hidden so it is not taken for a cluster directory.
+fn path() -> PathBuf {
+    crate::get_config_dir().join(".history")
+}
+
+fn append(entry: &Entry) -> Result<()> {
+    let path = path();
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    // a single write per line, so concurrent runs do not interleave
+    file.write_all(format!("{}\n", serde_json::to_string(entry)?).as_bytes())?;
+
+    Ok(())
+}
+
+/// Records an operation. Failing to do so only warns, the operation itself
+/// already happened.
+pub fn record(entry: &Entry) {
+    if let Err(e) = append(entry) {
+        warn!(
+            "Could not record the operation in {}: {}",
+            path().display(),
+            e
+        );
+    }
+}
+
+/// Parses the log, skipping lines that cannot be read, like one cut short by
+/// a full disk.
+fn parse(contents: &str) -> Vec<Entry> {
+    contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Reads every recorded operation, oldest first.
+pub fn read() -> Result<Vec<Entry>> {
+    match fs::read_to_string(path()) {
+        Ok(contents) => Ok(parse(&contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether an operation was run against a cluster, named with --name.
+fn mentions(entry: &Entry, cluster: &str) -> bool {
+    entry.args.iter().enumerate().any(|(i, arg)| {
+        arg == &format!("--name={}", cluster)
+            || (arg == "--name" && entry.args.get(i + 1).map(String::as_str) == Some(cluster))
+    })
+}
+
+/// Rows for the last `limit` operations, optionally only those on a
+/// cluster, as of `now`.
+pub fn rows(entries: &[Entry], cluster: Option<&str>, limit: usize, now: u64) -> Vec<Vec<String>> {
+    let entries: Vec<&Entry> = entries
+        .iter()
+        .filter(|entry| match cluster {
+            Some(cluster) => mentions(entry, cluster),
+            None => true,
+        })
+        .collect();
+    let skip = entries.len().saturating_sub(limit);
+
+    let mut rows = vec![vec![
+        String::from("AGE"),
+        String::from("USER"),
+        String::from("COMMAND"),
+        String::from("OUTCOME"),
+        String::from("DURATION"),
+        String::from("ARGS"),
+    ]];
+    for entry in entries.into_iter().skip(skip) {
+        rows.push(vec![
+            ttl::format_age(now.saturating_sub(entry.time)),
+            entry.user.clone(),
+            entry.command.clone(),
+            entry.outcome().to_string(),
+            format!("{:.1}s", entry.duration),
+            entry.args.join(" "),
+        ]);
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::{parse, rows, Entry};
+
+    fn entry(time: u64, args: &[&str], error: Option<&str>) -> Entry {
+        Entry {
+            time,
+            user: String::from("alice"),
+            command: String::from(args[0]),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            error: error.map(String::from),
+            duration: 12.34,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let line =
+            r#"{"time":1,"user":"bob","command":"gc","args":["gc"],"error":null,"duration":0.5}"#;
+        let entries = parse(&format!("{}\n{{\"time\":2,\"us\n", line));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].user, "bob");
+    }
+
+    #[test]
+    fn test_rows() {
+        let entries = vec![
+            entry(100, &["create", "--name", "foo"], None),
+            entry(200, &["delete", "--name=bar"], Some("not found")),
+            entry(300, &["delete", "--name", "foo"], None),
+        ];
+
+        let all = rows(&entries, None, 2, 400);
+        assert_eq!(all.len(), 3);
+        assert_eq!(
+            all[1],
+            vec![
+                "3m",
+                "alice",
+                "delete",
+                "failed",
+                "12.3s",
+                "delete --name=bar"
+            ]
+        );
+
+        let foo = rows(&entries, Some("foo"), 10, 400);
+        assert_eq!(foo.len(), 3);
+        assert_eq!(foo[1][2], "create");
+        assert_eq!(foo[2][2], "delete");
+        assert_eq!(rows(&entries, Some("bar"), 10, 400).len(), 2);
+    }
+}
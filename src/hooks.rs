@@ -0,0 +1,130 @@
+// Scripts run before and after the lifecycle operations of a cluster, set in
+// the configuration file:
+//
+//     [hooks]
+//     post_create = ["./scripts/seed-secrets.sh", "dns-register $HAKE_CLUSTER"]
+//
+// They run with the shell, in the directory of the configuration file and
+// with the cluster in their environment, and a failing script stops the
+// operation. --no-hooks skips them.
+use anyhow::Result;
+use console::Style;
+use serde_derive::{Deserialize, Serialize};
+use tracing::info;
+
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config;
+use crate::dryrun;
+use crate::error::{spawn_error, HakeError};
+use crate::kubectl;
+use crate::logging::LogCommand;
+use crate::plugin::{ENV_CLUSTER, ENV_CONFIG_DIR};
+
+/// Provider of the cluster, kind or digitalocean.
+pub const ENV_PROVIDER: &str = "HAKE_PROVIDER";
+/// Hook being run, like post_create.
+pub const ENV_HOOK: &str = "HAKE_HOOK";
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Skips every hook, for --no-hooks.
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub pre_create: Vec<String>,
+    pub post_create: Vec<String>,
+    pub pre_delete: Vec<String>,
+}
+
+impl Hooks {
+    /// Adds the scripts of `other` after these ones.
+    pub fn extend(&mut self, other: &Hooks) {
+        self.pre_create.extend(other.pre_create.iter().cloned());
+        self.post_create.extend(other.post_create.iter().cloned());
+        self.pre_delete.extend(other.pre_delete.iter().cloned());
+    }
+}
+
+fn shell(script: &str) -> Command {
+    if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(script);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+}
+
+/// Runs the scripts of a hook in order, stopping at the first one failing.
+/// Relative paths in them are resolved from the directory of the
+/// configuration file they come from, wherever hake runs.
+pub fn run(hook: &str, scripts: &[String], cluster: &str, provider: &str) -> Result<()> {
+    let cyan = Style::new().cyan();
+    if DISABLED.load(Ordering::Relaxed) && !scripts.is_empty() {
+        info!("Skipping the {} hooks", hook);
+        return Ok(());
+    }
+    let dir = config::config_path()
+        .and_then(|path| path.parent().map(|dir| dir.to_path_buf()))
+        .filter(|dir| dir.is_dir());
+    for script in scripts {
+        if dryrun::enabled() {
+            dryrun::print(&format!("would run {} hook: {}", hook, script));
+            continue;
+        }
+
+        info!("Running {} hook: {}", hook, cyan.apply_to(script));
+        let mut command = shell(script);
+        if let Some(dir) = &dir {
+            command.current_dir(dir);
+        }
+        let status = command
+            .env(ENV_CLUSTER, cluster)
+            .env(ENV_CONFIG_DIR, crate::get_config_dir())
+            .env(ENV_PROVIDER, provider)
+            .env(ENV_HOOK, hook)
+            .env("KUBECONFIG", kubectl::kubeconfig_path(cluster))
+            .logged()
+            .status()
+            .map_err(|e| spawn_error("sh", e))?;
+        if !status.success() {
+            return Err(HakeError::CommandFailed {
+                command: format!("{} hook `{}`", hook, script),
+                message: format!("exit status {}", status),
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hooks::Hooks;
+
+    #[test]
+    fn test_extend() {
+        let mut hooks = Hooks {
+            post_create: vec![String::from("global.sh")],
+            ..Default::default()
+        };
+        hooks.extend(&Hooks {
+            post_create: vec![String::from("profile.sh")],
+            pre_delete: vec![String::from("cleanup.sh")],
+            ..Default::default()
+        });
+
+        assert_eq!(hooks.post_create, vec!["global.sh", "profile.sh"]);
+        assert_eq!(hooks.pre_delete, vec!["cleanup.sh"]);
+        assert!(hooks.pre_create.is_empty());
+    }
+}
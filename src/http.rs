@@ -0,0 +1,215 @@
+// Requests to provider APIs, retried with exponential backoff when they fail
+// for reasons that usually go away: rate limits, server errors and network
+// blips. Requests that create something, like POST, are only retried when
+// they could not be sent, so a cluster is never created twice. Clients go
+// through the proxy in HTTPS_PROXY, except for the hosts in NO_PROXY, and
+// trust the CA bundle in the configuration on top of the system ones, for
+// networks behind a corporate proxy.
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::{Client, ClientBuilder, Request, Response};
+use reqwest::header::RETRY_AFTER;
+use reqwest::Certificate;
+use reqwest::{Method, StatusCode};
+use tracing::{debug, warn};
+
+use std::env;
+use std::fs;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::UserConfig;
+
+/// Attempts per request, unless set in the [http] section of the
+/// configuration.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+fn max_attempts() -> u32 {
+    UserConfig::load()
+        .ok()
+        .and_then(|config| config.http.max_attempts)
+        .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+        .max(1)
+}
+
+/// Splits a PEM bundle into its certificates.
+fn split_pem(bundle: &str) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+
+    bundle
+        .split_inclusive(END)
+        .filter_map(|block| {
+            let start = block.find("-----BEGIN CERTIFICATE-----")?;
+            if block.ends_with(END) {
+                Some(format!("{}\n", &block[start..]))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// CA bundle to trust, from HAKE_CA_BUNDLE or the [http] section of the
+/// configuration.
+fn ca_bundle() -> Option<String> {
+    env::var("HAKE_CA_BUNDLE")
+        .ok()
+        .filter(|path| !path.is_empty())
+        .or_else(|| UserConfig::load().ok()?.http.ca_bundle)
+}
+
+/// Builder for the clients of provider APIs. reqwest already honors
+/// HTTPS_PROXY, HTTP_PROXY and NO_PROXY.
+pub fn client_builder() -> Result<ClientBuilder> {
+    let mut builder = Client::builder();
+    if let Some(path) = ca_bundle() {
+        let bundle = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read CA bundle {}", path))?;
+        let certificates = split_pem(&bundle);
+        if certificates.is_empty() {
+            return Err(anyhow!("No certificates found in CA bundle {}", path));
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(Certificate::from_pem(certificate.as_bytes())?);
+        }
+    }
+
+    Ok(builder)
+}
+
+/// Whether sending a request twice does the same as sending it once.
+fn idempotent(method: &Method) -> bool {
+    [
+        Method::GET,
+        Method::HEAD,
+        Method::PUT,
+        Method::DELETE,
+        Method::OPTIONS,
+    ]
+    .contains(method)
+}
+
+fn retryable(method: &Method, status: StatusCode) -> bool {
+    idempotent(method) && (status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error())
+}
+
+/// Pseudo-random number between 0 and 1, good enough to spread retries.
+fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+
+    f64::from(nanos % 1000) / 1000.0
+}
+
+/// Time to wait before an attempt, the first one being 1: doubles on every
+/// attempt up to a limit, and half of it is random so clients retrying at
+/// the same time spread out.
+fn backoff(attempt: u32, jitter: f64) -> Duration {
+    let exponential = BASE_DELAY * 2u32.saturating_pow(attempt.saturating_sub(1).min(16));
+    let delay = exponential.min(MAX_DELAY);
+
+    delay / 2 + delay.mul_f64(jitter / 2.0)
+}
+
+/// Delay asked for by the server in a Retry-After header, in seconds.
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let seconds = resp
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+
+    Some(Duration::from_secs(seconds).min(MAX_DELAY))
+}
+
+/// Sends a request, retrying it on rate limits, server errors and network
+/// errors, or only when it could not connect for those that are not
+/// idempotent: the server may have acted on them before the answer was lost.
+/// The request is logged without its headers, which carry tokens.
+pub fn send(client: &Client, mut request: Request) -> Result<Response> {
+    let attempts = max_attempts();
+    let mut attempt = 1;
+    loop {
+        debug!("{} {}", request.method(), request.url());
+        // requests with a streamed body cannot be sent twice
+        let retry = if attempt < attempts {
+            request.try_clone()
+        } else {
+            None
+        };
+        let next = match retry {
+            Some(next) => next,
+            None => return Ok(client.execute(request)?),
+        };
+
+        let wait = match client.execute(request) {
+            Ok(resp) if retryable(next.method(), resp.status()) => {
+                warn!(
+                    "{} {} returned {}, retrying",
+                    next.method(),
+                    next.url(),
+                    resp.status()
+                );
+                retry_after(&resp).unwrap_or_else(|| backoff(attempt, jitter()))
+            }
+            Ok(resp) => return Ok(resp),
+            Err(e)
+                if e.is_connect()
+                    || (idempotent(next.method()) && (e.is_timeout() || e.is_request())) =>
+            {
+                warn!("{} {} failed: {}, retrying", next.method(), next.url(), e);
+                backoff(attempt, jitter())
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        thread::sleep(wait);
+        attempt += 1;
+        request = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{backoff, retryable, split_pem, MAX_DELAY};
+    use reqwest::{Method, StatusCode};
+    use std::time::Duration;
+
+    #[test]
+    fn test_split_pem() {
+        let bundle = "# corporate root\n-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n\n-----BEGIN CERTIFICATE-----\nMIIC\n-----END CERTIFICATE-----\n";
+        assert_eq!(
+            split_pem(bundle),
+            vec![
+                "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n",
+                "-----BEGIN CERTIFICATE-----\nMIIC\n-----END CERTIFICATE-----\n",
+            ]
+        );
+        assert!(split_pem("not a certificate").is_empty());
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1, 0.0), Duration::from_millis(250));
+        assert_eq!(backoff(1, 1.0), Duration::from_millis(500));
+        assert_eq!(backoff(3, 0.0), Duration::from_millis(1000));
+        assert_eq!(backoff(3, 1.0), Duration::from_millis(2000));
+        assert_eq!(backoff(20, 1.0), MAX_DELAY);
+        assert_eq!(backoff(u32::MAX, 0.0), MAX_DELAY / 2);
+    }
+
+    #[test]
+    fn test_retryable() {
+        assert!(retryable(&Method::GET, StatusCode::TOO_MANY_REQUESTS));
+        assert!(retryable(&Method::DELETE, StatusCode::BAD_GATEWAY));
+        assert!(!retryable(&Method::GET, StatusCode::NOT_FOUND));
+        assert!(!retryable(&Method::GET, StatusCode::UNPROCESSABLE_ENTITY));
+        // DigitalOcean may have created the cluster before failing
+        assert!(!retryable(&Method::POST, StatusCode::BAD_GATEWAY));
+    }
+}
@@ -0,0 +1,100 @@
+// Pull-through cache of Docker Hub images shared by every kind cluster. It
+// runs as a registry container keeping the images in a docker volume, so
+// deleting and recreating clusters does not download the same images again.
+use anyhow::Result;
+
+use std::process::Stdio;
+
+use crate::error::{spawn_error, HakeError};
+use crate::logging::LogCommand;
+use crate::runtime;
+
+/// Name of the registry container, as resolved from the nodes.
+pub const CONTAINER: &str = "hake-image-cache";
+/// Volume the cached images are kept in, it outlives the container.
+pub const VOLUME: &str = "hake-image-cache";
+const IMAGE: &str = "registry:2";
+const UPSTREAM: &str = "https://registry-1.docker.io";
+
+fn docker(args: &[&str]) -> Result<bool> {
+    Ok(runtime::command()
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .logged()
+        .status()
+        .map_err(|e| spawn_error(runtime::binary(), e))?
+        .success())
+}
+
+/// Starts the cache registry unless it is running already.
+pub fn start() -> Result<()> {
+    let running = runtime::command()
+        .args(["inspect", "-f", "{{.State.Running}}", CONTAINER])
+        .logged()
+        .output()
+        .map_err(|e| spawn_error(runtime::binary(), e))?;
+    match String::from_utf8_lossy(&running.stdout).trim() {
+        "true" => return Ok(()),
+        // stopped, like after a restart of docker
+        "false" if docker(&["start", CONTAINER])? => return Ok(()),
+        _ => {}
+    }
+
+    let volume = format!("{}:/var/lib/registry", VOLUME);
+    let upstream = format!("REGISTRY_PROXY_REMOTEURL={}", UPSTREAM);
+    let args = [
+        "run",
+        "-d",
+        "--restart=always",
+        "--name",
+        CONTAINER,
+        "-v",
+        &volume,
+        "-e",
+        &upstream,
+        IMAGE,
+    ];
+    if !docker(&args)? {
+        return Err(HakeError::CommandFailed {
+            command: format!("{} {}", runtime::binary(), args.join(" ")),
+            message: String::from("could not start the image cache"),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Connects the cache to the network of the nodes, which kind creates with the
+/// first cluster, so the nodes can reach it by name.
+pub fn connect(network: &str) -> Result<()> {
+    runtime::connect_network(CONTAINER, network)
+}
+
+/// containerd config patch pulling Docker Hub images through the cache, and
+/// from Docker Hub itself when the cache is not reachable.
+pub fn containerd_config_patch() -> String {
+    format!(
+        r#"
+[plugins."io.containerd.grpc.v1.cri".registry.mirrors."docker.io"]
+  endpoint = ["http://{}:5000", "{}"]"#,
+        CONTAINER, UPSTREAM
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image_cache::containerd_config_patch;
+
+    #[test]
+    fn test_containerd_config_patch() {
+        let patch: toml::Value = toml::from_str(&containerd_config_patch()).unwrap();
+        let mirror =
+            &patch["plugins"]["io.containerd.grpc.v1.cri"]["registry"]["mirrors"]["docker.io"];
+        assert_eq!(
+            mirror["endpoint"][0].as_str(),
+            Some("http://hake-image-cache:5000")
+        );
+    }
+}
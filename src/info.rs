@@ -0,0 +1,201 @@
+// Report of the environment hake runs in: its version, the versions of the
+// tools it calls, where it keeps its files and which credentials it would use,
+// to paste into bug reports.
+use anyhow::Result;
+use serde_derive::Serialize;
+use serde_json::Value;
+
+use std::env;
+use std::process::Command;
+
+use crate::kind::Kind;
+use crate::logging::LogCommand;
+use crate::{auth, cluster, config, r#do, runtime, self_update};
+
+#[derive(Serialize, Debug)]
+pub struct Credential {
+    pub provider: String,
+    /// Where the key comes from, the environment or the keychain.
+    pub source: String,
+    /// The last characters of the key, enough to tell keys apart.
+    pub key: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Info {
+    pub version: String,
+    pub os: String,
+    pub arch: String,
+    pub kind: Option<String>,
+    pub kubectl: Option<String>,
+    /// Container runtime, docker or podman.
+    pub runtime: String,
+    /// Version of the container runtime.
+    pub docker: Option<String>,
+    pub helm: Option<String>,
+    pub config_dir: String,
+    pub config_file: Option<String>,
+    pub clusters: usize,
+    pub credentials: Vec<Credential>,
+}
+
+/// Hides all but the last 4 characters of a secret.
+fn redact(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 8 {
+        return String::from("****");
+    }
+    let last: String = chars[chars.len() - 4..].iter().collect();
+
+    format!("****{}", last)
+}
+
+/// First line of the output of a tool, None if it could not run.
+fn tool_output(program: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(program).args(args).logged().output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+
+    String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .next()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+}
+
+/// Reads the client version from `kubectl version --client -o json`.
+fn parse_kubectl_version(output: &str) -> Option<String> {
+    let version: Value = serde_json::from_str(output).ok()?;
+
+    version["clientVersion"]["gitVersion"]
+        .as_str()
+        .map(String::from)
+}
+
+fn kubectl_version() -> Option<String> {
+    let out = Command::new("kubectl")
+        .args(["version", "--client", "-o", "json"])
+        .logged()
+        .output()
+        .ok()?;
+
+    parse_kubectl_version(&String::from_utf8_lossy(&out.stdout))
+}
+
+fn credentials() -> Vec<Credential> {
+    let mut credentials = vec![];
+    if let Ok(key) = env::var(r#do::ENV_DO_PROVIDER) {
+        credentials.push(Credential {
+            provider: String::from("digitalocean"),
+            source: format!("environment ({})", r#do::ENV_DO_PROVIDER),
+            key: redact(&key),
+        });
+    }
+    for provider in auth::PROVIDERS {
+        if let Ok(Some(key)) = auth::token(provider) {
+            credentials.push(Credential {
+                provider: provider.to_string(),
+                source: String::from("keychain"),
+                key: redact(&key),
+            });
+        }
+    }
+
+    credentials
+}
+
+pub fn collect() -> Info {
+    Info {
+        version: String::from(self_update::CURRENT_VERSION),
+        os: String::from(env::consts::OS),
+        arch: String::from(env::consts::ARCH),
+        kind: Kind::version().ok().filter(|v| !v.is_empty()),
+        kubectl: kubectl_version(),
+        runtime: String::from(runtime::binary()),
+        docker: if runtime::is_podman() {
+            tool_output(
+                runtime::PODMAN,
+                &["version", "--format", "{{.Client.Version}}"],
+            )
+        } else {
+            tool_output(
+                runtime::DOCKER,
+                &["version", "--format", "{{.Server.Version}}"],
+            )
+        },
+        helm: tool_output("helm", &["version", "--short"]),
+        config_dir: crate::get_config_dir().display().to_string(),
+        config_file: config::config_path()
+            .filter(|path| path.exists())
+            .map(|path| path.display().to_string()),
+        clusters: cluster::all_clusters().len(),
+        credentials: credentials(),
+    }
+}
+
+fn or_missing(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or("not found")
+}
+
+pub fn print(info: &Info, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(info)?);
+        return Ok(());
+    }
+
+    let credentials: Vec<String> = info
+        .credentials
+        .iter()
+        .map(|c| format!("{} {} from {}", c.provider, c.key, c.source))
+        .collect();
+    let rows = [
+        (
+            "hake",
+            format!("{} {}/{}", info.version, info.os, info.arch),
+        ),
+        ("kind", or_missing(&info.kind).to_string()),
+        ("kubectl", or_missing(&info.kubectl).to_string()),
+        (info.runtime.as_str(), or_missing(&info.docker).to_string()),
+        ("helm", or_missing(&info.helm).to_string()),
+        ("config dir", info.config_dir.clone()),
+        (
+            "config file",
+            info.config_file
+                .clone()
+                .unwrap_or_else(|| String::from("none")),
+        ),
+        ("clusters", info.clusters.to_string()),
+        (
+            "credentials",
+            if credentials.is_empty() {
+                String::from("none")
+            } else {
+                credentials.join(", ")
+            },
+        ),
+    ];
+    for (key, value) in rows.iter() {
+        println!("{:<12} {}", key, value);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::info::{parse_kubectl_version, redact};
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact("dop_v1_0123456789abcdef"), "****cdef");
+        assert_eq!(redact("short"), "****");
+    }
+
+    #[test]
+    fn test_parse_kubectl_version() {
+        let output = r#"{"clientVersion":{"major":"1","minor":"28","gitVersion":"v1.28.2"}}"#;
+        assert_eq!(parse_kubectl_version(output).as_deref(), Some("v1.28.2"));
+        assert_eq!(parse_kubectl_version("error"), None);
+    }
+}
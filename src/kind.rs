@@ -1,32 +1,161 @@
 #![allow(non_snake_case)]
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use dirs;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
 
 use base64::encode;
-use std::collections::HashMap;
-use std::fs::{create_dir, remove_dir_all, File};
-use std::io::{Read, Write};
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, copy, create_dir, create_dir_all, read_dir, remove_dir_all, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
 use std::str;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
 use std::vec::Vec;
 
 use bollard::container::ListContainersOptions;
-use bollard::Docker;
 use tokio::runtime::Runtime;
 
+use console::Style;
 use regex::Regex;
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::arch;
+use crate::bench;
+use crate::ci;
+use crate::dryrun;
+use crate::error::{spawn_error, HakeError};
+use crate::image_cache;
+use crate::logging::LogCommand;
+use crate::metadata::ClusterMetadata;
+use crate::progress::{self, Progress};
+use crate::rootless;
+use crate::runtime;
+use tracing::{debug, info, warn};
+
+/// The kind binary, kind.exe on Windows.
+pub const KIND: &str = if cfg!(windows) { "kind.exe" } else { "kind" };
+
+/// Oldest kind release hake works with, the first one creating the `kind`
+/// docker network.
+pub const MIN_KIND_VERSION: (u64, u64, u64) = (0, 8, 0);
+
+/// kind release that introduced the v1alpha4 cluster config API.
+const V1ALPHA4_KIND_VERSION: (u64, u64, u64) = (0, 8, 0);
+
+/// First kind release whose node images pull with containerd registry
+/// configs, used by default to authenticate to private registries.
+const CONTAINERD_AUTH_KIND_VERSION: (u64, u64, u64) = (0, 11, 0);
+
+/// How nodes get the credentials of a private registry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegistryAuth {
+    /// A docker config.json mounted in /var/lib/kubelet, used by kubelet pulls.
+    Kubelet,
+    /// A containerd config patch, used by every pull on every node.
+    Containerd,
+    /// Nothing on the nodes, only the pull secrets of `--ecr-secret`.
+    Secret,
+}
+
+impl RegistryAuth {
+    pub fn parse(mode: &str) -> Result<RegistryAuth> {
+        match mode {
+            "kubelet" => Ok(RegistryAuth::Kubelet),
+            "containerd" => Ok(RegistryAuth::Containerd),
+            "secret" => Ok(RegistryAuth::Secret),
+            _ => Err(anyhow!(
+                "Unknown registry auth: {}, use kubelet, containerd or secret",
+                mode
+            )),
+        }
+    }
+
+    /// The default for a kind release, containerd when the version is unknown.
+    fn default_for(version: Option<(u64, u64, u64)>) -> RegistryAuth {
+        match version {
+            Some(v) if v < CONTAINERD_AUTH_KIND_VERSION => RegistryAuth::Kubelet,
+            _ => RegistryAuth::Containerd,
+        }
+    }
+}
+
+/// First kind release able to create dual-stack clusters.
+const DUAL_STACK_KIND_VERSION: (u64, u64, u64) = (0, 11, 0);
+
+/// IP families of the cluster networking.
+pub const IP_FAMILIES: [&str; 3] = ["ipv4", "ipv6", "dual"];
+
+/// Directory of the control plane nodes with the API server files hake
+/// writes, the audit policy and the encryption configuration.
+const API_SERVER_DIR: &str = "/etc/kubernetes/hake";
+/// Directory of the control plane nodes the audit log is written to.
+const AUDIT_LOG_DIR: &str = "/var/log/kubernetes/audit";
+
+/// Directory of every node the host directory of `--pv` is mounted in.
+pub const HOST_PV_DIR: &str = "/var/hake-pv";
+
+/// Seconds to wait for the API server of an imported cluster once its nodes
+/// are started again.
+const API_SERVER_TIMEOUT: u64 = 120;
+
+/// Most lines of the output of kind shown when it fails.
+const FAILURE_LINES: usize = 20;
+
+/// Mount asking the NVIDIA container runtime to expose every GPU to a node.
+const NVIDIA_DEVICES_MOUNT: &str = "/var/run/nvidia-container-devices/all";
+
+/// Installs the NVIDIA container toolkit in a node and makes it the default
+/// runtime of its containerd, like nvkind does.
+const NVIDIA_NODE_SETUP: &str = r#"set -e
+umount -R /proc/driver/nvidia || true
+apt-get update
+apt-get install -y gpg
+curl -fsSL https://nvidia.github.io/libnvidia-container/gpgkey | gpg --dearmor -o /usr/share/keyrings/nvidia-container-toolkit-keyring.gpg
+curl -fsSL https://nvidia.github.io/libnvidia-container/stable/deb/nvidia-container-toolkit.list | sed 's#deb https://#deb [signed-by=/usr/share/keyrings/nvidia-container-toolkit-keyring.gpg] https://#g' > /etc/apt/sources.list.d/nvidia-container-toolkit.list
+apt-get update
+apt-get install -y nvidia-container-toolkit
+nvidia-ctk runtime configure --runtime=containerd --set-as-default
+systemctl restart containerd"#;
+
+/// Returns the cluster config apiVersion understood by a kind release. When
+/// the version is unknown the current API is assumed.
+pub fn config_api_version(version: Option<(u64, u64, u64)>) -> &'static str {
+    match version {
+        Some(v) if v < V1ALPHA4_KIND_VERSION => "kind.sigs.k8s.io/v1alpha3",
+        _ => "kind.x-k8s.io/v1alpha4",
+    }
+}
+
+static BINARY: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Runs kind from `path` from now on, instead of looking it up in PATH.
+pub fn use_binary(path: PathBuf) {
+    *BINARY.lock().unwrap() = Some(path);
+}
+
+fn binary() -> PathBuf {
+    BINARY
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(KIND))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 struct ExtraMount {
     containerPath: String,
     hostPath: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    readOnly: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 struct PortMapping {
     containerPort: u32,
     hostPort: u32,
@@ -36,17 +165,37 @@ struct PortMapping {
 #[derive(Serialize, Deserialize, Debug)]
 struct Node {
     role: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
     extraMounts: Vec<ExtraMount>,
     extraPortMappings: Vec<PortMapping>,
     kubeadmConfigPatches: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Networking {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disableDefaultCNI: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipFamily: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    podSubnet: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    serviceSubnet: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct ClusterConfig {
     kind: String,
     apiVersion: String,
     nodes: Vec<Node>,
     containerdConfigPatches: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    networking: Option<Networking>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    featureGates: BTreeMap<String, bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    kubeadmConfigPatches: Vec<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -58,9 +207,32 @@ struct DockerLogin {
 pub struct Kind {
     pub name: String,
     pub ecr_repo: Option<String>,
-    config_dir: String,
+    config_dir: PathBuf,
     local_registry: Option<String>,
+    /// Local registry container to connect to the kind network, on podman.
+    registry_container: Option<String>,
+    /// Network of the nodes, instead of the kind one.
+    network: Option<String>,
     extra_port_mapping: Option<String>,
+    port_mappings: Vec<PortMapping>,
+    ingress_ready: bool,
+    /// Host ports mapped for the ingress controller, not given by the user.
+    ingress_ports: Vec<u32>,
+    disable_default_cni: bool,
+    ip_family: Option<String>,
+    pod_subnet: Option<String>,
+    service_subnet: Option<String>,
+    feature_gates: BTreeMap<String, bool>,
+    kubeadm_patches: Vec<String>,
+    audit_policy: Option<String>,
+    encrypt_secrets: bool,
+    mounts: Vec<ExtraMount>,
+    node_image: Option<String>,
+    arch: Option<String>,
+    registry_auth: Option<RegistryAuth>,
+    gpus: bool,
+    image_cache: bool,
+    workers: u16,
     verbose: bool,
 }
 
@@ -71,6 +243,7 @@ impl Kind {
                 return vec![ExtraMount {
                     containerPath: String::from(container_path),
                     hostPath: String::from(host_path),
+                    readOnly: None,
                 }];
             }
         }
@@ -81,6 +254,7 @@ impl Kind {
     fn kind_node(role: &str, container_path: Option<&str>, host_path: Option<&str>) -> Node {
         Node {
             role: String::from(role),
+            image: None,
             extraMounts: Kind::extra_mount(container_path, host_path),
             extraPortMappings: vec![],
             kubeadmConfigPatches: vec![],
@@ -100,31 +274,62 @@ nodeRegistration:
         &self,
         ecr: &Option<String>,
         local_reg: &Option<String>,
-    ) -> ClusterConfig {
+        version: Option<(u64, u64, u64)>,
+    ) -> Result<ClusterConfig> {
         let mut cc = ClusterConfig {
             kind: String::from("Cluster"),
-            apiVersion: String::from("kind.x-k8s.io/v1alpha4"),
+            apiVersion: String::from(config_api_version(version)),
             nodes: vec![],
             containerdConfigPatches: vec![],
+            networking: None,
+            featureGates: BTreeMap::new(),
+            kubeadmConfigPatches: vec![],
         };
 
         if let Some(ecr) = ecr {
-            if let Ok(docker_path) = self.create_docker_ecr_config_file(ecr) {
-                cc.nodes = vec![Kind::kind_node(
-                    "control-plane",
-                    Some("/var/lib/kubelet/config.json"),
-                    Some(&docker_path),
-                )];
+            let auth = self
+                .registry_auth
+                .unwrap_or_else(|| RegistryAuth::default_for(version));
+            match auth {
+                RegistryAuth::Kubelet => {
+                    let docker_path = self.create_docker_ecr_config_file(ecr)?;
+                    cc.nodes = vec![Kind::kind_node(
+                        "control-plane",
+                        Some("/var/lib/kubelet/config.json"),
+                        Some(&docker_path),
+                    )];
+                }
+                RegistryAuth::Containerd => {
+                    let auth = Kind::get_registry_auth(ecr)
+                        .with_context(|| format!("Could not get docker login for {}", ecr))?;
+                    cc.containerdConfigPatches
+                        .push(Kind::get_containerd_config_patch_to_auth(ecr, &auth));
+                }
+                RegistryAuth::Secret => {}
             }
         }
 
         if let Some(local_reg) = local_reg {
-            cc.containerdConfigPatches = vec![Kind::get_containerd_config_patch_to_local_registry(
-                local_reg,
-            )];
+            cc.containerdConfigPatches
+                .push(Kind::get_containerd_config_patch_to_local_registry(
+                    local_reg,
+                ));
+        }
+
+        if self.workers > 0 {
+            if cc.nodes.is_empty() {
+                cc.nodes = vec![Kind::kind_node("control-plane", None, None)];
+            }
+            // workers pull images too, so they get the registry credentials
+            let mounts = cc.nodes[0].extraMounts.clone();
+            for _ in 0..self.workers {
+                let mut worker = Kind::kind_node("worker", None, None);
+                worker.extraMounts = mounts.clone();
+                cc.nodes.push(worker);
+            }
         }
 
-        cc
+        Ok(cc)
     }
 
     fn get_containerd_config_patch_to_local_registry(ip: &str) -> String {
@@ -136,6 +341,62 @@ nodeRegistration:
         )
     }
 
+    fn get_containerd_config_patch_to_auth(registry: &str, auth: &str) -> String {
+        format!(
+            r#"
+[plugins."io.containerd.grpc.v1.cri".registry.configs."{}".auth]
+  auth = "{}""#,
+            registry, auth
+        )
+    }
+
+    /// Script replacing the credentials of a registry in the containerd config
+    /// of a node with the ones on its standard input, so they do not show in
+    /// the logged command, and restarting containerd, which keeps the
+    /// containers running.
+    fn containerd_auth_script(registry: &str) -> String {
+        format!(
+            r#"read -r auth && sed -i "/registry.configs.\"{}\".auth\]/,/auth =/ s|auth = \".*\"|auth = \"$auth\"|" /etc/containerd/config.toml && systemctl restart containerd"#,
+            registry
+        )
+    }
+
+    /// Fetches new credentials of a registry and puts them where the nodes of
+    /// a cluster read them: the docker config.json mounted for the kubelet,
+    /// or the containerd config of every node.
+    pub fn refresh_registry_auth(cluster_name: &str, registry: &str) -> Result<()> {
+        let home = Kind::get_config_dir()?;
+        let docker_config = home.join(cluster_name).join("docker_config");
+        if docker_config.exists() {
+            // written in place, the nodes mount the file and not its directory
+            fs::write(&docker_config, Kind::get_docker_login(registry)?)?;
+            return Ok(());
+        }
+
+        let auth = Kind::get_registry_auth(registry)?;
+        let script = Kind::containerd_auth_script(registry);
+        for (container, _) in Kind::get_node_containers(cluster_name)? {
+            let mut cmd = runtime::command()
+                .args(["exec", "-i", &container, "sh", "-c", &script])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .logged()
+                .spawn()
+                .map_err(|e| spawn_error(runtime::binary(), e))?;
+            writeln!(cmd.stdin.as_mut().unwrap(), "{}", auth)?;
+            let status = cmd.wait()?;
+            if !status.success() {
+                return Err(HakeError::CommandFailed {
+                    command: format!("{} exec {}", runtime::binary(), container),
+                    message: format!("could not update the containerd config: {}", status),
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
     /// Gets the Kind cluster name from the Docker container name.
     fn get_cluster_name(container_name: &str) -> Option<String> {
         if !container_name.ends_with("-control-plane") {
@@ -149,11 +410,14 @@ nodeRegistration:
         }
     }
 
-    // Removes every entry in ~/.hake that does not have a corresponding kind docker container.
+    // Returns the names of the kind clusters with node containers, running or not.
     async fn async_get_containers() -> Result<Vec<String>> {
-        let docker = Docker::connect_with_local_defaults()?;
+        let docker = runtime::connect()?;
         let mut filter = HashMap::new();
-        filter.insert(String::from("status"), vec![String::from("running")]);
+        filter.insert(
+            String::from("label"),
+            vec![String::from("io.x-k8s.kind.cluster")],
+        );
         let containers = &docker
             .list_containers(Some(ListContainersOptions {
                 all: true,
@@ -162,30 +426,163 @@ nodeRegistration:
             }))
             .await?;
 
-        let mut kind_containers = Vec::new();
+        let mut kind_clusters = Vec::new();
         for container in containers {
-            if container.image.starts_with("kindest/node") {
-                let name = String::from(container.names.get(0).unwrap());
-                match Kind::get_cluster_name(&name) {
-                    Some(name) => kind_containers.push(name),
-                    None => continue,
-                }
+            let cluster = match container.labels.get("io.x-k8s.kind.cluster") {
+                Some(cluster) => Some(cluster.clone()),
+                None => container
+                    .names
+                    .first()
+                    .and_then(|name| Kind::get_cluster_name(name)),
+            };
+            if let Some(cluster) = cluster {
+                kind_clusters.push(cluster);
             }
         }
+        kind_clusters.sort();
+        kind_clusters.dedup();
 
-        Ok(kind_containers)
+        Ok(kind_clusters)
     }
 
     pub fn get_kind_containers() -> Result<Vec<String>> {
-        let mut rt = Runtime::new().unwrap();
+        let mut rt = Runtime::new()?;
         rt.block_on(Kind::async_get_containers())
     }
 
-    fn get_docker_login(registry: &str) -> Result<String> {
+    // Returns the name and state of every node container of a cluster, running or not.
+    async fn async_get_node_containers(cluster_name: &str) -> Result<Vec<(String, String)>> {
+        let docker = runtime::connect()?;
+        let mut filter = HashMap::new();
+        filter.insert(
+            String::from("label"),
+            vec![format!("io.x-k8s.kind.cluster={}", cluster_name)],
+        );
+        let containers = &docker
+            .list_containers(Some(ListContainersOptions {
+                all: true,
+                filters: filter,
+                ..Default::default()
+            }))
+            .await?;
+
+        let mut nodes = Vec::new();
+        for container in containers {
+            if let Some(name) = container.names.first() {
+                nodes.push((name.replace("/", ""), container.state.clone()));
+            }
+        }
+        nodes.sort();
+
+        Ok(nodes)
+    }
+
+    pub fn get_node_containers(cluster_name: &str) -> Result<Vec<(String, String)>> {
+        let mut rt = Runtime::new()?;
+        rt.block_on(Kind::async_get_node_containers(cluster_name))
+    }
+
+    fn docker_on_nodes(action: &str, cluster_name: &str) -> Result<()> {
+        let nodes = Kind::get_node_containers(cluster_name)?;
+        if nodes.is_empty() {
+            return Err(anyhow!(
+                "No node containers found for cluster {}",
+                cluster_name
+            ));
+        }
+
+        let status = runtime::command()
+            .arg(action)
+            .args(nodes.iter().map(|(container, _)| container))
+            .stdout(Stdio::null())
+            .logged()
+            .status()
+            .map_err(|e| spawn_error(runtime::binary(), e))?;
+        if !status.success() {
+            return Err(HakeError::CommandFailed {
+                command: format!("{} {}", runtime::binary(), action),
+                message: format!("could not {} nodes of {}", action, cluster_name),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Stops the node containers of a cluster, keeping their state.
+    pub fn stop(cluster_name: &str) -> Result<()> {
+        Kind::docker_on_nodes("stop", cluster_name)
+    }
+
+    /// Starts the node containers of a previously stopped cluster.
+    pub fn start(cluster_name: &str) -> Result<()> {
+        Kind::docker_on_nodes("start", cluster_name)
+    }
+
+    /// Returns the image the control plane node of a cluster is running.
+    pub fn get_node_image(cluster_name: &str) -> Option<String> {
+        let out = runtime::command()
+            .arg("inspect")
+            .arg("-f")
+            .arg("{{.Config.Image}}")
+            .arg(format!("{}-control-plane", cluster_name))
+            .logged()
+            .output()
+            .ok()?;
+        if !out.status.success() {
+            return None;
+        }
+
+        Some(String::from_utf8(out.stdout).ok()?.trim().to_string())
+    }
+
+    /// Returns the IPv4 subnet of the docker network the kind nodes are attached to.
+    pub fn get_network_subnet(network: &str) -> Result<String> {
+        let format = if runtime::is_podman() {
+            "{{range .Subnets}}{{.Subnet}} {{end}}"
+        } else {
+            "{{range .IPAM.Config}}{{.Subnet}} {{end}}"
+        };
+        let out = runtime::command()
+            .args(["network", "inspect", network, "-f", format])
+            .logged()
+            .output()
+            .map_err(|e| spawn_error(runtime::binary(), e))?;
+
+        String::from_utf8(out.stdout)?
+            .split_whitespace()
+            .find(|subnet| !subnet.contains(':'))
+            .map(String::from)
+            .ok_or_else(|| anyhow!("Could not find the IPv4 subnet of the {} network", network))
+    }
+
+    /// Whether the kind docker network exists with no containers attached.
+    pub fn network_unused() -> bool {
+        match runtime::command()
+            .args(["network", "inspect", "kind", "-f", "{{len .Containers}}"])
+            .logged()
+            .output()
+        {
+            Ok(out) => out.status.success() && String::from_utf8_lossy(&out.stdout).trim() == "0",
+            Err(_) => false,
+        }
+    }
+
+    pub fn remove_network() -> Result<()> {
+        Kind::docker(&["network", "rm", "kind"])
+    }
+
+    /// Returns the base64 encoded user:secret for a registry.
+    fn get_registry_auth(registry: &str) -> Result<String> {
         let creds = Kind::get_docker_credentials_from_helper(registry)?;
 
         let login: DockerLogin = serde_json::from_str(&creds)?;
-        let encoded = encode(&format!("{}:{}", login.Username, login.Secret));
+        Ok(encode(&format!("{}:{}", login.Username, login.Secret)))
+    }
+
+    /// docker config.json with the credentials of a registry.
+    pub fn get_docker_login(registry: &str) -> Result<String> {
+        let encoded = Kind::get_registry_auth(registry)?;
 
         Ok(json!({
                 "auths": {
@@ -203,10 +600,9 @@ nodeRegistration:
             .arg("get")
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
+            .logged()
             .spawn()
-            .unwrap_or_else(|_| {
-                panic!("Could not find docker credentials helper for {}", registry)
-            });
+            .map_err(|e| spawn_error("docker-credential-ecr-login", e))?;
 
         cmd.stdin.as_mut().unwrap().write_all(registry.as_bytes())?;
         cmd.wait()?;
@@ -218,10 +614,16 @@ nodeRegistration:
     }
 
     fn create_docker_ecr_config_file(&self, ecr: &str) -> Result<String> {
-        let docker_login = Kind::get_docker_login(ecr).expect("could not get docker login");
+        let docker_login = Kind::get_docker_login(ecr)
+            .with_context(|| format!("Could not get docker login for {}", ecr))?;
 
         // save docker_login()
-        let docker_config_path = format!("{}/docker_config", self.config_dir);
+        let docker_config_path = self.config_file("docker_config");
+        debug!("docker config: {}", redact_auth(&docker_login));
+        if dryrun::enabled() {
+            dryrun::print_file(&docker_config_path, &redact_auth(&docker_login));
+            return Ok(docker_config_path);
+        }
         let mut docker_config = File::create(&docker_config_path)?;
         docker_config.write_all(&docker_login.into_bytes())?;
 
@@ -231,23 +633,24 @@ nodeRegistration:
     fn create_dirs(cluster_name: &str) -> Result<()> {
         let home = Kind::get_config_dir()?;
 
-        if !Path::new(&home).exists() {
+        if !home.exists() {
             create_dir(&home)?;
         }
-        create_dir(format!("{}/{}", &home, cluster_name))?;
+        create_dir(home.join(cluster_name))?;
 
         Ok(())
     }
 
-    pub fn get_config_dir() -> Result<String> {
-        let home = String::from(
-            dirs::home_dir()
-                .expect("User does not have a home")
-                .to_str()
-                .unwrap(),
-        );
+    pub fn get_config_dir() -> Result<PathBuf> {
+        let home = dirs::home_dir().ok_or(HakeError::NoHome)?;
+
+        Ok(home.join(".hake"))
+    }
 
-        Ok(format!("{}/.hake", home))
+    /// Path of a file in the config directory of the cluster, as an argument
+    /// for kind.
+    fn config_file(&self, file: &str) -> String {
+        self.config_dir.join(file).to_string_lossy().into_owned()
     }
 
     pub fn configure_private_registry(&mut self, reg: Option<String>) {
@@ -258,178 +661,1427 @@ nodeRegistration:
         self.verbose = verbose;
     }
 
-    fn find_local_registry(container_name: &str) -> Option<String> {
-        let ip = Command::new("docker")
+    /// Adds worker nodes next to the control plane.
+    pub fn set_workers(&mut self, workers: u16) {
+        self.workers = workers;
+    }
+
+    /// Picks how nodes authenticate to the ECR registry, instead of the
+    /// default for the kind release.
+    pub fn set_registry_auth(&mut self, mode: &str) -> Result<()> {
+        self.registry_auth = Some(RegistryAuth::parse(mode)?);
+        Ok(())
+    }
+
+    /// Uses a different kindest/node image, to pick the Kubernetes version.
+    pub fn set_node_image(&mut self, image: &str) {
+        self.node_image = Some(String::from(image));
+    }
+
+    /// Attaches the nodes to an existing network instead of the kind one, so
+    /// other containers on it reach the cluster by name.
+    pub fn set_network(&mut self, network: &str) {
+        self.network = Some(String::from(network));
+    }
+
+    /// Network the nodes of a cluster are attached to.
+    pub fn cluster_network(cluster_name: &str) -> String {
+        ClusterMetadata::read(cluster_name)
+            .and_then(|metadata| metadata.options)
+            .and_then(|opts| opts.network)
+            .unwrap_or_else(|| String::from(runtime::DEFAULT_NETWORK))
+    }
+
+    /// Runs the nodes on another architecture than the one of the docker
+    /// host, emulated.
+    pub fn set_arch(&mut self, arch: &str) {
+        self.arch = Some(String::from(arch));
+    }
+
+    fn find_local_registry(container_name: &str) -> Result<String> {
+        let ip = runtime::command()
             .arg("inspect")
             .arg("-f")
             .arg("{{.NetworkSettings.IPAddress}}")
             .arg(container_name)
+            .logged()
             .output()
-            .expect(&format!(
-                "Could not get IP from {} container",
-                container_name
-            ));
+            .map_err(|e| spawn_error(runtime::binary(), e))?;
+        if !ip.status.success() {
+            return Err(HakeError::CommandFailed {
+                command: format!("{} inspect", runtime::binary()),
+                message: format!("could not get IP from {} container", container_name),
+            }
+            .into());
+        }
 
-        Some(String::from_utf8(ip.stdout).unwrap().trim().to_string())
+        Ok(String::from_utf8(ip.stdout)?.trim().to_string())
     }
 
-    pub fn use_local_registry(&mut self, container_name: &str) {
-        self.local_registry = Kind::find_local_registry(container_name);
+    pub fn use_local_registry(&mut self, container_name: &str) -> Result<()> {
+        // podman networks do not route to each other, the registry joins the
+        // kind network once it exists and the nodes reach it by name
+        if runtime::is_podman() {
+            self.local_registry = Some(String::from(container_name));
+            self.registry_container = Some(String::from(container_name));
+            return Ok(());
+        }
+        self.local_registry = Some(Kind::find_local_registry(container_name)?);
+
+        Ok(())
     }
 
     pub fn extra_port_mapping(&mut self, extra_port_mapping: &str) {
         self.extra_port_mapping = Some(String::from(extra_port_mapping));
     }
 
-    /// receives a string like: 80:80:TCP or 80:80 or 80
-    fn parse_extra_port_mappings(epm: &str) -> Option<PortMapping> {
-        let mut container_port = 0;
-        let mut host_port = 0;
-        let mut proto = String::from("TCP");
+    /// Adds a port mapping from the host to the control plane node.
+    pub fn add_port_mapping(&mut self, port: &str) -> Result<()> {
+        self.port_mappings.push(Kind::parse_port_mapping(port)?);
 
-        let re0 = Regex::new(r"^(\d{2}):(\d{2}):(TCP|HTTP)$").unwrap();
-        let re1 = Regex::new(r"^(\d{2}):(\d{2})$").unwrap();
-        let re2 = Regex::new(r"^(\d+)$").unwrap();
+        Ok(())
+    }
 
-        if re0.is_match(epm) {
-            let cap = re0.captures(epm).unwrap();
-            container_port = cap[1].parse::<u32>().unwrap();
-            host_port = cap[2].parse::<u32>().unwrap();
-            proto = String::from(&cap[3]);
-        } else if let Some(cap) = re1.captures(epm) {
-            container_port = cap[1].parse::<u32>().unwrap();
-            host_port = cap[2].parse::<u32>().unwrap();
-        } else if let Some(cap) = re2.captures(epm) {
-            container_port = cap[1].parse::<u32>().unwrap();
-            host_port = container_port;
+    /// receives a string like: SomeFeature=true
+    fn parse_feature_gate(gate: &str) -> Result<(String, bool)> {
+        let parts: Vec<&str> = gate.split('=').collect();
+        if parts.len() != 2 || parts[0].is_empty() {
+            return Err(anyhow!("Invalid feature gate: {}", gate));
         }
+        let enabled = parts[1]
+            .parse::<bool>()
+            .map_err(|_| anyhow!("Invalid feature gate value: {}", gate))?;
 
-        if container_port != 0 {
-            Some(PortMapping {
-                containerPort: container_port,
-                hostPort: host_port,
-                protocol: proto,
-            })
-        } else {
-            None
-        }
+        Ok((String::from(parts[0]), enabled))
     }
 
-    pub fn create(self) -> Result<()> {
-        Kind::create_dirs(&self.name)?;
+    pub fn add_feature_gate(&mut self, gate: &str) -> Result<()> {
+        let (name, enabled) = Kind::parse_feature_gate(gate)?;
+        self.feature_gates.insert(name, enabled);
 
-        let mut args = vec!["create", "cluster"];
-        let kubeconfig;
+        Ok(())
+    }
 
-        args.push("--name");
-        args.push(&self.name);
+    /// Adds a kubeadm config patch, read from a file, applied to every node.
+    pub fn add_kubeadm_patch(&mut self, path: &str) -> Result<()> {
+        let mut patch = String::new();
+        File::open(path)
+            .map_err(|e| anyhow!("Could not read kubeadm patch {}: {}", path, e))?
+            .read_to_string(&mut patch)?;
+        self.kubeadm_patches.push(patch);
 
-        kubeconfig = format!("{}/kubeconfig", self.config_dir);
-        args.push("--kubeconfig");
-        args.push(&kubeconfig);
+        Ok(())
+    }
 
-        args.push("--config");
-        let mut kind_config = self.get_kind_cluster_config(&self.ecr_repo, &self.local_registry);
-        if let Some(extra_port_mapping) = self.extra_port_mapping {
-            let epm = Kind::parse_extra_port_mappings(&extra_port_mapping);
-            if let Some(epm) = epm {
-                if let Some(mut node) = kind_config.nodes.get_mut(0) {
-                    node.extraPortMappings = vec![epm];
-                } else {
-                    let mut nn = Kind::kind_node("control-plane", None, None);
-                    nn.extraPortMappings = vec![epm];
-                    kind_config.nodes = vec![nn];
-                };
-                kind_config.nodes[0].kubeadmConfigPatches = vec![Kind::init_config_ingress_ready()];
+    /// Enables API server audit logging with the policy in a file.
+    pub fn set_audit_policy(&mut self, path: &str) -> Result<()> {
+        let policy = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Could not read audit policy {}: {}", path, e))?;
+        self.audit_policy = Some(policy);
+
+        Ok(())
+    }
+
+    /// Encrypts secrets at rest with a key generated for the cluster.
+    pub fn encrypt_secrets(&mut self) {
+        self.encrypt_secrets = true;
+    }
+
+    /// kubeadm patch pointing the API server to the audit policy and the
+    /// encryption configuration in API_SERVER_DIR.
+    fn api_server_patch(audit: bool, encryption: bool) -> String {
+        let mut args = String::new();
+        let mut volumes = format!(
+            r#"
+  - name: hake
+    hostPath: {dir}
+    mountPath: {dir}
+    readOnly: true
+    pathType: Directory"#,
+            dir = API_SERVER_DIR
+        );
+        if audit {
+            args.push_str(&format!(
+                r#"
+    audit-policy-file: {}/audit-policy.yaml
+    audit-log-path: {}/audit.log"#,
+                API_SERVER_DIR, AUDIT_LOG_DIR
+            ));
+            volumes.push_str(&format!(
+                r#"
+  - name: audit-logs
+    hostPath: {dir}
+    mountPath: {dir}
+    pathType: DirectoryOrCreate"#,
+                dir = AUDIT_LOG_DIR
+            ));
+        }
+        if encryption {
+            args.push_str(&format!(
+                r#"
+    encryption-provider-config: {}/encryption.yaml"#,
+                API_SERVER_DIR
+            ));
+        }
+
+        format!(
+            r#"kind: ClusterConfiguration
+apiServer:
+  extraArgs:{}
+  extraVolumes:{}"#,
+            args, volumes
+        )
+    }
+
+    fn encryption_config(key: &str) -> String {
+        format!(
+            r#"apiVersion: apiserver.config.k8s.io/v1
+kind: EncryptionConfiguration
+resources:
+  - resources:
+      - secrets
+    providers:
+      - aescbc:
+          keys:
+            - name: hake
+              secret: {}
+      - identity: {{}}
+"#,
+            key
+        )
+    }
+
+    /// Writes the audit policy and the encryption configuration to the config
+    /// directory of the cluster, mounts them in the control plane and returns
+    /// the kubeadm patch using them.
+    fn configure_api_server(&self, kind_config: &mut ClusterConfig) -> Result<Option<String>> {
+        if self.audit_policy.is_none() && !self.encrypt_secrets {
+            return Ok(None);
+        }
+
+        let dir = self.config_dir.join("apiserver");
+        let mut files = vec![];
+        if let Some(policy) = &self.audit_policy {
+            files.push(("audit-policy.yaml", policy.clone(), policy.clone()));
+        }
+        if self.encrypt_secrets {
+            let mut key = [0u8; 32];
+            getrandom::getrandom(&mut key)
+                .map_err(|e| anyhow!("Could not generate an encryption key: {}", e))?;
+            files.push((
+                "encryption.yaml",
+                Kind::encryption_config(&encode(&key)),
+                Kind::encryption_config("<redacted>"),
+            ));
+        }
+        for (file, contents, redacted) in files {
+            let path = dir.join(file);
+            if dryrun::enabled() {
+                dryrun::print_file(&path.to_string_lossy(), &redacted);
+                continue;
             }
+            create_dir_all(&dir)?;
+            fs::write(&path, contents)?;
         }
 
-        let kind_cluster_config = serde_yaml::to_string(&kind_config)?;
+        if kind_config.nodes.is_empty() {
+            kind_config.nodes = vec![Kind::kind_node("control-plane", None, None)];
+        }
+        let mut mounts = vec![ExtraMount {
+            containerPath: String::from(API_SERVER_DIR),
+            hostPath: dir.to_string_lossy().into_owned(),
+            readOnly: Some(true),
+        }];
+        if self.audit_policy.is_some() {
+            mounts.push(ExtraMount {
+                containerPath: String::from(AUDIT_LOG_DIR),
+                hostPath: self.config_file("audit"),
+                readOnly: None,
+            });
+        }
+        for node in kind_config.nodes.iter_mut() {
+            if node.role == "control-plane" {
+                node.extraMounts.extend(mounts.iter().cloned());
+            }
+        }
 
-        let kind_config_path = format!("{}/kind_config", self.config_dir);
-        let mut kind_config = File::create(&kind_config_path)?;
-        kind_config.write_all(&kind_cluster_config.into_bytes())?;
+        Ok(Some(Kind::api_server_patch(
+            self.audit_policy.is_some(),
+            self.encrypt_secrets,
+        )))
+    }
 
-        // point the config file to the one we just saved
-        args.push(&kind_config_path);
+    /// receives a string like: /host/path:/container/path or /host/path:/container/path:ro,
+    /// or C:\host\path:/container/path on Windows
+    fn parse_mount(mount: &str) -> Result<ExtraMount> {
+        // the drive letter of Windows paths is not a separator
+        let (drive, rest) = match mount.as_bytes() {
+            [letter, b':', separator, ..]
+                if letter.is_ascii_alphabetic() && (*separator == b'\\' || *separator == b'/') =>
+            {
+                mount.split_at(2)
+            }
+            _ => ("", mount),
+        };
+        let parts: Vec<&str> = rest.split(':').collect();
+        let read_only = match parts.len() {
+            2 => None,
+            3 if parts[2] == "ro" => Some(true),
+            3 if parts[2] == "rw" => Some(false),
+            _ => return Err(anyhow!("Invalid mount: {}", mount)),
+        };
+        if parts[0].is_empty() || !parts[1].starts_with('/') {
+            return Err(anyhow!("Invalid mount: {}", mount));
+        }
 
-        Kind::run(&args, self.verbose)?;
+        let host_path = format!("{}{}", drive, parts[0]);
+        let host_path = if Path::new(&host_path).is_absolute() {
+            host_path
+        } else {
+            env::current_dir()?
+                .join(host_path)
+                .to_string_lossy()
+                .into_owned()
+        };
 
-        let config_dir = Kind::get_config_dir()?;
-        let config_dir = format!("{}/{}/kind_args", config_dir, &self.name);
-        let mut saved_args = File::create(config_dir)?;
-        saved_args.write_all(args.join(" ").as_bytes())?;
+        Ok(ExtraMount {
+            containerPath: String::from(parts[1]),
+            hostPath: host_path,
+            readOnly: read_only,
+        })
+    }
+
+    /// Mounts a host directory into every node of the cluster.
+    pub fn add_mount(&mut self, mount: &str) -> Result<()> {
+        self.mounts.push(Kind::parse_mount(mount)?);
 
         Ok(())
     }
 
-    pub fn run(args: &Vec<&str>, verbose: bool) -> Result<()> {
-        let mut command = Command::new("kind");
-        command.args(args);
-        if verbose {
-            command.spawn()?.wait()?;
-        } else {
-            command.output()?;
+    /// Mounts a host directory in every node to back persistent volumes,
+    /// creating it if needed.
+    pub fn use_host_volumes(&mut self, dir: &str) -> Result<()> {
+        if !dryrun::enabled() {
+            create_dir_all(dir)?;
         }
+        let host_path = fs::canonicalize(dir).unwrap_or_else(|_| PathBuf::from(dir));
+        self.mounts.push(ExtraMount {
+            containerPath: String::from(HOST_PV_DIR),
+            hostPath: host_path.to_string_lossy().into_owned(),
+            readOnly: None,
+        });
 
         Ok(())
     }
 
-    pub fn recreate(name: &str, verbose: bool) -> Result<()> {
-        let config_dir = format!("{}/{}", Kind::get_config_dir()?, name);
-        let args_file = format!("{}/kind_args", config_dir);
-
-        let mut contents = String::new();
-        let mut saved_args = File::open(args_file)?;
-        saved_args.read_to_string(&mut contents)?;
+    /// Pulls Docker Hub images through the image cache shared by the clusters.
+    pub fn use_image_cache(&mut self) {
+        self.image_cache = true;
+    }
 
-        Kind::delete_cluster(name)?;
+    /// Exposes the GPUs of the host to the nodes through the NVIDIA runtime.
+    pub fn enable_gpus(&mut self) {
+        self.gpus = true;
+    }
 
-        let args: Vec<&str> = contents.split_ascii_whitespace().collect();
-        Kind::run(&args, verbose)?;
+    /// The NVIDIA runtime has to be docker's default one for kind nodes to
+    /// get GPUs.
+    fn check_nvidia_runtime() -> Result<()> {
+        let out = runtime::command()
+            .args(["info", "--format", "{{.DefaultRuntime}}"])
+            .logged()
+            .output()
+            .map_err(|e| spawn_error(runtime::binary(), e))?;
+        let runtime = String::from_utf8_lossy(&out.stdout).trim().to_string();
+        if runtime != "nvidia" {
+            return Err(anyhow!(
+                "GPUs need nvidia as the default docker runtime, but it is {}. Run `sudo nvidia-ctk runtime configure --runtime=docker --set-as-default` and set accept-nvidia-visible-devices-as-volume-mounts = true in /etc/nvidia-container-runtime/config.toml",
+                runtime
+            ));
+        }
 
         Ok(())
     }
 
-    pub fn delete(&self) -> Result<()> {
-        Kind::delete_cluster(&self.name)?;
-
-        remove_dir_all(&self.config_dir)?;
+    /// Installs the NVIDIA container toolkit in the nodes of a cluster created
+    /// with GPUs.
+    pub fn configure_gpus(cluster_name: &str) -> Result<()> {
+        for (node, _) in Kind::get_node_containers(cluster_name)? {
+            let out = runtime::command()
+                .args(["exec", &node, "bash", "-c", NVIDIA_NODE_SETUP])
+                .logged()
+                .output()
+                .map_err(|e| spawn_error(runtime::binary(), e))?;
+            if !out.status.success() {
+                return Err(HakeError::CommandFailed {
+                    command: format!("{} exec {}", runtime::binary(), node),
+                    message: format!(
+                        "could not install the NVIDIA container toolkit: {}",
+                        String::from_utf8_lossy(&out.stderr).trim()
+                    ),
+                }
+                .into());
+            }
+        }
 
         Ok(())
     }
 
-    fn delete_cluster(name: &str) -> Result<()> {
-        let mut args = vec!["delete", "cluster"];
-        args.push("--name");
-        args.push(name);
+    /// Creates the cluster without kindnet, leaving room for another CNI.
+    pub fn disable_default_cni(&mut self) {
+        self.disable_default_cni = true;
+    }
 
-        let _cmd = Command::new("kind").args(args).output()?;
+    /// Creates the cluster with ipv4, ipv6 or dual-stack networking.
+    pub fn set_ip_family(&mut self, family: &str) -> Result<()> {
+        if !IP_FAMILIES.contains(&family) {
+            return Err(anyhow!(
+                "Unknown IP family: {}, use {}",
+                family,
+                IP_FAMILIES.join(", ")
+            ));
+        }
+        self.ip_family = Some(String::from(family));
+        Ok(())
+    }
 
+    /// Uses a pod subnet other than kind's default 10.244.0.0/16.
+    pub fn set_pod_subnet(&mut self, subnet: &str) -> Result<()> {
+        self.pod_subnet = Some(Kind::parse_subnet(subnet)?);
         Ok(())
     }
 
-    pub fn new(name: &str) -> Kind {
-        let config = Kind::get_config_dir();
-        if config.is_err() {
-            panic!("User has no home!!");
+    /// Uses a service subnet other than kind's default 10.96.0.0/12.
+    pub fn set_service_subnet(&mut self, subnet: &str) -> Result<()> {
+        self.service_subnet = Some(Kind::parse_subnet(subnet)?);
+        Ok(())
+    }
+
+    /// Checks a subnet in CIDR notation, like 10.10.0.0/16 or fd00:10::/56.
+    fn parse_subnet(subnet: &str) -> Result<String> {
+        let invalid = || {
+            anyhow!(
+                "Invalid subnet: {}, use CIDR notation like 10.10.0.0/16",
+                subnet
+            )
+        };
+        let (address, prefix) = subnet.split_once('/').ok_or_else(invalid)?;
+        let address = address.parse::<IpAddr>().map_err(|_| invalid())?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        match prefix.parse::<u8>() {
+            Ok(prefix) if prefix <= max_prefix => Ok(String::from(subnet)),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Labels the control plane node as ready for an ingress controller and maps
+    /// the host HTTP and HTTPS ports to it.
+    pub fn with_ingress(&mut self) {
+        self.ingress_ready = true;
+        for port in &[80, 443] {
+            if !self.port_mappings.iter().any(|pm| pm.hostPort == *port) {
+                self.port_mappings.push(PortMapping {
+                    containerPort: *port,
+                    hostPort: *port,
+                    protocol: String::from("TCP"),
+                });
+                self.ingress_ports.push(*port);
+            }
+        }
+    }
+
+    /// Moves the ports mapped for the ingress controller below `start` to
+    /// unprivileged ones, 8080 and 8443, which a rootless runtime can bind.
+    fn unprivileged_ingress_ports(&mut self, start: u32) {
+        for pm in self.port_mappings.iter_mut() {
+            if self.ingress_ports.contains(&pm.hostPort) && pm.hostPort < start {
+                let port = pm.hostPort + 8000;
+                info!(
+                    "The runtime is rootless, the ingress controller is on port {} instead of {}",
+                    Style::new().cyan().apply_to(port),
+                    pm.hostPort
+                );
+                pm.hostPort = port;
+            }
+        }
+    }
+
+    /// Fails for what the nodes of a rootless runtime cannot do: bind ports
+    /// below `start`, mount paths the user cannot read and use GPUs.
+    fn check_rootless(nodes: &[Node], start: u32, gpus: bool) -> Result<()> {
+        if gpus {
+            return Err(anyhow!(
+                "--gpus needs a rootful runtime, the NVIDIA runtime cannot run rootless"
+            ));
+        }
+        let ports: Vec<u32> = nodes
+            .iter()
+            .flat_map(|node| node.extraPortMappings.iter().map(|pm| pm.hostPort))
+            .collect();
+        rootless::check_ports(&ports, start)?;
+        // the files of the cluster are not written in a dry run
+        if !dryrun::enabled() {
+            let mounts: Vec<&str> = nodes
+                .iter()
+                .flat_map(|node| node.extraMounts.iter().map(|m| m.hostPath.as_str()))
+                .collect();
+            rootless::check_mounts(&mounts)?;
+        }
+
+        Ok(())
+    }
+
+    /// receives a string like: 8080:30080/udp or 8080:30080 or 80, with the
+    /// host port first and the container port second.
+    fn parse_port_mapping(port: &str) -> Result<PortMapping> {
+        let re = Regex::new(r"^(\d+)(?::(\d+))?(?:/(tcp|udp|sctp))?$").unwrap();
+        let cap = re
+            .captures(port)
+            .ok_or_else(|| anyhow!("Invalid port mapping: {}", port))?;
+
+        let host_port = cap[1].parse::<u32>()?;
+        let container_port = match cap.get(2) {
+            Some(container_port) => container_port.as_str().parse::<u32>()?,
+            None => host_port,
+        };
+        let protocol = match cap.get(3) {
+            Some(protocol) => protocol.as_str().to_uppercase(),
+            None => String::from("TCP"),
+        };
+
+        if host_port == 0 || host_port > 65535 || container_port == 0 || container_port > 65535 {
+            return Err(anyhow!("Invalid port mapping: {}", port));
+        }
+
+        Ok(PortMapping {
+            containerPort: container_port,
+            hostPort: host_port,
+            protocol,
+        })
+    }
+
+    /// receives a string like: 80:80:TCP or 80:80 or 80
+    fn parse_extra_port_mappings(epm: &str) -> Option<PortMapping> {
+        let mut container_port = 0;
+        let mut host_port = 0;
+        let mut proto = String::from("TCP");
+
+        let re0 = Regex::new(r"^(\d{2}):(\d{2}):(TCP|HTTP)$").unwrap();
+        let re1 = Regex::new(r"^(\d{2}):(\d{2})$").unwrap();
+        let re2 = Regex::new(r"^(\d+)$").unwrap();
+
+        if re0.is_match(epm) {
+            let cap = re0.captures(epm).unwrap();
+            container_port = cap[1].parse::<u32>().unwrap();
+            host_port = cap[2].parse::<u32>().unwrap();
+            proto = String::from(&cap[3]);
+        } else if let Some(cap) = re1.captures(epm) {
+            container_port = cap[1].parse::<u32>().unwrap();
+            host_port = cap[2].parse::<u32>().unwrap();
+        } else if let Some(cap) = re2.captures(epm) {
+            container_port = cap[1].parse::<u32>().unwrap();
+            host_port = container_port;
+        }
+
+        if container_port != 0 {
+            Some(PortMapping {
+                containerPort: container_port,
+                hostPort: host_port,
+                protocol: proto,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Fails when kind already has a cluster with this name, one hake does
+    /// not manage. Returns whether it could tell there is none, which it
+    /// cannot when the container runtime does not answer.
+    pub fn check_name_free(name: &str) -> Result<bool> {
+        match Kind::get_node_containers(name) {
+            Ok(nodes) if !nodes.is_empty() => Err(anyhow!(
+                "A kind cluster named {} exists but is not managed by hake, remove it with `kind delete cluster --name {}`",
+                name,
+                name
+            )),
+            Ok(_) => Ok(true),
+            Err(e) => {
+                debug!("Could not list the node containers of {}: {:#}", name, e);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Creates the cluster. When that fails, the node containers this create
+    /// made and the directory of the cluster are removed, so creating it
+    /// again does not stumble on them. Nodes that were there before are
+    /// never deleted.
+    pub fn create(self) -> Result<()> {
+        if dryrun::enabled() {
+            return self.provision();
+        }
+        let free = Kind::check_name_free(&self.name)?;
+        Kind::create_dirs(&self.name)?;
+        let name = self.name.clone();
+        let config_dir = self.config_dir.clone();
+
+        let created = self.provision();
+        if let Err(e) = &created {
+            if free && !already_exists(e) {
+                if let Err(e) = Kind::delete_cluster(&name) {
+                    warn!("Could not delete the kind cluster {}: {:#}", name, e);
+                }
+            }
+            if let Err(e) = remove_dir_all(&config_dir) {
+                warn!("Could not remove {}: {:#}", config_dir.display(), e);
+            }
+        }
+
+        created
+    }
+
+    fn provision(mut self) -> Result<()> {
+        let started = Instant::now();
+
+        let unprivileged_port_start = if rootless::enabled() {
+            Some(rootless::unprivileged_port_start())
+        } else {
+            None
+        };
+        if let Some(start) = unprivileged_port_start {
+            self.unprivileged_ingress_ports(start);
+        }
+
+        if let Some(network) = &self.network {
+            if !dryrun::enabled() && !runtime::network_exists(network) {
+                return Err(anyhow!(
+                    "Network {} does not exist, create it with `{} network create {}`",
+                    network,
+                    runtime::binary(),
+                    network
+                ));
+            }
+            runtime::use_network(network);
+        }
+
+        let kubeconfig = self.config_file("kubeconfig");
+        let mut args = vec!["create", "cluster"];
+
+        args.push("--name");
+        args.push(&self.name);
+
+        let kind_config_path = self.config_file("kind_config");
+        args.push("--kubeconfig");
+        args.push(&kubeconfig);
+
+        if let Some(image) = &self.node_image {
+            args.push("--image");
+            args.push(image);
+        }
+        if dryrun::enabled() {
+            if let Some(arch) = &self.arch {
+                dryrun::print(&format!("would pull the node image for {}", arch));
+            }
+        } else {
+            arch::prepare_node_image(self.node_image.as_deref(), self.arch.as_deref())?;
+        }
+
+        args.push("--config");
+        let version = Kind::version().ok().and_then(|v| Kind::parse_version(&v));
+        let mut kind_config =
+            self.get_kind_cluster_config(&self.ecr_repo, &self.local_registry, version)?;
+        let api_server_patch = self.configure_api_server(&mut kind_config)?;
+        if self.image_cache {
+            kind_config
+                .containerdConfigPatches
+                .push(image_cache::containerd_config_patch());
+            if dryrun::enabled() {
+                dryrun::print(&format!(
+                    "would start the {} container",
+                    image_cache::CONTAINER
+                ));
+            } else {
+                image_cache::start()?;
+            }
+        }
+        if self.gpus {
+            if !dryrun::enabled() {
+                Kind::check_nvidia_runtime()?;
+            }
+            if kind_config.nodes.is_empty() {
+                kind_config.nodes = vec![Kind::kind_node("control-plane", None, None)];
+            }
+            for node in kind_config.nodes.iter_mut() {
+                node.extraMounts.push(ExtraMount {
+                    containerPath: String::from(NVIDIA_DEVICES_MOUNT),
+                    hostPath: String::from("/dev/null"),
+                    readOnly: None,
+                });
+            }
+        }
+        if let Some(extra_port_mapping) = self.extra_port_mapping {
+            let epm = Kind::parse_extra_port_mappings(&extra_port_mapping);
+            if let Some(epm) = epm {
+                if let Some(node) = kind_config.nodes.get_mut(0) {
+                    node.extraPortMappings = vec![epm];
+                } else {
+                    let mut nn = Kind::kind_node("control-plane", None, None);
+                    nn.extraPortMappings = vec![epm];
+                    kind_config.nodes = vec![nn];
+                };
+                kind_config.nodes[0].kubeadmConfigPatches = vec![Kind::init_config_ingress_ready()];
+            }
+        }
+        if !self.port_mappings.is_empty() {
+            if kind_config.nodes.is_empty() {
+                kind_config.nodes = vec![Kind::kind_node("control-plane", None, None)];
+            }
+            kind_config.nodes[0]
+                .extraPortMappings
+                .extend(self.port_mappings);
+        }
+        if !self.mounts.is_empty() {
+            if kind_config.nodes.is_empty() {
+                kind_config.nodes = vec![Kind::kind_node("control-plane", None, None)];
+            }
+            for node in kind_config.nodes.iter_mut() {
+                node.extraMounts.extend(self.mounts.iter().cloned());
+            }
+        }
+        if kind_config.apiVersion != config_api_version(None) && !self.feature_gates.is_empty() {
+            return Err(anyhow!(
+                "Feature gates need kind v0.8.0 or newer, upgrade kind or use --kind-version"
+            ));
+        }
+        kind_config.featureGates = self.feature_gates;
+        kind_config.kubeadmConfigPatches = self.kubeadm_patches;
+        kind_config.kubeadmConfigPatches.extend(api_server_patch);
+        if self.disable_default_cni {
+            kind_config
+                .networking
+                .get_or_insert_with(Networking::default)
+                .disableDefaultCNI = Some(true);
+        }
+        if let Some(family) = self.ip_family {
+            if family == "dual" && matches!(version, Some(v) if v < DUAL_STACK_KIND_VERSION) {
+                return Err(anyhow!(
+                    "Dual-stack clusters need kind v0.11.0 or newer, upgrade kind or use --kind-version"
+                ));
+            }
+            kind_config
+                .networking
+                .get_or_insert_with(Networking::default)
+                .ipFamily = Some(family);
+        }
+        if self.pod_subnet.is_some() || self.service_subnet.is_some() {
+            let networking = kind_config
+                .networking
+                .get_or_insert_with(Networking::default);
+            networking.podSubnet = self.pod_subnet;
+            networking.serviceSubnet = self.service_subnet;
+        }
+        if self.ingress_ready {
+            if kind_config.nodes.is_empty() {
+                kind_config.nodes = vec![Kind::kind_node("control-plane", None, None)];
+            }
+            kind_config.nodes[0].kubeadmConfigPatches = vec![Kind::init_config_ingress_ready()];
+        }
+        if let Some(start) = unprivileged_port_start {
+            Kind::check_rootless(&kind_config.nodes, start, self.gpus)?;
+        }
+
+        let kind_cluster_config = serde_yaml::to_string(&kind_config)?;
+        debug!("kind config:\n{}", redact_auth(&kind_cluster_config));
+
+        if dryrun::enabled() {
+            dryrun::print_file(&kind_config_path, &redact_auth(&kind_cluster_config));
+            args.push(&kind_config_path);
+            dryrun::print(&format!("would run: kind {}", args.join(" ")));
+            return Ok(());
+        }
+        let mut kind_config = File::create(&kind_config_path)?;
+        kind_config.write_all(&kind_cluster_config.into_bytes())?;
+
+        // point the config file to the one we just saved
+        args.push(&kind_config_path);
+
+        bench::record(bench::CONFIG, started.elapsed());
+        let started = Instant::now();
+        Kind::run(&args, self.verbose)?;
+        bench::record(bench::KIND_CREATE, started.elapsed());
+        let network = self.network.as_deref().unwrap_or(runtime::DEFAULT_NETWORK);
+        if self.image_cache {
+            image_cache::connect(network)?;
+        }
+        if let Some(container) = &self.registry_container {
+            runtime::connect_network(container, network)?;
+        }
+
+        Kind::save_args(&self.config_dir, &args)
+    }
+
+    // One argument per line, paths can have spaces on Windows and macOS.
+    fn save_args(config_dir: &Path, args: &[&str]) -> Result<()> {
+        let mut saved_args = File::create(config_dir.join("kind_args"))?;
+        saved_args.write_all(args.join("\n").as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Pulls an image on the host, unless it is there already.
+    pub fn pull_image(image: &str) -> Result<()> {
+        let present = runtime::command()
+            .args(["image", "inspect", image])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .logged()
+            .status()
+            .map_err(|e| spawn_error(runtime::binary(), e))?
+            .success();
+        if !present {
+            Kind::docker(&["pull", image])?;
+        }
+
+        Ok(())
+    }
+
+    /// Pulls images on the host, unless they are there already, and loads
+    /// them into every node of a cluster.
+    pub fn preload(cluster_name: &str, images: &[String], verbose: bool) -> Result<()> {
+        for image in images {
+            Kind::pull_image(image)?;
+        }
+
+        // kind saves the images with the docker CLI, on podman they are
+        // loaded from an archive instead
+        if runtime::is_podman() {
+            let archive = env::temp_dir().join(format!(
+                "hake-preload-{}-{}.tar",
+                cluster_name,
+                std::process::id()
+            ));
+            let archive_path = archive.to_string_lossy().to_string();
+            let mut save = vec!["save", "-m", "-o", &archive_path];
+            save.extend(images.iter().map(|image| image.as_str()));
+            Kind::docker(&save)?;
+            let loaded = Kind::run(
+                &vec![
+                    "load",
+                    "image-archive",
+                    "--name",
+                    cluster_name,
+                    &archive_path,
+                ],
+                verbose,
+            );
+            fs::remove_file(&archive)?;
+            return loaded;
+        }
+
+        let mut args = vec!["load", "docker-image", "--name", cluster_name];
+        args.extend(images.iter().map(|image| image.as_str()));
+        Kind::run(&args, verbose)
+    }
+
+    pub fn run(args: &Vec<&str>, verbose: bool) -> Result<()> {
+        let mut command = Command::new(binary());
+        command.args(args).logged();
+        let step = format!(
+            "kind {}",
+            args.iter().take(2).cloned().collect::<Vec<_>>().join(" ")
+        );
+        if verbose {
+            let mut child = command.spawn().map_err(|e| spawn_error(KIND, e))?;
+            let status = ci::wait(&mut child, &step)?;
+            return Kind::check_status(&step, status, &[]);
+        }
+
+        // kind reports its steps on stderr, follow them with a spinner. They
+        // are read on another thread, so the CI time limit can be enforced.
+        let progress = Progress::new();
+        let mut child = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| spawn_error(KIND, e))?;
+        let stderr = child.stderr.take().unwrap();
+        let reader = thread::spawn(move || {
+            let mut output = vec![];
+            for line in BufReader::new(stderr).lines().map_while(|line| line.ok()) {
+                match progress::kind_step(&line) {
+                    Some(step) => progress.step(&step),
+                    None => output.push(line),
+                }
+            }
+            (progress, output)
+        });
+        let status = ci::wait(&mut child, &step)?;
+        let (progress, output) = reader
+            .join()
+            .map_err(|_| anyhow!("Could not read the output of kind"))?;
+        Kind::check_status(&step, status, &failure_lines(&output))?;
+        progress.finish("Done");
+
+        Ok(())
+    }
+
+    fn check_status(step: &str, status: ExitStatus, errors: &[String]) -> Result<()> {
+        if status.success() {
+            return Ok(());
+        }
+
+        let message = if errors.is_empty() {
+            format!("exited with {}", status)
+        } else {
+            errors.join("\n")
+        };
+        Err(HakeError::CommandFailed {
+            command: String::from(step),
+            message,
+        }
+        .into())
+    }
+
+    /// Returns the output of `kind version`.
+    pub fn version() -> Result<String> {
+        let out = Command::new(binary())
+            .arg("version")
+            .logged()
+            .output()
+            .map_err(|e| spawn_error(KIND, e))?;
+
+        Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+    }
+
+    /// Parses the output of `kind version`, like "kind v0.8.1 go1.14.2 linux/amd64".
+    pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+        let version = version.split_whitespace().find(|w| w.starts_with('v'))?;
+        let mut parts = version[1..]
+            .split(&['.', '-'][..])
+            .map(|p| p.parse::<u64>().ok());
+
+        Some((parts.next()??, parts.next()??, parts.next()??))
+    }
+
+    pub fn recreate(name: &str, verbose: bool) -> Result<()> {
+        let args_file = Kind::get_config_dir()?.join(name).join("kind_args");
+
+        let mut contents = String::new();
+        let mut saved_args = File::open(args_file)?;
+        saved_args.read_to_string(&mut contents)?;
+
+        Kind::delete_cluster(name)?;
+
+        // older versions of hake saved the arguments separated by spaces
+        let args: Vec<&str> = if contents.contains('\n') {
+            contents.lines().collect()
+        } else {
+            contents.split_ascii_whitespace().collect()
+        };
+        Kind::run(&args, verbose)?;
+
+        Ok(())
+    }
+
+    /// Gets the kind role of a node from its container name.
+    fn get_node_role(container_name: &str, cluster_name: &str) -> Option<&'static str> {
+        let suffix = container_name.strip_prefix(&format!("{}-", cluster_name))?;
+        if suffix.starts_with("control-plane") {
+            Some("control-plane")
+        } else if suffix.starts_with("worker") {
+            Some("worker")
+        } else {
+            None
+        }
+    }
+
+    fn docker(args: &[&str]) -> Result<()> {
+        let status = runtime::command()
+            .args(args)
+            .stdout(Stdio::null())
+            .logged()
+            .status()
+            .map_err(|e| spawn_error(runtime::binary(), e))?;
+        if !status.success() {
+            return Err(HakeError::CommandFailed {
+                command: format!("{} {}", runtime::binary(), args.join(" ")),
+                message: format!("exit status {}", status),
+            }
+            .into());
         }
-        let home = config.unwrap();
+
+        Ok(())
+    }
+
+    fn tar(args: &[&str]) -> Result<()> {
+        if !Command::new("tar")
+            .args(args)
+            .logged()
+            .status()
+            .map_err(|e| spawn_error("tar", e))?
+            .success()
+        {
+            return Err(anyhow!("tar {} failed", args.join(" ")));
+        }
+
+        Ok(())
+    }
+
+    fn staging_dir(name: &str) -> Result<PathBuf> {
+        let staging = env::temp_dir().join(format!("hake-{}-{}", name, std::process::id()));
+        create_dir_all(staging.join("config"))?;
+
+        Ok(staging)
+    }
+
+    /// Exports a cluster as a tarball with a commit of every node container
+    /// and a copy of its /var, the cluster configuration files and the list
+    /// of nodes and their images. The commits and the staging directory are
+    /// removed whether it succeeds or not.
+    pub fn export(name: &str, output: &str) -> Result<()> {
+        let nodes = Kind::get_node_containers(name)?;
+        if nodes.is_empty() {
+            return Err(anyhow!("No node containers found for cluster {}", name));
+        }
+
+        let staging = Kind::staging_dir(name)?;
+        let mut images = Vec::new();
+        let exported = Kind::export_to(name, &nodes, &staging, output, &mut images);
+        if !images.is_empty() {
+            let mut args = vec!["rmi"];
+            args.extend(images.iter().map(|i| i.as_str()));
+            if let Err(e) = Kind::docker(&args) {
+                warn!("Could not remove the images {}: {:#}", images.join(" "), e);
+            }
+        }
+        if let Err(e) = remove_dir_all(&staging) {
+            warn!("Could not remove {}: {:#}", staging.display(), e);
+        }
+
+        exported
+    }
+
+    fn export_to(
+        name: &str,
+        nodes: &[(String, String)],
+        staging: &Path,
+        output: &str,
+        images: &mut Vec<String>,
+    ) -> Result<()> {
+        // paused, so the commits and the copies of /var match
+        let running: Vec<&str> = nodes
+            .iter()
+            .filter(|(_, state)| state == "running")
+            .map(|(container, _)| container.as_str())
+            .collect();
+        if !running.is_empty() {
+            let mut args = vec!["pause"];
+            args.extend(running.iter());
+            Kind::docker(&args)?;
+        }
+        let saved = Kind::save_nodes(name, nodes, staging, images);
+        if !running.is_empty() {
+            let mut args = vec!["unpause"];
+            args.extend(running.iter());
+            Kind::docker(&args)?;
+        }
+        let node_list = saved?;
+
+        let images_path = staging.join("images.tar").to_string_lossy().into_owned();
+        let mut args = vec!["save", "-o", &images_path];
+        args.extend(images.iter().map(|i| i.as_str()));
+        Kind::docker(&args)?;
+
+        File::create(staging.join("nodes"))?.write_all(node_list.as_bytes())?;
+
+        // the audit logs are written by the API server, as root
+        copy_dir(
+            &Kind::get_config_dir()?.join(name),
+            &staging.join("config"),
+            &["audit"],
+        )?;
+
+        Kind::tar(&["-C", &staging.to_string_lossy(), "-czf", output, "."])
+    }
+
+    /// Commits every node and copies its /var, a volume commits leave out:
+    /// etcd, the images of containerd and the state of the kubelet are
+    /// there. Returns the list of nodes, with their role, image and archive
+    /// of /var.
+    fn save_nodes(
+        name: &str,
+        nodes: &[(String, String)],
+        staging: &Path,
+        images: &mut Vec<String>,
+    ) -> Result<String> {
+        let mut node_list = String::new();
+        for (container, _) in nodes.iter() {
+            let role = match Kind::get_node_role(container, name) {
+                Some(role) => role,
+                None => continue,
+            };
+            let image = format!("hake-export/{}:latest", container);
+            Kind::docker(&["commit", container, &image])?;
+            images.push(image.clone());
+
+            let suffix = &container[name.len() + 1..];
+            let var = format!("var-{}.tar", suffix);
+            let status = runtime::command()
+                .args(["cp", &format!("{}:/var", container), "-"])
+                .stdout(File::create(staging.join(&var))?)
+                .logged()
+                .status()
+                .map_err(|e| spawn_error(runtime::binary(), e))?;
+            if !status.success() {
+                return Err(HakeError::CommandFailed {
+                    command: format!("{} cp {}:/var", runtime::binary(), container),
+                    message: format!("exit status {}", status),
+                }
+                .into());
+            }
+            node_list.push_str(&format!("{} {} {}\n", role, image, var));
+        }
+
+        Ok(node_list)
+    }
+
+    /// Directory of the cluster an archive was exported from, where kind
+    /// wrote its kubeconfig.
+    fn exported_dir(kind_args: &str) -> Option<PathBuf> {
+        let args: Vec<&str> = kind_args.lines().collect();
+        args.iter()
+            .position(|arg| *arg == "--kubeconfig")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|kubeconfig| Path::new(kubeconfig).parent())
+            .map(Path::to_path_buf)
+    }
+
+    /// Points the mounts of files in the directory of the exported cluster,
+    /// like its registry credentials or the API server configuration, to
+    /// their copies in `new`. Mounts of other host directories have to exist
+    /// on this machine too.
+    fn relocate_mounts(
+        kind_config: &mut ClusterConfig,
+        old: Option<&Path>,
+        new: &Path,
+    ) -> Result<()> {
+        for mount in kind_config
+            .nodes
+            .iter_mut()
+            .flat_map(|node| node.extraMounts.iter_mut())
+        {
+            let relocated = old
+                .and_then(|old| Path::new(&mount.hostPath).strip_prefix(old).ok())
+                .map(|relative| new.join(relative).to_string_lossy().into_owned());
+            match relocated {
+                Some(relocated) => mount.hostPath = relocated,
+                None if !Path::new(&mount.hostPath).exists() => {
+                    return Err(anyhow!(
+                    "The exported cluster mounts {} at {}, but it does not exist on this machine",
+                    mount.hostPath,
+                    mount.containerPath
+                ))
+                }
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Imports a cluster exported with `export`, creating a new kind cluster
+    /// with the same configuration running on the exported node images, with
+    /// their /var back.
+    pub fn import(archive: &str, name: &str, verbose: bool) -> Result<()> {
+        let staging = Kind::staging_dir(name)?;
+        let imported = Kind::import_from(archive, name, &staging, verbose);
+        if let Err(e) = remove_dir_all(&staging) {
+            warn!("Could not remove {}: {:#}", staging.display(), e);
+        }
+
+        imported
+    }
+
+    fn import_from(archive: &str, name: &str, staging: &Path, verbose: bool) -> Result<()> {
+        Kind::tar(&["-C", &staging.to_string_lossy(), "-xzf", archive])?;
+        Kind::docker(&["load", "-i", &staging.join("images.tar").to_string_lossy()])?;
+
+        let mut node_list = String::new();
+        File::open(staging.join("nodes"))?.read_to_string(&mut node_list)?;
+
+        let mut contents = String::new();
+        File::open(staging.join("config").join("kind_config"))?.read_to_string(&mut contents)?;
+        let mut kind_config: ClusterConfig = serde_yaml::from_str(&contents)?;
+
+        // archives of older versions have no copy of /var
+        let mut vars = vec![];
+        for (i, line) in node_list.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 && fields.len() != 3 {
+                continue;
+            }
+            if kind_config.nodes.len() <= i {
+                kind_config
+                    .nodes
+                    .push(Kind::kind_node(fields[0], None, None));
+            }
+            kind_config.nodes[i].image = Some(String::from(fields[1]));
+            if let Some(var) = fields.get(2) {
+                vars.push(String::from(*var));
+            }
+        }
+
+        let cluster = Kind::new(name);
+        let mut kind_args = String::new();
+        if let Ok(mut file) = File::open(staging.join("config").join("kind_args")) {
+            file.read_to_string(&mut kind_args)?;
+        }
+        Kind::relocate_mounts(
+            &mut kind_config,
+            Kind::exported_dir(&kind_args).as_deref(),
+            &cluster.config_dir,
+        )?;
+
+        Kind::create_dirs(name)?;
+        copy_dir(
+            &staging.join("config"),
+            &cluster.config_dir,
+            &["kubeconfig", "kind_args"],
+        )?;
+        if let Some(mut metadata) = ClusterMetadata::read(name) {
+            metadata.options = metadata
+                .options
+                .map(|opts| crate::cluster::imported_options(&opts, name));
+            metadata.write(name)?;
+        }
+        // the same kind release the cluster was exported with
+        crate::kind_binary::use_recorded(name)?;
+
+        let kind_config_path = cluster.config_file("kind_config");
+        File::create(&kind_config_path)?
+            .write_all(serde_yaml::to_string(&kind_config)?.as_bytes())?;
+
+        let kubeconfig = cluster.config_file("kubeconfig");
+        let args = vec![
+            "create",
+            "cluster",
+            "--name",
+            name,
+            "--kubeconfig",
+            &kubeconfig,
+            "--config",
+            &kind_config_path,
+        ];
+        Kind::run(&args, verbose)?;
+        Kind::save_args(&cluster.config_dir, &args)?;
+
+        Kind::restore_var(name, staging, &vars)
+    }
+
+    /// Copies the exported /var of every node back into the one that
+    /// replaces it, while stopped, and starts them again.
+    fn restore_var(name: &str, staging: &Path, vars: &[String]) -> Result<()> {
+        if vars.is_empty() {
+            return Ok(());
+        }
+        // var-worker2.tar is the /var of <name>-worker2
+        let containers: Vec<String> = vars
+            .iter()
+            .map(|var| {
+                let suffix = var.trim_start_matches("var-").trim_end_matches(".tar");
+                format!("{}-{}", name, suffix)
+            })
+            .collect();
+        info!("Restoring /var of the nodes");
+        let mut args = vec!["stop"];
+        args.extend(containers.iter().map(|c| c.as_str()));
+        Kind::docker(&args)?;
+
+        for (var, container) in vars.iter().zip(containers.iter()) {
+            let status = runtime::command()
+                .args(["cp", "-", &format!("{}:/", container)])
+                .stdin(File::open(staging.join(var))?)
+                .stdout(Stdio::null())
+                .logged()
+                .status()
+                .map_err(|e| spawn_error(runtime::binary(), e))?;
+            if !status.success() {
+                return Err(HakeError::CommandFailed {
+                    command: format!("{} cp - {}:/", runtime::binary(), container),
+                    message: format!("exit status {}", status),
+                }
+                .into());
+            }
+        }
+
+        args[0] = "start";
+        Kind::docker(&args)?;
+        if !crate::kubectl::wait_for_api_server(name, API_SERVER_TIMEOUT) {
+            warn!("The API server of {} is not ready yet", name);
+        }
+
+        Ok(())
+    }
+
+    pub fn delete(&self) -> Result<()> {
+        if dryrun::enabled() {
+            dryrun::print(&format!(
+                "would run: kind delete cluster --name {}",
+                self.name
+            ));
+            dryrun::print(&format!("would remove {}", self.config_dir.display()));
+            return Ok(());
+        }
+        Kind::delete_cluster(&self.name)?;
+
+        remove_dir_all(&self.config_dir)?;
+
+        Ok(())
+    }
+
+    /// Takes over a cluster created with kind directly: creates its directory
+    /// and writes its kubeconfig there.
+    pub fn adopt(name: &str) -> Result<()> {
+        if !Kind::get_kind_containers()?.iter().any(|c| c == name) {
+            return Err(anyhow!("There is no kind cluster named {}", name));
+        }
+
+        let out = Command::new(binary())
+            .args(["get", "kubeconfig", "--name", name])
+            .logged()
+            .output()
+            .map_err(|e| spawn_error(KIND, e))?;
+        if !out.status.success() {
+            return Err(HakeError::CommandFailed {
+                command: format!("kind get kubeconfig --name {}", name),
+                message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+            }
+            .into());
+        }
+        create_dir_all(Kind::get_config_dir()?.join(name))?;
+        fs::write(Kind::new(name).config_file("kubeconfig"), &out.stdout)?;
+
+        Ok(())
+    }
+
+    pub fn delete_cluster(name: &str) -> Result<()> {
+        let mut args = vec!["delete", "cluster"];
+        args.push("--name");
+        args.push(name);
+
+        let out = Command::new(binary())
+            .args(args)
+            .logged()
+            .output()
+            .map_err(|e| spawn_error(KIND, e))?;
+        if !out.status.success() {
+            return Err(HakeError::CommandFailed {
+                command: format!("kind delete cluster --name {}", name),
+                message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    pub fn new(name: &str) -> Kind {
+        let home = Kind::get_config_dir().expect("User does not have a home");
 
         Kind {
             name: String::from(name),
             ecr_repo: None,
-            config_dir: format!("{}/{}", home, name),
+            config_dir: home.join(name),
             local_registry: None,
+            registry_container: None,
+            network: None,
             extra_port_mapping: None,
+            port_mappings: vec![],
+            ingress_ready: false,
+            ingress_ports: vec![],
+            disable_default_cni: false,
+            ip_family: None,
+            pod_subnet: None,
+            service_subnet: None,
+            feature_gates: BTreeMap::new(),
+            kubeadm_patches: vec![],
+            audit_policy: None,
+            encrypt_secrets: false,
+            mounts: vec![],
+            node_image: None,
+            arch: None,
+            registry_auth: None,
+            gpus: false,
+            image_cache: false,
+            workers: 0,
             verbose: false,
         }
     }
 }
 
+/// Copies the files and directories in `from` to `to`, but the ones of
+/// `from` named in `skip`.
+fn copy_dir(from: &Path, to: &Path, skip: &[&str]) -> Result<()> {
+    create_dir_all(to)?;
+    for entry in read_dir(from)? {
+        let entry = entry?;
+        if skip.iter().any(|name| entry.file_name() == *name) {
+            continue;
+        }
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target, &[])?;
+        } else {
+            copy(entry.path(), target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether kind refused to create a cluster because one with its name
+/// already existed, so the nodes it names are not the ones being created.
+fn already_exists(err: &anyhow::Error) -> bool {
+    format!("{:#}", err).contains("already exist for a cluster with the name")
+}
+
+/// Lines of the output of kind worth showing when it fails: its error and
+/// the details after it, or its last lines when it reported no error.
+fn failure_lines(output: &[String]) -> Vec<String> {
+    let lines: Vec<&String> = output
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let start = match lines.iter().position(|line| line.starts_with("ERROR")) {
+        Some(error) => error,
+        None => lines.len().saturating_sub(FAILURE_LINES),
+    };
+
+    lines[start..]
+        .iter()
+        .take(FAILURE_LINES)
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Hides the registry credentials of a docker config or of a containerd
+/// config patch, for logs.
+fn redact_auth(config: &str) -> String {
+    Regex::new(r#"("auth":|auth = \\?)"[^"\\]*(\\?)""#)
+        .unwrap()
+        .replace_all(config, r#"$1"<redacted>$2""#)
+        .to_string()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::kind::Kind;
+    use crate::kind::{
+        already_exists, config_api_version, copy_dir, failure_lines, redact_auth, ClusterConfig,
+        Kind, RegistryAuth,
+    };
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
 
     #[test]
     fn test_new() {
@@ -440,11 +2092,214 @@ mod tests {
 
         assert_eq!(k.name, "test");
         assert_eq!(k.ecr_repo, None);
+        assert_eq!(k.config_dir, home.join(".hake").join("test"));
+        assert_eq!(k.local_registry, None);
+    }
+
+    #[test]
+    fn test_redact_auth() {
         assert_eq!(
-            k.config_dir,
-            format!("{}/.hake/test", home.to_str().unwrap())
+            redact_auth(r#"{"auths":{"registry":{"auth":"dXNlcjpzZWNyZXQ="}}}"#),
+            r#"{"auths":{"registry":{"auth":"<redacted>"}}}"#
         );
-        assert_eq!(k.local_registry, None);
+        assert_eq!(
+            redact_auth("registry.configs.\"registry\".auth]\n  auth = \"dXNlcjpzZWNyZXQ=\""),
+            "registry.configs.\"registry\".auth]\n  auth = \"<redacted>\""
+        );
+        assert_eq!(
+            redact_auth(r#"- "\n[plugins]\n  auth = \"dXNlcjpzZWNyZXQ=\"""#),
+            r#"- "\n[plugins]\n  auth = \"<redacted>\"""#
+        );
+    }
+
+    #[test]
+    fn test_registry_auth() {
+        assert_eq!(
+            RegistryAuth::parse("containerd").unwrap(),
+            RegistryAuth::Containerd
+        );
+        assert_eq!(RegistryAuth::parse("secret").unwrap(), RegistryAuth::Secret);
+        assert!(RegistryAuth::parse("docker").is_err());
+        assert_eq!(
+            RegistryAuth::default_for(Some((0, 8, 1))),
+            RegistryAuth::Kubelet
+        );
+        assert_eq!(
+            RegistryAuth::default_for(Some((0, 11, 0))),
+            RegistryAuth::Containerd
+        );
+        assert_eq!(RegistryAuth::default_for(None), RegistryAuth::Containerd);
+    }
+
+    #[test]
+    fn test_workers() {
+        let mut k = Kind::new("test");
+        k.set_workers(2);
+
+        let config = k.get_kind_cluster_config(&None, &None, None).unwrap();
+        let roles: Vec<&str> = config.nodes.iter().map(|n| n.role.as_str()).collect();
+        assert_eq!(roles, vec!["control-plane", "worker", "worker"]);
+    }
+
+    #[test]
+    fn test_unprivileged_ingress_ports() {
+        let mut k = Kind::new("test");
+        k.add_port_mapping("80").unwrap();
+        k.with_ingress();
+        k.unprivileged_ingress_ports(1024);
+
+        // the port given by the user is kept, and fails the rootless check
+        let ports: Vec<u32> = k.port_mappings.iter().map(|pm| pm.hostPort).collect();
+        assert_eq!(ports, vec![80, 8443]);
+    }
+
+    #[test]
+    fn test_set_ip_family() {
+        let mut k = Kind::new("test");
+        assert!(k.set_ip_family("ipv6").is_ok());
+        assert_eq!(k.ip_family.as_deref(), Some("ipv6"));
+        assert!(k.set_ip_family("ipv5").is_err());
+    }
+
+    #[test]
+    fn test_parse_subnet() {
+        assert_eq!(Kind::parse_subnet("10.10.0.0/16").unwrap(), "10.10.0.0/16");
+        assert!(Kind::parse_subnet("fd00:10::/56").is_ok());
+        assert!(Kind::parse_subnet("10.10.0.0").is_err());
+        assert!(Kind::parse_subnet("10.10.0.0/33").is_err());
+        assert!(Kind::parse_subnet("10.10.0/16").is_err());
+    }
+
+    #[test]
+    fn test_api_server_patch() {
+        let patch: serde_yaml::Value =
+            serde_yaml::from_str(&Kind::api_server_patch(true, true)).unwrap();
+        let api_server = &patch["apiServer"];
+        assert_eq!(
+            api_server["extraArgs"]["audit-policy-file"].as_str(),
+            Some("/etc/kubernetes/hake/audit-policy.yaml")
+        );
+        assert_eq!(
+            api_server["extraArgs"]["encryption-provider-config"].as_str(),
+            Some("/etc/kubernetes/hake/encryption.yaml")
+        );
+        assert_eq!(api_server["extraVolumes"].as_sequence().unwrap().len(), 2);
+
+        let patch: serde_yaml::Value =
+            serde_yaml::from_str(&Kind::api_server_patch(false, true)).unwrap();
+        assert!(patch["apiServer"]["extraArgs"]["audit-log-path"].is_null());
+        assert_eq!(
+            patch["apiServer"]["extraVolumes"]
+                .as_sequence()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            Kind::parse_version("kind v0.8.1 go1.14.2 linux/amd64"),
+            Some((0, 8, 1))
+        );
+        assert_eq!(Kind::parse_version("kind v0.9.0-alpha"), Some((0, 9, 0)));
+        assert_eq!(Kind::parse_version("v0.7.0"), Some((0, 7, 0)));
+        assert_eq!(Kind::parse_version("kind version unknown"), None);
+    }
+
+    #[test]
+    fn test_config_api_version() {
+        assert_eq!(
+            config_api_version(Some((0, 7, 0))),
+            "kind.sigs.k8s.io/v1alpha3"
+        );
+        assert_eq!(
+            config_api_version(Some((0, 8, 1))),
+            "kind.x-k8s.io/v1alpha4"
+        );
+        assert_eq!(
+            config_api_version(Some((0, 20, 0))),
+            "kind.x-k8s.io/v1alpha4"
+        );
+        assert_eq!(config_api_version(None), "kind.x-k8s.io/v1alpha4");
+    }
+
+    #[test]
+    fn test_parse_port_mapping() {
+        let pm = Kind::parse_port_mapping("8080:30080").unwrap();
+        assert_eq!(pm.hostPort, 8080);
+        assert_eq!(pm.containerPort, 30080);
+        assert_eq!(pm.protocol, "TCP");
+
+        let pm = Kind::parse_port_mapping("5353:30053/udp").unwrap();
+        assert_eq!(pm.hostPort, 5353);
+        assert_eq!(pm.containerPort, 30053);
+        assert_eq!(pm.protocol, "UDP");
+
+        let pm = Kind::parse_port_mapping("80").unwrap();
+        assert_eq!(pm.hostPort, 80);
+        assert_eq!(pm.containerPort, 80);
+
+        assert!(Kind::parse_port_mapping("").is_err());
+        assert!(Kind::parse_port_mapping("80:").is_err());
+        assert!(Kind::parse_port_mapping("80:80/http").is_err());
+        assert!(Kind::parse_port_mapping("0:80").is_err());
+        assert!(Kind::parse_port_mapping("80:70000").is_err());
+    }
+
+    #[test]
+    fn test_parse_feature_gate() {
+        assert_eq!(
+            Kind::parse_feature_gate("EphemeralContainers=true").unwrap(),
+            (String::from("EphemeralContainers"), true)
+        );
+        assert_eq!(
+            Kind::parse_feature_gate("CSIMigration=false").unwrap(),
+            (String::from("CSIMigration"), false)
+        );
+        assert!(Kind::parse_feature_gate("CSIMigration").is_err());
+        assert!(Kind::parse_feature_gate("CSIMigration=yes").is_err());
+        assert!(Kind::parse_feature_gate("=true").is_err());
+    }
+
+    #[test]
+    fn test_parse_mount() {
+        let mount = Kind::parse_mount("/tmp/charts:/charts").unwrap();
+        assert_eq!(mount.hostPath, "/tmp/charts");
+        assert_eq!(mount.containerPath, "/charts");
+        assert_eq!(mount.readOnly, None);
+
+        let mount = Kind::parse_mount("/tmp/bin:/opt/bin:ro").unwrap();
+        assert_eq!(mount.readOnly, Some(true));
+
+        let mount = Kind::parse_mount("data:/data").unwrap();
+        assert!(mount.hostPath.starts_with('/'));
+        assert!(mount.hostPath.ends_with("/data"));
+
+        let mount = Kind::parse_mount(r"C:\src:/src:ro").unwrap();
+        assert!(mount.hostPath.ends_with(r"C:\src"));
+        assert_eq!(mount.containerPath, "/src");
+        assert_eq!(mount.readOnly, Some(true));
+
+        assert!(Kind::parse_mount("/tmp").is_err());
+        assert!(Kind::parse_mount("/tmp:relative").is_err());
+        assert!(Kind::parse_mount("/tmp:/tmp:rx").is_err());
+        assert!(Kind::parse_mount(":/tmp").is_err());
+    }
+
+    #[test]
+    fn test_get_node_role() {
+        assert_eq!(
+            Kind::get_node_role("test-control-plane", "test"),
+            Some("control-plane")
+        );
+        assert_eq!(Kind::get_node_role("test-worker2", "test"), Some("worker"));
+        assert_eq!(
+            Kind::get_node_role("test-external-load-balancer", "test"),
+            None
+        );
+        assert_eq!(Kind::get_node_role("other-worker", "test"), None);
     }
 
     #[test]
@@ -459,4 +2314,92 @@ mod tests {
             Some(String::from("this-is-us"))
         );
     }
+
+    #[test]
+    fn test_failure_lines() {
+        let output: Vec<String> = [
+            "Creating cluster \"dev\" ...",
+            "",
+            "ERROR: failed to create cluster: node(s) already exist for a cluster with the name \"dev\"",
+            "Stack Trace:",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+        assert_eq!(failure_lines(&output), &output[2..]);
+
+        let output: Vec<String> = (0..30).map(|line| line.to_string()).collect();
+        assert_eq!(failure_lines(&output), &output[10..]);
+    }
+
+    #[test]
+    fn test_copy_dir() {
+        let dir = env::temp_dir().join(format!("hake-copy-test-{}", std::process::id()));
+        let from = dir.join("from");
+        fs::create_dir_all(from.join("apiserver")).unwrap();
+        fs::create_dir_all(from.join("audit")).unwrap();
+        fs::write(from.join("kind_config"), "kind: Cluster").unwrap();
+        fs::write(from.join("apiserver").join("encryption.yaml"), "key").unwrap();
+        fs::write(from.join("audit").join("audit.log"), "{}").unwrap();
+
+        let to = dir.join("to");
+        copy_dir(&from, &to, &["audit"]).unwrap();
+        assert_eq!(
+            fs::read_to_string(to.join("apiserver").join("encryption.yaml")).unwrap(),
+            "key"
+        );
+        assert!(to.join("kind_config").exists());
+        assert!(!to.join("audit").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_exported_dir() {
+        let args = "create\ncluster\n--name\nold\n--kubeconfig\n/home/alice/.hake/old/kubeconfig\n--config\n/home/alice/.hake/old/kind_config";
+        assert_eq!(
+            Kind::exported_dir(args),
+            Some(PathBuf::from("/home/alice/.hake/old"))
+        );
+        assert_eq!(Kind::exported_dir(""), None);
+    }
+
+    #[test]
+    fn test_relocate_mounts() {
+        let config = r#"
+kind: Cluster
+apiVersion: kind.x-k8s.io/v1alpha4
+containerdConfigPatches: []
+nodes:
+- role: control-plane
+  extraPortMappings: []
+  kubeadmConfigPatches: []
+  extraMounts:
+  - containerPath: /etc/hake
+    hostPath: /home/alice/.hake/old/apiserver
+    readOnly: true
+  - containerPath: /dev/null
+    hostPath: /dev/null
+"#;
+        let old = Path::new("/home/alice/.hake/old");
+        let new = Path::new("/home/bob/.hake/new");
+        let mut kind_config: ClusterConfig = serde_yaml::from_str(config).unwrap();
+        Kind::relocate_mounts(&mut kind_config, Some(old), new).unwrap();
+        let mounts = &kind_config.nodes[0].extraMounts;
+        assert_eq!(mounts[0].hostPath, "/home/bob/.hake/new/apiserver");
+        assert_eq!(mounts[1].hostPath, "/dev/null");
+
+        let mut kind_config: ClusterConfig =
+            serde_yaml::from_str(&config.replace("/dev/null", "/home/alice/src")).unwrap();
+        assert!(Kind::relocate_mounts(&mut kind_config, Some(old), new).is_err());
+    }
+
+    #[test]
+    fn test_already_exists() {
+        let err = anyhow::anyhow!("`kind create cluster` failed: ERROR: failed to create cluster: node(s) already exist for a cluster with the name \"dev\"");
+        assert!(already_exists(&err));
+        assert!(!already_exists(&anyhow::anyhow!(
+            "`kind create cluster` failed: exited with exit status: 1"
+        )));
+    }
 }
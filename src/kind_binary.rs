@@ -0,0 +1,188 @@
+// Releases of kind downloaded on demand, so every machine working on a
+// cluster runs the same kind instead of whatever is in PATH. They are cached
+// in ~/.cache/hake/kind/<version> and checked against the published sha256.
+use anyhow::{anyhow, Result};
+use console::Style;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::dryrun;
+use crate::kind::{self, KIND};
+use crate::metadata::ClusterMetadata;
+
+const RELEASES: &str = "https://github.com/kubernetes-sigs/kind/releases/download";
+
+/// Name of the release asset of a binary for an operating system and
+/// architecture, as in std::env::consts. hake releases are named like kind's.
+pub(crate) fn asset_name(binary: &str, os: &str, arch: &str) -> Result<String> {
+    let os = match os {
+        "linux" => "linux",
+        "macos" => "darwin",
+        "windows" => "windows",
+        os => return Err(anyhow!("{} is not released for {}", binary, os)),
+    };
+    let arch = match arch {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => return Err(anyhow!("{} is not released for {}", binary, arch)),
+    };
+
+    Ok(format!("{}-{}-{}", binary, os, arch))
+}
+
+/// Reads the hash from a sha256sum file, like "<hash>  kind-linux-amd64".
+fn parse_checksum(sha256sum: &str) -> Option<String> {
+    let hash = sha256sum.split_whitespace().next()?;
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    Some(hash.to_lowercase())
+}
+
+fn normalize_version(version: &str) -> String {
+    if version.starts_with('v') {
+        String::from(version)
+    } else {
+        format!("v{}", version)
+    }
+}
+
+fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(|| crate::get_config_dir().join("cache"))
+        .join("hake")
+        .join("kind")
+}
+
+/// Where a release of kind is cached.
+pub fn path(version: &str) -> PathBuf {
+    cache_dir().join(normalize_version(version)).join(KIND)
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    let resp = crate::http::client_builder()?.build()?.get(url).send()?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("Could not download {}: {}", url, resp.status()));
+    }
+
+    Ok(resp.bytes()?.to_vec())
+}
+
+/// Downloads a release asset and checks it against the sha256sum file
+/// published next to it.
+pub(crate) fn download_verified(url: &str) -> Result<Vec<u8>> {
+    let checksum = download(&format!("{}.sha256sum", url))?;
+    let expected = parse_checksum(&String::from_utf8_lossy(&checksum))
+        .ok_or_else(|| anyhow!("Invalid checksum file for {}", url))?;
+    let binary = download(url)?;
+    let actual = format!("{:x}", Sha256::digest(&binary));
+    if actual != expected {
+        return Err(anyhow!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            url,
+            expected,
+            actual
+        ));
+    }
+
+    Ok(binary)
+}
+
+/// Downloads a release of kind unless it is cached, and returns its path.
+pub fn install(version: &str) -> Result<PathBuf> {
+    let version = normalize_version(version);
+    let path = path(&version);
+    if path.exists() {
+        return Ok(path);
+    }
+    if dryrun::enabled() {
+        dryrun::print(&format!(
+            "would download kind {} to {}",
+            version,
+            path.display()
+        ));
+        return Ok(path);
+    }
+
+    let url = format!(
+        "{}/{}/{}",
+        RELEASES,
+        version,
+        asset_name("kind", env::consts::OS, env::consts::ARCH)?
+    );
+    let cyan = Style::new().cyan();
+    info!("Downloading kind {}", cyan.apply_to(&version));
+    let binary = download_verified(&url)?;
+
+    // written next to its final path and renamed, so a partial download is
+    // never taken for a cached release
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+    let partial = dir.join(format!("{}.partial", KIND));
+    fs::write(&partial, &binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&partial, fs::Permissions::from_mode(0o755))?;
+    }
+    fs::rename(&partial, &path)?;
+
+    Ok(path)
+}
+
+/// Makes hake run the given release of kind, if any.
+pub fn select(version: Option<&str>) -> Result<()> {
+    if let Some(version) = version {
+        kind::use_binary(install(version)?);
+    }
+
+    Ok(())
+}
+
+/// Makes hake run the release of kind a cluster was created with.
+pub fn use_recorded(name: &str) -> Result<()> {
+    let version = ClusterMetadata::read(name)
+        .and_then(|metadata| metadata.options)
+        .and_then(|opts| opts.kind_version);
+
+    select(version.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kind_binary::{asset_name, normalize_version, parse_checksum};
+
+    #[test]
+    fn test_asset_name() {
+        assert_eq!(
+            asset_name("kind", "linux", "x86_64").unwrap(),
+            "kind-linux-amd64"
+        );
+        assert_eq!(
+            asset_name("hake", "macos", "aarch64").unwrap(),
+            "hake-darwin-arm64"
+        );
+        assert!(asset_name("kind", "freebsd", "x86_64").is_err());
+    }
+
+    #[test]
+    fn test_parse_checksum() {
+        let hash = "781c3db479b805d161b7c2c7a31896d1a504b583ebfcce87a3d8bcc38bf3fc24";
+        assert_eq!(
+            parse_checksum(&format!("{}  kind-linux-amd64\n", hash)).as_deref(),
+            Some(hash)
+        );
+        assert_eq!(parse_checksum("Not Found"), None);
+    }
+
+    #[test]
+    fn test_normalize_version() {
+        assert_eq!(normalize_version("0.8.1"), "v0.8.1");
+        assert_eq!(normalize_version("v0.8.1"), "v0.8.1");
+    }
+}
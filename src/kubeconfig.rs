@@ -0,0 +1,416 @@
+#![allow(non_snake_case)]
+// Merges the kubeconfig of the clusters into the user's main kubeconfig, as a
+// context named hake-<name>, for tools that only read ~/.kube/config.
+use anyhow::{anyhow, Result};
+use serde::Deserializer;
+use serde_derive::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use std::collections::BTreeMap;
+use std::fs::{self, create_dir_all, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct NamedCluster {
+    name: String,
+    cluster: Value,
+}
+
+/// The cluster, user, namespace and extensions of a context, kept as they
+/// are so rewriting the main kubeconfig does not lose any of them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct NamedContext {
+    name: String,
+    context: Value,
+}
+
+impl NamedContext {
+    fn cluster(&self) -> &str {
+        self.context["cluster"].as_str().unwrap_or_default()
+    }
+
+    fn user(&self) -> &str {
+        self.context["user"].as_str().unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct NamedUser {
+    name: String,
+    user: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct KubeConfig {
+    apiVersion: String,
+    kind: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    clusters: Vec<NamedCluster>,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    contexts: Vec<NamedContext>,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    users: Vec<NamedUser>,
+    #[serde(rename = "current-context", default)]
+    current_context: String,
+    #[serde(flatten)]
+    extra: BTreeMap<String, Value>,
+}
+
+impl Default for KubeConfig {
+    fn default() -> Self {
+        KubeConfig {
+            apiVersion: String::from("v1"),
+            kind: String::from("Config"),
+            clusters: vec![],
+            contexts: vec![],
+            users: vec![],
+            current_context: String::new(),
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+// kubectl writes `clusters: null` when the last cluster is removed.
+fn null_as_empty<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    let value: Option<Vec<T>> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(value.unwrap_or_default())
+}
+
+pub fn context_name(name: &str) -> String {
+    format!("hake-{}", name)
+}
+
+fn main_kubeconfig_path() -> PathBuf {
+    dirs::home_dir()
+        .expect("User does not have a home")
+        .join(".kube")
+        .join("config")
+}
+
+fn read(path: &Path) -> Result<KubeConfig> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+
+    Ok(serde_yaml::from_str(&contents)?)
+}
+
+/// Writes a kubeconfig to a file next to it first, renamed over it once
+/// complete, so an interrupted write does not leave it truncated. It keeps
+/// the permissions of the file it replaces.
+fn write(path: &Path, config: &KubeConfig) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".hake-partial");
+    let partial = PathBuf::from(partial);
+
+    let mut file = File::create(&partial)?;
+    file.write_all(serde_yaml::to_string(config)?.as_bytes())?;
+    file.sync_all()?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(&partial, metadata.permissions())?;
+    } else {
+        // it has the credentials of the clusters
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&partial, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    fs::rename(&partial, path)?;
+
+    Ok(())
+}
+
+/// Current context of a kubeconfig, or its first one, with the cluster and
+/// user it points to.
+fn current_entries(config: &KubeConfig) -> Result<(&NamedContext, &NamedCluster, &NamedUser)> {
+    let context = config
+        .contexts
+        .iter()
+        .find(|c| c.name == config.current_context)
+        .or_else(|| config.contexts.first())
+        .ok_or_else(|| anyhow!("Kubeconfig has no contexts"))?;
+
+    let cluster = config
+        .clusters
+        .iter()
+        .find(|c| c.name == context.cluster())
+        .ok_or_else(|| anyhow!("Kubeconfig has no cluster {}", context.cluster()))?;
+    let user = config
+        .users
+        .iter()
+        .find(|u| u.name == context.user())
+        .ok_or_else(|| anyhow!("Kubeconfig has no user {}", context.user()))?;
+
+    Ok((context, cluster, user))
+}
+
+/// Renames the current context of a cluster kubeconfig, and the cluster and
+/// user it points to, so they do not collide with other entries.
+fn rename(config: KubeConfig, new_name: &str) -> Result<KubeConfig> {
+    let (context, cluster, user) = current_entries(&config)?;
+    let mut context = context.context.clone();
+    if let Value::Mapping(entries) = &mut context {
+        entries.insert(Value::from("cluster"), Value::from(new_name));
+        entries.insert(Value::from("user"), Value::from(new_name));
+    }
+
+    Ok(KubeConfig {
+        clusters: vec![NamedCluster {
+            name: String::from(new_name),
+            cluster: cluster.cluster.clone(),
+        }],
+        contexts: vec![NamedContext {
+            name: String::from(new_name),
+            context,
+        }],
+        users: vec![NamedUser {
+            name: String::from(new_name),
+            user: user.user.clone(),
+        }],
+        current_context: String::from(new_name),
+        ..Default::default()
+    })
+}
+
+fn remove_entries(config: &mut KubeConfig, name: &str) {
+    config.clusters.retain(|c| c.name != name);
+    config.contexts.retain(|c| c.name != name);
+    config.users.retain(|u| u.name != name);
+    if config.current_context == name {
+        config.current_context = String::new();
+    }
+}
+
+/// Replaces the entries of `other` in `config`, keeping its current context.
+fn merge_entries(config: &mut KubeConfig, other: KubeConfig) {
+    remove_entries(config, &other.current_context);
+    config.clusters.extend(other.clusters);
+    config.contexts.extend(other.contexts);
+    config.users.extend(other.users);
+}
+
+/// Fields of the kubeconfig of a cluster `config --field` prints.
+pub const FIELDS: &[&str] = &[
+    "server",
+    "context",
+    "ca-cert",
+    "client-cert",
+    "client-key",
+    "token",
+];
+
+/// Contents of a certificate or key, inline in base64 under `<key>-data` or
+/// in the file under `<key>`.
+fn data(entry: &Value, key: &str) -> Result<Option<Vec<u8>>> {
+    if let Some(data) = entry[format!("{}-data", key).as_str()].as_str() {
+        return Ok(Some(base64::decode(data.trim())?));
+    }
+    match entry[key].as_str() {
+        Some(path) => Ok(Some(std::fs::read(path)?)),
+        None => Ok(None),
+    }
+}
+
+/// Value of one of `FIELDS` for the current context of a kubeconfig, with
+/// certificates and keys decoded to PEM.
+fn field_of(config: &KubeConfig, field: &str) -> Result<Vec<u8>> {
+    let (context, cluster, user) = current_entries(config)?;
+    let text = |value: &Value| value.as_str().map(|s| s.as_bytes().to_vec());
+    let value = match field {
+        "server" => text(&cluster.cluster["server"]),
+        "context" => Some(context.name.as_bytes().to_vec()),
+        "ca-cert" => data(&cluster.cluster, "certificate-authority")?,
+        "client-cert" => data(&user.user, "client-certificate")?,
+        "client-key" => data(&user.user, "client-key")?,
+        "token" => text(&user.user["token"]),
+        _ => {
+            return Err(anyhow!(
+                "Unknown field {}, expected one of: {}",
+                field,
+                FIELDS.join(", ")
+            ))
+        }
+    };
+
+    value.ok_or_else(|| anyhow!("Kubeconfig has no {} for context {}", field, context.name))
+}
+
+/// Value of a field of the kubeconfig of a cluster.
+pub fn field(name: &str, field: &str) -> Result<Vec<u8>> {
+    field_of(&read(&crate::kubectl::kubeconfig_path(name))?, field)
+}
+
+/// Name of the current context in the kubeconfig of a cluster.
+pub fn current_context(name: &str) -> Result<String> {
+    Ok(read(&crate::kubectl::kubeconfig_path(name))?.current_context)
+}
+
+/// Adds the cluster to the main kubeconfig as the hake-<name> context.
+pub fn merge(name: &str) -> Result<()> {
+    let cluster_config = read(&crate::kubectl::kubeconfig_path(name))?;
+    let renamed = rename(cluster_config, &context_name(name))?;
+
+    let main_path = main_kubeconfig_path();
+    let mut main_config = if main_path.exists() {
+        read(&main_path)?
+    } else {
+        KubeConfig::default()
+    };
+    merge_entries(&mut main_config, renamed);
+
+    write(&main_path, &main_config)
+}
+
+/// Removes the hake-<name> context from the main kubeconfig, if present.
+pub fn remove(name: &str) -> Result<()> {
+    let main_path = main_kubeconfig_path();
+    if !main_path.exists() {
+        return Ok(());
+    }
+
+    let mut main_config = read(&main_path)?;
+    let context = context_name(name);
+    if !main_config.contexts.iter().any(|c| c.name == context) {
+        return Ok(());
+    }
+    remove_entries(&mut main_config, &context);
+
+    write(&main_path, &main_config)
+}
+
+/// Merges the cluster into the main kubeconfig and makes it the current context.
+pub fn use_context(name: &str) -> Result<()> {
+    merge(name)?;
+
+    let main_path = main_kubeconfig_path();
+    let mut main_config = read(&main_path)?;
+    main_config.current_context = context_name(name);
+
+    write(&main_path, &main_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kubeconfig::{field_of, merge_entries, rename, KubeConfig};
+
+    const KIND_KUBECONFIG: &str = r#"apiVersion: v1
+kind: Config
+clusters:
+- cluster:
+    server: https://127.0.0.1:32768
+  name: kind-test
+contexts:
+- context:
+    cluster: kind-test
+    user: kind-test
+  name: kind-test
+current-context: kind-test
+preferences: {}
+users:
+- name: kind-test
+  user:
+    token: abc
+"#;
+
+    #[test]
+    fn test_rename() {
+        let config: KubeConfig = serde_yaml::from_str(KIND_KUBECONFIG).unwrap();
+        let renamed = rename(config, "hake-test").unwrap();
+
+        assert_eq!(renamed.current_context, "hake-test");
+        assert_eq!(renamed.clusters[0].name, "hake-test");
+        assert_eq!(
+            renamed.clusters[0].cluster["server"].as_str(),
+            Some("https://127.0.0.1:32768")
+        );
+        assert_eq!(renamed.contexts[0].cluster(), "hake-test");
+        assert_eq!(renamed.contexts[0].user(), "hake-test");
+        assert_eq!(renamed.users[0].user["token"].as_str(), Some("abc"));
+    }
+
+    #[test]
+    fn test_field_of() {
+        let config: KubeConfig = serde_yaml::from_str(KIND_KUBECONFIG).unwrap();
+        assert_eq!(
+            field_of(&config, "server").unwrap(),
+            b"https://127.0.0.1:32768"
+        );
+        assert_eq!(field_of(&config, "context").unwrap(), b"kind-test");
+        assert_eq!(field_of(&config, "token").unwrap(), b"abc");
+        assert!(field_of(&config, "client-cert").is_err());
+        assert!(field_of(&config, "password").is_err());
+
+        let config: KubeConfig = serde_yaml::from_str(
+            &KIND_KUBECONFIG.replace(
+                "server: https",
+                "certificate-authority-data: LS0tLS1CRUdJTiBDRVJUSUZJQ0FURS0tLS0tCg==\n    server: https",
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            field_of(&config, "ca-cert").unwrap(),
+            b"-----BEGIN CERTIFICATE-----\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_entries() {
+        let mut main: KubeConfig = serde_yaml::from_str(
+            "apiVersion: v1\nkind: Config\nclusters: null\ncontexts: null\nusers: null\ncurrent-context: other\n",
+        )
+        .unwrap();
+        let config: KubeConfig = serde_yaml::from_str(KIND_KUBECONFIG).unwrap();
+
+        merge_entries(&mut main, rename(config, "hake-test").unwrap());
+        let config: KubeConfig = serde_yaml::from_str(KIND_KUBECONFIG).unwrap();
+        merge_entries(&mut main, rename(config, "hake-test").unwrap());
+
+        assert_eq!(main.current_context, "other");
+        assert_eq!(main.clusters.len(), 1);
+        assert_eq!(main.contexts.len(), 1);
+        assert_eq!(main.users.len(), 1);
+    }
+
+    #[test]
+    fn test_contexts_kept() {
+        // a context without user, and one with extensions of another tool
+        let mut main: KubeConfig = serde_yaml::from_str(
+            r#"apiVersion: v1
+kind: Config
+contexts:
+- name: no-user
+  context:
+    cluster: other
+- name: extended
+  context:
+    cluster: other
+    user: other
+    namespace: dev
+    extensions:
+    - name: tool
+      extension: {color: red}
+"#,
+        )
+        .unwrap();
+        let config: KubeConfig = serde_yaml::from_str(KIND_KUBECONFIG).unwrap();
+        merge_entries(&mut main, rename(config, "hake-test").unwrap());
+
+        let written = serde_yaml::to_string(&main).unwrap();
+        let read: KubeConfig = serde_yaml::from_str(&written).unwrap();
+        assert_eq!(read.contexts.len(), 3);
+        assert_eq!(read.contexts[0].user(), "");
+        assert_eq!(
+            read.contexts[1].context["extensions"][0]["extension"]["color"].as_str(),
+            Some("red")
+        );
+        assert_eq!(read.contexts[1].context["namespace"].as_str(), Some("dev"));
+    }
+}
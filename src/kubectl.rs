@@ -0,0 +1,173 @@
+// Helpers to run kubectl against one of the clusters managed by hake, using
+// the kubeconfig stored in the cluster's config directory.
+use anyhow::{anyhow, Result};
+
+use crate::error::spawn_error;
+use crate::logging::LogCommand;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::{thread, time};
+
+pub fn kubeconfig_path(name: &str) -> PathBuf {
+    crate::cluster_dir(name).join("kubeconfig")
+}
+
+/// Shells `config` can print the KUBECONFIG statement for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    /// bash, zsh and other POSIX shells.
+    Posix,
+    Fish,
+    PowerShell,
+    Cmd,
+}
+
+impl Shell {
+    /// PowerShell on Windows, a POSIX shell everywhere else.
+    pub fn default_for_platform() -> Shell {
+        if cfg!(windows) {
+            Shell::PowerShell
+        } else {
+            Shell::Posix
+        }
+    }
+
+    pub fn parse(shell: &str) -> Result<Shell> {
+        match shell {
+            "bash" | "zsh" | "sh" | "posix" => Ok(Shell::Posix),
+            "fish" => Ok(Shell::Fish),
+            "powershell" | "pwsh" => Ok(Shell::PowerShell),
+            "cmd" => Ok(Shell::Cmd),
+            _ => Err(anyhow!(
+                "Unknown shell {}, expected bash, zsh, fish, powershell or cmd",
+                shell
+            )),
+        }
+    }
+
+    /// Statement setting an environment variable.
+    pub fn export(self, variable: &str, value: &str) -> String {
+        match self {
+            Shell::Posix => format!("export {}={}", variable, value),
+            Shell::Fish => format!("set -gx {} \"{}\";", variable, value),
+            Shell::PowerShell => format!("$env:{} = \"{}\"", variable, value),
+            Shell::Cmd => format!("SET {}={}", variable, value),
+        }
+    }
+}
+
+/// Shell statement pointing KUBECONFIG at a cluster, for PowerShell on Windows.
+pub fn export_kubeconfig(name: &str) -> String {
+    export_kubeconfig_for(name, Shell::default_for_platform())
+}
+
+/// Statement pointing KUBECONFIG at a cluster for a given shell.
+pub fn export_kubeconfig_for(name: &str, shell: Shell) -> String {
+    shell.export("KUBECONFIG", &kubeconfig_path(name).to_string_lossy())
+}
+
+/// Returns a command for a tool that reads the cluster from KUBECONFIG.
+pub fn command(name: &str, program: &str) -> Command {
+    let mut command = Command::new(program);
+    command.env("KUBECONFIG", kubeconfig_path(name));
+
+    command
+}
+
+/// Runs kubectl with the given arguments and captures its output.
+pub fn output(name: &str, args: &[&str]) -> Result<Output> {
+    Command::new("kubectl")
+        .arg("--kubeconfig")
+        .arg(kubeconfig_path(name))
+        .args(args)
+        .logged()
+        .output()
+        .map_err(|e| spawn_error("kubectl", e))
+}
+
+/// Runs kubectl with the given arguments attached to the terminal.
+pub fn run(name: &str, args: &[String]) -> Result<ExitStatus> {
+    Command::new("kubectl")
+        .arg("--kubeconfig")
+        .arg(kubeconfig_path(name))
+        .args(args)
+        .logged()
+        .status()
+        .map_err(|e| spawn_error("kubectl", e))
+}
+
+/// Applies a manifest passed through kubectl's standard input.
+pub fn apply_manifest(name: &str, manifest: &str) -> Result<Output> {
+    output_with_input(name, &["apply", "-f", "-"], manifest)
+}
+
+/// Runs kubectl with the given arguments and `input` on its standard input.
+pub fn output_with_input(name: &str, args: &[&str], input: &str) -> Result<Output> {
+    let mut cmd = Command::new("kubectl")
+        .arg("--kubeconfig")
+        .arg(kubeconfig_path(name))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged()
+        .spawn()
+        .map_err(|e| spawn_error("kubectl", e))?;
+
+    cmd.stdin.as_mut().unwrap().write_all(input.as_bytes())?;
+
+    Ok(cmd.wait_with_output()?)
+}
+
+/// Returns true if the API server answers its health endpoint.
+pub fn api_server_healthy(name: &str) -> bool {
+    match output(name, &["get", "--raw", "/healthz", "--request-timeout=5s"]) {
+        Ok(out) => out.status.success(),
+        Err(_) => false,
+    }
+}
+
+/// Polls the API server until it is healthy or `timeout` seconds have passed.
+pub fn wait_for_api_server(name: &str, timeout: u64) -> bool {
+    let deadline = time::Instant::now() + time::Duration::from_secs(timeout);
+    while time::Instant::now() < deadline {
+        if api_server_healthy(name) {
+            return true;
+        }
+        thread::sleep(time::Duration::from_secs(2));
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kubectl::Shell;
+
+    #[test]
+    fn test_shell_export() {
+        let path = "/home/me/.hake/dev/kubeconfig";
+        assert_eq!(
+            Shell::parse("bash").unwrap().export("KUBECONFIG", path),
+            "export KUBECONFIG=/home/me/.hake/dev/kubeconfig"
+        );
+        assert_eq!(
+            Shell::parse("fish").unwrap().export("KUBECONFIG", path),
+            "set -gx KUBECONFIG \"/home/me/.hake/dev/kubeconfig\";"
+        );
+        assert_eq!(
+            Shell::parse("powershell")
+                .unwrap()
+                .export("KUBECONFIG", "C:\\kubeconfig"),
+            "$env:KUBECONFIG = \"C:\\kubeconfig\""
+        );
+        assert_eq!(
+            Shell::parse("cmd")
+                .unwrap()
+                .export("KUBECONFIG", "C:\\kubeconfig"),
+            "SET KUBECONFIG=C:\\kubeconfig"
+        );
+        assert!(Shell::parse("tcsh").is_err());
+    }
+}
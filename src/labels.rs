@@ -0,0 +1,145 @@
+// Labels of a cluster, like ticket=DEV-123 or owner=ana, kept in its metadata
+// to tell who a cluster on a shared machine is for and to filter `list` by.
+use anyhow::{anyhow, Result};
+
+use std::collections::BTreeMap;
+
+use crate::cluster;
+use crate::dryrun;
+use crate::metadata::ClusterMetadata;
+
+pub type Labels = BTreeMap<String, String>;
+
+/// Reads a key=value label.
+pub fn parse(label: &str) -> Result<(String, String)> {
+    match label.split_once('=') {
+        Some((key, value)) if valid_key(key) => Ok((String::from(key), String::from(value))),
+        _ => Err(anyhow!(
+            "Invalid label {}, expected key=value with a key of letters, digits, '-', '_', '.' or '/'",
+            label
+        )),
+    }
+}
+
+fn valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c))
+}
+
+/// Reads labels given as key=value.
+pub fn parse_all(labels: &[String]) -> Result<Labels> {
+    labels.iter().map(|label| parse(label)).collect()
+}
+
+/// Labels like key=value,other=value, or - without any.
+pub fn format(labels: &Labels) -> String {
+    if labels.is_empty() {
+        return String::from("-");
+    }
+
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Whether the labels have every key=value of the selector.
+pub fn matches(labels: &Labels, selector: &Labels) -> bool {
+    selector
+        .iter()
+        .all(|(key, value)| labels.get(key) == Some(value))
+}
+
+/// Applies key=value changes to labels, and removes the ones given as key-.
+fn change(labels: &mut Labels, changes: &[String]) -> Result<()> {
+    for change in changes {
+        match change.strip_suffix('-') {
+            Some(key) if !change.contains('=') && valid_key(key) => {
+                labels.remove(key);
+            }
+            _ => {
+                let (key, value) = parse(change)?;
+                labels.insert(key, value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Changes the labels of a cluster and returns them.
+pub fn annotate(name: &str, changes: &[String]) -> Result<Labels> {
+    cluster::ensure_exists(name)?;
+    let mut metadata = ClusterMetadata::read(name).ok_or_else(|| {
+        anyhow!(
+            "Cluster {} was created by an older version of hake and has no metadata",
+            name
+        )
+    })?;
+    if changes.is_empty() {
+        return Ok(metadata.labels);
+    }
+    change(&mut metadata.labels, changes)?;
+    if dryrun::enabled() {
+        dryrun::print(&format!(
+            "would label {}: {}",
+            name,
+            format(&metadata.labels)
+        ));
+        return Ok(metadata.labels);
+    }
+    metadata.write(name)?;
+
+    Ok(metadata.labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::labels::{change, format, matches, parse, parse_all, Labels};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("ticket=DEV-123").unwrap(),
+            (String::from("ticket"), String::from("DEV-123"))
+        );
+        assert_eq!(
+            parse("note=").unwrap(),
+            (String::from("note"), String::new())
+        );
+        assert!(parse("ticket").is_err());
+        assert!(parse("=DEV-123").is_err());
+        assert!(parse("my ticket=DEV-123").is_err());
+    }
+
+    #[test]
+    fn test_change() {
+        let mut labels =
+            parse_all(&[String::from("owner=ana"), String::from("ticket=DEV-1")]).unwrap();
+        change(
+            &mut labels,
+            &[String::from("ticket=DEV-2"), String::from("owner-")],
+        )
+        .unwrap();
+        assert_eq!(format(&labels), "ticket=DEV-2");
+        assert!(change(&mut labels, &[String::from("-")]).is_err());
+        assert_eq!(format(&Labels::new()), "-");
+    }
+
+    #[test]
+    fn test_matches() {
+        let labels = parse_all(&[String::from("owner=ana"), String::from("ticket=DEV-1")]).unwrap();
+        assert!(matches(&labels, &Labels::new()));
+        assert!(matches(
+            &labels,
+            &parse_all(&[String::from("owner=ana")]).unwrap()
+        ));
+        assert!(!matches(
+            &labels,
+            &parse_all(&[String::from("owner=joe")]).unwrap()
+        ));
+    }
+}
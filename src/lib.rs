@@ -0,0 +1,89 @@
+//! hake creates and manages local kind clusters and DigitalOcean Kubernetes
+//! clusters. The `hake` binary is a thin front end over this library, so the
+//! same operations can be embedded in test harnesses:
+//!
+//! ```no_run
+//! use hake::cluster::{self, CreateOptions};
+//!
+//! let mut opts = CreateOptions::new("integration");
+//! opts.with_loadbalancer = true;
+//! cluster::create(opts)?;
+//! cluster::delete("integration")?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+pub mod add;
+pub mod arch;
+pub mod auth;
+pub mod batch;
+pub mod bench;
+pub mod ci;
+pub mod cluster;
+pub mod completions;
+pub mod config;
+pub mod cost;
+pub mod current;
+pub mod dashboard;
+pub mod deadline;
+pub mod devconfig;
+pub mod r#do;
+pub mod doctor;
+pub mod dryrun;
+pub mod error;
+pub mod forward;
+pub mod gitops;
+pub mod history;
+pub mod hooks;
+pub mod http;
+pub mod image_cache;
+pub mod info;
+pub mod kind;
+pub mod kind_binary;
+pub mod kubeconfig;
+pub mod kubectl;
+pub mod labels;
+pub mod lock;
+pub mod logging;
+pub mod logs;
+pub mod metadata;
+pub mod names;
+pub mod node;
+pub mod notify;
+pub mod plugin;
+pub mod progress;
+pub mod pull_secret;
+pub mod refresh;
+pub mod remote_state;
+pub mod rootless;
+pub mod runtime;
+pub mod self_update;
+pub mod serve;
+pub mod snapshot;
+pub mod state;
+pub mod status;
+pub mod table;
+pub mod ttl;
+pub mod ui;
+pub mod watch;
+
+use std::path::PathBuf;
+
+/// Directory holding the configuration of every cluster, ~/.hake, or
+/// %USERPROFILE%\.hake on Windows.
+pub fn get_config_dir() -> PathBuf {
+    dirs::home_dir()
+        .expect("User does not have a home")
+        .join(".hake")
+}
+
+/// Configuration directory of a cluster.
+pub fn cluster_dir(name: &str) -> PathBuf {
+    get_config_dir().join(name)
+}
+
+/// Name of the user running hake, as the owner of the clusters it creates.
+pub fn user_name() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|user| !user.is_empty())
+}
@@ -0,0 +1,98 @@
+// Lock files that keep two hake processes from working on the same cluster at
+// once, like parallel CI jobs creating the same cluster. Operations on a
+// cluster share a global lock that `clean` and `gc`, which touch every
+// cluster, take exclusively. The locks are released when the process exits,
+// even if it crashes.
+use anyhow::Result;
+use fs2::FileExt;
+
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use crate::error::HakeError;
+
+const GLOBAL: &str = "hake";
+
+/// Held locks, released when dropped.
+#[derive(Debug)]
+pub struct Lock {
+    _files: Vec<File>,
+}
+
+/// Hidden so it is not taken for a cluster directory.
+fn lock_dir() -> PathBuf {
+    crate::get_config_dir().join(".locks")
+}
+
+/// Takes a lock without waiting, returning None when someone else holds it.
+fn try_lock(dir: &Path, name: &str, exclusive: bool) -> Result<Option<File>> {
+    fs::create_dir_all(dir)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(format!("{}.lock", name)))?;
+    let locked = if exclusive {
+        FileExt::try_lock_exclusive(&file)
+    } else {
+        FileExt::try_lock_shared(&file)
+    };
+
+    match locked {
+        Ok(()) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn in_progress(target: &str) -> anyhow::Error {
+    HakeError::OperationInProgress(String::from(target)).into()
+}
+
+/// Locks a cluster for an operation that changes it.
+pub fn cluster(name: &str) -> Result<Lock> {
+    let dir = lock_dir();
+    let global = try_lock(&dir, GLOBAL, false)?.ok_or_else(|| in_progress("every cluster"))?;
+    // the global lock file is named so no cluster can share it
+    let cluster = try_lock(&dir, &format!("cluster-{}", name), true)?
+        .ok_or_else(|| in_progress(&format!("cluster {}", name)))?;
+
+    Ok(Lock {
+        _files: vec![global, cluster],
+    })
+}
+
+/// Locks every cluster, for operations that go through all of them.
+pub fn all() -> Result<Lock> {
+    let global = try_lock(&lock_dir(), GLOBAL, true)?.ok_or_else(|| in_progress("a cluster"))?;
+
+    Ok(Lock {
+        _files: vec![global],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lock::try_lock;
+    use std::env;
+
+    #[test]
+    fn test_try_lock() {
+        let dir = env::temp_dir().join(format!("hake-lock-test-{}", std::process::id()));
+
+        let shared = try_lock(&dir, "global", false).unwrap();
+        assert!(shared.is_some());
+        assert!(try_lock(&dir, "global", false).unwrap().is_some());
+        assert!(try_lock(&dir, "global", true).unwrap().is_none());
+        drop(shared);
+
+        let exclusive = try_lock(&dir, "cluster-foo", true).unwrap();
+        assert!(exclusive.is_some());
+        assert!(try_lock(&dir, "cluster-foo", true).unwrap().is_none());
+        assert!(try_lock(&dir, "cluster-bar", true).unwrap().is_some());
+        drop(exclusive);
+        assert!(try_lock(&dir, "cluster-foo", true).unwrap().is_some());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
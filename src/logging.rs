@@ -0,0 +1,117 @@
+// Logging of what hake is doing, through tracing. Informational messages are
+// printed as they always were, the other levels get a prefix.
+use console::{Style, Term};
+use std::env;
+use std::fmt;
+use std::io;
+use std::process::Command;
+use tracing::{debug, Event, Level, Subscriber};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+struct Format {
+    /// Starts every line with the time, for CI logs.
+    timestamps: bool,
+}
+
+impl<S, N> FormatEvent<S, N> for Format
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        writer: &mut dyn fmt::Write,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        if self.timestamps {
+            write!(
+                writer,
+                "{} ",
+                crate::ci::format_timestamp(crate::ttl::now())
+            )?;
+        }
+        let level = *event.metadata().level();
+        let prefix = match level {
+            Level::ERROR => Some(Style::new().red().apply_to("error:")),
+            Level::WARN => Some(Style::new().yellow().apply_to("warning:")),
+            Level::INFO => None,
+            Level::DEBUG => Some(Style::new().dim().apply_to("debug:")),
+            Level::TRACE => Some(Style::new().dim().apply_to("trace:")),
+        };
+        if let Some(prefix) = prefix {
+            write!(writer, "{} ", prefix)?;
+        }
+        ctx.format_fields(writer, event)?;
+        writeln!(writer)
+    }
+}
+
+/// Maps the number of -v flags, or -q, to the most detailed level logged.
+pub fn level(verbosity: u64, quiet: bool) -> Level {
+    match (quiet, verbosity) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    }
+}
+
+/// Logs to stderr, so the output of commands like `config` stays clean.
+pub fn init(level: Level, timestamps: bool) {
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .event_format(Format { timestamps })
+        .init();
+}
+
+/// Whether the output is styled: not with --no-color or a non-empty NO_COLOR,
+/// and not when it goes to a pipe or a file, like a CI log.
+fn colors_enabled(no_color: bool, no_color_env: Option<&str>, terminals: bool) -> bool {
+    !no_color && !matches!(no_color_env, Some(value) if !value.is_empty()) && terminals
+}
+
+/// Turns off the styling of the output when it should not be styled.
+pub fn init_colors(no_color: bool) {
+    let terminals = Term::stdout().is_term() && Term::stderr().is_term();
+    if !colors_enabled(no_color, env::var("NO_COLOR").ok().as_deref(), terminals) {
+        console::set_colors_enabled(false);
+    }
+}
+
+/// Logs external commands at debug level before running them.
+pub trait LogCommand {
+    fn logged(&mut self) -> &mut Self;
+}
+
+impl LogCommand for Command {
+    fn logged(&mut self) -> &mut Self {
+        debug!("running {:?}", self);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logging::{colors_enabled, level};
+    use tracing::Level;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(0, false), Level::INFO);
+        assert_eq!(level(1, false), Level::DEBUG);
+        assert_eq!(level(3, false), Level::TRACE);
+        assert_eq!(level(2, true), Level::WARN);
+    }
+
+    #[test]
+    fn test_colors_enabled() {
+        assert!(colors_enabled(false, None, true));
+        assert!(colors_enabled(false, Some(""), true));
+        assert!(!colors_enabled(true, None, true));
+        assert!(!colors_enabled(false, Some("1"), true));
+        assert!(!colors_enabled(false, None, false));
+    }
+}
@@ -0,0 +1,146 @@
+// Logs of the node containers of a kind cluster, and of the services running
+// inside them, with every line prefixed by the node it comes from.
+use anyhow::{anyhow, Result};
+use console::Style;
+
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::error::spawn_error;
+use crate::kind::Kind;
+use crate::logging::LogCommand;
+use crate::runtime;
+
+pub const SERVICES: &[&str] = &["kubelet", "containerd"];
+
+/// What to read and how much of it.
+#[derive(Debug, Default)]
+pub struct LogOptions {
+    /// Node to read from, like control-plane or worker2. All of them by default.
+    pub node: Option<String>,
+    /// Service whose journal is read instead of the container output.
+    pub service: Option<String>,
+    pub follow: bool,
+    /// Number of lines from the end to start with.
+    pub tail: Option<u32>,
+}
+
+/// Name of a node without the cluster prefix, like control-plane.
+pub(crate) fn short_name<'a>(container: &'a str, cluster: &str) -> &'a str {
+    container
+        .strip_prefix(cluster)
+        .and_then(|n| n.strip_prefix('-'))
+        .unwrap_or(container)
+}
+
+fn log_command(container: &str, opts: &LogOptions) -> Command {
+    let mut command = runtime::command();
+    match &opts.service {
+        Some(service) => {
+            command.args(["exec", container, "journalctl", "--no-pager", "-u", service]);
+            if opts.follow {
+                command.arg("--follow");
+            }
+            if let Some(tail) = opts.tail {
+                command.args(["--lines", &tail.to_string()]);
+            }
+        }
+        None => {
+            command.arg("logs");
+            if opts.follow {
+                command.arg("--follow");
+            }
+            if let Some(tail) = opts.tail {
+                command.args(["--tail", &tail.to_string()]);
+            }
+            command.arg(container);
+        }
+    }
+
+    command
+}
+
+/// Prints the lines read from a child process, each one after a prefix.
+pub(crate) fn prefix_lines<R: Read + Send + 'static>(
+    prefix: String,
+    reader: R,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(|line| line.ok()) {
+            println!("{} {}", prefix, line);
+        }
+    })
+}
+
+/// Prints the logs of the nodes of a cluster, until they end or, with
+/// `follow`, until interrupted.
+pub fn logs(cluster: &str, opts: &LogOptions) -> Result<()> {
+    if let Some(service) = &opts.service {
+        if !SERVICES.contains(&service.as_str()) {
+            return Err(anyhow!(
+                "Unknown service {}, expected one of: {}",
+                service,
+                SERVICES.join(", ")
+            ));
+        }
+    }
+
+    let containers: Vec<String> = Kind::get_node_containers(cluster)?
+        .into_iter()
+        .map(|(container, _)| container)
+        .filter(|container| match &opts.node {
+            Some(node) => short_name(container, cluster) == node || container == node,
+            None => true,
+        })
+        .collect();
+    if containers.is_empty() {
+        return Err(match &opts.node {
+            Some(node) => anyhow!("Cluster {} has no node {}", cluster, node),
+            None => anyhow!("Cluster {} has no node containers", cluster),
+        });
+    }
+
+    let cyan = Style::new().cyan();
+    let mut children = vec![];
+    let mut readers = vec![];
+    for container in containers.iter() {
+        let mut child = log_command(container, opts)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .logged()
+            .spawn()
+            .map_err(|e| spawn_error(runtime::binary(), e))?;
+        // a single node needs no prefix
+        let prefix = if containers.len() > 1 {
+            cyan.apply_to(format!("[{}]", short_name(container, cluster)))
+                .to_string()
+        } else {
+            String::new()
+        };
+        readers.push(prefix_lines(prefix.clone(), child.stdout.take().unwrap()));
+        readers.push(prefix_lines(prefix, child.stderr.take().unwrap()));
+        children.push(child);
+    }
+
+    for reader in readers {
+        let _ = reader.join();
+    }
+    for mut child in children {
+        child.wait()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logs::short_name;
+
+    #[test]
+    fn test_short_name() {
+        assert_eq!(short_name("dev-control-plane", "dev"), "control-plane");
+        assert_eq!(short_name("dev-worker2", "dev"), "worker2");
+        assert_eq!(short_name("other-worker", "dev"), "other-worker");
+    }
+}
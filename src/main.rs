@@ -1,195 +1,912 @@
-use anyhow::Result;
-
-mod add;
-mod r#do;
-mod kind;
+use anyhow::{anyhow, Result};
 
+use std::env;
 use std::fs;
-use std::path::Path;
-use std::vec::Vec;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::time::Instant;
 
-use console::Style;
+use console::{Style, Term};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::FuzzySelect;
 
-use crate::kind::Kind;
+use hake::cluster::{self, ClusterType, CreateOptions, DEFAULT_NAME};
+use hake::config::UserConfig;
+use hake::error::{self, HakeError};
+use hake::logs::LogOptions;
+use hake::{
+    add, auth, batch, bench, ci, completions, cost, current, devconfig, doctor, dryrun, forward,
+    history, hooks, info, kind_binary, kubeconfig, kubectl, labels, lock, logging, names, notify,
+    plugin, progress, refresh, self_update, serve, status, table, ttl, ui, watch,
+};
+use structopt::clap::{AppSettings, ArgMatches, Shell};
 use structopt::StructOpt;
-
-const DEFAULT_NAME: &str = "hake-default";
-const DEFAULT_PROVIDER: &str = "kind";
+use tracing::{info, warn};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "Kind")]
 /// The kind starter with simpler advanced options.
+struct Cli {
+    /// Prints what would be done without changing anything
+    #[structopt(long, global = true)]
+    dry_run: bool,
+
+    /// Logs more details: -v shows the commands that run and the output of kind, -vv everything
+    #[structopt(short, long, parse(from_occurrences), global = true)]
+    verbose: u64,
+
+    /// Only logs warnings and errors
+    #[structopt(short, long, global = true)]
+    quiet: bool,
+
+    /// CI mode: no colors, spinners or prompts, timestamped logs and time limits on every step.
+    /// Also turned on by CI=true
+    #[structopt(long, global = true)]
+    ci: bool,
+
+    /// Reports the progress of long operations as text, or as JSON lines on stdout with the
+    /// phase, percent, message and timestamp of every step
+    #[structopt(long, global = true, default_value = "text", possible_values = progress::FORMATS)]
+    progress: String,
+
+    /// Sends a desktop notification when create, delete or upgrade finishes. Also turned on by
+    /// notify = true in the configuration
+    #[structopt(long, global = true)]
+    notify: bool,
+
+    /// Prints without colors, also when NO_COLOR is set or the output is not a terminal
+    #[structopt(long, global = true)]
+    no_color: bool,
+
+    /// Skips the hooks of the configuration file, like a pre_delete script that fails
+    #[structopt(long, global = true)]
+    no_hooks: bool,
+    #[structopt(subcommand)]
+    cmd: Opt,
+}
+
+#[derive(StructOpt, Debug)]
 enum Opt {
     /// Creates a kind cluster
-    Create {
+    Create(Box<CreateOptions>),
+    /// Creates and deletes a cluster a number of times and prints the timings of every phase
+    Bench {
+        /// Number of times the cluster is created and deleted
+        #[structopt(long, default_value = "5")]
+        runs: u32,
+
+        #[structopt(flatten)]
+        opts: Box<CreateOptions>,
+    },
+    /// Keeps a cluster in line with a spec file, reconciling it on every change
+    Watch {
+        /// Spec file with the options of create, in YAML
+        #[structopt(short, long, default_value = watch::DEFAULT_SPEC, parse(from_os_str))]
+        file: PathBuf,
+
+        /// Reconciles once and exits, instead of watching the file
+        #[structopt(long)]
+        once: bool,
+
+        /// Deletes and creates the cluster again when its nodes change
+        #[structopt(long)]
+        yes: bool,
+    },
+    /// Deletes and creates a cluster again with the options it was created with
+    Recreate {
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+    },
+    /// Moves a cluster to a newer Kubernetes version
+    Upgrade {
         /// Name of the cluster
         #[structopt(long, default_value = DEFAULT_NAME)]
         name: String,
 
-        /// Configures access to an ECR private registry
+        /// Kubernetes version, like v1.19.1 for kind or 1.18.8-do.0 for DigitalOcean,
+        /// where it defaults to the newest one available
         #[structopt(long)]
-        ecr: Option<String>,
+        version: Option<String>,
+    },
+    /// Changes the number of nodes of a DigitalOcean node pool
+    Scale {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
 
-        /// Configure access to local Docker registry
+        /// Number of nodes
         #[structopt(long)]
-        use_local_registry: Option<String>,
+        count: u16,
 
-        /// Pass extra port mappings
+        /// Name or id of the node pool, needed when the cluster has several
         #[structopt(long)]
-        extra_port_mappings: Option<String>,
-
-        /// Verbose
-        #[structopt(short)]
-        verbose: bool,
+        pool: Option<String>,
+    },
+    /// Manages the node pools of a DigitalOcean cluster
+    NodePool(NodePoolCommand),
+    /// Runs commands in the node containers of a kind cluster
+    Node(NodeCommand),
+    /// Keeps kubectl port-forwards to a cluster running in the background
+    Forward(ForwardCommand),
+    /// Prints a login token for the dashboard of a cluster and opens it
+    Dashboard {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
 
-        /// Provider
-        #[structopt(long, default_value = DEFAULT_PROVIDER)]
-        provider: String,
+        /// Local port of kubectl proxy
+        #[structopt(long, default_value = "8001")]
+        port: u16,
 
-        /// Metadata
+        /// Does not open a browser
         #[structopt(long)]
-        metadata: Option<String>,
+        no_open: bool,
     },
-    /// Recreates a cluster by name
-    Recreate {
+    /// Copies files between the host and a node, like cp ./bridge worker:/opt/cni/bin/
+    Cp {
+        /// Name of the cluster
         #[structopt(long, default_value = DEFAULT_NAME)]
         name: String,
+
+        /// Path on the host, or in a node as <node>:<path>
+        source: String,
+
+        /// Path on the host, or in a node as <node>:<path>
+        destination: String,
     },
     /// Deletes a kind cluster
     Delete {
         /// Name of the cluster
         #[structopt(long, default_value = DEFAULT_NAME)]
         name: String,
+
+        /// Deletes every cluster, of every provider
+        #[structopt(long, conflicts_with = "pattern")]
+        all: bool,
+
+        /// Deletes the clusters whose names match a glob, like 'pr-*'
+        #[structopt(long = "match")]
+        pattern: Option<String>,
+
+        /// Takes the --match pattern as a regular expression
+        #[structopt(long)]
+        regex: bool,
+
+        /// Does not ask for confirmation before deleting several clusters
+        #[structopt(short, long)]
+        yes: bool,
     },
     /// Get cluster configuration
     Config {
         /// name of the cluster
         #[structopt(long, default_value = DEFAULT_NAME)]
         name: String,
+
+        /// Shell to print the statement for: bash, zsh, fish, powershell or cmd
+        #[structopt(long)]
+        shell: Option<String>,
+
+        /// Prints the contents of the kubeconfig instead of its path
+        #[structopt(long, conflicts_with = "shell")]
+        raw: bool,
+
+        /// Prints the contents of the kubeconfig encoded in base64, for CI secrets, or the field
+        /// given with --field
+        #[structopt(long, conflicts_with_all = &["shell", "raw"])]
+        base64: bool,
+
+        /// Prints one field of the kubeconfig, with certificates and keys in PEM
+        #[structopt(long, possible_values = kubeconfig::FIELDS, conflicts_with_all = &["shell", "raw"])]
+        field: Option<String>,
+
+        /// Downloads the kubeconfig of a DigitalOcean cluster again, with new credentials
+        #[structopt(long)]
+        refresh: bool,
+    },
+    /// Prints a Tiltfile or skaffold.yaml pointing at a cluster and its local registry
+    Devconfig {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Tool to configure: tilt or skaffold
+        #[structopt(long)]
+        tool: String,
+    },
+    /// Runs kubectl against a cluster, like: hake kubectl --name foo -- get pods
+    Kubectl {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Arguments passed to kubectl
+        #[structopt(last = true)]
+        args: Vec<String>,
+    },
+    /// Opens a shell with KUBECONFIG pointing at a cluster
+    Shell {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+    },
+    /// Makes a cluster the current context of ~/.kube/config
+    Use {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+    },
+    /// Prints the cluster selected with `use`, for shell prompts
+    Current {
+        /// Prints only its name
+        #[structopt(long)]
+        short: bool,
     },
     /// Display list of known clusters
-    List,
-    /// Removes clusters that are not reachable anymore
+    List {
+        /// Also lists the clusters on DigitalOcean that are missing locally, as remote only
+        #[structopt(long)]
+        remote: bool,
+
+        /// Only the remote clusters with this tag, like owner:me
+        #[structopt(long, requires = "remote")]
+        tag: Option<String>,
+
+        /// Lists the clusters in the shared state of the team
+        #[structopt(long, conflicts_with = "remote")]
+        shared: bool,
+
+        /// Only the clusters with this label, like ticket=DEV-123. Can be repeated
+        #[structopt(long = "label", conflicts_with = "shared")]
+        labels: Vec<String>,
+    },
+    /// Opens a terminal UI with the clusters and their live status
+    Ui,
+    /// Serves an HTTP API with JSON to list, create, delete and check clusters, creating and
+    /// deleting them in background jobs
+    Serve {
+        /// Address to listen on
+        #[structopt(long, default_value = serve::DEFAULT_LISTEN)]
+        listen: String,
+    },
+    /// Stops the node containers of a kind cluster
+    Stop {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+    },
+    /// Starts a stopped kind cluster
+    Start {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+    },
+    /// Exports a kind cluster as a portable archive
+    Export {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Path of the archive, defaults to <name>.tar.gz
+        #[structopt(long)]
+        output: Option<String>,
+    },
+    /// Manages a cluster created without hake, a kind cluster by name or a DigitalOcean one by id
+    Adopt {
+        /// Name of the kind cluster, or the name to give the DigitalOcean one
+        #[structopt(long, required_unless = "id")]
+        name: Option<String>,
+
+        /// Id of the DigitalOcean cluster
+        #[structopt(long)]
+        id: Option<String>,
+    },
+    /// Sets labels of a cluster like ticket=DEV-123, or removes them like ticket-. Prints them
+    /// without any
+    Annotate {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Labels to set or remove
+        labels: Vec<String>,
+    },
+    /// Imports a cluster from an archive created with export
+    Import {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Path of the archive
+        #[structopt(long)]
+        archive: String,
+    },
+    /// Prints the logs of the node containers of a kind cluster
+    Logs {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Only this node, like control-plane or worker2
+        #[structopt(long)]
+        node: Option<String>,
+
+        /// Reads the journal of a service inside the nodes: kubelet or containerd
+        #[structopt(long)]
+        service: Option<String>,
+
+        /// Keeps printing new lines
+        #[structopt(short, long)]
+        follow: bool,
+
+        /// Number of lines from the end of the logs to show
+        #[structopt(long)]
+        tail: Option<u32>,
+    },
+    /// Reports the health of a cluster
+    Status {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+    },
+    /// Removes the leftovers of half-deleted clusters
     Clean {
-        /// Force removal of directories
+        /// Removes the leftovers instead of only listing them
         #[structopt(long)]
         force: bool,
+
+        /// Also removes, with --force, kind clusters without hake state, which hake may not have created
+        #[structopt(long, requires = "force")]
+        include_unmanaged: bool,
+    },
+    /// Estimates the cost of the clusters running on a cloud provider
+    Cost {
+        /// Name of the cluster, all of them by default
+        #[structopt(long)]
+        name: Option<String>,
+    },
+    /// Deletes clusters whose time to live has expired
+    Gc {
+        /// Also deletes the expired clusters of everyone in the shared state
+        #[structopt(long)]
+        shared: bool,
     },
+    /// Refreshes the registry credentials of the clusters created with --ecr before they expire
+    RefreshCreds {
+        /// Name of the cluster, all of them by default
+        #[structopt(long, conflicts_with_all = &["watch", "detach"])]
+        name: Option<String>,
+
+        /// Keeps refreshing them every --interval until interrupted
+        #[structopt(long)]
+        watch: bool,
+
+        /// Time between refreshes, like 6h or 90m
+        #[structopt(long, default_value = refresh::DEFAULT_INTERVAL)]
+        interval: String,
+
+        /// Keeps refreshing them in the background, logging to ~/.hake/refresh-creds.log
+        #[structopt(long)]
+        detach: bool,
+
+        /// Stops refreshing them in the background
+        #[structopt(long, conflicts_with_all = &["watch", "detach"])]
+        stop: bool,
+    },
+    /// Checks that docker, kind and the rest of the prerequisites are in place
+    Doctor {
+        /// Also checks the ECR credential helper
+        #[structopt(long)]
+        ecr: bool,
+
+        /// Also checks the credentials of a provider, like digitalocean
+        #[structopt(long)]
+        provider: Option<String>,
+
+        /// Also checks that docker supports IPv6, for ipv6 and dual-stack clusters
+        #[structopt(long)]
+        ipv6: bool,
+    },
+    /// Prints the versions of hake and the tools it uses, its files and credentials, for bug reports
+    Info {
+        /// Prints the report as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Shows the operations run with hake, newest last
+    History {
+        /// Only the operations on this cluster
+        #[structopt(long)]
+        name: Option<String>,
+
+        /// Number of operations to show
+        #[structopt(long, default_value = "20")]
+        limit: usize,
+    },
+    /// Updates hake to its latest release
+    SelfUpdate {
+        /// Only checks whether there is a newer release
+        #[structopt(long)]
+        check: bool,
+    },
+    /// Manages the provider API keys stored in the OS keychain
+    Auth(AuthCommand),
     /// Adds a capability
     Add {
         /// name of the capability
         #[structopt(long)]
         name: String,
     },
+    /// Lists the hake-<command> plugins found in PATH
+    Plugins,
+    /// Prints the completion script of a shell, which completes --name with the existing clusters
+    Completions {
+        /// The shell
+        #[structopt(possible_values = &Shell::variants())]
+        shell: Shell,
+    },
+    /// Prints the names of the clusters, for the completion scripts
+    #[structopt(setting = AppSettings::Hidden)]
+    ClusterNames,
+    #[structopt(external_subcommand)]
+    Plugin(Vec<String>),
 }
 
-enum ClusterType {
-    Kind,
-    DigitalOcean,
+#[derive(StructOpt, Debug)]
+enum NodePoolCommand {
+    /// Lists the node pools of a cluster
+    List {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+    },
+    /// Adds a node pool, like --pool name=gpu,size=s-4vcpu-8gb,count=2,label=role=gpu
+    Add {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// The node pool, in the format of create --node-pool
+        #[structopt(long)]
+        pool: String,
+    },
+    /// Removes a node pool and its nodes
+    Remove {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Name or id of the node pool
+        #[structopt(long)]
+        pool: String,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum NodeCommand {
+    /// Runs a command in a node, like node exec --node worker -- crictl ps
+    Exec {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Node to run the command in, like worker2. The control plane by default
+        #[structopt(long)]
+        node: Option<String>,
+
+        /// Command to run
+        #[structopt(last = true)]
+        command: Vec<String>,
+    },
+    /// Opens a shell in a node
+    Ssh {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Node to open the shell in, like worker2. The control plane by default
+        #[structopt(long)]
+        node: Option<String>,
+    },
 }
 
-fn create(
-    name: String,
-    provider: String,
-    ecr: Option<String>,
-    use_local_registry: Option<String>,
-    extra_port_mapping: Option<String>,
-    metadata: Option<String>,
-    verbose: bool,
+#[derive(StructOpt, Debug)]
+enum ForwardCommand {
+    /// Starts a port-forward in the background, like forward start svc/web 8080:80
+    Start {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Namespace of the service or pod
+        #[structopt(short, long)]
+        namespace: Option<String>,
+
+        /// Service or pod to forward to, like svc/web or pod/web-0
+        target: String,
+
+        /// Ports to forward, like 8080:80
+        #[structopt(required = true)]
+        ports: Vec<String>,
+    },
+    /// Lists the port-forwards of a cluster
+    List {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+    },
+    /// Stops port-forwards, every one of the cluster unless given their ids
+    Stop {
+        /// Name of the cluster
+        #[structopt(long, default_value = DEFAULT_NAME)]
+        name: String,
+
+        /// Ids of the port-forwards, from forward list
+        ids: Vec<u32>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum AuthCommand {
+    /// Stores the API key of a provider, read from stdin or prompted for
+    Login {
+        /// Provider, like digitalocean
+        provider: String,
+    },
+    /// Removes the API key of a provider
+    Logout {
+        /// Provider, like digitalocean
+        provider: String,
+    },
+}
+
+fn config(
+    name: &str,
+    shell: Option<&str>,
+    raw: bool,
+    encode: bool,
+    field: Option<&str>,
+    refresh: bool,
 ) -> Result<()> {
-    let cluster_dir = format!("{}/{}", get_config_dir(), name);
-    if Path::new(&cluster_dir).exists() {
-        println!("Cluster with name {} already exists", name);
+    cluster::ensure_exists(name)?;
+    if refresh {
+        cluster::refresh_kubeconfig(name)?;
+    } else {
+        cluster::refresh_expiring_kubeconfig(name);
+    }
+    if let Some(field) = field {
+        let value = kubeconfig::field(name, field)?;
+        if encode {
+            println!("{}", base64::encode(&value));
+        } else {
+            io::stdout().write_all(&value)?;
+            if !value.ends_with(b"\n") {
+                println!();
+            }
+        }
+        return Ok(());
+    }
+    if raw || encode {
+        let kubeconfig = fs::read(kubectl::kubeconfig_path(name))?;
+        if encode {
+            println!("{}", base64::encode(&kubeconfig));
+        } else {
+            io::stdout().write_all(&kubeconfig)?;
+        }
         return Ok(());
     }
 
-    let cyan = Style::new().cyan();
-    println!("Creating cluster: {}", cyan.apply_to(&name));
+    let shell = match shell {
+        Some(shell) => kubectl::Shell::parse(shell)?,
+        None => kubectl::Shell::default_for_platform(),
+    };
+    println!("{}", kubectl::export_kubeconfig_for(name, shell));
 
-    match &provider[..] {
-        "digitalocean" | "do" => r#do::create(&name, metadata),
-        "kind" => {
-            let mut cluster = Kind::new(&name);
-            cluster.configure_private_registry(ecr);
+    Ok(())
+}
 
-            if let Some(container_name) = use_local_registry {
-                cluster.use_local_registry(&container_name)
-            }
-            if let Some(extra_port_mapping) = extra_port_mapping {
-                cluster.extra_port_mapping(&extra_port_mapping);
-            }
-            cluster.set_verbose(verbose);
+fn devconfig(name: &str, tool: &str) -> Result<()> {
+    cluster::ensure_exists(name)?;
+    let target = devconfig::DevTarget::new(name)?;
+    print!("{}", devconfig::render(tool, &target)?);
 
-            cluster.create()
-        }
-        _ => Ok(()),
+    Ok(())
+}
+
+fn kubectl(name: &str, args: &[String]) -> Result<()> {
+    cluster::ensure_exists(name)?;
+    cluster::refresh_expiring_kubeconfig(name);
+
+    let status = kubectl::run(name, args)?;
+    if !status.success() {
+        process::exit(status.code().unwrap_or(1));
     }
+
+    Ok(())
 }
 
-fn recreate(name: &str) -> Result<()> {
+fn shell(name: &str) -> Result<()> {
+    cluster::ensure_exists(name)?;
+    cluster::refresh_expiring_kubeconfig(name);
+    if let Ok(current) = env::var("HAKE_CLUSTER") {
+        warn!("Already in a shell for cluster {}, nesting", current);
+    }
+
+    let shell = match env::var("SHELL") {
+        Ok(shell) => shell,
+        Err(_) if cfg!(windows) => env::var("COMSPEC").unwrap_or_else(|_| String::from("cmd.exe")),
+        Err(_) => String::from("/bin/sh"),
+    };
+    let ps1 = env::var("PS1").unwrap_or_else(|_| String::from("\\$ "));
+
     let cyan = Style::new().cyan();
-    println!("Recreating cluster: {}", cyan.apply_to(name));
+    info!(
+        "Starting {} for cluster {}, exit to return",
+        shell,
+        cyan.apply_to(name)
+    );
 
-    Kind::recreate(name, false)
+    kubectl::command(name, &shell)
+        .env("HAKE_CLUSTER", name)
+        .env("PS1", format!("(hake:{}) {}", name, ps1))
+        .status()?;
+
+    Ok(())
 }
 
-fn get_config_dir() -> String {
-    let home = String::from(
-        dirs::home_dir()
-            .expect("User does not have a home")
-            .to_str()
-            .expect("User does not have a home"),
+fn use_cluster(name: &str) -> Result<()> {
+    cluster::ensure_exists(name)?;
+    cluster::refresh_expiring_kubeconfig(name);
+
+    kubeconfig::use_context(name)?;
+    current::set(name)?;
+    let cyan = Style::new().cyan();
+    info!(
+        "Switched to context {}",
+        cyan.apply_to(kubeconfig::context_name(name))
     );
 
-    format!("{}/.hake", home)
+    Ok(())
 }
 
-fn cluster_type(name: &str) -> ClusterType {
-    let config_dir = get_config_dir();
-    let cluster_dir = format!("{}/{}", config_dir, name);
-
-    if Path::new(&format!("{}/cluster_uuid", cluster_dir)).exists() {
-        ClusterType::DigitalOcean
+fn list(remote: bool, tag: Option<&str>, shared: bool, labels: &[String]) -> Result<()> {
+    let selector = labels::parse_all(labels)?;
+    if remote {
+        table::print(&cluster::remote_rows(tag, &selector)?);
+    } else if shared {
+        table::print(&cluster::shared_rows()?);
     } else {
-        ClusterType::Kind
+        table::print(&cluster::list_rows(&selector));
     }
+
+    Ok(())
 }
 
-fn delete(name: String) -> Result<()> {
-    let cyan = Style::new().cyan();
-    println!("Deleting cluster: {}", cyan.apply_to(&name));
-    match cluster_type(&name) {
-        ClusterType::Kind => {
-            let cluster = Kind::new(&name);
-            cluster.delete()
+fn annotate(name: &str, changes: &[String]) -> Result<()> {
+    let labels = labels::annotate(name, changes)?;
+    for (key, value) in labels.iter() {
+        println!("{}={}", key, value);
+    }
+
+    Ok(())
+}
+
+fn show_cost(name: Option<String>) -> Result<()> {
+    let names = match name {
+        Some(name) => {
+            cluster::ensure_exists(&name)?;
+            vec![name]
+        }
+        None => cluster::all_clusters(),
+    };
+
+    let mut rows = vec![vec![
+        String::from("NAME"),
+        String::from("HOURLY"),
+        String::from("MONTHLY"),
+        String::from("SO FAR"),
+    ]];
+    rows.extend(cost::cost_rows(&names)?);
+    if rows.len() == 1 {
+        info!("No clusters running on a cloud provider");
+        return Ok(());
+    }
+    table::print(&rows);
+
+    Ok(())
+}
+
+fn status(name: &str) -> Result<()> {
+    cluster::ensure_exists(name)?;
+    match cluster::cluster_type(name) {
+        ClusterType::Kind => status::status(name, true),
+        ClusterType::DigitalOcean => status::status(name, false),
+    }
+}
+
+fn doctor(ecr: bool, provider: Option<String>, ipv6: bool) -> Result<()> {
+    let config = UserConfig::load()?;
+    let is_digitalocean =
+        |p: &Option<String>| matches!(p.as_deref(), Some("digitalocean") | Some("do"));
+
+    let requirements = doctor::Requirements {
+        ecr: ecr || config.kind.ecr.is_some() || config.profiles.values().any(|p| p.ecr.is_some()),
+        digitalocean: is_digitalocean(&provider)
+            || is_digitalocean(&config.provider)
+            || config
+                .profiles
+                .values()
+                .any(|p| is_digitalocean(&p.provider)),
+        ipv6: ipv6
+            || config
+                .profiles
+                .values()
+                .any(|p| matches!(p.ip_family.as_deref(), Some("ipv6") | Some("dual"))),
+    };
+
+    // checks the pinned release of kind, not the one in PATH
+    kind_binary::select(config.kind.version.as_deref())?;
+    doctor::doctor(&requirements)
+}
+
+fn node_pool(command: NodePoolCommand) -> Result<()> {
+    match command {
+        NodePoolCommand::List { name } => {
+            let mut rows = vec![vec![
+                String::from("NAME"),
+                String::from("SIZE"),
+                String::from("NODES"),
+                String::from("LABELS"),
+                String::from("TAINTS"),
+            ]];
+            rows.extend(cluster::node_pools(&name)?);
+            table::print(&rows);
+            Ok(())
+        }
+        NodePoolCommand::Add { name, pool } => cluster::add_node_pool(&name, &pool),
+        NodePoolCommand::Remove { name, pool } => cluster::remove_node_pool(&name, &pool),
+    }
+}
+
+fn node(command: NodeCommand) -> Result<()> {
+    let code = match command {
+        NodeCommand::Exec {
+            name,
+            node,
+            command,
+        } => cluster::node_exec(&name, node.as_deref(), &command)?,
+        NodeCommand::Ssh { name, node } => cluster::node_ssh(&name, node.as_deref())?,
+    };
+    if code != 0 {
+        process::exit(code);
+    }
+
+    Ok(())
+}
+
+fn refresh_creds(
+    name: Option<&str>,
+    watch: bool,
+    interval: &str,
+    detach: bool,
+    stop: bool,
+) -> Result<()> {
+    if stop {
+        return refresh::stop();
+    }
+    if detach {
+        let pid = refresh::detach(interval)?;
+        info!(
+            "Refreshing registry credentials every {} in the background, pid {}, logs in {}",
+            interval,
+            pid,
+            refresh::log_path().display()
+        );
+        return Ok(());
+    }
+    if watch {
+        return refresh::watch(interval);
+    }
+
+    match name {
+        Some(name) => {
+            cluster::ensure_exists(name)?;
+            if !refresh::refresh(name)? {
+                return Err(anyhow!(
+                    "Cluster {} has no registry credentials, it was not created with --ecr",
+                    name
+                ));
+            }
+            info!("Refreshed registry credentials of {}", name);
+        }
+        None => {
+            if refresh::refresh_all() == 0 {
+                info!("No cluster has registry credentials to refresh");
+            }
         }
-        ClusterType::DigitalOcean => r#do::delete(&name),
     }
+
+    Ok(())
 }
 
-fn config(name: &str) {
-    println!("export KUBECONFIG={}/{}/kubeconfig", get_config_dir(), name);
+fn forward(command: ForwardCommand) -> Result<()> {
+    match command {
+        ForwardCommand::Start {
+            name,
+            namespace,
+            target,
+            ports,
+        } => cluster::forward(&name, &target, namespace.as_deref(), &ports),
+        ForwardCommand::List { name } => {
+            cluster::ensure_exists(&name)?;
+            table::print(&forward::list(&name)?);
+            Ok(())
+        }
+        ForwardCommand::Stop { name, ids } => {
+            cluster::ensure_exists(&name)?;
+            forward::stop(&name, &ids)
+        }
+    }
 }
 
-fn all_clusters() -> Vec<String> {
-    let mut clusters = Vec::new();
+fn auth(command: AuthCommand) -> Result<()> {
+    match command {
+        AuthCommand::Login { provider } => {
+            let provider = auth::provider_name(&provider)?;
+            let term = Term::stderr();
+            let token = if term.is_term() && !ci::enabled() {
+                term.write_str(&format!("API key for {}: ", provider))?;
+                term.read_secure_line()?
+            } else {
+                let mut token = String::new();
+                io::stdin().read_line(&mut token)?;
+                token
+            };
+            let token = token.trim();
+            if token.is_empty() {
+                return Err(anyhow!("Empty API key"));
+            }
 
-    if let Ok(config) = Kind::get_config_dir() {
-        let config = Path::new(&config);
-        for entry in fs::read_dir(config).expect("could not read dir") {
-            let entry = entry.unwrap();
-            let entry = entry.file_name().to_str().unwrap().to_string();
-            clusters.push(entry);
+            auth::store_token(provider, token)?;
+            info!("Stored the API key for {} in the keychain", provider);
+            Ok(())
+        }
+        AuthCommand::Logout { provider } => {
+            auth::delete_token(&provider)?;
+            info!("Removed the API key for {} from the keychain", provider);
+            Ok(())
         }
     }
+}
+
+/// Asks the user to confirm an operation that cannot be undone. Without a
+/// terminal to ask, it has to be confirmed with --yes.
+fn confirm(question: &str) -> Result<bool> {
+    let term = Term::stderr();
+    if !term.is_term() || ci::enabled() {
+        return Err(anyhow!("{}: confirm with --yes", question));
+    }
 
-    clusters
+    term.write_str(&format!("{} [y/N] ", question))?;
+    let answer = term.read_line()?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
 }
 
-fn list() {
-    for cluster in all_clusters() {
-        println!("{}", cluster);
+fn delete_many(mut names: Vec<String>, yes: bool) -> Result<()> {
+    names.sort();
+    if names.is_empty() {
+        info!("There are no clusters to delete");
+        return Ok(());
     }
+
+    if !yes && !dryrun::enabled() {
+        eprintln!("{}", names.join("\n"));
+        if !confirm(&format!("Delete these {} clusters?", names.len()))? {
+            return Err(anyhow!("Cancelled"));
+        }
+    }
+
+    cluster::delete_clusters(&names)
 }
 
 fn add(cap: &str) -> Result<()> {
@@ -200,18 +917,15 @@ fn add(cap: &str) -> Result<()> {
     }
 }
 
-fn clean(force: bool) -> Result<()> {
-    let kc = Kind::get_kind_containers()?;
-    let clusters = all_clusters();
-
-    for cluster in clusters {
-        if !kc.iter().any(|c| *c == cluster) {
-            let dir = format!("{}/{}", Kind::get_config_dir()?, cluster);
-            if force {
-                println!("Removing {}", dir);
-                fs::remove_dir_all(dir)?
-            } else {
-                println!("Not removing {}. Use --force", dir);
+/// Asks for the cluster to use when the command takes a name and none was
+/// given, before what the command runs on is recorded or notified.
+fn pick_name(cmd: &mut Opt, name_given: bool) -> Result<()> {
+    // every path hake uses lives in the home directory.
+    dirs::home_dir().ok_or(HakeError::NoHome)?;
+    if !name_given {
+        if let Some(name) = name_mut(cmd) {
+            if let Some(picked) = pick_cluster()? {
+                *name = picked;
             }
         }
     }
@@ -219,32 +933,404 @@ fn clean(force: bool) -> Result<()> {
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let matches = Opt::from_args();
+fn run(cli: Cli) -> Result<()> {
+    let verbose = cli.verbose > 0;
+    let _lock = if cli.dry_run {
+        None
+    } else {
+        lock_for(&cli.cmd)?
+    };
 
-    match matches {
-        Opt::Create {
+    match cli.cmd {
+        Opt::Create(opts) if opts.count.is_some() => {
+            let args: Vec<String> = env::args().skip(1).collect();
+            let count = opts.count.unwrap_or_default();
+            batch::create(
+                &env::current_exe()?,
+                &args,
+                &opts.name,
+                count,
+                opts.parallel,
+            )
+        }
+        Opt::Create(mut opts) => {
+            opts.verbose = verbose;
+            UserConfig::load()?.apply(&mut opts)?;
+            cluster::create(*opts)
+        }
+        Opt::Bench { runs, mut opts } => {
+            opts.verbose = verbose;
+            UserConfig::load()?.apply(&mut opts)?;
+            bench::bench(*opts, runs)
+        }
+        Opt::Watch {
+            file,
+            once: true,
+            yes,
+        } => watch::reconcile(&file, verbose, yes),
+        Opt::Watch { file, yes, .. } => watch::watch(&file, verbose, yes),
+        Opt::Recreate { name } => cluster::recreate(&name, verbose),
+        Opt::Upgrade { name, version } => cluster::upgrade(&name, version, verbose),
+        Opt::Scale { name, count, pool } => cluster::scale(&name, count, pool.as_deref()),
+        Opt::NodePool(command) => node_pool(command),
+        Opt::Node(command) => node(command),
+        Opt::Forward(command) => forward(command),
+        Opt::Cp {
             name,
-            provider,
-            ecr,
-            use_local_registry,
-            extra_port_mappings,
-            verbose,
-            metadata,
-        } => create(
+            source,
+            destination,
+        } => cluster::cp(&name, &source, &destination),
+        Opt::Dashboard {
             name,
-            provider,
-            ecr,
-            use_local_registry,
-            extra_port_mappings,
-            metadata,
-            verbose,
+            port,
+            no_open,
+        } => cluster::dashboard(&name, port, no_open),
+        Opt::Delete { all: true, yes, .. } => delete_many(cluster::all_clusters(), yes),
+        Opt::Delete {
+            pattern: Some(pattern),
+            regex,
+            yes,
+            ..
+        } => delete_many(cluster::matching_clusters(&pattern, regex)?, yes),
+        Opt::Delete { name, .. } => cluster::delete(&name),
+        Opt::Config {
+            name,
+            shell,
+            raw,
+            base64,
+            field,
+            refresh,
+        } => config(
+            &name,
+            shell.as_deref(),
+            raw,
+            base64,
+            field.as_deref(),
+            refresh,
+        ),
+        Opt::Devconfig { name, tool } => devconfig(&name, &tool),
+        Opt::Kubectl { name, args } => kubectl(&name, &args),
+        Opt::Shell { name } => shell(&name),
+        Opt::Use { name } => use_cluster(&name),
+        Opt::Current { short } => {
+            if let Some(current) = current::show(short) {
+                println!("{}", current);
+            }
+            Ok(())
+        }
+        Opt::List {
+            remote,
+            tag,
+            shared,
+            labels,
+        } => list(remote, tag.as_deref(), shared, &labels),
+        Opt::Ui => ui::run(env::current_exe()?),
+        Opt::Serve { listen } => serve::serve(&listen, &env::current_exe()?),
+        Opt::Stop { name } => cluster::stop(&name),
+        Opt::Start { name } => cluster::start(&name),
+        Opt::Export { name, output } => cluster::export(&name, output),
+        Opt::Adopt { name, id } => cluster::adopt(name.as_deref(), id.as_deref()),
+        Opt::Annotate { name, labels } => annotate(&name, &labels),
+        Opt::Import { name, archive } => cluster::import(&name, &archive, verbose),
+        Opt::Status { name } => status(&name),
+        Opt::Logs {
+            name,
+            node,
+            service,
+            follow,
+            tail,
+        } => cluster::logs(
+            &name,
+            &LogOptions {
+                node,
+                service,
+                follow,
+                tail,
+            },
         ),
-        Opt::Recreate { name } => recreate(&name),
-        Opt::Delete { name } => delete(name),
-        Opt::Config { name } => Ok(config(&name)),
-        Opt::List => Ok(list()),
+        Opt::Cost { name } => show_cost(name),
+        Opt::Gc { shared } => cluster::gc(shared),
+        Opt::RefreshCreds {
+            name,
+            watch,
+            interval,
+            detach,
+            stop,
+        } => refresh_creds(name.as_deref(), watch, &interval, detach, stop),
+        Opt::SelfUpdate { check } => self_update::self_update(check),
+        Opt::History { name, limit } => {
+            let entries = history::read()?;
+            table::print(&history::rows(&entries, name.as_deref(), limit, ttl::now()));
+            Ok(())
+        }
+        Opt::Info { json } => {
+            // reports the pinned release of kind, like doctor
+            kind_binary::select(UserConfig::load()?.kind.version.as_deref())?;
+            info::print(&info::collect(), json)
+        }
+        Opt::Doctor {
+            ecr,
+            provider,
+            ipv6,
+        } => doctor(ecr, provider, ipv6),
+        Opt::Auth(command) => auth(command),
         Opt::Add { name } => add(&name),
-        Opt::Clean { force } => clean(force),
+        Opt::Clean {
+            force,
+            include_unmanaged,
+        } => cluster::clean(force, include_unmanaged),
+        Opt::Plugins => {
+            let mut rows = vec![vec![String::from("COMMAND"), String::from("PATH")]];
+            for (command, path) in plugin::list() {
+                rows.push(vec![command, path.display().to_string()]);
+            }
+            table::print(&rows);
+            Ok(())
+        }
+        Opt::Completions { shell } => {
+            let mut script = vec![];
+            Cli::clap().gen_completions_to("hake", shell, &mut script);
+            print!(
+                "{}",
+                completions::complete_names(shell, &String::from_utf8(script)?)
+            );
+            Ok(())
+        }
+        Opt::ClusterNames => {
+            for name in cluster::all_clusters() {
+                println!("{}", name);
+            }
+            Ok(())
+        }
+        Opt::Plugin(args) => match plugin::run(&args)? {
+            0 => Ok(()),
+            code => process::exit(code),
+        },
+    }
+}
+
+/// Whether --name was given to the subcommand, instead of taking its default.
+fn name_given(matches: &ArgMatches) -> bool {
+    let mut matches = matches;
+    while let (_, Some(subcommand)) = matches.subcommand() {
+        matches = subcommand;
+    }
+
+    matches.occurrences_of("name") > 0
+}
+
+/// Name of the existing cluster a subcommand works on, which can be picked
+/// from a list when not given.
+fn name_mut(cmd: &mut Opt) -> Option<&mut String> {
+    match cmd {
+        Opt::Recreate { name }
+        | Opt::Upgrade { name, .. }
+        | Opt::Scale { name, .. }
+        | Opt::NodePool(NodePoolCommand::List { name })
+        | Opt::NodePool(NodePoolCommand::Add { name, .. })
+        | Opt::NodePool(NodePoolCommand::Remove { name, .. })
+        | Opt::Node(NodeCommand::Exec { name, .. })
+        | Opt::Node(NodeCommand::Ssh { name, .. })
+        | Opt::Cp { name, .. }
+        | Opt::Forward(ForwardCommand::Start { name, .. })
+        | Opt::Forward(ForwardCommand::List { name })
+        | Opt::Forward(ForwardCommand::Stop { name, .. })
+        | Opt::Dashboard { name, .. }
+        | Opt::Delete {
+            name,
+            all: false,
+            pattern: None,
+            ..
+        }
+        | Opt::Config { name, .. }
+        | Opt::Devconfig { name, .. }
+        | Opt::Kubectl { name, .. }
+        | Opt::Shell { name }
+        | Opt::Use { name }
+        | Opt::Stop { name }
+        | Opt::Start { name }
+        | Opt::Export { name, .. }
+        | Opt::Logs { name, .. }
+        | Opt::Status { name } => Some(name),
+        _ => None,
+    }
+}
+
+/// Asks which cluster to use when there are several and a terminal to ask
+/// on. Otherwise the default cluster is used, as before.
+fn pick_cluster() -> Result<Option<String>> {
+    let mut names = cluster::all_clusters();
+    if names.len() < 2 || !Term::stderr().is_term() || ci::enabled() {
+        return Ok(None);
+    }
+
+    names.sort();
+    let default = names.iter().position(|n| n == DEFAULT_NAME).unwrap_or(0);
+    let picked = FuzzySelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Cluster")
+        .items(&names)
+        .default(default)
+        .interact_opt()?;
+    match picked {
+        Some(index) => Ok(Some(names.swap_remove(index))),
+        None => Err(anyhow!("No cluster selected")),
+    }
+}
+
+/// Locks what an operation changes, so concurrent runs fail instead of
+/// stepping on each other.
+fn lock_for(cmd: &Opt) -> Result<Option<lock::Lock>> {
+    let name = match cmd {
+        // every cluster of a batch is locked by the process creating it
+        Opt::Create(opts) if opts.count.is_some() => return Ok(None),
+        Opt::Create(opts) | Opt::Bench { opts, .. } => &opts.name,
+        Opt::Clean { force: true, .. }
+        | Opt::Gc { .. }
+        | Opt::Delete { all: true, .. }
+        | Opt::Delete {
+            pattern: Some(_), ..
+        } => return Ok(Some(lock::all()?)),
+        Opt::Recreate { name }
+        | Opt::Upgrade { name, .. }
+        | Opt::Scale { name, .. }
+        | Opt::NodePool(NodePoolCommand::Add { name, .. })
+        | Opt::NodePool(NodePoolCommand::Remove { name, .. })
+        | Opt::Delete { name, .. }
+        | Opt::Stop { name }
+        | Opt::Start { name }
+        | Opt::Import { name, .. }
+        | Opt::Annotate { name, .. }
+        | Opt::Adopt {
+            name: Some(name), ..
+        } => name,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(lock::cluster(name)?))
+}
+
+/// Checks the name of the clusters a command creates before anything is
+/// locked or created, normalizing it first with --normalize-name.
+fn check_new_name(cmd: &mut Opt) -> Result<()> {
+    match cmd {
+        Opt::Create(opts) | Opt::Bench { opts, .. } => {
+            opts.name = names::check(&opts.name, opts.normalize_name)?;
+            let last = format!("{}-{}", opts.name, opts.count.unwrap_or_default());
+            if opts.count.is_some() && last.len() > names::MAX_LENGTH {
+                return Err(anyhow!(
+                    "The last cluster of the batch would be named {}, longer than {} characters",
+                    last,
+                    names::MAX_LENGTH
+                ));
+            }
+        }
+        Opt::Import { name, .. } => names::validate(name)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Operation and cluster notified on the desktop when the operation finishes,
+/// with --notify or `notify = true` in the configuration.
+fn notified_command(cmd: &Opt, notify: bool) -> Option<(&'static str, String)> {
+    let (command, name) = match cmd {
+        Opt::Create(opts) if opts.count.is_none() => ("create", &opts.name),
+        Opt::Delete {
+            name,
+            all: false,
+            pattern: None,
+            ..
+        } => ("delete", name),
+        Opt::Upgrade { name, .. } => ("upgrade", name),
+        _ => return None,
+    };
+    if !notify && !UserConfig::load().is_ok_and(|config| config.notify) {
+        return None;
+    }
+
+    Some((command, name.clone()))
+}
+
+/// Name under which an operation is recorded in the history, for those
+/// that change clusters or credentials.
+fn recorded_command(cmd: &Opt) -> Option<&'static str> {
+    let command = match cmd {
+        Opt::Create(opts) if opts.count.is_none() => "create",
+        Opt::Bench { .. } => "bench",
+        Opt::Recreate { .. } => "recreate",
+        Opt::Upgrade { .. } => "upgrade",
+        Opt::Scale { .. } => "scale",
+        Opt::NodePool(NodePoolCommand::Add { .. }) => "node-pool add",
+        Opt::NodePool(NodePoolCommand::Remove { .. }) => "node-pool remove",
+        Opt::Delete { .. } => "delete",
+        Opt::Use { .. } => "use",
+        Opt::Stop { .. } => "stop",
+        Opt::Start { .. } => "start",
+        Opt::Import { .. } => "import",
+        Opt::Adopt { .. } => "adopt",
+        Opt::Clean { force: true, .. } => "clean",
+        Opt::Gc { .. } => "gc",
+        Opt::Auth(_) => "auth",
+        Opt::Add { .. } => "add",
+        _ => return None,
+    };
+
+    Some(command)
+}
+
+fn main() {
+    let matches = Cli::clap().get_matches();
+    let name_given = name_given(&matches);
+    let mut cli = Cli::from_clap(&matches);
+    if cli.ci || ci::detected() {
+        ci::enable();
+    }
+    logging::init_colors(cli.no_color);
+    logging::init(logging::level(cli.verbose, cli.quiet), ci::enabled());
+    if cli.dry_run {
+        dryrun::enable();
+    }
+    if cli.no_hooks {
+        hooks::disable();
+    }
+    if cli.progress == "json" {
+        progress::enable_json();
+    }
+    let checked = pick_name(&mut cli.cmd, name_given).and_then(|()| check_new_name(&mut cli.cmd));
+
+    let recorded = if cli.dry_run {
+        None
+    } else {
+        recorded_command(&cli.cmd)
+    };
+    let notified = if cli.dry_run || ci::enabled() {
+        None
+    } else {
+        notified_command(&cli.cmd, cli.notify)
+    };
+    let started = (ttl::now(), Instant::now());
+
+    let result = checked.and_then(|()| run(cli));
+    if let Some((command, name)) = notified {
+        let error = result.as_ref().err().map(|err| format!("{:#}", err));
+        let (title, body) = notify::message(command, &name, started.1.elapsed(), error.as_deref());
+        notify::send(&title, &body);
+    }
+    if let Some(command) = recorded {
+        let error = result.as_ref().err().map(|err| format!("{:#}", err));
+        history::record(&history::Entry::new(
+            command,
+            started.0,
+            started.1.elapsed(),
+            error,
+        ));
+    }
+
+    if let Err(err) = result {
+        progress::error(&format!("{:#}", err));
+        let red = Style::new().red();
+        eprintln!("{} {:#}", red.apply_to("Error:"), err);
+        process::exit(error::exit_code(&err));
     }
 }
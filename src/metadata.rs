@@ -0,0 +1,128 @@
+// Metadata of a cluster, stored as metadata.json in its config directory.
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use crate::cluster::CreateOptions;
+use crate::kubectl;
+use crate::labels::Labels;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct ClusterMetadata {
+    pub provider: String,
+    /// Creation time in seconds since the epoch.
+    pub created_at: u64,
+    #[serde(default)]
+    pub kubernetes_version: Option<String>,
+    #[serde(default)]
+    pub node_image: Option<String>,
+    #[serde(default)]
+    pub ecr: Option<String>,
+    #[serde(default)]
+    pub local_registry: Option<String>,
+    /// Expiration time in seconds since the epoch, for clusters with a TTL.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// Command line arguments used to create the cluster.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Options the cluster was created with, after applying the profile and
+    /// the user configuration.
+    #[serde(default)]
+    pub options: Option<CreateOptions>,
+    /// Labels given with --label or `annotate`.
+    #[serde(default)]
+    pub labels: Labels,
+}
+
+fn metadata_path(name: &str) -> PathBuf {
+    crate::cluster_dir(name).join("metadata.json")
+}
+
+impl ClusterMetadata {
+    pub fn new(provider: &str) -> ClusterMetadata {
+        ClusterMetadata {
+            provider: String::from(provider),
+            created_at: crate::ttl::now(),
+            args: std::env::args().skip(1).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Reads the metadata of a cluster. Clusters created by older versions of
+    /// hake do not have one.
+    pub fn read(name: &str) -> Option<ClusterMetadata> {
+        let mut contents = String::new();
+        File::open(metadata_path(name))
+            .ok()?
+            .read_to_string(&mut contents)
+            .ok()?;
+
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn write(&self, name: &str) -> Result<()> {
+        let mut file = File::create(metadata_path(name))?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Gets the version of the API server from `kubectl version`.
+pub fn get_kubernetes_version(name: &str) -> Option<String> {
+    let out = kubectl::output(name, &["version", "-o", "json", "--request-timeout=5s"]).ok()?;
+    let version: Value = serde_json::from_slice(&out.stdout).ok()?;
+
+    version["serverVersion"]["gitVersion"]
+        .as_str()
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cluster::CreateOptions;
+    use crate::metadata::ClusterMetadata;
+
+    #[test]
+    fn test_read_minimal_metadata() {
+        let metadata: ClusterMetadata =
+            serde_json::from_str(r#"{"provider": "kind", "created_at": 1590000000}"#).unwrap();
+
+        assert_eq!(
+            metadata,
+            ClusterMetadata {
+                provider: String::from("kind"),
+                created_at: 1590000000,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_options_round_trip() {
+        let mut opts = CreateOptions::new("test");
+        opts.with_ingress = Some(Some(String::from("contour")));
+        opts.ports = vec![String::from("8080:30080")];
+        opts.workers = 2;
+        opts.reuse = true;
+
+        let metadata = ClusterMetadata {
+            provider: String::from("kind"),
+            options: Some(opts),
+            ..Default::default()
+        };
+        let read: ClusterMetadata =
+            serde_json::from_str(&serde_json::to_string(&metadata).unwrap()).unwrap();
+
+        let opts = read.options.unwrap();
+        assert_eq!(opts.with_ingress, Some(Some(String::from("contour"))));
+        assert_eq!(opts.ports, vec!["8080:30080"]);
+        assert_eq!(opts.workers, 2);
+        assert!(!opts.reuse);
+    }
+}
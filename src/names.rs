@@ -0,0 +1,103 @@
+// Names of new clusters. They end up as DNS labels, in the hostnames of the
+// kind node containers and as DigitalOcean cluster names, so they are checked
+// before anything is created instead of failing halfway inside kind.
+use regex::Regex;
+use tracing::info;
+
+use crate::error::HakeError;
+
+/// Longest name of a cluster: kind appends -control-plane to it for the
+/// hostname of its node, which cannot be longer than 64 characters.
+pub const MAX_LENGTH: usize = 50;
+
+/// Why `name` cannot be the name of a cluster, if it cannot.
+fn problem(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        Some("it is empty")
+    } else if name.len() > MAX_LENGTH {
+        Some("names have at most 50 characters")
+    } else if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        Some("names only have lowercase letters, digits and '-'")
+    } else if name.starts_with('-') || name.ends_with('-') {
+        Some("names start and end with a letter or a digit")
+    } else {
+        None
+    }
+}
+
+/// Checks that `name` is a lowercase DNS label kind accepts.
+pub fn validate(name: &str) -> Result<(), HakeError> {
+    match problem(name) {
+        None => Ok(()),
+        Some(reason) => Err(HakeError::InvalidName {
+            name: String::from(name),
+            reason: String::from(reason),
+        }),
+    }
+}
+
+/// Turns `name` into a valid one: lowercase, with every run of other
+/// characters replaced by '-', and cut to the longest name allowed.
+pub fn normalize(name: &str) -> String {
+    let lowercase = name.to_ascii_lowercase();
+    let dashed = Regex::new("[^a-z0-9]+")
+        .unwrap()
+        .replace_all(&lowercase, "-");
+    let trimmed = dashed.trim_matches('-');
+
+    String::from(trimmed[..trimmed.len().min(MAX_LENGTH)].trim_end_matches('-'))
+}
+
+/// The name a cluster is created with: `name` normalized when asked to,
+/// then validated.
+pub fn check(name: &str, normalize_name: bool) -> Result<String, HakeError> {
+    let name = if normalize_name && problem(name).is_some() {
+        let normalized = normalize(name);
+        info!("Using {} as the name of the cluster", normalized);
+        normalized
+    } else {
+        String::from(name)
+    };
+    validate(&name)?;
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::names::{check, normalize, validate};
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("pr-123").is_ok());
+        assert!(validate(&"a".repeat(50)).is_ok());
+        assert!(validate("").is_err());
+        assert!(validate(&"a".repeat(51)).is_err());
+        assert!(validate("My_Cluster").is_err());
+        assert!(validate("feature.x").is_err());
+        assert!(validate("-dev").is_err());
+        assert!(validate("dev-").is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize("Feature/JIRA-123_Login"),
+            "feature-jira-123-login"
+        );
+        assert_eq!(normalize("--dev--"), "dev");
+        assert_eq!(normalize(&format!("{}-x", "a".repeat(49))), "a".repeat(49));
+        assert_eq!(normalize("__"), "");
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check("pr-123", false).unwrap(), "pr-123");
+        assert!(check("Feature/X", false).is_err());
+        assert_eq!(check("Feature/X", true).unwrap(), "feature-x");
+        assert!(check("__", true).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+// Commands run inside the node containers of a kind cluster, and files copied
+// in and out of them, to debug the kubelet or containerd without looking up
+// the names of the containers.
+use anyhow::{anyhow, Result};
+use console::Term;
+
+use crate::error::{spawn_error, HakeError};
+use crate::kind::Kind;
+use crate::logging::LogCommand;
+use crate::logs::short_name;
+use crate::runtime;
+
+/// Picks the container of a node, like control-plane or worker2, from the
+/// containers of a cluster. The control plane by default.
+fn find_node(containers: &[String], cluster: &str, node: Option<&str>) -> Result<String> {
+    let node = node.unwrap_or("control-plane");
+    containers
+        .iter()
+        .find(|container| short_name(container, cluster) == node || *container == node)
+        .cloned()
+        .ok_or_else(|| {
+            let nodes: Vec<&str> = containers
+                .iter()
+                .map(|container| short_name(container, cluster))
+                .collect();
+            anyhow!(
+                "Cluster {} has no node {}, its nodes are: {}",
+                cluster,
+                node,
+                nodes.join(", ")
+            )
+        })
+}
+
+/// Name of the container of a node of a cluster.
+pub fn container(cluster: &str, node: Option<&str>) -> Result<String> {
+    let containers: Vec<String> = Kind::get_node_containers(cluster)?
+        .into_iter()
+        .map(|(container, _)| container)
+        .collect();
+    if containers.is_empty() {
+        return Err(anyhow!("Cluster {} has no node containers", cluster));
+    }
+
+    find_node(&containers, cluster, node)
+}
+
+fn docker_exec(container: &str, interactive: bool, command: &[String]) -> Result<i32> {
+    let mut docker = runtime::command();
+    docker.arg("exec").arg("-i");
+    if interactive {
+        docker.arg("-t");
+    }
+    let status = docker
+        .arg(container)
+        .args(command)
+        .logged()
+        .status()
+        .map_err(|e| spawn_error(runtime::binary(), e))?;
+
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Runs a command in a node and returns its exit code. It gets a terminal
+/// when hake has one.
+pub fn exec(cluster: &str, node: Option<&str>, command: &[String]) -> Result<i32> {
+    if command.is_empty() {
+        return Err(anyhow!("Missing the command to run, give it after --"));
+    }
+    let container = container(cluster, node)?;
+
+    docker_exec(&container, Term::stdout().is_term(), command)
+}
+
+/// Opens an interactive shell in a node.
+pub fn ssh(cluster: &str, node: Option<&str>) -> Result<i32> {
+    let container = container(cluster, node)?;
+
+    docker_exec(&container, true, &[String::from("bash")])
+}
+
+/// One end of a copy: a path on the host, or a path in a node given as
+/// `<node>:<path>`, like worker:/var/log/containers.
+#[derive(Debug, PartialEq)]
+pub enum Location {
+    Host(String),
+    Node(String, String),
+}
+
+impl Location {
+    pub fn parse(location: &str) -> Location {
+        match location.split_once(':') {
+            // C:\Users is a Windows path, not a node
+            Some((node, _)) if node.len() == 1 && cfg!(windows) => {
+                Location::Host(String::from(location))
+            }
+            Some((node, path))
+                if !node.is_empty() && !node.contains('/') && !node.contains('\\') =>
+            {
+                Location::Node(String::from(node), String::from(path))
+            }
+            _ => Location::Host(String::from(location)),
+        }
+    }
+}
+
+/// Copies files between the host and a node, in either direction, with
+/// `docker cp`.
+pub fn cp(cluster: &str, source: &str, destination: &str) -> Result<()> {
+    let (source, destination) = match (Location::parse(source), Location::parse(destination)) {
+        (Location::Host(from), Location::Node(node, to)) => {
+            (from, format!("{}:{}", container(cluster, Some(&node))?, to))
+        }
+        (Location::Node(node, from), Location::Host(to)) => {
+            (format!("{}:{}", container(cluster, Some(&node))?, from), to)
+        }
+        _ => {
+            return Err(anyhow!(
+                "One of source and destination has to be in a node, like worker:/path, and the other on the host"
+            ))
+        }
+    };
+
+    let status = runtime::command()
+        .args(["cp", &source, &destination])
+        .logged()
+        .status()
+        .map_err(|e| spawn_error(runtime::binary(), e))?;
+    if !status.success() {
+        return Err(HakeError::CommandFailed {
+            command: format!("{} cp {} {}", runtime::binary(), source, destination),
+            message: format!("exit status {}", status),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::{find_node, Location};
+
+    #[test]
+    fn test_parse_location() {
+        assert_eq!(
+            Location::parse("worker:/opt/cni/bin/"),
+            Location::Node(String::from("worker"), String::from("/opt/cni/bin/"))
+        );
+        assert_eq!(
+            Location::parse("./crash.log"),
+            Location::Host(String::from("./crash.log"))
+        );
+        assert_eq!(
+            Location::parse("./logs/a:b"),
+            Location::Host(String::from("./logs/a:b"))
+        );
+        assert_eq!(
+            Location::parse(":/etc"),
+            Location::Host(String::from(":/etc"))
+        );
+    }
+
+    #[test]
+    fn test_find_node() {
+        let containers = vec![
+            String::from("dev-control-plane"),
+            String::from("dev-worker"),
+            String::from("dev-worker2"),
+        ];
+        assert_eq!(
+            find_node(&containers, "dev", None).unwrap(),
+            "dev-control-plane"
+        );
+        assert_eq!(
+            find_node(&containers, "dev", Some("worker2")).unwrap(),
+            "dev-worker2"
+        );
+        assert_eq!(
+            find_node(&containers, "dev", Some("dev-worker")).unwrap(),
+            "dev-worker"
+        );
+        let err = find_node(&containers, "dev", Some("worker3")).unwrap_err();
+        assert!(err.to_string().contains("control-plane, worker, worker2"));
+    }
+}
@@ -0,0 +1,117 @@
+// Desktop notifications when long operations finish, like creating a
+// DigitalOcean cluster, for those who switch to something else meanwhile.
+// They are opt-in, with --notify or `notify = true` in the configuration, and
+// sent with notify-send on Linux and osascript on macOS.
+use tracing::debug;
+
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::logging::LogCommand;
+use crate::ttl;
+
+/// Title and body of the notification of an operation on a cluster.
+pub fn message(
+    command: &str,
+    cluster: &str,
+    elapsed: Duration,
+    error: Option<&str>,
+) -> (String, String) {
+    match error {
+        None => (
+            format!("hake {} finished", command),
+            format!(
+                "{} {} in {}",
+                past_tense(command),
+                cluster,
+                ttl::format_age(elapsed.as_secs())
+            ),
+        ),
+        Some(error) => (
+            format!("hake {} failed", command),
+            format!("{}: {}", cluster, error),
+        ),
+    }
+}
+
+fn past_tense(command: &str) -> String {
+    let mut done = match command.strip_suffix('e') {
+        Some(stem) => format!("{}ed", stem),
+        None => format!("{}ed", command),
+    };
+    done[..1].make_ascii_uppercase();
+
+    done
+}
+
+/// AppleScript showing a notification, with the quotes of the text escaped.
+fn apple_script(title: &str, body: &str) -> String {
+    let quote = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+
+    format!(
+        "display notification \"{}\" with title \"{}\"",
+        quote(body),
+        quote(title)
+    )
+}
+
+/// Shows a desktop notification. Failing to is not worth more than a debug
+/// message, the operation is done either way.
+pub fn send(title: &str, body: &str) {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.args(["-e", &apple_script(title, body)]);
+        command
+    } else if cfg!(windows) {
+        debug!("Desktop notifications are not supported on Windows");
+        return;
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args(["--app-name", "hake", title, body]);
+        command
+    };
+    let sent = command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .logged()
+        .status();
+    if !matches!(sent, Ok(status) if status.success()) {
+        debug!("Could not send a desktop notification: {:?}", sent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notify::{apple_script, message};
+    use std::time::Duration;
+
+    #[test]
+    fn test_message() {
+        assert_eq!(
+            message("create", "dev", Duration::from_secs(400), None),
+            (
+                String::from("hake create finished"),
+                String::from("Created dev in 6m")
+            )
+        );
+        assert_eq!(
+            message("upgrade", "dev", Duration::from_secs(30), None).1,
+            "Upgraded dev in 30s"
+        );
+        assert_eq!(
+            message("delete", "dev", Duration::from_secs(5), Some("API error")),
+            (
+                String::from("hake delete failed"),
+                String::from("dev: API error")
+            )
+        );
+    }
+
+    #[test]
+    fn test_apple_script() {
+        assert_eq!(
+            apple_script("hake create failed", r#"dev: file "a\b" not found"#),
+            r#"display notification "dev: file \"a\\b\" not found" with title "hake create failed""#
+        );
+    }
+}
@@ -0,0 +1,154 @@
+// External subcommands: `hake foo` runs a `hake-foo` executable from PATH, like
+// kubectl plugins, so teams can extend hake without forking it. Plugins get
+// the cluster they work on in their environment.
+use anyhow::{anyhow, Result};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cluster::{self, DEFAULT_NAME};
+use crate::error::spawn_error;
+use crate::logging::LogCommand;
+use crate::{current, kubectl};
+
+const PREFIX: &str = "hake-";
+
+/// Name of the cluster a plugin works on, given with --name.
+pub const ENV_CLUSTER: &str = "HAKE_CLUSTER";
+/// Directory with the configuration of every cluster.
+pub const ENV_CONFIG_DIR: &str = "HAKE_CONFIG_DIR";
+
+fn is_executable(path: &Path) -> bool {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return false,
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        true
+    }
+}
+
+/// Subcommand a plugin file provides, like `foo` for hake-foo or hake-foo.exe.
+fn subcommand(file_name: &str) -> Option<&str> {
+    let name = file_name.strip_prefix(PREFIX)?;
+    let name = if cfg!(windows) {
+        name.strip_suffix(".exe").unwrap_or(name)
+    } else {
+        name
+    };
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Plugins in PATH by subcommand, the first one found winning like in a shell.
+pub fn list() -> BTreeMap<String, PathBuf> {
+    let mut plugins = BTreeMap::new();
+    let path = env::var_os("PATH").unwrap_or_default();
+    for dir in env::split_paths(&path) {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(name) = subcommand(&file_name) {
+                if !plugins.contains_key(name) && is_executable(&entry.path()) {
+                    plugins.insert(String::from(name), entry.path());
+                }
+            }
+        }
+    }
+
+    plugins
+}
+
+/// Cluster given to a plugin with `--name <name>` or `--name=<name>`.
+fn name_arg(args: &[String]) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--name" {
+            return args.next().cloned();
+        }
+        if let Some(name) = arg.strip_prefix("--name=") {
+            return Some(String::from(name));
+        }
+    }
+
+    None
+}
+
+/// Runs the plugin for `args[0]` with the rest of the arguments and returns
+/// its exit code. The cluster is the one given with --name, or the current
+/// one, and its kubeconfig is exported when it exists.
+pub fn run(args: &[String]) -> Result<i32> {
+    let (subcommand, args) = args
+        .split_first()
+        .ok_or_else(|| anyhow!("Missing plugin name"))?;
+    let plugin = list().remove(subcommand).ok_or_else(|| {
+        anyhow!(
+            "Unknown command {}, and there is no {}{} plugin in PATH",
+            subcommand,
+            PREFIX,
+            subcommand
+        )
+    })?;
+
+    let name = name_arg(args)
+        .or_else(current::get)
+        .unwrap_or_else(|| String::from(DEFAULT_NAME));
+    let mut command = Command::new(&plugin);
+    command
+        .args(args)
+        .env(ENV_CLUSTER, &name)
+        .env(ENV_CONFIG_DIR, crate::get_config_dir());
+    if cluster::cluster_exists(&name) {
+        cluster::refresh_expiring_kubeconfig(&name);
+        command.env("KUBECONFIG", kubectl::kubeconfig_path(&name));
+    }
+
+    let status = command
+        .logged()
+        .status()
+        .map_err(|e| spawn_error(&plugin.to_string_lossy(), e))?;
+
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plugin::{name_arg, subcommand};
+
+    #[test]
+    fn test_subcommand() {
+        assert_eq!(subcommand("hake-seed"), Some("seed"));
+        assert_eq!(subcommand("hake-dns-register"), Some("dns-register"));
+        assert_eq!(subcommand("hake-"), None);
+        assert_eq!(subcommand("kind"), None);
+    }
+
+    #[test]
+    fn test_name_arg() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<String>>();
+        assert_eq!(
+            name_arg(&args(&["--name", "dev", "--force"])).as_deref(),
+            Some("dev")
+        );
+        assert_eq!(name_arg(&args(&["--name=dev"])).as_deref(), Some("dev"));
+        assert_eq!(name_arg(&args(&["--force"])), None);
+    }
+}
@@ -0,0 +1,165 @@
+// Reports the steps of long running operations, with a spinner when the
+// output is a terminal and with plain log lines otherwise. With
+// `--progress json` they are JSON lines on stdout instead, for IDE plugins and
+// wrappers to show their own progress.
+use console::Term;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde_derive::Serialize;
+use tracing::info;
+use tracing::level_filters::LevelFilter;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Formats of `--progress`.
+pub const FORMATS: &[&str] = &["text", "json"];
+
+static JSON: AtomicBool = AtomicBool::new(false);
+/// Phase of the running operation and how far along it is, in percent.
+static PHASE: Mutex<(&str, u8)> = Mutex::new(("", 0));
+
+pub fn enable_json() {
+    JSON.store(true, Ordering::Relaxed);
+}
+
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+#[derive(Serialize)]
+struct Event<'a> {
+    /// phase, step or error.
+    event: &'a str,
+    phase: &'a str,
+    percent: u8,
+    message: &'a str,
+    /// Seconds since the epoch.
+    timestamp: u64,
+}
+
+fn event_line(event: &str, phase: &str, percent: u8, message: &str, timestamp: u64) -> String {
+    serde_json::to_string(&Event {
+        event,
+        phase,
+        percent,
+        message,
+        timestamp,
+    })
+    .unwrap()
+}
+
+fn emit(event: &str, message: &str) {
+    let (phase, percent) = *PHASE.lock().unwrap();
+    println!(
+        "{}",
+        event_line(event, phase, percent, message, crate::ttl::now())
+    );
+}
+
+/// Starts a phase of a long running operation, reported as an event in JSON
+/// mode.
+pub fn phase(phase: &'static str, percent: u8, message: &str) {
+    if !json() {
+        return;
+    }
+    *PHASE.lock().unwrap() = (phase, percent);
+    emit("phase", message);
+}
+
+/// Reports the error an operation failed with, in JSON mode.
+pub fn error(message: &str) {
+    if json() {
+        emit("error", message);
+    }
+}
+
+pub struct Progress {
+    spinner: Option<ProgressBar>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Progress::new()
+    }
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        // the spinner is drawn on stderr, where the logs go
+        if !Term::stderr().is_term()
+            || crate::ci::enabled()
+            || json()
+            || LevelFilter::current() < LevelFilter::INFO
+        {
+            return Progress { spinner: None };
+        }
+
+        let spinner = ProgressBar::new_spinner();
+        spinner.set_style(ProgressStyle::default_spinner().template("{spinner:.cyan} {msg}"));
+        spinner.enable_steady_tick(100);
+
+        Progress {
+            spinner: Some(spinner),
+        }
+    }
+
+    pub fn step(&self, message: &str) {
+        match &self.spinner {
+            Some(spinner) => spinner.set_message(message),
+            None if json() => emit("step", message),
+            None => info!("{}...", message),
+        }
+    }
+
+    pub fn finish(&self, message: &str) {
+        match &self.spinner {
+            Some(spinner) => spinner.finish_with_message(message),
+            None if json() => emit("step", message),
+            None => info!("{}", message),
+        }
+    }
+}
+
+/// Gets the step kind is working on from a line of its output, like
+/// " • Ensuring node image (kindest/node:v1.18.2) 🖼".
+pub fn kind_step(line: &str) -> Option<String> {
+    let step = line.trim().strip_prefix('•')?;
+    let step: String = step
+        .chars()
+        .filter(|c| c.is_ascii() && !c.is_ascii_control())
+        .collect();
+    let step = step.trim().trim_end_matches("...").trim();
+
+    if step.is_empty() {
+        None
+    } else {
+        Some(String::from(step))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{event_line, kind_step};
+
+    #[test]
+    fn test_kind_step() {
+        assert_eq!(
+            kind_step(" • Ensuring node image (kindest/node:v1.18.2) 🖼  ..."),
+            Some(String::from("Ensuring node image (kindest/node:v1.18.2)"))
+        );
+        assert_eq!(
+            kind_step(" • Starting control-plane 🕹️  ..."),
+            Some(String::from("Starting control-plane"))
+        );
+        assert_eq!(kind_step(" ✓ Preparing nodes 📦"), None);
+        assert_eq!(kind_step("Creating cluster \"kind\" ..."), None);
+    }
+
+    #[test]
+    fn test_event_line() {
+        assert_eq!(
+            event_line("step", "create", 10, "Starting control-plane", 1600000000),
+            r#"{"event":"step","phase":"create","percent":10,"message":"Starting control-plane","timestamp":1600000000}"#
+        );
+    }
+}
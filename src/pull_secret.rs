@@ -0,0 +1,196 @@
+// Image pull secrets for the workloads of a cluster. Nodes authenticate to
+// the registries of the cluster on their own, but charts and operators that
+// reference a secret in imagePullSecrets need a kubernetes.io/dockerconfigjson
+// one in their namespace, which hake creates and adds to the default
+// ServiceAccount so plain pods use it too.
+use anyhow::{anyhow, Result};
+use base64::encode;
+use serde_json::{json, Value};
+
+use std::thread;
+use std::time::Duration;
+
+use crate::error::HakeError;
+use crate::kubectl;
+
+/// Name of the pull secret created in the namespaces given with --namespace.
+pub const DEFAULT_NAME: &str = "hake-registry";
+/// The controller manager creates the default ServiceAccount of a namespace
+/// shortly after the namespace, this is how long hake waits for it.
+const SERVICE_ACCOUNT_ATTEMPTS: u32 = 30;
+
+/// Reads the <namespace>/<name> of a pull secret.
+pub fn parse(spec: &str) -> Result<(&str, &str)> {
+    match spec.split_once('/') {
+        Some((namespace, name))
+            if !namespace.is_empty() && !name.is_empty() && !name.contains('/') =>
+        {
+            Ok((namespace, name))
+        }
+        _ => Err(anyhow!(
+            "Invalid pull secret {}, expected <namespace>/<name>",
+            spec
+        )),
+    }
+}
+
+fn check(args: &[&str], out: &std::process::Output) -> Result<()> {
+    if !out.status.success() {
+        return Err(HakeError::CommandFailed {
+            command: format!("kubectl {}", args.join(" ")),
+            message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+fn namespace_manifest(namespace: &str) -> String {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": {"name": namespace},
+    })
+    .to_string()
+}
+
+/// Creates a namespace, unless it exists.
+pub fn create_namespace(cluster: &str, namespace: &str) -> Result<()> {
+    let out = kubectl::apply_manifest(cluster, &namespace_manifest(namespace))?;
+    check(&["apply", "-f", "-"], &out)
+}
+
+/// Secret with a docker config.json, as returned by `Kind::get_docker_login`.
+fn manifest(namespace: &str, name: &str, docker_config: &str) -> String {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "type": "kubernetes.io/dockerconfigjson",
+        "metadata": {
+            "name": name,
+            "namespace": namespace,
+            "labels": {"app.kubernetes.io/managed-by": "hake"},
+        },
+        "data": {".dockerconfigjson": encode(docker_config)},
+    })
+    .to_string()
+}
+
+/// Creates or updates a pull secret in a namespace.
+pub fn create(cluster: &str, namespace: &str, name: &str, docker_config: &str) -> Result<()> {
+    let out = kubectl::apply_manifest(cluster, &manifest(namespace, name, docker_config))?;
+    check(&["apply", "-f", "-"], &out)
+}
+
+/// imagePullSecrets of a ServiceAccount with `name` added, None if it has it
+/// already.
+fn with_secret(service_account: &Value, name: &str) -> Option<Value> {
+    let mut secrets = service_account["imagePullSecrets"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    if secrets.iter().any(|secret| secret["name"] == name) {
+        return None;
+    }
+    secrets.push(json!({ "name": name }));
+
+    Some(Value::Array(secrets))
+}
+
+/// Adds a pull secret to the default ServiceAccount of a namespace, keeping
+/// the ones it has.
+pub fn attach(cluster: &str, namespace: &str, name: &str) -> Result<()> {
+    let get = [
+        "get",
+        "serviceaccount",
+        "default",
+        "--namespace",
+        namespace,
+        "-o",
+        "json",
+    ];
+    let mut attempts = 0;
+    let out = loop {
+        let out = kubectl::output(cluster, &get)?;
+        attempts += 1;
+        if out.status.success() {
+            break out;
+        }
+        if attempts == SERVICE_ACCOUNT_ATTEMPTS {
+            return Err(anyhow!(
+                "Namespace {} has no default ServiceAccount after {} seconds",
+                namespace,
+                SERVICE_ACCOUNT_ATTEMPTS
+            ));
+        }
+        thread::sleep(Duration::from_secs(1));
+    };
+
+    let service_account: Value = serde_json::from_slice(&out.stdout)?;
+    let secrets = match with_secret(&service_account, name) {
+        Some(secrets) => secrets,
+        None => return Ok(()),
+    };
+    let patch = json!({ "imagePullSecrets": secrets }).to_string();
+    let args = [
+        "patch",
+        "serviceaccount",
+        "default",
+        "--namespace",
+        namespace,
+        "-p",
+        &patch,
+    ];
+    let out = kubectl::output(cluster, &args)?;
+    check(&args, &out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pull_secret::{manifest, parse, with_secret};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("team-a/ecr").unwrap(), ("team-a", "ecr"));
+        assert!(parse("ecr").is_err());
+        assert!(parse("/ecr").is_err());
+        assert!(parse("team-a/").is_err());
+        assert!(parse("team-a/ecr/x").is_err());
+    }
+
+    #[test]
+    fn test_manifest() {
+        let secret: Value = serde_json::from_str(&manifest(
+            "team-a",
+            "hake-registry",
+            r#"{"auths":{"registry.local":{"auth":"dXNlcjpwYXNz"}}}"#,
+        ))
+        .unwrap();
+        assert_eq!(secret["type"], "kubernetes.io/dockerconfigjson");
+        assert_eq!(secret["metadata"]["namespace"], "team-a");
+        assert_eq!(
+            secret["data"][".dockerconfigjson"],
+            "eyJhdXRocyI6eyJyZWdpc3RyeS5sb2NhbCI6eyJhdXRoIjoiZFhObGNqcHdZWE56In19fQ=="
+        );
+    }
+
+    #[test]
+    fn test_with_secret() {
+        let service_account = json!({"metadata": {"name": "default"}});
+        assert_eq!(
+            with_secret(&service_account, "hake-registry"),
+            Some(json!([{"name": "hake-registry"}]))
+        );
+
+        let service_account = json!({"imagePullSecrets": [{"name": "other"}]});
+        assert_eq!(
+            with_secret(&service_account, "hake-registry"),
+            Some(json!([{"name": "other"}, {"name": "hake-registry"}]))
+        );
+
+        let service_account = json!({"imagePullSecrets": [{"name": "hake-registry"}]});
+        assert_eq!(with_secret(&service_account, "hake-registry"), None);
+    }
+}
@@ -0,0 +1,188 @@
+// Registry credentials of long running clusters. ECR tokens last 12 hours,
+// after which the nodes and pull secrets of a cluster created with --ecr stop
+// pulling images, so they are fetched again and put everywhere hake put the
+// old ones: the docker config.json of the kubelet, the containerd config of
+// the nodes and the pull secrets of --namespace and --ecr-secret.
+use anyhow::{anyhow, Result};
+use console::Style;
+use tracing::{info, warn};
+
+use std::env;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crate::cluster::{self, CreateOptions};
+use crate::error::spawn_error;
+use crate::forward::{kill, running_as};
+use crate::kind::Kind;
+use crate::logging::LogCommand;
+use crate::metadata::ClusterMetadata;
+use crate::{pull_secret, runtime, ttl};
+
+/// Time between refreshes, well within the 12 hours of an ECR token.
+pub const DEFAULT_INTERVAL: &str = "6h";
+
+fn pid_path() -> PathBuf {
+    crate::get_config_dir().join("refresh-creds.pid")
+}
+
+pub fn log_path() -> PathBuf {
+    crate::get_config_dir().join("refresh-creds.log")
+}
+
+/// Namespace and name of the pull secrets hake created in a cluster.
+fn pull_secrets(opts: &CreateOptions) -> Result<Vec<(String, String)>> {
+    let mut secrets: Vec<(String, String)> = opts
+        .namespaces
+        .iter()
+        .map(|namespace| (namespace.clone(), String::from(pull_secret::DEFAULT_NAME)))
+        .collect();
+    for spec in opts.ecr_secrets.iter() {
+        let (namespace, name) = pull_secret::parse(spec)?;
+        secrets.push((String::from(namespace), String::from(name)));
+    }
+
+    Ok(secrets)
+}
+
+/// Refreshes the registry credentials of a cluster, false when it has none.
+pub fn refresh(name: &str) -> Result<bool> {
+    let metadata = match ClusterMetadata::read(name) {
+        Some(metadata) => metadata,
+        None => return Ok(false),
+    };
+    let registry = match &metadata.ecr {
+        Some(registry) => registry.clone(),
+        None => return Ok(false),
+    };
+    let opts = metadata.options.unwrap_or_else(|| CreateOptions::new(name));
+
+    if metadata.provider == "kind" && opts.registry_auth.as_deref() != Some("secret") {
+        runtime::use_recorded(name)?;
+        Kind::refresh_registry_auth(name, &registry)?;
+    }
+    let secrets = pull_secrets(&opts)?;
+    if !secrets.is_empty() {
+        let docker_config = Kind::get_docker_login(&registry)?;
+        for (namespace, secret) in secrets.iter() {
+            pull_secret::create(name, namespace, secret, &docker_config)?;
+        }
+    }
+
+    Ok(true)
+}
+
+/// Refreshes the credentials of every cluster with a registry, reporting the
+/// ones that fail instead of stopping at them.
+pub fn refresh_all() -> usize {
+    let cyan = Style::new().cyan();
+    let mut refreshed = 0;
+    for name in cluster::all_clusters() {
+        match refresh(&name) {
+            Ok(true) => {
+                info!("Refreshed registry credentials of {}", cyan.apply_to(&name));
+                refreshed += 1;
+            }
+            Ok(false) => {}
+            Err(e) => warn!(
+                "Could not refresh the registry credentials of {}: {:#}",
+                name, e
+            ),
+        }
+    }
+
+    refreshed
+}
+
+/// Refreshes the credentials of every cluster every `interval`, until
+/// interrupted.
+pub fn watch(interval: &str) -> Result<()> {
+    let interval = Duration::from_secs(ttl::parse_duration(interval)?);
+    loop {
+        refresh_all();
+        thread::sleep(interval);
+    }
+}
+
+/// Pid of the refresh running in the background, if it still runs.
+fn running() -> Option<u32> {
+    let program = env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| String::from("hake"));
+
+    fs::read_to_string(pid_path())
+        .ok()
+        .and_then(|pid| pid.trim().parse().ok())
+        .filter(|pid| running_as(*pid, &program, &["refresh-creds", "--watch"]))
+}
+
+/// Starts `refresh-creds --watch` in the background, logging to
+/// ~/.hake/refresh-creds.log.
+pub fn detach(interval: &str) -> Result<u32> {
+    ttl::parse_duration(interval)?;
+    if let Some(pid) = running() {
+        return Err(anyhow!(
+            "Registry credentials are refreshed in the background already, by pid {}",
+            pid
+        ));
+    }
+
+    let log = File::create(log_path())?;
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(["refresh-creds", "--watch", "--interval", interval])
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    // in a process group of its own, out of reach of the SIGHUP sent to the
+    // jobs of the terminal when it is closed
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let child = command
+        .logged()
+        .spawn()
+        .map_err(|e| spawn_error("hake", e))?;
+    fs::write(pid_path(), child.id().to_string())?;
+
+    Ok(child.id())
+}
+
+/// Stops the refresh running in the background.
+pub fn stop() -> Result<()> {
+    match running() {
+        Some(pid) => kill(pid)?,
+        None => warn!("Registry credentials are not refreshed in the background"),
+    }
+    fs::remove_file(pid_path()).ok();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cluster::CreateOptions;
+    use crate::refresh::pull_secrets;
+
+    #[test]
+    fn test_pull_secrets() {
+        let mut opts = CreateOptions::new("dev");
+        assert!(pull_secrets(&opts).unwrap().is_empty());
+
+        opts.namespaces = vec![String::from("team-a")];
+        opts.ecr_secrets = vec![String::from("operators/ecr-pull")];
+        assert_eq!(
+            pull_secrets(&opts).unwrap(),
+            vec![
+                (String::from("team-a"), String::from("hake-registry")),
+                (String::from("operators"), String::from("ecr-pull")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,441 @@
+// State of the cloud clusters shared by a team, kept in an S3 bucket or a git
+// repository set in the configuration file:
+//
+//     [state]
+//     remote = "s3://team-bucket/hake"
+//
+// Every hake pointing at it registers the clusters it creates there, so
+// names do not collide, everyone can see who owns what and `gc --shared`
+// deletes the expired clusters of the whole team.
+use anyhow::{anyhow, Context, Result};
+use console::Style;
+use serde_derive::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use crate::config::UserConfig;
+use crate::error::{spawn_error, HakeError};
+use crate::logging::LogCommand;
+use crate::{dryrun, r#do, state, ttl};
+
+/// Version of the format of the shared state.
+pub const VERSION: u32 = 1;
+const FILE: &str = "hake-state.json";
+/// Attempts to push to a git repository others push to as well.
+const GIT_ATTEMPTS: usize = 3;
+
+#[derive(Debug, PartialEq)]
+pub enum Backend {
+    /// URL of the state object, like s3://bucket/prefix/hake-state.json.
+    S3(String),
+    /// URL of the repository the state file is committed to.
+    Git(String),
+}
+
+impl Backend {
+    /// Parses s3://bucket[/prefix] or git+<repository url>.
+    pub fn parse(remote: &str) -> Result<Backend> {
+        if let Some(path) = remote.strip_prefix("s3://") {
+            let path = path.trim_end_matches('/');
+            if path.is_empty() {
+                return Err(anyhow!("Missing bucket in remote state: {}", remote));
+            }
+            return Ok(Backend::S3(format!("s3://{}/{}", path, FILE)));
+        }
+        if let Some(url) = remote.strip_prefix("git+") {
+            return Ok(Backend::Git(String::from(url)));
+        }
+
+        Err(anyhow!(
+            "Unknown remote state: {}, expected s3://bucket[/prefix] or git+<repository url>",
+            remote
+        ))
+    }
+
+    /// The backend in the configuration file, if any.
+    pub fn configured() -> Result<Option<Backend>> {
+        match UserConfig::load()?.state.remote {
+            Some(remote) => Ok(Some(Backend::parse(&remote)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SharedCluster {
+    pub provider: String,
+    /// Id of the cluster with its provider.
+    pub id: Option<String>,
+    pub owner: Option<String>,
+    /// Creation time in seconds since the epoch.
+    pub created_at: u64,
+    /// Expiration time in seconds since the epoch, for clusters with a TTL.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SharedState {
+    pub version: u32,
+    pub clusters: BTreeMap<String, SharedCluster>,
+}
+
+impl Default for SharedState {
+    fn default() -> Self {
+        SharedState {
+            version: VERSION,
+            clusters: BTreeMap::new(),
+        }
+    }
+}
+
+fn parse(contents: &str) -> Result<SharedState> {
+    let state: SharedState = serde_json::from_str(contents)?;
+    if state.version > VERSION {
+        return Err(anyhow!(
+            "The shared state was written by a newer hake (version {}), update hake",
+            state.version
+        ));
+    }
+
+    Ok(state)
+}
+
+fn run(program: &str, args: &[&str], stdin: Option<&[u8]>) -> Result<Output> {
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if stdin.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    let mut child = command
+        .logged()
+        .spawn()
+        .map_err(|e| spawn_error(program, e))?;
+    if let Some(input) = stdin {
+        child.stdin.take().unwrap().write_all(input)?;
+    }
+
+    Ok(child.wait_with_output()?)
+}
+
+fn check(program: &str, args: &[&str], out: Output) -> Result<Output> {
+    if !out.status.success() {
+        return Err(HakeError::CommandFailed {
+            command: format!("{} {}", program, args.join(" ")),
+            message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    Ok(out)
+}
+
+fn aws(args: &[&str], stdin: Option<&[u8]>) -> Result<Output> {
+    check("aws", args, run("aws", args, stdin)?)
+}
+
+/// Clone of the state repository.
+fn checkout() -> PathBuf {
+    crate::get_config_dir().join(".remote-state")
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<Output> {
+    let dir = dir.to_string_lossy();
+    let mut all = vec!["-C", &dir];
+    all.extend_from_slice(args);
+
+    check("git", &all, run("git", &all, None)?)
+}
+
+/// Brings the clone of the repository up to date, cloning it the first time.
+fn git_sync(url: &str) -> Result<PathBuf> {
+    let dir = checkout();
+    if !dir.join(".git").exists() {
+        let target = dir.to_string_lossy();
+        let args = ["clone", "--quiet", url, &target];
+        check("git", &args, run("git", &args, None)?)?;
+    }
+    // a new repository has nothing to pull yet
+    if git(&dir, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_ok() {
+        git(&dir, &["reset", "--quiet", "--hard", "HEAD"])?;
+        git(&dir, &["pull", "--quiet", "--rebase"])?;
+    }
+
+    Ok(dir)
+}
+
+fn read_file(path: &Path) -> Result<SharedState> {
+    match fs::read_to_string(path) {
+        Ok(contents) => parse(&contents).with_context(|| format!("Invalid {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SharedState::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Reads the shared state, empty if nobody wrote it yet.
+pub fn fetch(backend: &Backend) -> Result<SharedState> {
+    match backend {
+        Backend::S3(url) => {
+            let out = run("aws", &["s3", "cp", url, "-"], None)?;
+            if !out.status.success() {
+                let stderr = String::from_utf8_lossy(&out.stderr);
+                if stderr.contains("404") || stderr.contains("NoSuchKey") {
+                    return Ok(SharedState::default());
+                }
+                return Err(HakeError::CommandFailed {
+                    command: format!("aws s3 cp {} -", url),
+                    message: stderr.trim().to_string(),
+                }
+                .into());
+            }
+            parse(&String::from_utf8_lossy(&out.stdout)).with_context(|| format!("Invalid {}", url))
+        }
+        Backend::Git(url) => read_file(&git_sync(url)?.join(FILE)),
+    }
+}
+
+/// Changes the shared state. S3 has no locking, so the last writer wins;
+/// git pushes are retried on top of the changes of others.
+pub fn update<F>(backend: &Backend, change: F) -> Result<()>
+where
+    F: Fn(&mut SharedState),
+{
+    if dryrun::enabled() {
+        dryrun::print("would update the shared state");
+        return Ok(());
+    }
+
+    match backend {
+        Backend::S3(url) => {
+            let mut state = fetch(backend)?;
+            change(&mut state);
+            state.version = VERSION;
+            let contents = serde_json::to_vec_pretty(&state)?;
+            aws(&["s3", "cp", "-", url], Some(&contents))?;
+        }
+        Backend::Git(url) => {
+            let mut attempt = 1;
+            loop {
+                let dir = git_sync(url)?;
+                let mut state = read_file(&dir.join(FILE))?;
+                change(&mut state);
+                state.version = VERSION;
+                fs::write(dir.join(FILE), serde_json::to_string_pretty(&state)?)?;
+                git(&dir, &["add", FILE])?;
+                if git(&dir, &["diff", "--cached", "--quiet"]).is_ok() {
+                    return Ok(());
+                }
+                git(&dir, &["commit", "--quiet", "-m", "Update hake state"])?;
+                match git(&dir, &["push", "--quiet", "origin", "HEAD"]) {
+                    Ok(_) => break,
+                    Err(e) if attempt < GIT_ATTEMPTS => {
+                        warn!("Could not push the shared state, retrying: {:#}", e);
+                        git(&dir, &["reset", "--quiet", "--hard", "HEAD~1"])?;
+                        attempt += 1;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails if another cluster with the same name is in the shared state.
+pub fn ensure_available(backend: &Backend, name: &str) -> Result<()> {
+    match fetch(backend)?.clusters.get(name) {
+        Some(cluster) => Err(
+            anyhow::Error::from(HakeError::ClusterExists(String::from(name))).context(format!(
+                "{} is in the shared state, owned by {}",
+                name,
+                cluster.owner.as_deref().unwrap_or("unknown")
+            )),
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Registers a cluster just created.
+pub fn register(
+    backend: &Backend,
+    name: &str,
+    provider: &str,
+    id: Option<String>,
+    expires_at: Option<u64>,
+) -> Result<()> {
+    let cluster = SharedCluster {
+        provider: String::from(provider),
+        id,
+        owner: crate::user_name(),
+        created_at: ttl::now(),
+        expires_at,
+    };
+    update(backend, |state| {
+        state.clusters.insert(String::from(name), cluster.clone());
+    })
+}
+
+pub fn unregister(backend: &Backend, name: &str) -> Result<()> {
+    update(backend, |state| {
+        state.clusters.remove(name);
+    })
+}
+
+/// The shared clusters as table rows.
+pub fn rows(shared: &SharedState, now: u64) -> Vec<Vec<String>> {
+    let mut rows = vec![vec![
+        String::from("NAME"),
+        String::from("PROVIDER"),
+        String::from("OWNER"),
+        String::from("AGE"),
+        String::from("EXPIRES"),
+    ]];
+    for (name, cluster) in shared.clusters.iter() {
+        let expires = match cluster.expires_at {
+            Some(at) if at <= now => String::from("expired"),
+            Some(at) => format!("in {}", ttl::format_age(at - now)),
+            None => String::from("-"),
+        };
+        rows.push(vec![
+            name.clone(),
+            cluster.provider.clone(),
+            cluster.owner.clone().unwrap_or_else(|| String::from("-")),
+            ttl::format_age(now.saturating_sub(cluster.created_at)),
+            expires,
+        ]);
+    }
+
+    rows
+}
+
+/// Clusters of the shared state past their expiration time.
+fn expired(shared: &SharedState, now: u64) -> Vec<(String, SharedCluster)> {
+    shared
+        .clusters
+        .iter()
+        .filter(|(_, cluster)| matches!(cluster.expires_at, Some(at) if at <= now))
+        .map(|(name, cluster)| (name.clone(), cluster.clone()))
+        .collect()
+}
+
+/// Deletes the expired clusters of the whole team, with the provider API,
+/// and takes them out of the shared state, going on with the rest when one
+/// of them fails.
+pub fn gc(backend: &Backend) -> Result<()> {
+    let expired = expired(&fetch(backend)?, ttl::now());
+    let mut failed = 0;
+    for (name, cluster) in &expired {
+        if let Err(e) = gc_cluster(backend, name, cluster) {
+            warn!("Could not delete shared cluster {}: {:#}", name, e);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} expired shared clusters could not be deleted",
+            failed,
+            expired.len()
+        ));
+    }
+
+    Ok(())
+}
+
+fn gc_cluster(backend: &Backend, name: &str, cluster: &SharedCluster) -> Result<()> {
+    let cyan = Style::new().cyan();
+    info!("Deleting expired shared cluster: {}", cyan.apply_to(name));
+    match (cluster.provider.as_str(), &cluster.id) {
+        ("digitalocean", Some(id)) if dryrun::enabled() => dryrun::print(&format!(
+            "would DELETE https://api.digitalocean.com/v2/kubernetes/clusters/{}",
+            id
+        )),
+        // a cluster removed already is reported as deleted
+        ("digitalocean", Some(id)) => r#do::delete_by_id(id)?,
+        _ => {
+            warn!(
+                "Cannot delete {} on {} without its id",
+                name, cluster.provider
+            );
+            return Ok(());
+        }
+    }
+    unregister(backend, name)?;
+    // it may have been created from this machine
+    if !dryrun::enabled() && state::get(name).is_some() {
+        let dir = crate::cluster_dir(name);
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        state::remove(name)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::remote_state::{expired, parse, rows, Backend, SharedCluster, SharedState};
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!(
+            Backend::parse("s3://team-bucket/hake/").unwrap(),
+            Backend::S3(String::from("s3://team-bucket/hake/hake-state.json"))
+        );
+        assert_eq!(
+            Backend::parse("git+git@github.com:org/state.git").unwrap(),
+            Backend::Git(String::from("git@github.com:org/state.git"))
+        );
+        assert!(Backend::parse("s3://").is_err());
+        assert!(Backend::parse("https://example.com/state").is_err());
+    }
+
+    #[test]
+    fn test_expired() {
+        let cluster = |expires_at| SharedCluster {
+            provider: String::from("digitalocean"),
+            id: Some(String::from("1234")),
+            owner: Some(String::from("jane")),
+            created_at: 1000,
+            expires_at,
+        };
+        let mut shared = SharedState::default();
+        shared
+            .clusters
+            .insert(String::from("old"), cluster(Some(2000)));
+        shared
+            .clusters
+            .insert(String::from("new"), cluster(Some(9000)));
+        shared.clusters.insert(String::from("kept"), cluster(None));
+
+        let names: Vec<String> = expired(&shared, 5000).into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec!["old"]);
+
+        let rows = rows(&shared, 5000);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[1][0], "kept");
+        assert_eq!(rows[1][4], "-");
+        assert_eq!(rows[3][0], "old");
+        assert_eq!(rows[3][2], "jane");
+        assert_eq!(rows[3][4], "expired");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(r#"{"version": 1, "clusters": {}}"#).unwrap(),
+            SharedState::default()
+        );
+        assert!(parse(r#"{"version": 9, "clusters": {}}"#).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+// Rootless docker and podman. The nodes run in a user namespace, which needs
+// cgroup v2 with the cpu, memory and pids controllers delegated to the user,
+// cannot bind the privileged ports of the host and only reach the files the
+// user can read, so hake checks for those before kind fails halfway through.
+use anyhow::{anyhow, Result};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::logging::LogCommand;
+use crate::runtime;
+
+/// cgroup controllers kind needs delegated to run the nodes rootless.
+pub const REQUIRED_CONTROLLERS: &[&str] = &["cpu", "memory", "pids"];
+/// First port an unprivileged process can bind unless the kernel says
+/// otherwise.
+const DEFAULT_UNPRIVILEGED_PORT_START: u32 = 1024;
+
+/// Whether the runtime runs without root, from `docker info` or `podman info`.
+pub fn enabled() -> bool {
+    let format = if runtime::is_podman() {
+        "{{.Host.Security.Rootless}}"
+    } else {
+        "{{.SecurityOptions}}"
+    };
+    match runtime::command()
+        .args(["info", "--format", format])
+        .logged()
+        .output()
+    {
+        Ok(out) if out.status.success() => is_rootless(&String::from_utf8_lossy(&out.stdout)),
+        _ => false,
+    }
+}
+
+/// Reads the output of the info commands, `true` from podman or a list of
+/// security options with name=rootless from docker.
+fn is_rootless(info: &str) -> bool {
+    let info = info.trim();
+    info == "true" || info.contains("name=rootless")
+}
+
+/// First port the user can bind, from ip_unprivileged_port_start.
+pub fn unprivileged_port_start() -> u32 {
+    fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+        .ok()
+        .and_then(|start| start.trim().parse().ok())
+        .unwrap_or(DEFAULT_UNPRIVILEGED_PORT_START)
+}
+
+/// Fails for the host ports a rootless runtime cannot bind.
+pub fn check_ports(ports: &[u32], start: u32) -> Result<()> {
+    let mut privileged: Vec<String> = ports
+        .iter()
+        .filter(|port| **port < start)
+        .map(|port| port.to_string())
+        .collect();
+    privileged.dedup();
+    if privileged.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "Ports {} are below {} and cannot be mapped by a rootless runtime. Map them to ports above it, like --port 8080:80, or run `sudo sysctl net.ipv4.ip_unprivileged_port_start={}`",
+        privileged.join(", "),
+        start,
+        privileged[0]
+    ))
+}
+
+/// Fails for the mounted host paths the user cannot read, which the nodes of
+/// a rootless runtime cannot either.
+pub fn check_mounts(paths: &[&str]) -> Result<()> {
+    for path in paths {
+        let readable = match fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => fs::read_dir(path).is_ok(),
+            Ok(_) => fs::File::open(path).is_ok(),
+            Err(_) => false,
+        };
+        if !readable {
+            return Err(anyhow!(
+                "Cannot mount {}, a rootless runtime only mounts paths the user can read",
+                path
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Controllers missing from the contents of cgroup.controllers.
+fn missing_controllers(controllers: &str) -> Vec<&'static str> {
+    let delegated: Vec<&str> = controllers.split_whitespace().collect();
+    REQUIRED_CONTROLLERS
+        .iter()
+        .filter(|controller| !delegated.contains(controller))
+        .copied()
+        .collect()
+}
+
+#[cfg(unix)]
+fn user_id() -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata("/proc/self")
+        .ok()
+        .map(|metadata| metadata.uid())
+}
+
+#[cfg(not(unix))]
+fn user_id() -> Option<u32> {
+    None
+}
+
+/// cgroup the systemd user instance delegates to the containers of the user.
+fn user_cgroup(uid: u32) -> PathBuf {
+    PathBuf::from(format!(
+        "/sys/fs/cgroup/user.slice/user-{uid}.slice/user@{uid}.service",
+        uid = uid
+    ))
+}
+
+/// Checks for cgroup v2 and the delegation of the controllers kind needs.
+pub fn check_cgroups() -> Result<()> {
+    if !Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+        return Err(anyhow!(
+            "cgroup v1 in use, rootless kind needs cgroup v2, boot with systemd.unified_cgroup_hierarchy=1"
+        ));
+    }
+    let uid = user_id().ok_or_else(|| anyhow!("could not tell the id of the user"))?;
+    let controllers = fs::read_to_string(user_cgroup(uid).join("cgroup.controllers"))
+        .map_err(|_| anyhow!("no systemd user cgroup for user {}", uid))?;
+    let missing = missing_controllers(&controllers);
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "{} not delegated, add Delegate=yes to /etc/systemd/system/user@.service.d/delegate.conf and run `sudo systemctl daemon-reload`",
+            missing.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rootless::{check_ports, is_rootless, missing_controllers};
+
+    #[test]
+    fn test_is_rootless() {
+        assert!(is_rootless("true\n"));
+        assert!(is_rootless(
+            "[name=seccomp,profile=builtin name=rootless name=cgroupns]"
+        ));
+        assert!(!is_rootless("false"));
+        assert!(!is_rootless("[name=seccomp,profile=builtin name=cgroupns]"));
+    }
+
+    #[test]
+    fn test_missing_controllers() {
+        assert!(missing_controllers("cpuset cpu io memory pids\n").is_empty());
+        assert_eq!(missing_controllers("memory pids"), vec!["cpu"]);
+        assert_eq!(missing_controllers(""), vec!["cpu", "memory", "pids"]);
+    }
+
+    #[test]
+    fn test_check_ports() {
+        assert!(check_ports(&[8080, 30080], 1024).is_ok());
+        assert!(check_ports(&[80], 80).is_ok());
+        let err = check_ports(&[80, 443, 8080], 1024).unwrap_err();
+        assert!(err.to_string().starts_with("Ports 80, 443 are below 1024"));
+    }
+}
@@ -0,0 +1,224 @@
+// Container runtime running the kind nodes, docker or podman. kind runs on
+// podman with KIND_EXPERIMENTAL_PROVIDER=podman, and hake runs the same
+// commands through the docker compatible CLI and API socket of podman, so
+// hosts without a docker daemon, like most Fedora and RHEL ones, work too.
+use anyhow::{anyhow, Result};
+use bollard::Docker;
+use tracing::debug;
+
+use std::env;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use crate::error::{spawn_error, HakeError};
+use crate::logging::LogCommand;
+use crate::metadata::ClusterMetadata;
+
+pub const DOCKER: &str = "docker";
+pub const PODMAN: &str = "podman";
+pub const RUNTIMES: &[&str] = &[DOCKER, PODMAN];
+/// Network kind attaches the nodes to by default.
+pub const DEFAULT_NETWORK: &str = "kind";
+
+static RUNTIME: Mutex<Option<&'static str>> = Mutex::new(None);
+
+pub fn parse(runtime: &str) -> Result<&'static str> {
+    RUNTIMES
+        .iter()
+        .find(|r| **r == runtime)
+        .copied()
+        .ok_or_else(|| {
+            anyhow!(
+                "Unknown container runtime {}, expected one of: {}",
+                runtime,
+                RUNTIMES.join(", ")
+            )
+        })
+}
+
+fn in_path(binary: &str) -> bool {
+    let path = env::var_os("PATH").unwrap_or_default();
+    env::split_paths(&path)
+        .any(|dir| dir.join(binary).is_file() || dir.join(format!("{}.exe", binary)).is_file())
+}
+
+/// Whether the output of `docker --version` comes from the docker shim of
+/// podman, installed by podman-docker.
+fn is_podman_shim(version: &str) -> bool {
+    version.to_lowercase().contains("podman")
+}
+
+/// docker when it is installed, podman when only it is. HAKE_RUNTIME wins
+/// over both.
+fn detect() -> Result<&'static str> {
+    if let Ok(runtime) = env::var("HAKE_RUNTIME") {
+        if !runtime.is_empty() {
+            return parse(&runtime);
+        }
+    }
+    if in_path(DOCKER) {
+        let version = Command::new(DOCKER).arg("--version").logged().output();
+        return match version {
+            Ok(out) if is_podman_shim(&String::from_utf8_lossy(&out.stdout)) => Ok(PODMAN),
+            _ => Ok(DOCKER),
+        };
+    }
+    if in_path(PODMAN) {
+        return Ok(PODMAN);
+    }
+
+    Ok(DOCKER)
+}
+
+/// API socket of podman, the one of the user first as podman runs rootless
+/// by default.
+fn podman_socket() -> Option<PathBuf> {
+    let mut sockets = vec![];
+    if let Ok(dir) = env::var("XDG_RUNTIME_DIR") {
+        sockets.push(PathBuf::from(dir).join("podman/podman.sock"));
+    }
+    sockets.push(PathBuf::from("/run/podman/podman.sock"));
+
+    sockets.into_iter().find(|socket| socket.exists())
+}
+
+fn configure(runtime: &'static str) {
+    debug!("Using the {} container runtime", runtime);
+    if runtime != PODMAN {
+        return;
+    }
+    env::set_var("KIND_EXPERIMENTAL_PROVIDER", PODMAN);
+    if env::var_os("DOCKER_HOST").is_none() {
+        if let Some(socket) = podman_socket() {
+            env::set_var("DOCKER_HOST", format!("unix://{}", socket.display()));
+        }
+    }
+}
+
+/// Makes hake and kind use the given runtime, or the detected one.
+pub fn select(runtime: Option<&str>) -> Result<()> {
+    let runtime = match runtime {
+        Some(runtime) => parse(runtime)?,
+        None => detect()?,
+    };
+    configure(runtime);
+    *RUNTIME.lock().unwrap() = Some(runtime);
+
+    Ok(())
+}
+
+/// Makes hake use the runtime a cluster was created with, if it was given.
+pub fn use_recorded(name: &str) -> Result<()> {
+    let runtime = ClusterMetadata::read(name)
+        .and_then(|metadata| metadata.options)
+        .and_then(|opts| opts.runtime);
+    if runtime.is_some() {
+        select(runtime.as_deref())?;
+    }
+
+    Ok(())
+}
+
+/// Binary of the runtime in use, detected the first time.
+pub fn binary() -> &'static str {
+    let mut selected = RUNTIME.lock().unwrap();
+    if let Some(runtime) = *selected {
+        return runtime;
+    }
+    let runtime = detect().unwrap_or(DOCKER);
+    configure(runtime);
+    *selected = Some(runtime);
+
+    runtime
+}
+
+pub fn is_podman() -> bool {
+    binary() == PODMAN
+}
+
+/// Command running the runtime CLI, docker or podman.
+pub fn command() -> Command {
+    Command::new(binary())
+}
+
+/// Whether a network of the runtime exists.
+pub fn network_exists(network: &str) -> bool {
+    matches!(
+        command()
+            .args(["network", "inspect", network])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .logged()
+            .status(),
+        Ok(status) if status.success()
+    )
+}
+
+/// Makes kind attach the nodes of the clusters it creates to `network`
+/// instead of its own one.
+pub fn use_network(network: &str) {
+    let variable = if is_podman() {
+        "KIND_EXPERIMENTAL_PODMAN_NETWORK"
+    } else {
+        "KIND_EXPERIMENTAL_DOCKER_NETWORK"
+    };
+    env::set_var(variable, network);
+}
+
+/// Connects a container to a network, unless it is already, so the nodes on
+/// it can reach the container by name.
+pub fn connect_network(container: &str, network: &str) -> Result<()> {
+    let networks = command()
+        .args([
+            "inspect",
+            "-f",
+            "{{json .NetworkSettings.Networks}}",
+            container,
+        ])
+        .logged()
+        .output()
+        .map_err(|e| spawn_error(binary(), e))?;
+    if String::from_utf8_lossy(&networks.stdout).contains(&format!("\"{}\"", network)) {
+        return Ok(());
+    }
+    let status = command()
+        .args(["network", "connect", network, container])
+        .stdout(Stdio::null())
+        .logged()
+        .status()
+        .map_err(|e| spawn_error(binary(), e))?;
+    if !status.success() {
+        return Err(HakeError::CommandFailed {
+            command: format!("{} network connect {} {}", binary(), network, container),
+            message: format!("could not connect {} to the {} network", container, network),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Client of the docker API, or of the compatible one of podman.
+pub fn connect() -> Result<Docker> {
+    binary();
+    Ok(Docker::connect_with_local_defaults()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{is_podman_shim, parse};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("podman").unwrap(), "podman");
+        assert_eq!(parse("docker").unwrap(), "docker");
+        assert!(parse("containerd").is_err());
+    }
+
+    #[test]
+    fn test_is_podman_shim() {
+        assert!(is_podman_shim("podman version 4.9.3"));
+        assert!(!is_podman_shim("Docker version 24.0.7, build afdd53b"));
+    }
+}
@@ -0,0 +1,156 @@
+// Updates hake to its latest release on GitHub, for installs that did not come
+// from a package manager. Releases have a binary per platform, named like
+// hake-linux-amd64, with a sha256sum file next to it.
+use anyhow::{anyhow, Result};
+use console::Style;
+use serde_derive::Deserialize;
+use tracing::info;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::dryrun;
+use crate::kind_binary::{asset_name, download_verified};
+
+const REPOSITORY: &str = "rodrigovalin/nomake";
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+/// Parses versions like v0.2.1 or 0.2.1.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version
+        .trim_start_matches('v')
+        .splitn(3, '.')
+        .map(|part| part.parse::<u64>().ok());
+
+    Some((parts.next()??, parts.next()??, parts.next()??))
+}
+
+/// Whether a release is newer than the running version.
+fn is_newer(release: &str, current: &str) -> bool {
+    match (parse_version(release), parse_version(current)) {
+        (Some(release), Some(current)) => release > current,
+        _ => false,
+    }
+}
+
+fn latest_release() -> Result<Release> {
+    let url = format!(
+        "https://api.github.com/repos/{}/releases/latest",
+        REPOSITORY
+    );
+    // GitHub rejects requests without a user agent
+    let resp = crate::http::client_builder()?
+        .user_agent(concat!("hake/", env!("CARGO_PKG_VERSION")))
+        .build()?
+        .get(&url)
+        .send()?;
+    if !resp.status().is_success() {
+        return Err(anyhow!(
+            "Could not get the latest release: {}",
+            resp.status()
+        ));
+    }
+
+    Ok(resp.json()?)
+}
+
+/// Puts the new binary in place of the running one. Windows does not allow
+/// replacing a running executable, but it allows renaming it.
+fn replace(exe: &Path, binary: &[u8]) -> Result<()> {
+    let dir = exe
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid path {}", exe.display()))?;
+    let partial = dir.join(".hake.partial");
+    fs::write(&partial, binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&partial, fs::Permissions::from_mode(0o755))?;
+    }
+    if cfg!(windows) {
+        fs::rename(exe, exe.with_extension("old.exe"))?;
+    }
+    fs::rename(&partial, exe)?;
+
+    Ok(())
+}
+
+/// Updates the running binary to the latest release, or with `check_only`
+/// only tells whether there is one.
+pub fn self_update(check_only: bool) -> Result<()> {
+    let cyan = Style::new().cyan();
+    let release = latest_release()?;
+    if !is_newer(&release.tag_name, CURRENT_VERSION) {
+        info!("hake {} is the latest version", CURRENT_VERSION);
+        return Ok(());
+    }
+    if check_only {
+        info!(
+            "hake {} is available, this is {}",
+            cyan.apply_to(&release.tag_name),
+            CURRENT_VERSION
+        );
+        return Ok(());
+    }
+
+    let name = asset_name("hake", env::consts::OS, env::consts::ARCH)?;
+    let asset = release
+        .assets
+        .iter()
+        .find(|asset| asset.name == name)
+        .ok_or_else(|| anyhow!("Release {} has no {} binary", release.tag_name, name))?;
+    let exe = env::current_exe()?;
+    if dryrun::enabled() {
+        dryrun::print(&format!(
+            "would replace {} with {}",
+            exe.display(),
+            asset.browser_download_url
+        ));
+        return Ok(());
+    }
+
+    info!("Downloading hake {}", cyan.apply_to(&release.tag_name));
+    let binary = download_verified(&asset.browser_download_url)?;
+    replace(&exe, &binary)?;
+    info!(
+        "Updated hake from {} to {}",
+        CURRENT_VERSION,
+        cyan.apply_to(&release.tag_name)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::self_update::{is_newer, parse_version};
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("v0.2.1"), Some((0, 2, 1)));
+        assert_eq!(parse_version("1.10.0"), Some((1, 10, 0)));
+        assert_eq!(parse_version("v1.0"), None);
+        assert_eq!(parse_version("nightly"), None);
+    }
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("v0.2.0", "0.1.0"));
+        assert!(is_newer("v0.10.0", "0.9.3"));
+        assert!(!is_newer("v0.1.0", "0.1.0"));
+        assert!(!is_newer("nightly", "0.1.0"));
+    }
+}
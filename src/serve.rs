@@ -0,0 +1,788 @@
+// Local daemon to drive hake over HTTP with JSON, for editors, test harnesses
+// and dev portals. Clusters are created and deleted by background jobs, each
+// one a hake process reporting its progress as JSON lines, like the clusters
+// of a batch, so it takes its own lock and gets its own history entry. The
+// API also serves a web UI at /, for those who would rather not use the
+// command line. Every request but the page itself needs the token written to
+// ~/.hake/serve/token when the server starts, so other users and the pages
+// open in a browser cannot drive it.
+use anyhow::{anyhow, Result};
+use console::Style;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::cluster;
+use crate::config::UserConfig;
+use crate::error::spawn_error;
+use crate::labels::{self, Labels};
+use crate::logging::LogCommand;
+use crate::metadata::ClusterMetadata;
+use crate::names;
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:7070";
+/// Web UI with the clusters, to create them from a profile, stop and delete
+/// them without the command line.
+const PAGE: &str = include_str!("web.html");
+/// Largest request body read, create requests are a few hundred bytes.
+const MAX_BODY: usize = 1 << 20;
+/// Time a client gets to send its request and to take the response, so idle
+/// or slow connections do not keep their thread forever.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Job {
+    pub id: u32,
+    /// create or delete.
+    pub command: String,
+    pub cluster: String,
+    /// running, succeeded or failed.
+    pub status: String,
+    /// From the last progress event of the job.
+    pub phase: String,
+    pub percent: u8,
+    pub message: String,
+    pub error: Option<String>,
+    /// Logs of the hake process.
+    pub log: PathBuf,
+    /// Seconds since the epoch.
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+type Jobs = Arc<Mutex<Vec<Job>>>;
+
+/// Options a create request can give. Anything else, like mounts of host
+/// directories or manifests to apply, comes from a profile of the
+/// configuration file, which only its owner can change.
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+struct CreateRequest {
+    name: String,
+    profile: Option<String>,
+    provider: Option<String>,
+    workers: Option<u16>,
+    labels: Vec<String>,
+    ttl: Option<String>,
+    timeout: Option<String>,
+}
+
+/// Arguments of `create` for a request, or why it is invalid.
+fn create_args(request: &CreateRequest, profiles: &[String]) -> Result<Vec<String>> {
+    let mut args = vec![];
+    if let Some(profile) = &request.profile {
+        if !profiles.contains(profile) {
+            return Err(anyhow!("Unknown profile {}", profile));
+        }
+        args.extend([String::from("--profile"), profile.clone()]);
+    }
+    if let Some(provider) = &request.provider {
+        if !["kind", "digitalocean"].contains(&provider.as_str()) {
+            return Err(anyhow!("Unknown provider {}", provider));
+        }
+        args.extend([String::from("--provider"), provider.clone()]);
+    }
+    if let Some(workers) = request.workers {
+        args.extend([String::from("--workers"), workers.to_string()]);
+    }
+    for label in &request.labels {
+        labels::parse(label)?;
+        args.extend([String::from("--label"), label.clone()]);
+    }
+    for (option, duration) in [("--ttl", &request.ttl), ("--timeout", &request.timeout)] {
+        if let Some(duration) = duration {
+            crate::ttl::parse_duration(duration)?;
+            args.extend([String::from(option), duration.clone()]);
+        }
+    }
+
+    Ok(args)
+}
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    /// Origin, Host and Content-Type headers, to turn away other sites open
+    /// in a browser.
+    origin: Option<String>,
+    host: Option<String>,
+    content_type: Option<String>,
+    /// Token of `Authorization: Bearer <token>`.
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (String::from(method), String::from(path)),
+        _ => return Err(anyhow!("Invalid request line: {}", line.trim())),
+    };
+
+    let mut length = 0;
+    let mut origin = None;
+    let mut host = None;
+    let mut content_type = None;
+    let mut token = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_lowercase().as_str() {
+                "content-length" => length = value.parse()?,
+                "origin" => origin = Some(String::from(value)),
+                "host" => host = Some(String::from(value)),
+                "content-type" => content_type = Some(String::from(value)),
+                "authorization" => token = value.strip_prefix("Bearer ").map(String::from),
+                _ => {}
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(anyhow!("Request body of {} bytes is too large", length));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        method,
+        path,
+        origin,
+        host,
+        content_type,
+        token,
+        body,
+    })
+}
+
+/// Whether a request comes from the web UI or a client other than a browser,
+/// which sends no Origin. Pages of other sites could otherwise create and
+/// delete clusters through the browser of whoever has them open.
+fn same_origin(request: &Request) -> bool {
+    match (&request.origin, &request.host) {
+        (None, _) => true,
+        (Some(origin), Some(host)) => origin == &format!("http://{}", host),
+        (Some(_), None) => false,
+    }
+}
+
+/// Whether a request was sent to an address of the server, an IP address or
+/// localhost with its port. A page whose domain was rebound to 127.0.0.1
+/// sends its own name, which passes the check of the origin.
+fn allowed_host(request: &Request, port: u16) -> bool {
+    match &request.host {
+        Some(host) if *host == format!("localhost:{}", port) => true,
+        Some(host) => matches!(host.parse::<SocketAddr>(), Ok(address) if address.port() == port),
+        None => false,
+    }
+}
+
+/// Whether a request that changes clusters says it sends JSON. Browsers
+/// only send that to other sites after asking with a preflight request,
+/// which the server does not answer.
+fn sends_json(request: &Request) -> bool {
+    match request.method.as_str() {
+        "POST" | "DELETE" => matches!(
+            &request.content_type,
+            Some(content_type) if content_type.starts_with("application/json")
+        ),
+        _ => true,
+    }
+}
+
+/// Whether a request has the token of the server, compared without stopping
+/// at the first difference.
+fn authorized(request: &Request, token: &str) -> bool {
+    match &request.token {
+        Some(given) if given.len() == token.len() => {
+            given
+                .bytes()
+                .zip(token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+        }
+        _ => false,
+    }
+}
+
+fn token_path() -> PathBuf {
+    crate::get_config_dir().join("serve").join("token")
+}
+
+/// Generates the token of a server and writes it where only the user can
+/// read it.
+fn write_token() -> Result<String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|e| anyhow!("Could not generate a token: {}", e))?;
+    let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    let path = token_path();
+    fs::create_dir_all(path.parent().unwrap())?;
+    // created anew, so the permissions of an older file do not stay
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&path)?.write_all(token.as_bytes())?;
+
+    Ok(token)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    }
+}
+
+fn write_response(
+    stream: &mut impl Write,
+    status: u16,
+    content_type: &str,
+    body: &str,
+) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        content_type,
+        body.len(),
+        body
+    )?;
+
+    Ok(())
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (status, json!({ "error": message }))
+}
+
+/// Rows of a table as objects keyed by the lowercase header.
+fn objects(rows: &[Vec<String>]) -> Value {
+    let (header, rows) = match rows.split_first() {
+        Some(split) => split,
+        None => return json!([]),
+    };
+
+    rows.iter()
+        .map(|row| {
+            header
+                .iter()
+                .map(|column| column.to_lowercase())
+                .zip(row.iter().cloned().map(Value::String))
+                .collect::<serde_json::Map<String, Value>>()
+        })
+        .map(Value::Object)
+        .collect()
+}
+
+fn cluster_status(name: &str) -> Value {
+    let metadata = ClusterMetadata::read(name);
+    let (status, nodes) = cluster::cluster_status(name);
+    let provider = match cluster::cluster_type(name) {
+        cluster::ClusterType::Kind => "kind",
+        cluster::ClusterType::DigitalOcean => "digitalocean",
+    };
+
+    json!({
+        "name": name,
+        "provider": provider,
+        "status": status,
+        "nodes": nodes,
+        "version": metadata.as_ref().and_then(|m| m.kubernetes_version.clone()),
+        "created_at": metadata.as_ref().map(|m| m.created_at),
+        "expires_at": metadata.as_ref().and_then(|m| m.expires_at),
+        "labels": metadata.map(|m| m.labels).unwrap_or_default(),
+    })
+}
+
+fn log_path(id: u32) -> PathBuf {
+    crate::get_config_dir()
+        .join("serve")
+        .join(format!("job-{}.log", id))
+}
+
+/// Updates a job with a progress event of its hake process.
+fn apply_event(job: &mut Job, line: &str) {
+    let event: Value = match serde_json::from_str(line) {
+        Ok(event) => event,
+        // the rest of the output, like the kubeconfig of create --reuse
+        Err(_) => return,
+    };
+    let message = event["message"].as_str().unwrap_or_default().to_string();
+    if event["event"] == "error" {
+        job.error = Some(message);
+        return;
+    }
+    job.phase = event["phase"].as_str().unwrap_or_default().to_string();
+    job.percent = event["percent"].as_u64().unwrap_or_default().min(100) as u8;
+    job.message = message;
+}
+
+/// Runs `hake <command> --name <cluster> <args>` in the background.
+fn start_job(
+    exe: &Path,
+    jobs: &Jobs,
+    command: &str,
+    cluster: &str,
+    args: &[String],
+) -> Result<Job> {
+    let mut jobs_list = jobs.lock().unwrap();
+    let id = jobs_list.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+    let log = log_path(id);
+    fs::create_dir_all(log.parent().unwrap())?;
+    let output = File::create(&log)?;
+
+    let mut child = Command::new(exe)
+        .args(["--progress", "json", command, "--name", cluster])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(output)
+        .logged()
+        .spawn()
+        .map_err(|e| spawn_error(&exe.to_string_lossy(), e))?;
+    let job = Job {
+        id,
+        command: String::from(command),
+        cluster: String::from(cluster),
+        status: String::from("running"),
+        phase: String::new(),
+        percent: 0,
+        message: String::new(),
+        error: None,
+        log,
+        started_at: crate::ttl::now(),
+        finished_at: None,
+    };
+    jobs_list.push(job.clone());
+    drop(jobs_list);
+
+    let stdout = child.stdout.take().unwrap();
+    let jobs = Arc::clone(jobs);
+    thread::spawn(move || {
+        let update = |change: &dyn Fn(&mut Job)| {
+            if let Some(job) = jobs.lock().unwrap().iter_mut().find(|job| job.id == id) {
+                change(job);
+            }
+        };
+        for line in BufReader::new(stdout).lines().map_while(|line| line.ok()) {
+            update(&|job| apply_event(job, &line));
+        }
+        let success = matches!(child.wait(), Ok(status) if status.success());
+        update(&|job| {
+            job.status = String::from(if success { "succeeded" } else { "failed" });
+            job.finished_at = Some(crate::ttl::now());
+            // like invalid arguments, rejected before any progress is reported
+            if !success && job.error.is_none() {
+                job.error = Some(format!(
+                    "hake {} failed, see {}",
+                    job.command,
+                    job.log.display()
+                ));
+            }
+        });
+    });
+
+    Ok(job)
+}
+
+fn create(exe: &Path, jobs: &Jobs, body: &[u8]) -> Result<(u16, Value)> {
+    let request: CreateRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return Ok(error(400, &format!("Invalid create request: {}", e))),
+    };
+    let profiles: Vec<String> = UserConfig::load()?.profiles.into_keys().collect();
+    let args = match create_args(&request, &profiles) {
+        Ok(args) => args,
+        Err(e) => return Ok(error(400, &format!("Invalid create request: {:#}", e))),
+    };
+    if let Err(e) = names::validate(&request.name) {
+        return Ok(error(400, &e.to_string()));
+    }
+    if cluster::cluster_exists(&request.name) {
+        return Ok(error(
+            409,
+            &format!("Cluster {} already exists", request.name),
+        ));
+    }
+    let job = start_job(exe, jobs, "create", &request.name, &args)?;
+
+    Ok((202, serde_json::to_value(job)?))
+}
+
+/// Handles a request, returning the status and body of the response.
+fn route(exe: &Path, jobs: &Jobs, request: &Request) -> Result<(u16, Value)> {
+    let (path, query) = request
+        .path
+        .split_once('?')
+        .unwrap_or((request.path.as_str(), ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    let response = match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["clusters"]) => {
+            let selector = selector(query)?;
+            (200, objects(&cluster::list_rows(&selector)))
+        }
+        ("POST", ["clusters"]) => create(exe, jobs, &request.body)?,
+        ("GET", ["profiles"]) => {
+            let profiles: Vec<String> = UserConfig::load()?.profiles.into_keys().collect();
+            (200, json!(profiles))
+        }
+        ("GET", ["clusters", name]) if cluster::cluster_exists(name) => (200, cluster_status(name)),
+        ("DELETE", ["clusters", name]) if cluster::cluster_exists(name) => {
+            let job = start_job(exe, jobs, "delete", name, &[])?;
+            (202, serde_json::to_value(job)?)
+        }
+        ("POST", ["clusters", name, action @ ("stop" | "start")])
+            if cluster::cluster_exists(name) =>
+        {
+            let job = start_job(exe, jobs, action, name, &[])?;
+            (202, serde_json::to_value(job)?)
+        }
+        (_, ["clusters", name]) | (_, ["clusters", name, "stop" | "start"])
+            if !cluster::cluster_exists(name) =>
+        {
+            error(404, &format!("Cluster {} does not exist", name))
+        }
+        ("GET", ["jobs"]) => (200, serde_json::to_value(&*jobs.lock().unwrap())?),
+        ("GET", ["jobs", id]) => {
+            let job = jobs
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|job| job.id.to_string() == *id)
+                .cloned();
+            match job {
+                Some(job) => (200, serde_json::to_value(job)?),
+                None => error(404, &format!("Job {} does not exist", id)),
+            }
+        }
+        (_, ["clusters"])
+        | (_, ["clusters", _])
+        | (_, ["clusters", _, "stop" | "start"])
+        | (_, ["profiles"])
+        | (_, ["jobs"])
+        | (_, ["jobs", _]) => error(405, "Method not allowed"),
+        _ => error(404, &format!("Unknown path {}", path)),
+    };
+
+    Ok(response)
+}
+
+/// Labels of `GET /clusters?label=key=value&label=...`.
+fn selector(query: &str) -> Result<Labels> {
+    let labels: Vec<String> = query
+        .split('&')
+        .filter_map(|param| param.strip_prefix("label="))
+        .map(String::from)
+        .collect();
+
+    labels::parse_all(&labels)
+}
+
+fn handle(exe: &Path, jobs: &Jobs, token: &str, port: u16, mut stream: TcpStream) -> Result<()> {
+    let request = read_request(&mut BufReader::new(&mut stream));
+    let (status, body) = match request {
+        Ok(request) if !allowed_host(&request, port) => error(
+            403,
+            &format!(
+                "Unknown host {}, use 127.0.0.1:{} or localhost:{}",
+                request.host.as_deref().unwrap_or("-"),
+                port,
+                port
+            ),
+        ),
+        Ok(request) if request.method == "GET" && request.path == "/" => {
+            return write_response(&mut stream, 200, "text/html; charset=utf-8", PAGE);
+        }
+        Ok(request) if !same_origin(&request) => {
+            error(403, "Requests from other sites are not allowed")
+        }
+        Ok(request) if !sends_json(&request) => error(
+            415,
+            "Requests that change clusters need Content-Type: application/json",
+        ),
+        Ok(request) if !authorized(&request, token) => error(
+            401,
+            &format!(
+                "Missing or wrong token, send the one of {} as Authorization: Bearer <token>",
+                token_path().display()
+            ),
+        ),
+        Ok(request) => match route(exe, jobs, &request) {
+            Ok(response) => response,
+            Err(e) => error(500, &format!("{:#}", e)),
+        },
+        Err(e) => error(400, &format!("{:#}", e)),
+    };
+
+    write_response(&mut stream, status, "application/json", &body.to_string())
+}
+
+/// Serves the API on `listen` until interrupted, running the jobs with `exe`.
+pub fn serve(listen: &str, exe: &Path) -> Result<()> {
+    let address: SocketAddr = listen.parse().map_err(|_| {
+        anyhow!(
+            "Invalid address {}, expected like {}",
+            listen,
+            DEFAULT_LISTEN
+        )
+    })?;
+    if !address.ip().is_loopback() {
+        warn!(
+            "Listening on {}, anyone who can reach it can create and delete clusters",
+            address
+        );
+    }
+    let listener = TcpListener::bind(address)?;
+    let token = Arc::new(write_token()?);
+    // the token goes in the fragment, which browsers do not send
+    info!(
+        "Serving the hake API on {}, press Ctrl-C to stop",
+        Style::new()
+            .cyan()
+            .apply_to(format!("http://{}/#token={}", address, token))
+    );
+
+    let jobs: Jobs = Arc::new(Mutex::new(vec![]));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Could not accept a connection: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = stream
+            .set_read_timeout(Some(CLIENT_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(CLIENT_TIMEOUT)))
+        {
+            warn!("Could not set the timeouts of a connection: {}", e);
+            continue;
+        }
+        let exe = exe.to_path_buf();
+        let jobs = Arc::clone(&jobs);
+        let token = Arc::clone(&token);
+        thread::spawn(move || {
+            if let Err(e) = handle(&exe, &jobs, &token, address.port(), stream) {
+                warn!("Could not answer a request: {:#}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serve::{
+        allowed_host, apply_event, authorized, create_args, objects, read_request, same_origin,
+        selector, sends_json, CreateRequest, Job, Request,
+    };
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_read_request() {
+        let request = "POST /clusters HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer abc\r\nContent-Length: 15\r\n\r\n{\"name\":\"dev\"}\n";
+        assert_eq!(
+            read_request(&mut request.as_bytes()).unwrap(),
+            Request {
+                method: String::from("POST"),
+                path: String::from("/clusters"),
+                origin: None,
+                host: Some(String::from("localhost")),
+                content_type: None,
+                token: Some(String::from("abc")),
+                body: b"{\"name\":\"dev\"}\n".to_vec(),
+            }
+        );
+
+        let request = "GET /jobs HTTP/1.1\r\n\r\n";
+        assert!(read_request(&mut request.as_bytes())
+            .unwrap()
+            .body
+            .is_empty());
+        assert!(read_request(&mut "\r\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_same_origin() {
+        let request = |origin: Option<&str>| Request {
+            method: String::from("DELETE"),
+            path: String::from("/clusters/dev"),
+            origin: origin.map(String::from),
+            host: Some(String::from("127.0.0.1:7070")),
+            content_type: None,
+            token: None,
+            body: vec![],
+        };
+        assert!(same_origin(&request(None)));
+        assert!(same_origin(&request(Some("http://127.0.0.1:7070"))));
+        assert!(!same_origin(&request(Some("https://example.com"))));
+    }
+
+    #[test]
+    fn test_allowed_host() {
+        let request = |host: Option<&str>, content_type: Option<&str>| Request {
+            method: String::from("POST"),
+            path: String::from("/clusters"),
+            origin: None,
+            host: host.map(String::from),
+            content_type: content_type.map(String::from),
+            token: None,
+            body: vec![],
+        };
+        for host in [
+            "127.0.0.1:7070",
+            "localhost:7070",
+            "[::1]:7070",
+            "192.168.1.5:7070",
+        ] {
+            assert!(allowed_host(&request(Some(host), None), 7070));
+        }
+        assert!(!allowed_host(
+            &request(Some("attacker.example:7070"), None),
+            7070
+        ));
+        assert!(!allowed_host(&request(Some("localhost:8080"), None), 7070));
+        assert!(!allowed_host(&request(None, None), 7070));
+
+        assert!(sends_json(&request(None, Some("application/json"))));
+        assert!(!sends_json(&request(None, Some("text/plain"))));
+        assert!(!sends_json(&request(None, None)));
+    }
+
+    #[test]
+    fn test_authorized() {
+        let request = |token: Option<&str>| Request {
+            method: String::from("GET"),
+            path: String::from("/jobs"),
+            origin: None,
+            host: None,
+            content_type: None,
+            token: token.map(String::from),
+            body: vec![],
+        };
+        assert!(authorized(&request(Some("abc")), "abc"));
+        assert!(!authorized(&request(Some("abd")), "abc"));
+        assert!(!authorized(&request(Some("ab")), "abc"));
+        assert!(!authorized(&request(None), "abc"));
+    }
+
+    #[test]
+    fn test_create_args() {
+        let profiles = vec![String::from("ci")];
+        let request: CreateRequest = serde_json::from_str(
+            r#"{"name": "dev", "profile": "ci", "workers": 2, "labels": ["team=a"], "ttl": "4h"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            create_args(&request, &profiles).unwrap(),
+            vec![
+                "--profile",
+                "ci",
+                "--workers",
+                "2",
+                "--label",
+                "team=a",
+                "--ttl",
+                "4h"
+            ]
+        );
+
+        let unknown = CreateRequest {
+            profile: Some(String::from("other")),
+            ..Default::default()
+        };
+        assert!(create_args(&unknown, &profiles).is_err());
+        // raw arguments, like --mount, are not accepted
+        assert!(serde_json::from_str::<CreateRequest>(
+            r#"{"name": "dev", "args": ["--mount", "/:/host"]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_objects() {
+        let rows = vec![
+            vec![String::from("NAME"), String::from("STATUS")],
+            vec![String::from("dev"), String::from("running")],
+        ];
+        assert_eq!(
+            objects(&rows),
+            json!([{"name": "dev", "status": "running"}])
+        );
+    }
+
+    #[test]
+    fn test_selector() {
+        assert!(selector("").unwrap().is_empty());
+        let labels = selector("label=team=a&other=1&label=ticket=DEV-1").unwrap();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels["team"], "a");
+        assert!(selector("label=team").is_err());
+    }
+
+    #[test]
+    fn test_apply_event() {
+        let mut job = Job {
+            id: 1,
+            command: String::from("create"),
+            cluster: String::from("dev"),
+            status: String::from("running"),
+            phase: String::new(),
+            percent: 0,
+            message: String::new(),
+            error: None,
+            log: PathBuf::from("job-1.log"),
+            started_at: 1600000000,
+            finished_at: None,
+        };
+        apply_event(
+            &mut job,
+            r#"{"event":"phase","phase":"addons","percent":70,"message":"Installing addons","timestamp":1600000060}"#,
+        );
+        assert_eq!((job.phase.as_str(), job.percent), ("addons", 70));
+        assert_eq!(job.message, "Installing addons");
+
+        apply_event(&mut job, "apiVersion: v1");
+        apply_event(
+            &mut job,
+            r#"{"event":"error","phase":"addons","percent":70,"message":"helm failed","timestamp":1600000061}"#,
+        );
+        assert_eq!(job.error.as_deref(), Some("helm failed"));
+        assert_eq!(job.message, "Installing addons");
+    }
+}
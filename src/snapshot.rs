@@ -0,0 +1,193 @@
+// Snapshot of the workloads of a cluster, taken before its nodes are
+// recreated and applied again afterwards. Only the objects are kept, the data
+// in volumes is not.
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::error::HakeError;
+use crate::kubectl;
+
+/// Objects worth saving. Pods, replica sets and jobs are left out, their
+/// controllers create them again.
+const RESOURCES: &str = "namespaces,serviceaccounts,configmaps,secrets,services,\
+persistentvolumeclaims,deployments,statefulsets,daemonsets,cronjobs,ingresses,roles,rolebindings";
+
+/// Namespaces owned by Kubernetes or kind, recreated with the cluster.
+const SYSTEM_NAMESPACES: &[&str] = &[
+    "default",
+    "kube-system",
+    "kube-public",
+    "kube-node-lease",
+    "local-path-storage",
+];
+
+/// Whether an object was created by the cluster or a controller rather than
+/// by the user.
+fn is_generated(object: &Value) -> bool {
+    let metadata = &object["metadata"];
+    let name = metadata["name"].as_str().unwrap_or("");
+    let kind = object["kind"].as_str().unwrap_or("");
+
+    if metadata["ownerReferences"].is_array() {
+        return true;
+    }
+    if kind == "Namespace" {
+        return SYSTEM_NAMESPACES.contains(&name);
+    }
+    if let Some(namespace) = metadata["namespace"].as_str() {
+        if namespace != "default" && SYSTEM_NAMESPACES.contains(&namespace) {
+            return true;
+        }
+    }
+
+    match kind {
+        "Secret" => object["type"] == "kubernetes.io/service-account-token",
+        "ConfigMap" => name == "kube-root-ca.crt",
+        "ServiceAccount" => name == "default",
+        "Service" => name == "kubernetes",
+        _ => false,
+    }
+}
+
+/// Removes the fields set by the API server, which would conflict when the
+/// object is applied to a new cluster.
+fn clean(object: &mut Value) {
+    if let Some(metadata) = object["metadata"].as_object_mut() {
+        for field in &[
+            "uid",
+            "resourceVersion",
+            "creationTimestamp",
+            "selfLink",
+            "generation",
+            "managedFields",
+        ] {
+            metadata.remove(*field);
+        }
+        if let Some(annotations) = metadata
+            .get_mut("annotations")
+            .and_then(|a| a.as_object_mut())
+        {
+            annotations.retain(|key, _| {
+                !key.starts_with("kubectl.kubernetes.io/")
+                    && !key.starts_with("deployment.kubernetes.io/")
+                    && !key.starts_with("pv.kubernetes.io/")
+                    && !key.starts_with("volume.")
+            });
+        }
+    }
+    if let Some(object) = object.as_object_mut() {
+        object.remove("status");
+    }
+
+    let kind = object["kind"].as_str().unwrap_or("").to_string();
+    if let Some(spec) = object.get_mut("spec").and_then(|s| s.as_object_mut()) {
+        match kind.as_str() {
+            "Service" => {
+                spec.remove("clusterIP");
+                spec.remove("clusterIPs");
+            }
+            "PersistentVolumeClaim" => {
+                spec.remove("volumeName");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Turns the output of `kubectl get -o json` into a list with the objects of
+/// the user, ready to be applied.
+fn from_list(list: &Value) -> Value {
+    let items: Vec<Value> = list["items"]
+        .as_array()
+        .map(|items| items.as_slice())
+        .unwrap_or(&[])
+        .iter()
+        .filter(|object| !is_generated(object))
+        .cloned()
+        .map(|mut object| {
+            clean(&mut object);
+            object
+        })
+        .collect();
+
+    json!({"apiVersion": "v1", "kind": "List", "items": items})
+}
+
+/// Takes a snapshot of the workloads of a cluster.
+pub fn take(name: &str) -> Result<Value> {
+    let out = kubectl::output(name, &["get", RESOURCES, "--all-namespaces", "-o", "json"])?;
+    if !out.status.success() {
+        return Err(HakeError::CommandFailed {
+            command: String::from("kubectl get"),
+            message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    Ok(from_list(&serde_json::from_slice(&out.stdout)?))
+}
+
+/// Keeps a copy of a snapshot out of the cluster directory, which is removed
+/// with the cluster.
+pub fn save(name: &str, snapshot: &Value) -> Result<PathBuf> {
+    let path = env::temp_dir().join(format!("hake-{}-snapshot.json", name));
+    fs::write(&path, serde_json::to_string_pretty(snapshot)?)?;
+    // it has the secrets of the cluster
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    Ok(path)
+}
+
+/// Applies a snapshot to a cluster.
+pub fn restore(name: &str, snapshot: &Value) -> Result<()> {
+    let out = kubectl::apply_manifest(name, &serde_json::to_string(snapshot)?)?;
+    if !out.status.success() {
+        return Err(HakeError::CommandFailed {
+            command: String::from("kubectl apply"),
+            message: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::snapshot::from_list;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_list() {
+        let list = json!({"items": [
+            {"kind": "Namespace", "metadata": {"name": "kube-system"}},
+            {"kind": "Namespace", "metadata": {"name": "operator", "uid": "1"}, "status": {}},
+            {"kind": "Service", "metadata": {"name": "kubernetes", "namespace": "default"}},
+            {"kind": "Service", "metadata": {"name": "web", "namespace": "default",
+                "annotations": {"kubectl.kubernetes.io/last-applied-configuration": "{}", "team": "a"}},
+             "spec": {"clusterIP": "10.96.0.20", "ports": []}},
+            {"kind": "ConfigMap", "metadata": {"name": "coredns", "namespace": "kube-system"}},
+            {"kind": "Secret", "type": "kubernetes.io/service-account-token",
+             "metadata": {"name": "default-token-abcde", "namespace": "operator"}},
+            {"kind": "Deployment", "metadata": {"name": "child", "namespace": "operator",
+                "ownerReferences": [{"kind": "MyResource"}]}}
+        ]});
+
+        assert_eq!(
+            from_list(&list),
+            json!({"apiVersion": "v1", "kind": "List", "items": [
+                {"kind": "Namespace", "metadata": {"name": "operator"}},
+                {"kind": "Service", "metadata": {"name": "web", "namespace": "default",
+                    "annotations": {"team": "a"}}, "spec": {"ports": []}}
+            ]})
+        );
+    }
+}
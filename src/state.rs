@@ -0,0 +1,274 @@
+// State of every cluster hake manages, kept in a versioned file in the
+// configuration directory instead of taking every directory in it for a
+// cluster. The file is written whole and renamed into place, under a lock, so
+// parallel hake processes do not lose each other's changes.
+use anyhow::{anyhow, Context, Result};
+use fs2::FileExt;
+use serde_derive::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// Version of the format of the state file.
+pub const VERSION: u32 = 1;
+
+pub const CREATING: &str = "creating";
+pub const RUNNING: &str = "running";
+pub const STOPPED: &str = "stopped";
+/// Creation failed half-way and left files behind, `delete` removes them.
+pub const FAILED: &str = "failed";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClusterState {
+    pub provider: String,
+    pub status: String,
+    /// Time of the last change in seconds since the epoch.
+    pub updated_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct State {
+    pub version: u32,
+    pub clusters: BTreeMap<String, ClusterState>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            version: VERSION,
+            clusters: BTreeMap::new(),
+        }
+    }
+}
+
+/// Hidden so it is not taken for a cluster directory by older versions.
+fn state_path(dir: &Path) -> PathBuf {
+    dir.join(".state.json")
+}
+
+/// Files only a cluster directory has.
+const CLUSTER_FILES: [&str; 3] = ["kubeconfig", "metadata.json", "cluster_uuid"];
+
+/// Clusters in the layout of older versions, one directory per cluster.
+/// Directories without any cluster file are not clusters.
+fn scan(dir: &Path) -> BTreeMap<String, ClusterState> {
+    let mut clusters = BTreeMap::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return clusters,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if name.starts_with('.') || !path.is_dir() {
+            continue;
+        }
+        if !CLUSTER_FILES.iter().any(|file| path.join(file).exists()) {
+            debug!("Skipping {}, it is not a cluster", path.display());
+            continue;
+        }
+
+        let provider = if path.join("cluster_uuid").exists() {
+            "digitalocean"
+        } else {
+            "kind"
+        };
+        clusters.insert(
+            name,
+            ClusterState {
+                provider: String::from(provider),
+                status: String::from(RUNNING),
+                updated_at: crate::ttl::now(),
+            },
+        );
+    }
+
+    clusters
+}
+
+fn parse(contents: &str) -> Result<State> {
+    let state: State = serde_json::from_str(contents)?;
+    if state.version > VERSION {
+        return Err(anyhow!(
+            "The state was written by a newer hake (version {}), update hake",
+            state.version
+        ));
+    }
+
+    Ok(state)
+}
+
+fn read(dir: &Path) -> Result<State> {
+    let path = state_path(dir);
+    match fs::read_to_string(&path) {
+        Ok(contents) => parse(&contents).with_context(|| format!("Invalid {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State {
+            clusters: scan(dir),
+            ..Default::default()
+        }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write(dir: &Path, state: &State) -> Result<()> {
+    let path = state_path(dir);
+    let staging = dir.join(".state.json.tmp");
+    fs::write(&staging, serde_json::to_string_pretty(state)?)?;
+    fs::rename(&staging, &path)?;
+
+    Ok(())
+}
+
+/// Changes the state under a lock, migrating from the old layout the first
+/// time.
+fn update_in<F>(dir: &Path, change: F) -> Result<()>
+where
+    F: FnOnce(&mut State),
+{
+    fs::create_dir_all(dir)?;
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(".state.lock"))?;
+    lock.lock_exclusive()?;
+
+    let mut state = read(dir)?;
+    change(&mut state);
+    state.version = VERSION;
+
+    write(dir, &state)
+}
+
+fn update<F>(change: F) -> Result<()>
+where
+    F: FnOnce(&mut State),
+{
+    update_in(&crate::get_config_dir(), change)
+}
+
+/// Reads the state, migrating it from the old layout if there is no state
+/// file yet.
+pub fn load() -> Result<State> {
+    let dir = crate::get_config_dir();
+    if dir.exists() && !state_path(&dir).exists() {
+        update_in(&dir, |_| {})?;
+    }
+
+    read(&dir)
+}
+
+/// State of a cluster, None if hake does not manage it.
+pub fn get(name: &str) -> Option<ClusterState> {
+    match load() {
+        Ok(mut state) => state.clusters.remove(name),
+        Err(e) => {
+            warn!("Could not read the state: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Names of every cluster, sorted.
+pub fn names() -> Vec<String> {
+    match load() {
+        Ok(state) => state.clusters.into_keys().collect(),
+        Err(e) => {
+            warn!("Could not read the state: {:#}", e);
+            vec![]
+        }
+    }
+}
+
+/// Adds a cluster, or changes its status when it is already there.
+pub fn set(name: &str, provider: &str, status: &str) -> Result<()> {
+    update(|state| {
+        state.clusters.insert(
+            String::from(name),
+            ClusterState {
+                provider: String::from(provider),
+                status: String::from(status),
+                updated_at: crate::ttl::now(),
+            },
+        );
+    })
+}
+
+/// Changes the status of a cluster hake manages.
+pub fn set_status(name: &str, status: &str) -> Result<()> {
+    update(|state| {
+        if let Some(cluster) = state.clusters.get_mut(name) {
+            cluster.status = String::from(status);
+            cluster.updated_at = crate::ttl::now();
+        }
+    })
+}
+
+pub fn remove(name: &str) -> Result<()> {
+    update(|state| {
+        state.clusters.remove(name);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::{parse, read, update_in, ClusterState, RUNNING, VERSION};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_migration() {
+        let dir = env::temp_dir().join(format!("hake-state-{}", std::process::id()));
+        fs::create_dir_all(dir.join("kind-cluster")).unwrap();
+        fs::write(dir.join("kind-cluster/kubeconfig"), "").unwrap();
+        fs::create_dir_all(dir.join("do-cluster")).unwrap();
+        fs::write(dir.join("do-cluster/cluster_uuid"), "1234").unwrap();
+        fs::create_dir_all(dir.join("stray")).unwrap();
+        fs::create_dir_all(dir.join(".locks")).unwrap();
+
+        let state = read(&dir).unwrap();
+        let names: Vec<&String> = state.clusters.keys().collect();
+        assert_eq!(names, vec!["do-cluster", "kind-cluster"]);
+        assert_eq!(state.clusters["do-cluster"].provider, "digitalocean");
+        assert_eq!(state.clusters["kind-cluster"].status, RUNNING);
+
+        update_in(&dir, |state| {
+            state.clusters.remove("do-cluster");
+        })
+        .unwrap();
+        // the state file wins over the directories from now on
+        let state = read(&dir).unwrap();
+        assert_eq!(state.version, VERSION);
+        assert!(!state.clusters.contains_key("do-cluster"));
+        assert_eq!(
+            state
+                .clusters
+                .get("kind-cluster")
+                .map(|c| c.provider.as_str()),
+            Some("kind")
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        let state = parse(
+            r#"{"version": 1, "clusters": {"dev": {"provider": "kind", "status": "stopped", "updated_at": 1600000000}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            state.clusters["dev"],
+            ClusterState {
+                provider: String::from("kind"),
+                status: String::from("stopped"),
+                updated_at: 1600000000,
+            }
+        );
+
+        assert!(parse(r#"{"version": 2, "clusters": {}}"#).is_err());
+        assert!(parse("{}").is_err());
+    }
+}
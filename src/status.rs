@@ -0,0 +1,154 @@
+// Reports the health of a cluster: node containers, API server, node
+// readiness and kube-system pods that are not running.
+use anyhow::Result;
+use console::Style;
+
+use crate::error::HakeError;
+use crate::kind::Kind;
+use crate::kubectl;
+
+fn ok_or_fail(ok: bool, yes: &str, no: &str) -> String {
+    if ok {
+        Style::new().green().apply_to(yes).to_string()
+    } else {
+        Style::new().red().apply_to(no).to_string()
+    }
+}
+
+/// Parses the output of `kubectl get nodes` using the custom columns
+/// NAME and READY into a list of (node, ready) pairs.
+fn parse_node_readiness(output: &str) -> Vec<(String, bool)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                return None;
+            }
+            Some((fields[0].to_string(), fields[1] == "True"))
+        })
+        .collect()
+}
+
+fn node_containers(name: &str) -> Result<bool> {
+    let nodes = Kind::get_node_containers(name)?;
+    println!("Node containers:");
+    if nodes.is_empty() {
+        println!("  {}", ok_or_fail(false, "", "no containers found"));
+    }
+    for (container, state) in nodes.iter() {
+        println!(
+            "  {:<40} {}",
+            container,
+            ok_or_fail(state == "running", state, state)
+        );
+    }
+
+    Ok(!nodes.is_empty() && nodes.iter().all(|(_, state)| state == "running"))
+}
+
+/// Prints the readiness of the nodes, and returns whether they are all Ready.
+fn node_readiness(name: &str) -> Result<bool> {
+    let out = kubectl::output(
+        name,
+        &[
+            "get",
+            "nodes",
+            "--no-headers",
+            "-o",
+            r#"custom-columns=NAME:.metadata.name,READY:.status.conditions[?(@.type=="Ready")].status"#,
+        ],
+    )?;
+
+    let nodes = parse_node_readiness(&String::from_utf8_lossy(&out.stdout));
+    println!("Nodes:");
+    for (node, ready) in nodes.iter() {
+        println!("  {:<40} {}", node, ok_or_fail(*ready, "Ready", "NotReady"));
+    }
+
+    Ok(!nodes.is_empty() && nodes.iter().all(|(_, ready)| *ready))
+}
+
+fn system_pods(name: &str) -> Result<()> {
+    let out = kubectl::output(
+        name,
+        &[
+            "get",
+            "pods",
+            "--namespace",
+            "kube-system",
+            "--no-headers",
+            "--field-selector",
+            "status.phase!=Running,status.phase!=Succeeded",
+        ],
+    )?;
+
+    let pods = String::from_utf8_lossy(&out.stdout);
+    if pods.trim().is_empty() {
+        println!("System pods: {}", ok_or_fail(true, "all running", ""));
+    } else {
+        println!("System pods not running:");
+        for pod in pods.lines() {
+            println!("  {}", pod);
+        }
+    }
+
+    Ok(())
+}
+
+fn unhealthy(name: &str, reason: &str) -> anyhow::Error {
+    HakeError::Unhealthy {
+        name: String::from(name),
+        reason: String::from(reason),
+    }
+    .into()
+}
+
+/// Prints the health of a cluster, and fails with its own exit code when the
+/// node containers, the API server or the nodes are not up.
+pub fn status(name: &str, kind: bool) -> Result<()> {
+    let cyan = Style::new().cyan();
+    println!("Cluster: {}", cyan.apply_to(name));
+
+    if kind && !node_containers(name)? {
+        return Err(unhealthy(name, "its node containers are not running"));
+    }
+
+    let healthy = kubectl::api_server_healthy(name);
+    println!(
+        "API server: {}",
+        ok_or_fail(healthy, "reachable", "unreachable")
+    );
+    if !healthy {
+        return Err(unhealthy(name, "its API server is unreachable"));
+    }
+
+    let ready = node_readiness(name)?;
+    system_pods(name)?;
+    if !ready {
+        return Err(unhealthy(name, "not every node is Ready"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::status::parse_node_readiness;
+
+    #[test]
+    fn test_parse_node_readiness() {
+        assert_eq!(
+            parse_node_readiness("hake-control-plane   True\nhake-worker   False\n"),
+            vec![
+                (String::from("hake-control-plane"), true),
+                (String::from("hake-worker"), false)
+            ]
+        );
+        assert_eq!(
+            parse_node_readiness("hake-worker   <none>"),
+            vec![(String::from("hake-worker"), false)]
+        );
+        assert_eq!(parse_node_readiness(""), vec![]);
+    }
+}
@@ -0,0 +1,52 @@
+// Renders rows of text as a table with aligned columns.
+
+/// Renders the rows, the first one being the header, padding every column
+/// to the width of its widest cell.
+pub fn render(rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = vec![];
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            if widths.len() <= i {
+                widths.push(0);
+            }
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| format!("{:width$}", cell, width = widths[i]))
+            .collect();
+        table.push_str(cells.join("   ").trim_end());
+        table.push('\n');
+    }
+
+    table
+}
+
+pub fn print(rows: &[Vec<String>]) {
+    print!("{}", render(rows));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::table::render;
+
+    #[test]
+    fn test_render() {
+        let rows = vec![
+            vec![String::from("NAME"), String::from("STATUS")],
+            vec![String::from("hake-default"), String::from("running")],
+            vec![String::from("x"), String::from("")],
+        ];
+
+        assert_eq!(
+            render(&rows),
+            "NAME           STATUS\nhake-default   running\nx\n"
+        );
+        assert_eq!(render(&[]), "");
+    }
+}
@@ -0,0 +1,104 @@
+// Cluster time-to-live. The expiration time is stored in the cluster's
+// metadata as seconds since the epoch.
+use anyhow::{anyhow, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::metadata::ClusterMetadata;
+
+/// Longest duration accepted, 100 years: longer ones overflow once added to
+/// the current time, both here and for --timeout deadlines.
+const MAX_DURATION: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// Parses durations like 90s, 30m, 4h, 2d or 1h30m into seconds.
+pub fn parse_duration(ttl: &str) -> Result<u64> {
+    let mut total = 0;
+    let mut number = String::new();
+
+    for c in ttl.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let multiplier = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(anyhow!("Invalid unit '{}' in duration: {}", c, ttl)),
+        };
+        if number.is_empty() {
+            return Err(anyhow!("Missing value before '{}' in duration: {}", c, ttl));
+        }
+        total = number
+            .parse::<u64>()
+            .ok()
+            .and_then(|value| value.checked_mul(multiplier))
+            .and_then(|seconds| seconds.checked_add(total))
+            .filter(|total| *total <= MAX_DURATION)
+            .ok_or_else(|| anyhow!("Duration is too long: {}", ttl))?;
+        number.clear();
+    }
+
+    if !number.is_empty() || total == 0 {
+        return Err(anyhow!("Invalid duration: {}", ttl));
+    }
+
+    Ok(total)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System clock is before the epoch")
+        .as_secs()
+}
+
+/// Formats a number of seconds like kubectl does for ages: 45s, 10m, 3h, 2d.
+pub fn format_age(seconds: u64) -> String {
+    match seconds {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / (60 * 60)),
+        s => format!("{}d", s / (24 * 60 * 60)),
+    }
+}
+
+pub fn is_expired(name: &str) -> bool {
+    match ClusterMetadata::read(name).and_then(|metadata| metadata.expires_at) {
+        Some(expires_at) => expires_at <= now(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ttl::{format_age, parse_duration};
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(45), "45s");
+        assert_eq!(format_age(10 * 60 + 5), "10m");
+        assert_eq!(format_age(3 * 60 * 60), "3h");
+        assert_eq!(format_age(50 * 60 * 60), "2d");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s").unwrap(), 90);
+        assert_eq!(parse_duration("30m").unwrap(), 30 * 60);
+        assert_eq!(parse_duration("4h").unwrap(), 4 * 60 * 60);
+        assert_eq!(parse_duration("2d").unwrap(), 2 * 24 * 60 * 60);
+        assert_eq!(parse_duration("1h30m").unwrap(), 90 * 60);
+
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("4").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("4w").is_err());
+        assert!(parse_duration("0h").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+        assert!(parse_duration("9999999999999999d").is_err());
+        assert!(parse_duration("36500d1s").is_err());
+        assert_eq!(parse_duration("36500d").unwrap(), 36500 * 24 * 60 * 60);
+    }
+}
@@ -0,0 +1,314 @@
+// Terminal UI listing the clusters with their live status, with keys to act
+// on the selected one. The actions run hake itself outside of the UI, so
+// their output and prompts look the same as on the command line.
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cluster;
+use crate::kubectl;
+use crate::labels::Labels;
+
+/// How often the status of the clusters is refreshed.
+const REFRESH: Duration = Duration::from_secs(5);
+const KEYS: &str =
+    "↑↓ select  c create  d delete  s stop  t start  l logs  y copy KUBECONFIG  r refresh  q quit";
+
+/// What the keys do at the moment.
+enum Mode {
+    Browse,
+    /// Typing the name of a new cluster.
+    Create(String),
+    /// Asking for confirmation to delete a cluster.
+    Delete(String),
+}
+
+struct App {
+    /// Rows from `cluster::list_rows`, the first one being the header.
+    rows: Vec<Vec<String>>,
+    table: TableState,
+    mode: Mode,
+    message: String,
+}
+
+impl App {
+    fn new() -> App {
+        App {
+            rows: vec![],
+            table: TableState::default(),
+            mode: Mode::Browse,
+            message: String::from("Loading clusters..."),
+        }
+    }
+
+    fn clusters(&self) -> &[Vec<String>] {
+        self.rows.get(1..).unwrap_or(&[])
+    }
+
+    /// Replaces the rows, keeping the same cluster selected if it is still
+    /// there.
+    fn set_rows(&mut self, rows: Vec<Vec<String>>) {
+        let selected = self.selected().map(String::from);
+        self.rows = rows;
+        let index = selected
+            .and_then(|name| self.clusters().iter().position(|row| row[0] == name))
+            .or(if self.clusters().is_empty() {
+                None
+            } else {
+                Some(0)
+            });
+        self.table.select(index);
+        if self.message.starts_with("Loading") {
+            self.message.clear();
+        }
+    }
+
+    fn selected(&self) -> Option<&str> {
+        let row = self.clusters().get(self.table.selected()?)?;
+        Some(&row[0])
+    }
+
+    /// Moves the selection by `offset` rows, wrapping around.
+    fn move_selection(&mut self, offset: isize) {
+        let len = self.clusters().len() as isize;
+        if len == 0 {
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0) as isize;
+        self.table
+            .select(Some((current + offset).rem_euclid(len) as usize));
+    }
+}
+
+/// Escape sequence that makes the terminal copy text to the clipboard, which
+/// also works over ssh.
+pub(crate) fn clipboard_sequence(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64::encode(text))
+}
+
+/// Lists the clusters in the background, so slow provider APIs do not freeze
+/// the UI.
+fn refresh() -> Receiver<Vec<Vec<String>>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(cluster::list_rows(&Labels::new()));
+    });
+
+    receiver
+}
+
+/// Leaves the UI to run hake with `args`, and comes back once the user has
+/// read its output.
+fn run_outside(terminal: &mut DefaultTerminal, exe: &Path, args: &[&str]) -> Result<()> {
+    ratatui::restore();
+    println!("$ hake {}", args.join(" "));
+    let status = Command::new(exe).args(args).status();
+    match status {
+        Ok(status) if !status.success() => println!("hake exited with {}", status),
+        Err(e) => println!("Could not run hake: {}", e),
+        _ => {}
+    }
+    print!("Press Enter to go back");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    *terminal = ratatui::init();
+
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [list, status, keys] = Layout::vertical([
+        Constraint::Min(3),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let header = app.rows.first().cloned().unwrap_or_default();
+    let widths: Vec<Constraint> = header
+        .iter()
+        .enumerate()
+        .map(|(i, _)| {
+            let width = app
+                .rows
+                .iter()
+                .map(|row| row.get(i).map_or(0, |cell| cell.chars().count()))
+                .max()
+                .unwrap_or(0);
+            Constraint::Length(width as u16)
+        })
+        .collect();
+    let table = Table::new(
+        app.clusters().iter().map(|row| Row::new(row.clone())),
+        widths,
+    )
+    .header(Row::new(header).bold())
+    .column_spacing(3)
+    .row_highlight_style(Style::new().reversed())
+    .block(Block::bordered().title(" hake "));
+    frame.render_stateful_widget(table, list, &mut app.table);
+
+    let prompt = match &app.mode {
+        Mode::Browse => app.message.clone(),
+        Mode::Create(name) => format!("Name of the new cluster: {}_", name),
+        Mode::Delete(name) => format!("Delete cluster {}? (y/n)", name),
+    };
+    frame.render_widget(Paragraph::new(prompt).cyan(), status);
+    frame.render_widget(Paragraph::new(KEYS).dim(), keys);
+}
+
+/// Handles a key, returning false when the user quits.
+fn handle_key(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    exe: &Path,
+    key: KeyCode,
+) -> Result<bool> {
+    let selected = app.selected().map(String::from);
+    match (&mut app.mode, key) {
+        (Mode::Create(name), KeyCode::Char(c)) => name.push(c),
+        (Mode::Create(name), KeyCode::Backspace) => {
+            name.pop();
+        }
+        (Mode::Create(name), KeyCode::Enter) => {
+            let name = name.clone();
+            app.mode = Mode::Browse;
+            if !name.is_empty() {
+                run_outside(terminal, exe, &["create", "--name", &name])?;
+            }
+        }
+        (Mode::Delete(name), KeyCode::Char('y')) => {
+            let name = name.clone();
+            app.mode = Mode::Browse;
+            run_outside(terminal, exe, &["delete", "--name", &name])?;
+        }
+        (Mode::Create(_), _) | (Mode::Delete(_), _) => app.mode = Mode::Browse,
+        (Mode::Browse, KeyCode::Char('q')) | (Mode::Browse, KeyCode::Esc) => return Ok(false),
+        (Mode::Browse, KeyCode::Down) | (Mode::Browse, KeyCode::Char('j')) => app.move_selection(1),
+        (Mode::Browse, KeyCode::Up) | (Mode::Browse, KeyCode::Char('k')) => app.move_selection(-1),
+        (Mode::Browse, KeyCode::Char('c')) => app.mode = Mode::Create(String::new()),
+        (Mode::Browse, key) => {
+            let name = match selected {
+                Some(name) => name,
+                None => return Ok(true),
+            };
+            match key {
+                KeyCode::Char('d') => app.mode = Mode::Delete(name),
+                KeyCode::Char('s') => run_outside(terminal, exe, &["stop", "--name", &name])?,
+                KeyCode::Char('t') => run_outside(terminal, exe, &["start", "--name", &name])?,
+                KeyCode::Char('l') => {
+                    run_outside(terminal, exe, &["logs", "--name", &name, "--tail", "200"])?
+                }
+                KeyCode::Char('y') => {
+                    let path = kubectl::kubeconfig_path(&name);
+                    print!("{}", clipboard_sequence(&path.to_string_lossy()));
+                    io::stdout().flush()?;
+                    app.message = format!("Copied {}", path.display());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Runs the UI until the user quits. `exe` is the hake binary that runs the
+/// actions.
+pub fn run(exe: PathBuf) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &exe);
+    ratatui::restore();
+
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, exe: &Path) -> Result<()> {
+    let mut app = App::new();
+    let mut pending = Some(refresh());
+    let mut refreshed = Instant::now();
+
+    loop {
+        if let Some(receiver) = &pending {
+            if let Ok(rows) = receiver.try_recv() {
+                app.set_rows(rows);
+                pending = None;
+                refreshed = Instant::now();
+            }
+        } else if refreshed.elapsed() >= REFRESH {
+            pending = Some(refresh());
+        }
+
+        terminal.draw(|frame| draw(frame, &mut app))?;
+
+        if !event::poll(Duration::from_millis(200))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let (Mode::Browse, KeyCode::Char('r')) = (&app.mode, key.code) {
+                pending = pending.or_else(|| Some(refresh()));
+                continue;
+            }
+            let ran_action = matches!(app.mode, Mode::Create(_) | Mode::Delete(_))
+                || matches!(key.code, KeyCode::Char('s') | KeyCode::Char('t'));
+            if !handle_key(terminal, &mut app, exe, key.code)? {
+                return Ok(());
+            }
+            // the clusters changed, or are about to
+            if ran_action && pending.is_none() {
+                pending = Some(refresh());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::{clipboard_sequence, App};
+
+    fn rows(names: &[&str]) -> Vec<Vec<String>> {
+        let mut rows = vec![vec![String::from("NAME")]];
+        rows.extend(names.iter().map(|name| vec![name.to_string()]));
+        rows
+    }
+
+    #[test]
+    fn test_selection() {
+        let mut app = App::new();
+        assert_eq!(app.selected(), None);
+
+        app.set_rows(rows(&["a", "b", "c"]));
+        assert_eq!(app.selected(), Some("a"));
+        app.move_selection(-1);
+        assert_eq!(app.selected(), Some("c"));
+        app.move_selection(1);
+        assert_eq!(app.selected(), Some("a"));
+
+        app.move_selection(1);
+        app.set_rows(rows(&["0", "a", "b"]));
+        assert_eq!(app.selected(), Some("b"));
+        app.set_rows(rows(&["a"]));
+        assert_eq!(app.selected(), Some("a"));
+        app.set_rows(rows(&[]));
+        assert_eq!(app.selected(), None);
+    }
+
+    #[test]
+    fn test_clipboard_sequence() {
+        assert_eq!(clipboard_sequence("/a"), "\x1b]52;c;L2E=\x07");
+    }
+}
@@ -0,0 +1,224 @@
+// Keeps a cluster in line with a spec file: the options of `create` in YAML,
+// like
+//
+//     name: dev
+//     workers: 2
+//     with_ingress: nginx
+//     apply: [deploy/]
+//
+// The cluster is created if it does not exist, recreated when the nodes
+// change and has its addons installed again when only those change. Clusters
+// are only deleted to be recreated with --yes.
+use anyhow::{anyhow, Context, Result};
+use console::Style;
+use serde_yaml::{Mapping, Value};
+use tracing::{info, warn};
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::cluster::{self, CreateOptions, DEFAULT_NAME};
+use crate::config::UserConfig;
+use crate::lock;
+use crate::metadata::ClusterMetadata;
+use crate::names;
+
+pub const DEFAULT_SPEC: &str = "hake.yaml";
+/// How often the spec file is checked for changes.
+const POLL: Duration = Duration::from_secs(1);
+
+/// What it takes to bring a cluster to its spec.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    None,
+    Addons,
+    Recreate,
+}
+
+/// Options that can be compared with the ones recorded for a cluster,
+/// without the ones that are not recorded.
+fn comparable(opts: &CreateOptions) -> CreateOptions {
+    let mut opts = cluster::recorded_options(opts);
+    opts.verbose = false;
+    opts.reuse = false;
+    opts.count = None;
+    opts.parallel = 0;
+    opts.profile = None;
+
+    opts
+}
+
+/// The options that do not change the nodes of the cluster.
+fn without_addons(opts: &CreateOptions) -> CreateOptions {
+    let mut opts = comparable(opts);
+    opts.with_ingress = None;
+    opts.with_loadbalancer = false;
+    opts.with_dashboard = false;
+    opts.with_cert_manager = None;
+    opts.self_signed_issuer = false;
+    opts.with_olm = None;
+    opts.helm = vec![];
+    opts.apply = vec![];
+    opts.crds = vec![];
+    opts.namespaces = vec![];
+    opts.ecr_secrets = vec![];
+    opts.labels = vec![];
+    opts.preload = vec![];
+    opts.bootstrap = None;
+    opts.merge_kubeconfig = false;
+    opts.ttl = None;
+    opts.timeout = None;
+    opts.hooks = Default::default();
+
+    opts
+}
+
+pub fn plan(current: &CreateOptions, spec: &CreateOptions) -> Change {
+    if without_addons(current) != without_addons(spec) {
+        Change::Recreate
+    } else if comparable(current) != comparable(spec) {
+        Change::Addons
+    } else {
+        Change::None
+    }
+}
+
+/// Parses the options of a spec file. The options default every field they
+/// miss, so fields they do not have are refused here: a typo like `worker: 2`
+/// would otherwise reconcile the cluster to the defaults.
+fn parse_spec(contents: &str) -> Result<CreateOptions> {
+    let spec: Value = serde_yaml::from_str(contents)?;
+    if let Value::Mapping(fields) = &spec {
+        // the fields that are read, the skipped ones are not written either
+        let known = match serde_yaml::to_value(CreateOptions::default())? {
+            Value::Mapping(known) => known,
+            _ => Mapping::new(),
+        };
+        if let Some((field, _)) = fields.iter().find(|(field, _)| !known.contains_key(field)) {
+            return Err(anyhow!(
+                "unknown field `{}`",
+                field.as_str().unwrap_or_default()
+            ));
+        }
+    }
+
+    Ok(serde_yaml::from_value(spec)?)
+}
+
+/// Reads a spec file, filling the options it does not set from the user
+/// configuration like `create` does.
+pub fn read_spec(path: &Path) -> Result<CreateOptions> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let mut spec = parse_spec(&contents).with_context(|| format!("Invalid {}", path.display()))?;
+    if spec.name.is_empty() {
+        spec.name = String::from(DEFAULT_NAME);
+    }
+    UserConfig::load()?.apply(&mut spec)?;
+
+    Ok(spec)
+}
+
+/// Brings the cluster of a spec file to it. Recreating it needs `yes`.
+pub fn reconcile(path: &Path, verbose: bool, yes: bool) -> Result<()> {
+    let mut spec = read_spec(path)?;
+    spec.verbose = verbose;
+    let name = spec.name.clone();
+    if !cluster::cluster_exists(&name) {
+        names::validate(&name)?;
+    }
+    let _lock = lock::cluster(&name)?;
+
+    if !cluster::cluster_exists(&name) {
+        return cluster::create(spec);
+    }
+
+    // like adopted clusters, or the ones of older versions of hake
+    let current = ClusterMetadata::read(&name)
+        .and_then(|metadata| metadata.options)
+        .ok_or_else(|| {
+            anyhow!(
+                "Cluster {} has no recorded options to compare with the spec, delete it or use another name",
+                name
+            )
+        })?;
+    let change = plan(&current, &spec);
+    let cyan = Style::new().cyan();
+    match change {
+        Change::None => {
+            info!("Cluster is up to date: {}", cyan.apply_to(&name));
+            Ok(())
+        }
+        Change::Addons => cluster::update_addons(&name, &current, &spec),
+        Change::Recreate if !yes => Err(anyhow!(
+            "The nodes of cluster {} changed, it has to be deleted and created again: run with --yes to let watch do it",
+            name
+        )),
+        Change::Recreate => {
+            cluster::check_recreate(&spec)?;
+            cluster::delete(&name)?;
+            cluster::create(spec)
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reconciles the cluster every time the spec file changes, until
+/// interrupted. Failures are reported and wait for the next change.
+pub fn watch(path: &Path, verbose: bool, yes: bool) -> Result<()> {
+    let cyan = Style::new().cyan();
+    let mut last = None;
+    loop {
+        let current = modified(path);
+        if current.is_some() && current != last {
+            last = current;
+            info!("Reconciling: {}", cyan.apply_to(path.display()));
+            if let Err(e) = reconcile(path, verbose, yes) {
+                warn!("Could not reconcile {}: {:#}", path.display(), e);
+            }
+        }
+        thread::sleep(POLL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cluster::CreateOptions;
+    use crate::watch::{parse_spec, plan, Change};
+
+    #[test]
+    fn test_plan() {
+        let mut current = CreateOptions::new("dev");
+        current.workers = 1;
+        current.with_ingress = Some(None);
+
+        let mut spec = current.clone();
+        spec.verbose = true;
+        assert_eq!(plan(&current, &spec), Change::None);
+
+        spec.apply = vec![String::from("deploy/")];
+        assert_eq!(plan(&current, &spec), Change::Addons);
+
+        spec.workers = 2;
+        assert_eq!(plan(&current, &spec), Change::Recreate);
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let spec = parse_spec("name: dev\nworkers: 2\napply: [deploy/]\n").unwrap();
+        assert_eq!(spec.name, "dev");
+        assert_eq!(spec.workers, 2);
+        assert_eq!(spec.apply, vec![String::from("deploy/")]);
+
+        let err = parse_spec("name: dev\nworker: 2\n").unwrap_err();
+        assert_eq!(err.to_string(), "unknown field `worker`");
+        // skipped when recorded, so not read either
+        assert!(parse_spec("name: dev\nprofile: ci\n").is_err());
+        assert!(parse_spec("hooks:\n  pre_delet: [true]\n").is_err());
+    }
+}